@@ -0,0 +1,63 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use image::{DynamicImage, Rgba, RgbaImage};
+use webp_converter::encoder::{Encoder, WebpEncoder};
+use webp_converter::types::EncodeOptions;
+
+// A small icon with hard edges and transparency, typical of UI assets.
+fn small_icon() -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, y| {
+        let inside = (x as i32 - 32).pow(2) + (y as i32 - 32).pow(2) < 24 * 24;
+        if inside {
+            Rgba([(x * 4) as u8, 90, (y * 4) as u8, 255])
+        } else {
+            Rgba([0, 0, 0, 0])
+        }
+    }))
+}
+
+// A large opaque image with smooth gradients and fine noise, standing in for a photo.
+fn large_photo() -> DynamicImage {
+    let mut seed: u32 = 0x2545_f491;
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(1920, 1280, |x, y| {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        let noise = (seed % 24) as u8;
+        Rgba([
+            ((x * 255 / 1920) as u8).saturating_add(noise),
+            ((y * 255 / 1280) as u8).saturating_add(noise),
+            (((x + y) * 255 / 3200) as u8).saturating_add(noise),
+            255,
+        ])
+    }))
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let images = [("small_icon", small_icon()), ("large_photo", large_photo())];
+
+    for (name, img) in images.iter() {
+        let mut group = c.benchmark_group(format!("encode_webp/{}", name));
+        group.sample_size(10);
+        for lossless in [0, 1] {
+            for method in [4, 6] {
+                let options = EncodeOptions {
+                    lossless,
+                    method,
+                    ..EncodeOptions::default()
+                };
+                let id = format!(
+                    "{}/method{}",
+                    if lossless == 1 { "lossless" } else { "lossy" },
+                    method
+                );
+                group.bench_with_input(BenchmarkId::from_parameter(id), &options, |b, options| {
+                    b.iter(|| WebpEncoder.encode(img, options).expect("encoding failed"))
+                });
+            }
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_encode);
+criterion_main!(benches);
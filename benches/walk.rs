@@ -0,0 +1,50 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::fs;
+use std::hint::black_box;
+use std::time::Duration;
+use tempfile::TempDir;
+use webp_converter::estimate::estimate_batch;
+use webp_converter::helpers::walk_files;
+use webp_converter::types::ConversionOptions;
+
+const FILES: usize = 5_000;
+
+// A flat folder of tiny WebP files, which a run copies or counts at their size without
+// decoding them, so what is left to measure is the file system calls.
+fn folder() -> TempDir {
+    let dir = TempDir::new().expect("create a temporary directory");
+    for i in 0..FILES {
+        fs::write(dir.path().join(format!("{:05}.webp", i)), [0u8; 16]).unwrap();
+    }
+    dir
+}
+
+// `walk` is the one `stat` per file everything else reads from. `estimate_since` runs
+// `estimate_batch` with `--since`: the walk, the `--since` filter and the size of every
+// copy, which before `FileInfo` made a `stat` of their own each.
+fn bench_walk(c: &mut Criterion) {
+    let dir = folder();
+    let runtime = tokio::runtime::Runtime::new().expect("start a tokio runtime");
+    let options = ConversionOptions {
+        since: Some(Duration::from_secs(3600)),
+        ..ConversionOptions::default()
+    };
+    let mut group = c.benchmark_group(format!("walk/{}_files", FILES));
+    group.sample_size(20);
+    group.bench_function("walk", |b| {
+        b.iter(|| {
+            let files = walk_files(dir.path(), false, false, &[]);
+            black_box(files.iter().map(|file| file.size).sum::<u64>())
+        })
+    });
+    group.bench_function("estimate_since", |b| {
+        b.iter(|| {
+            let estimate = runtime.block_on(estimate_batch(dir.path(), false, &options));
+            black_box(estimate.input_bytes)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_walk);
+criterion_main!(benches);
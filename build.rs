@@ -1,5 +1,3 @@
-use winres;
-
 fn main() {
     build_icon();
 }
@@ -0,0 +1,76 @@
+use crate::encoder::{webp_config, Channels};
+use crate::types::{EncodeOptions, ErrorKind, WebpConverterError};
+use image::{DynamicImage, GenericImageView, RgbaImage};
+use webp::{AnimEncoder, AnimFrame};
+
+/// One frame of an animated GIF or APNG, composited onto the full canvas, and how long
+/// it shows.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub image: DynamicImage,
+    pub duration_ms: u32,
+}
+
+// Browsers show frames without a delay (common in GIFs) for 100ms rather than not at all.
+const DEFAULT_DURATION_MS: u32 = 100;
+
+impl From<image::Frame> for Frame {
+    fn from(frame: image::Frame) -> Self {
+        let (numerator, denominator) = frame.delay().numer_denom_ms();
+        let duration_ms = match numerator / denominator.max(1) {
+            0 => DEFAULT_DURATION_MS,
+            duration => duration,
+        };
+        Frame {
+            image: DynamicImage::ImageRgba8(frame.into_buffer()),
+            duration_ms,
+        }
+    }
+}
+
+/// Encodes `frames`, which all have the dimensions of the first one, as an animated WebP
+/// that loops forever. Every frame gets the libwebp settings a still image would from
+/// `options` and `--encode-config`; a target size or PSNR, `--roi` and the palette
+/// settings only apply to still images.
+pub fn encode(frames: &[Frame], options: &EncodeOptions) -> Result<Vec<u8>, WebpConverterError> {
+    let Some(first) = frames.first() else {
+        return Err(WebpConverterError {
+            message: "An animation needs at least one frame".to_string(),
+            kind: ErrorKind::Other,
+        });
+    };
+    let (width, height) = first.image.dimensions();
+    let config = webp::WebPConfig {
+        target_size: 0,
+        target_PSNR: 0.0,
+        ..webp_config(options)
+    };
+    let config = match &options.advanced {
+        Some(advanced) => advanced.apply(config),
+        None => config,
+    };
+    let pixels: Vec<RgbaImage> = frames
+        .iter()
+        .map(|frame| match options.channels {
+            Some(Channels::Rgb) => DynamicImage::ImageRgb8(frame.image.to_rgb8()).to_rgba8(),
+            _ => frame.image.to_rgba8(),
+        })
+        .collect();
+
+    let mut encoder = AnimEncoder::new(width, height, &config);
+    encoder.set_loop_count(0);
+    // Frames are placed by the time they start. The last one gets the average duration
+    // of the others: the `webp` crate closes the animation without an end time.
+    let mut timestamp = 0;
+    for (frame, rgba) in frames.iter().zip(&pixels) {
+        encoder.add_frame(AnimFrame::from_rgba(rgba, width, height, timestamp));
+        timestamp += frame.duration_ms as i32;
+    }
+    encoder
+        .try_encode()
+        .map(|memory| memory.to_vec())
+        .map_err(|e| WebpConverterError {
+            message: format!("libwebp couldn't encode the animation: {:?}", e),
+            kind: ErrorKind::Other,
+        })
+}
@@ -0,0 +1,96 @@
+use crate::types::{EncodeOptions, ErrorKind, WebpConverterError};
+use image::DynamicImage;
+
+#[cfg(feature = "avif")]
+impl From<ravif::Error> for WebpConverterError {
+    fn from(error: ravif::Error) -> Self {
+        WebpConverterError {
+            message: format!("AVIF Encoding Error: {}", error),
+            kind: ErrorKind::Other,
+        }
+    }
+}
+
+// Encodes `img` as AVIF with rav1e. `quality` is ravif's JPEG-like 1-100 scale and
+// `method` picks its speed. AV1 as ravif drives it has no lossless mode: `-l true` is
+// refused by the builder, and the lossless `-q 100` implies encodes at quality 100.
+// `target_size` and `noise_ratio` only exist in libwebp.
+#[cfg(feature = "avif")]
+pub fn encode(img: &DynamicImage, options: &EncodeOptions) -> Result<Vec<u8>, WebpConverterError> {
+    use ravif::{Img, RGB8, RGBA8};
+
+    let has_alpha = match options.channels {
+        Some(channels) => channels == crate::encoder::Channels::Rgba,
+        None => !options.no_alpha && img.color().has_alpha(),
+    };
+    let quality = if options.lossless == 1 {
+        100.0
+    } else {
+        options.quality.clamp(1.0, 100.0)
+    };
+    let encoder = ravif::Encoder::new()
+        .with_quality(quality)
+        .with_alpha_quality(quality)
+        .with_speed(speed(options.method));
+
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let encoded = if has_alpha {
+        let pixels: Vec<RGBA8> = img
+            .to_rgba8()
+            .pixels()
+            .map(|p| RGBA8::new(p[0], p[1], p[2], p[3]))
+            .collect();
+        encoder.encode_rgba(Img::new(&pixels[..], width, height))?
+    } else {
+        let pixels: Vec<RGB8> = img
+            .to_rgb8()
+            .pixels()
+            .map(|p| RGB8::new(p[0], p[1], p[2]))
+            .collect();
+        encoder.encode_rgb(Img::new(&pixels[..], width, height))?
+    };
+    Ok(encoded.avif_file)
+}
+
+#[cfg(not(feature = "avif"))]
+pub fn encode(
+    _img: &DynamicImage,
+    _options: &EncodeOptions,
+) -> Result<Vec<u8>, WebpConverterError> {
+    Err(unavailable())
+}
+
+// The size an AVIF file decodes to, for `--verify-output`. Nothing here decodes AV1, so
+// it is read from the `ispe` (image spatial extents) property every image item carries:
+// a box size, `ispe`, a version and flags word, then the width and height.
+pub fn decoded_dimensions(bytes: &[u8]) -> Result<(u32, u32), WebpConverterError> {
+    let read = |at: usize| {
+        bytes
+            .get(at..at + 4)
+            .map(|word| u32::from_be_bytes(word.try_into().unwrap()))
+    };
+    bytes
+        .windows(4)
+        .position(|window| window == b"ispe")
+        .and_then(|at| Some((read(at + 8)?, read(at + 12)?)))
+        .filter(|&(width, height)| width > 0 && height > 0)
+        .ok_or_else(|| WebpConverterError {
+            message: "it has no image size (`ispe`) property".to_string(),
+            kind: ErrorKind::Other,
+        })
+}
+
+// libwebp's `method` 0-6 over ravif's speeds 10 (fastest) to 4. The slower speeds
+// take many times longer for a few percent.
+#[cfg(feature = "avif")]
+fn speed(method: i32) -> u8 {
+    10 - method.clamp(0, 6) as u8
+}
+
+#[cfg(not(feature = "avif"))]
+fn unavailable() -> WebpConverterError {
+    WebpConverterError {
+        message: "AVIF output requires building with `--features avif`".to_string(),
+        kind: ErrorKind::Other,
+    }
+}
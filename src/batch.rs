@@ -0,0 +1,233 @@
+use crate::types::{
+    ConversionOptions, Crop, ErrorKind, Flip, QualityMap, Rotation, WebpConverterError,
+};
+use crate::wio;
+use clap::ValueEnum;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Every column a `--batch-spec` CSV may have. Only `path` is required; the others can
+/// be left out, or left empty on a row, to keep the command line's setting.
+pub const COLUMNS: [&str; 7] = [
+    "path", "quality", "resize", "crop", "rotate", "flip", "output",
+];
+
+/// One row of a `--batch-spec` file: an input and the settings that differ for it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BatchEntry {
+    // The line of the CSV the row is on, for messages about it.
+    pub line: usize,
+    pub path: PathBuf,
+    pub quality: Option<f32>,
+    pub resize: Option<bool>,
+    pub crop: Option<Crop>,
+    pub rotate: Option<Rotation>,
+    pub flip: Option<Flip>,
+    // Stem of the output file name, the extension still follows the output format.
+    pub output: Option<String>,
+}
+
+impl BatchEntry {
+    /// `options` with this row's settings in place of the command line's.
+    pub fn options(&self, options: &ConversionOptions) -> ConversionOptions {
+        let mut options = options.clone();
+        if let Some(quality) = self.quality {
+            options.quality = quality;
+            // A row's quality is for that file, whatever its extension.
+            options.quality_map = QualityMap::default();
+        }
+        if let Some(resize) = self.resize {
+            options.should_resize = resize;
+        }
+        options.crop = self.crop.or(options.crop);
+        options.rotate = self.rotate.or(options.rotate);
+        options.flip = self.flip.or(options.flip);
+        options.output_name = self.output.clone().or(options.output_name);
+        options
+    }
+}
+
+/// Reads the batch spec at `path`. Relative input paths are taken from the folder the
+/// spec is in, so a spec and its images can be moved together.
+pub fn read(path: &Path) -> Result<Vec<BatchEntry>, WebpConverterError> {
+    let text = fs::read_to_string(path).map_err(|e| WebpConverterError {
+        message: format!("Couldn't read the batch spec {:?}: {}", path, e),
+        kind: ErrorKind::Other,
+    })?;
+    let base = path.parent().unwrap_or(Path::new(""));
+    parse(&text, base).map_err(|e| WebpConverterError {
+        message: format!("Invalid batch spec {:?}:\n{}", path, e.message),
+        kind: ErrorKind::Other,
+    })
+}
+
+/// Parses a batch spec: a header row naming some of [`COLUMNS`], then one row per
+/// input. Blank lines are skipped. Every problem is reported, one per line, with the
+/// line it is on; nothing is returned unless the whole spec is valid.
+pub fn parse(text: &str, base: &Path) -> Result<Vec<BatchEntry>, WebpConverterError> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .filter(|(_, line)| !line.trim().is_empty());
+    let (header_line, header) = lines.next().ok_or_else(|| WebpConverterError {
+        message: "line 1: the spec is empty, expected a header like `path,quality,resize`"
+            .to_string(),
+        kind: ErrorKind::Other,
+    })?;
+    let columns = parse_header(header_line, header)?;
+
+    let mut entries = vec![];
+    let mut errors = vec![];
+    for (line, row) in lines {
+        match parse_row(line, row, &columns, base) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => errors.push(e),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(WebpConverterError {
+            message: errors.join("\n"),
+            kind: ErrorKind::Other,
+        });
+    }
+    if entries.is_empty() {
+        return Err(WebpConverterError {
+            message: format!("line {}: the spec lists no files", header_line),
+            kind: ErrorKind::Other,
+        });
+    }
+    Ok(entries)
+}
+
+fn parse_header(line: usize, header: &str) -> Result<Vec<String>, WebpConverterError> {
+    let error = |message: String| WebpConverterError {
+        message: format!("line {}: {}", line, message),
+        kind: ErrorKind::Other,
+    };
+    let columns: Vec<String> = split_row(header)
+        .map_err(error)?
+        .into_iter()
+        .map(|column| column.trim().to_ascii_lowercase())
+        .collect();
+    for (i, column) in columns.iter().enumerate() {
+        if !COLUMNS.contains(&column.as_str()) {
+            return Err(error(format!(
+                "unknown column `{}`, expected some of {}",
+                column,
+                COLUMNS.join(", ")
+            )));
+        }
+        if columns[..i].contains(column) {
+            return Err(error(format!("column `{}` is given twice", column)));
+        }
+    }
+    if !columns.iter().any(|column| column == "path") {
+        return Err(error("the header has no `path` column".to_string()));
+    }
+    Ok(columns)
+}
+
+fn parse_row(
+    line: usize,
+    row: &str,
+    columns: &[String],
+    base: &Path,
+) -> Result<BatchEntry, String> {
+    let error = |message: String| format!("line {}: {}", line, message);
+    let values = split_row(row).map_err(error)?;
+    if values.len() != columns.len() {
+        return Err(error(format!(
+            "expected {} values like the header, got {}",
+            columns.len(),
+            values.len()
+        )));
+    }
+
+    let mut entry = BatchEntry {
+        line,
+        ..BatchEntry::default()
+    };
+    for (column, value) in columns.iter().zip(&values) {
+        let value = value.trim();
+        if value.is_empty() {
+            if column == "path" {
+                return Err(error("`path` is empty".to_string()));
+            }
+            continue;
+        }
+        let invalid = |expected: &str| error(format!("`{}` {}, got `{}`", column, expected, value));
+        match column.as_str() {
+            "path" => entry.path = base.join(value),
+            "quality" => {
+                entry.quality = Some(
+                    value
+                        .parse()
+                        .ok()
+                        .filter(|q| (0.0..=100.0).contains(q))
+                        .ok_or_else(|| invalid("must be a number between 0 and 100"))?,
+                )
+            }
+            "resize" => {
+                entry.resize = Some(
+                    value
+                        .parse()
+                        .map_err(|_| invalid("must be true or false"))?,
+                )
+            }
+            "crop" => {
+                entry.crop = Some(
+                    value
+                        .parse()
+                        .map_err(|_| invalid("must be WIDTHxHEIGHT+X+Y, like 300x200+10+20"))?,
+                )
+            }
+            "rotate" => {
+                entry.rotate = Some(
+                    Rotation::from_str(value, true)
+                        .map_err(|_| invalid("must be 90, 180 or 270"))?,
+                )
+            }
+            "flip" => {
+                entry.flip =
+                    Some(Flip::from_str(value, true).map_err(|_| invalid("must be h or v"))?)
+            }
+            "output" => {
+                wio::validate_output_dir_name(value)
+                    .map_err(|_| invalid("must be a file name without a folder"))?;
+                entry.output = Some(value.to_string());
+            }
+            _ => unreachable!("the header only has known columns"),
+        }
+    }
+    Ok(entry)
+}
+
+// Splits one CSV row on commas. A value may be quoted to hold commas, with `""` for a
+// quote inside it; rows can't span lines.
+fn split_row(row: &str) -> Result<Vec<String>, String> {
+    let mut values = vec![];
+    let mut value = String::new();
+    let mut chars = row.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                value.push('"');
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if value.trim().is_empty() => {
+                value.clear();
+                quoted = true;
+            }
+            (',', false) => values.push(std::mem::take(&mut value)),
+            _ => value.push(c),
+        }
+    }
+    if quoted {
+        return Err("a quoted value is never closed".to_string());
+    }
+    values.push(value);
+    Ok(values)
+}
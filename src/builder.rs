@@ -0,0 +1,503 @@
+use crate::batch::BatchEntry;
+use crate::converter;
+use crate::encoder::{EncodeConfig, OutputFormat};
+use crate::types::{
+    ConversionOptions, ConversionRecord, ErrorKind, ReportFormat, ResizeMode, ResizeTarget,
+    Summary, TiffPages, WebpConverterError, DEFAULT_EFFORT,
+};
+use std::path::{Path, PathBuf};
+
+/// A converter whose options were checked by [`ConverterBuilder::build`], so every
+/// encode runs with settings that mean what they say.
+#[derive(Debug, Clone)]
+pub struct Converter {
+    options: ConversionOptions,
+}
+
+impl Converter {
+    pub fn builder() -> ConverterBuilder {
+        ConverterBuilder::default()
+    }
+
+    pub fn options(&self) -> &ConversionOptions {
+        &self.options
+    }
+
+    pub async fn convert_file(&self, path: &Path) -> Result<ConversionRecord, WebpConverterError> {
+        converter::convert_single_photo(path, &self.options).await
+    }
+
+    pub async fn convert_dir(&self, path: &Path, recursive: bool) -> Summary {
+        converter::convert_images_to_webp(path, recursive, &self.options).await
+    }
+
+    /// Converts files found by the caller's own walker (a manifest, a glob, stdin)
+    /// instead of walking `root`, which outputs and manifests are still placed
+    /// relative to. See [`converter::convert_paths`].
+    pub async fn convert_dir_with_walker<I>(&self, root: &Path, paths: I) -> Summary
+    where
+        I: IntoIterator<Item = PathBuf>,
+    {
+        converter::convert_paths(root, paths, &self.options).await
+    }
+
+    /// Converts the rows of a `--batch-spec`, see [`converter::convert_batch`].
+    pub async fn convert_batch(&self, root: &Path, entries: Vec<BatchEntry>) -> Summary {
+        converter::convert_batch(root, entries, &self.options).await
+    }
+
+    pub async fn convert_bytes(
+        &self,
+        bytes: &[u8],
+        file_name: &str,
+    ) -> Result<Vec<u8>, WebpConverterError> {
+        converter::convert_bytes(bytes, file_name, &self.options).await
+    }
+}
+
+/// Collects the encoding settings that depend on each other and resolves them in one
+/// place. Every setter records an explicit choice; `build` refuses choices that
+/// contradict each other instead of letting one of them silently win, and fills in the
+/// rest from what was chosen:
+///
+/// - lossless is used when asked for with `lossless(true)` or `lossless_effort`, or
+///   when nothing says otherwise and the quality is 100 with no compression factor;
+/// - the compression factor (`-c`, a target size of input size / factor) defaults to 0,
+///   no target size, so the PSNR target (`-n`, 40 by default) applies. libwebp aims for
+///   one or the other, so both can't be chosen.
+///
+/// The quality bounds of that search, `min_quality` and `max_quality`, are plain
+/// options; `build` only checks they are percentages in the right order.
+#[derive(Debug, Clone, Default)]
+pub struct ConverterBuilder {
+    options: ConversionOptions,
+    quality: Option<f32>,
+    lossless: Option<bool>,
+    lossless_effort: Option<f32>,
+    compression_factor: Option<f32>,
+    noise_ratio: Option<f32>,
+    effort: Option<i32>,
+    resize: Option<ResizeMode>,
+    encode_config: Option<EncodeConfig>,
+}
+
+impl ConverterBuilder {
+    /// Every other option. Its quality, lossless, compression factor, PSNR, lossless
+    /// effort and effort fields are replaced by what `build` resolves, and the resize
+    /// and encode config by `resize` and `encode_config` when those are set, whichever
+    /// order they are called in.
+    pub fn options(mut self, options: ConversionOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn quality(mut self, quality: f32) -> Self {
+        self.quality = Some(quality);
+        self
+    }
+
+    pub fn lossless(mut self, lossless: bool) -> Self {
+        self.lossless = Some(lossless);
+        self
+    }
+
+    pub fn lossless_effort(mut self, effort: f32) -> Self {
+        self.lossless_effort = Some(effort);
+        self
+    }
+
+    pub fn compression_factor(mut self, factor: f32) -> Self {
+        self.compression_factor = Some(factor);
+        self
+    }
+
+    // libwebp's `target_PSNR`, `-n`.
+    pub fn noise_ratio(mut self, psnr: f32) -> Self {
+        self.noise_ratio = Some(psnr);
+        self
+    }
+
+    pub fn effort(mut self, effort: i32) -> Self {
+        self.effort = Some(effort);
+        self
+    }
+
+    /// Fits every image to the resize target of the options (the 700x700 box by default)
+    /// like `-s true`, the way `mode` says.
+    pub fn resize(mut self, mode: ResizeMode) -> Self {
+        self.resize = Some(mode);
+        self
+    }
+
+    /// libwebp settings the derived ones can't express (filter strength and sharpness,
+    /// SNS, segments, a fixed target size, ...), applied over them like `--encode-config`.
+    pub fn encode_config(mut self, config: EncodeConfig) -> Self {
+        self.encode_config = Some(config);
+        self
+    }
+
+    pub fn build(mut self) -> Result<Converter, WebpConverterError> {
+        if let Some(mode) = self.resize {
+            self.options.should_resize = true;
+            self.options.resize_mode = mode;
+        }
+        if let Some(config) = self.encode_config {
+            self.options.encode_config = Some(config);
+        }
+        self.check_ranges()?;
+        self.check_conflicts()?;
+        let lossless_asked = self.lossless == Some(true) || self.lossless_effort.is_some();
+        check_features(&self.options, lossless_asked)?;
+
+        let mut options = self.options;
+        options.quality = self.quality.unwrap_or(75.0);
+        options.compression_factor = self.compression_factor.unwrap_or(0.0);
+        // Lossless used to be reachable only through `-q 100 -c 0`, where libwebp reads
+        // the quality as compression effort; that still works when `-l` isn't given.
+        let lossless = lossless_asked
+            || self
+                .lossless
+                .unwrap_or(options.compression_factor == 0.0 && options.quality >= 100.0);
+        options.lossless = i32::from(lossless);
+        options.lossless_effort = self.lossless_effort;
+        options.noise_ratio = self.noise_ratio.unwrap_or(40.0);
+        options.effort = self.effort.unwrap_or(DEFAULT_EFFORT);
+        Ok(Converter { options })
+    }
+
+    fn check_ranges(&self) -> Result<(), WebpConverterError> {
+        let percent = |name: &str, value: Option<f32>| match value {
+            Some(value) if !(0.0..=100.0).contains(&value) => Err(invalid(format!(
+                "{} must be between 0 and 100, got {}",
+                name, value
+            ))),
+            _ => Ok(()),
+        };
+        percent("Quality", self.quality)?;
+        percent("Lossless effort", self.lossless_effort)?;
+        let (min, max) = (self.options.min_quality, self.options.max_quality);
+        percent("--min-quality", Some(min))?;
+        percent("--max-quality", Some(max))?;
+        percent("--roi-quality", Some(self.options.roi_quality))?;
+        match self.options.resize_target {
+            ResizeTarget::Scale(percent) if !(percent > 0.0 && percent <= 100.0) => {
+                return Err(invalid(format!(
+                    "--scale must be above 0% and up to 100%, got {}%",
+                    percent
+                )));
+            }
+            ResizeTarget::Box { width, height } if width == Some(0) || height == Some(0) => {
+                return Err(invalid("The resize box needs sides of at least 1px"));
+            }
+            _ => {}
+        }
+        if let Some(config) = &self.options.encode_config {
+            config
+                .validate()
+                .map_err(|e| invalid(format!("Invalid encode config: {}", e)))?;
+        }
+        if min > max {
+            return Err(invalid(format!(
+                "--min-quality {} is above --max-quality {}, the quality search would have nowhere to go",
+                min, max
+            )));
+        }
+        if let Some(factor) = self
+            .compression_factor
+            .filter(|f| !f.is_finite() || *f < 0.0)
+        {
+            return Err(invalid(format!(
+                "The compression factor must be 0 or more, got {}",
+                factor
+            )));
+        }
+        if let Some(psnr) = self.noise_ratio.filter(|p| !p.is_finite() || *p < 0.0) {
+            return Err(invalid(format!(
+                "The target PSNR must be a positive number, got {}",
+                psnr
+            )));
+        }
+        if let Some(effort) = self.effort.filter(|e| !(0..=6).contains(e)) {
+            return Err(invalid(format!(
+                "Effort must be between 0 and 6, got {}",
+                effort
+            )));
+        }
+        Ok(())
+    }
+
+    fn check_conflicts(&self) -> Result<(), WebpConverterError> {
+        // `--lossless-effort` leaves `-q` and `-c` to the lossy files of `--auto-format`,
+        // only an explicit `-l true` rules them out.
+        let lossless_asked = self.lossless == Some(true);
+        if self.lossless == Some(false) && self.lossless_effort.is_some() {
+            return Err(invalid(
+                "--lossless-effort encodes losslessly and can't be combined with -l false",
+            ));
+        }
+        if let (true, Some(quality)) = (lossless_asked, self.quality.filter(|q| *q < 100.0)) {
+            return Err(invalid(format!(
+                "Lossless output and -q {} conflict: lossless never loses quality, use --lossless-effort to trade encode time for size",
+                quality
+            )));
+        }
+        let factor = self.compression_factor.filter(|f| *f != 0.0);
+        if let (true, Some(factor)) = (lossless_asked, factor) {
+            return Err(invalid(format!(
+                "Lossless output and -c {} conflict: a target size only applies to lossy output, use -c 0",
+                factor
+            )));
+        }
+        if let (Some(factor), Some(psnr)) = (factor, self.noise_ratio) {
+            return Err(invalid(format!(
+                "-c {} (a target size) and -n {} (a target PSNR) conflict: libwebp aims for only one of them",
+                factor, psnr
+            )));
+        }
+        match (self.options.resize_mode, self.options.resize_target) {
+            (ResizeMode::Fit, _) => {}
+            (_, ResizeTarget::Scale(_)) => {
+                return Err(invalid(
+                    "--scale only goes with --resize-mode fit: a percentage has no box to fill or side to shrink to",
+                ));
+            }
+            (ResizeMode::Fill, ResizeTarget::Box { width, height })
+                if width.is_none() || height.is_none() =>
+            {
+                return Err(invalid(
+                    "--resize-mode fill crops to an exact size, give both --max-width and --max-height or --max-dimension",
+                ));
+            }
+            (
+                ResizeMode::ShrinkLongest | ResizeMode::ShrinkShortest,
+                ResizeTarget::Box { width, height },
+            ) if width != height => {
+                return Err(invalid(
+                    "--resize-mode shrink-longest and shrink-shortest shrink one side to a length, give it with --max-dimension",
+                ));
+            }
+            _ => {}
+        }
+        if self.options.keep_icc && self.options.target_colorspace.is_some() {
+            return Err(invalid(
+                "--keep-icc and --target-colorspace conflict: the converted pixels no longer match the source's profile",
+            ));
+        }
+        if !self.options.sizes.is_empty() {
+            let sizes = &self.options.sizes;
+            if let Some(size) =
+                (1..sizes.len()).find_map(|i| sizes[..i].contains(&sizes[i]).then_some(sizes[i]))
+            {
+                return Err(invalid(format!("--sizes lists {} twice", size)));
+            }
+            let replaced = [
+                (self.options.should_resize, "-s"),
+                (!self.options.extra_formats.is_empty(), "several --formats"),
+                (self.options.reoptimize, "--reoptimize"),
+                (self.options.lqip, "--lqip"),
+                (
+                    self.options.min_savings_percent > 0.0,
+                    "--min-savings-percent",
+                ),
+            ];
+            if let Some((_, flag)) = replaced.iter().find(|(on, _)| *on) {
+                return Err(invalid(format!(
+                    "--sizes and {} conflict: every image is written once per width instead",
+                    flag
+                )));
+            }
+        }
+        if self.options.tiff_pages == Some(TiffPages::All) && self.options.min_savings_percent > 0.0
+        {
+            return Err(invalid(
+                "--tiff-pages all writes one output per page, there's no original page to keep for --min-savings-percent",
+            ));
+        }
+        if self.options.strip_metadata {
+            let keeping = [
+                (self.options.keep_exif, "--keep-exif"),
+                (self.options.keep_xmp, "--keep-xmp"),
+                (self.options.reoptimize, "--reoptimize"),
+                (
+                    self.options.min_savings_percent > 0.0,
+                    "--min-savings-percent",
+                ),
+            ];
+            if let Some((_, flag)) = keeping.iter().find(|(on, _)| *on) {
+                return Err(invalid(format!(
+                    "--strip-metadata and {} conflict: it would write outputs that keep the source's metadata",
+                    flag
+                )));
+            }
+        }
+        if self.options.deterministic {
+            let keeping = [
+                (self.options.keep_exif, "--keep-exif"),
+                (self.options.keep_icc, "--keep-icc"),
+                (self.options.keep_xmp, "--keep-xmp"),
+            ];
+            if let Some((_, flag)) = keeping.iter().find(|(on, _)| *on) {
+                return Err(invalid(format!(
+                    "--deterministic and {} conflict: deterministic outputs carry no metadata",
+                    flag
+                )));
+            }
+        }
+        if self.options.hash_names && self.options.overwrite_if_smaller {
+            return Err(invalid(
+                "--overwrite-if-smaller compares with the existing output, which --hash-names renames on every change",
+            ));
+        }
+        if let (true, Some(roi)) = (lossless_asked, self.options.roi) {
+            return Err(invalid(format!(
+                "Lossless output and --roi {} conflict: lossless keeps every region exact",
+                roi
+            )));
+        }
+        let skipping = [
+            (self.options.skip_existing, "--skip-existing"),
+            (self.options.cache.is_some(), "--cache"),
+        ];
+        for (_, flag) in skipping.iter().filter(|(on, _)| *on) {
+            if self.options.hash_names || self.options.output.is_some() {
+                return Err(invalid(format!(
+                    "{} looks for local outputs by name, which --hash-names and --output don't leave",
+                    flag
+                )));
+            }
+            if self.options.lqip || !self.options.extra_formats.is_empty() {
+                return Err(invalid(format!(
+                    "{} leaves files alone that the --lqip or --format manifests would then be missing",
+                    flag
+                )));
+            }
+        }
+        if self.options.clean_output && self.options.hash_names {
+            return Err(invalid(
+                "--clean-output can't map --hash-names outputs back to their sources",
+            ));
+        }
+        if self.options.clean_output && self.options.output.is_some() {
+            return Err(invalid(
+                "--clean-output only cleans local output folders and can't be used with --output",
+            ));
+        }
+        if self.options.output_dir.is_some() && self.options.output.is_some() {
+            return Err(invalid(
+                "--output-dir and --output both say where outputs go, pick one",
+            ));
+        }
+        if self.options.mirror && self.options.output_dir.is_none() {
+            return Err(invalid(
+                "--mirror recreates the input tree under --output-dir, which isn't set",
+            ));
+        }
+        if self.options.in_place
+            && (self.options.output.is_some() || self.options.output_dir.is_some())
+        {
+            return Err(invalid(
+                "--in-place writes next to the sources and can't be used with --output or --output-dir",
+            ));
+        }
+        if self.options.replace && !self.options.in_place {
+            return Err(invalid(
+                "--replace deletes the sources of in-place outputs and needs --in-place",
+            ));
+        }
+        if self.options.clean_output && self.options.output_dir.is_some() {
+            return Err(invalid(
+                "--clean-output cleans the output folders next to the sources and can't be used with --output-dir",
+            ));
+        }
+        if self.options.tar.is_some() && self.options.output.is_some() {
+            return Err(invalid(
+                "--tar archives local outputs and can't be used with --output",
+            ));
+        }
+        if self.options.tar.as_deref() == Some(Path::new("-"))
+            && self.options.report_format != ReportFormat::Text
+        {
+            return Err(invalid(
+                "--tar - and a JSON or CSV --report-format would both write to stdout",
+            ));
+        }
+        let formats: Vec<OutputFormat> = self.options.output_formats().collect();
+        if let Some(format) = formats
+            .iter()
+            .enumerate()
+            .find_map(|(i, format)| formats[..i].contains(format).then_some(format))
+        {
+            return Err(invalid(format!(
+                "--format lists {} twice",
+                format.extension()
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Options that need a cargo feature, or a format they don't apply to.
+fn check_features(
+    options: &ConversionOptions,
+    lossless_asked: bool,
+) -> Result<(), WebpConverterError> {
+    if options.output.is_some() && cfg!(not(feature = "s3")) {
+        return Err(invalid(
+            "--output s3://... requires building with `--features s3`",
+        ));
+    }
+    if options.target_colorspace.is_some() && cfg!(not(feature = "color")) {
+        return Err(invalid(
+            "--target-colorspace requires building with `--features color`",
+        ));
+    }
+    for format in options.output_formats() {
+        let built = match format {
+            OutputFormat::Webp => continue,
+            OutputFormat::Jxl => cfg!(feature = "jxl"),
+            OutputFormat::Avif => cfg!(feature = "avif"),
+        };
+        let name = format.extension();
+        if format == OutputFormat::Avif && lossless_asked {
+            return Err(invalid(
+                "--format avif has no lossless mode and can't be combined with -l true or --lossless-effort",
+            ));
+        }
+        if !built {
+            return Err(invalid(format!(
+                "--format {} requires building with `--features {}`",
+                name, name
+            )));
+        }
+        if options.encode_config.is_some() {
+            return Err(invalid(format!(
+                "--encode-config sets libwebp options and can't be used with --format {}",
+                name
+            )));
+        }
+        if options.roi.is_some() {
+            return Err(invalid(format!(
+                "--roi is built on libwebp and can't be used with --format {}",
+                name
+            )));
+        }
+    }
+    if options.roi.is_some() && options.encode_config.is_some() {
+        return Err(invalid(
+            "--encode-config is used exactly as written and can't be combined with --roi",
+        ));
+    }
+    if options.reoptimize && cfg!(not(feature = "reoptimize")) {
+        return Err(invalid(
+            "--reoptimize requires building with `--features reoptimize`",
+        ));
+    }
+    Ok(())
+}
+
+fn invalid<M: Into<String>>(message: M) -> WebpConverterError {
+    WebpConverterError {
+        message: message.into(),
+        kind: ErrorKind::Other,
+    }
+}
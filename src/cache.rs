@@ -0,0 +1,122 @@
+use crate::types::ConversionOptions;
+use colored::Colorize;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// File `--cache` keeps in the input folder when it isn't given one.
+pub const CACHE_FILE_NAME: &str = ".webp_converter_cache.json";
+
+/// What `--cache` knows of the files converted by earlier runs, keyed by their path
+/// relative to the input folder: the hash of their content and of the settings they
+/// were converted with, and where the output went. Clones share the same entries,
+/// like `Manifest`.
+#[derive(Debug, Clone, Default)]
+pub struct Cache {
+    entries: Arc<Mutex<BTreeMap<String, CacheEntry>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub source: String,
+    pub settings: String,
+    pub output: PathBuf,
+}
+
+impl Cache {
+    // The cache in `file`. A missing file is an empty cache, and so is one that can't be
+    // read: everything is converted again and the file rewritten at the end.
+    pub fn load(file: &Path) -> Cache {
+        let entries = match fs::read(file) {
+            Ok(json) => serde_json::from_slice(&json).unwrap_or_else(|e| {
+                warn!(
+                    "{}",
+                    format!("Ignoring the unreadable cache {:?}: {}", file, e).yellow()
+                );
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        Cache {
+            entries: Arc::new(Mutex::new(entries)),
+        }
+    }
+
+    // Sorted by key, so an unchanged folder always gives the same file.
+    pub fn save(&self, file: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(&*self.entries.lock().unwrap())?;
+        fs::write(file, json)
+    }
+
+    // The output recorded for `key` when it was converted from the same content with the
+    // same settings and is still there.
+    pub fn unchanged(&self, key: &str, entry: &CacheEntry) -> Option<PathBuf> {
+        let entries = self.entries.lock().unwrap();
+        let cached = entries.get(key)?;
+        (cached.source == entry.source && cached.settings == entry.settings)
+            .then(|| cached.output.clone())
+            .filter(|output| output.is_file())
+    }
+
+    pub fn record(&self, key: String, entry: CacheEntry) {
+        self.entries.lock().unwrap().insert(key, entry);
+    }
+}
+
+// Hex SHA-256 of a source's content.
+pub(crate) fn source_hash(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+// Hex SHA-256 of every setting that can change an output. What only shapes the run
+// (the files it picks, how it reports, the shared collectors) is left out; anything
+// else counts, options added later included, so a new setting never hits an entry
+// written without it.
+pub(crate) fn settings_hash(options: &ConversionOptions) -> String {
+    let defaults = ConversionOptions::default();
+    // A `HashMap` is printed in a different order every run.
+    let quality_map: BTreeMap<_, _> = options.quality_map.0.iter().collect();
+    let settings = ConversionOptions {
+        quality_map: defaults.quality_map,
+        jobs: defaults.jobs,
+        input_root: None,
+        manifest: defaults.manifest,
+        lqip_manifest: defaults.lqip_manifest,
+        formats_manifest: defaults.formats_manifest,
+        written: defaults.written,
+        cancellation: defaults.cancellation,
+        diff_report: false,
+        explain: false,
+        report_format: defaults.report_format,
+        respect_ignore: false,
+        exclude_dirs: vec![],
+        interactive: false,
+        yes: false,
+        timeout: None,
+        sample: None,
+        limit: None,
+        sort: None,
+        since: None,
+        verify_output: false,
+        copy_others: false,
+        clean_output: false,
+        quarantine: None,
+        tar: None,
+        replace: false,
+        progress: false,
+        skip_existing: false,
+        cache: None,
+        cached: defaults.cached,
+        ..options.clone()
+    };
+    hex(&Sha256::digest(format!("{:?} {:?}", settings, quality_map)))
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
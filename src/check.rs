@@ -0,0 +1,144 @@
+use crate::converter::resolve_concurrency;
+use crate::helpers;
+use crate::source::ImageSource;
+use crate::types::{ConversionOptions, FileInfo, WebpConverterError};
+use colored::Colorize;
+use log::{error, info, warn};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+use tokio::task::spawn_blocking;
+
+/// Outcome of a `--check` run.
+#[derive(Debug, Clone, Default)]
+pub struct Check {
+    // Images that decoded, header and pixels.
+    pub decoded: usize,
+    // Images that didn't, with the reason.
+    pub failures: Vec<(PathBuf, String)>,
+    // Files that can't be checked without converting them (PDFs, camera RAW files).
+    pub skipped: usize,
+}
+
+impl Check {
+    pub fn checked(&self) -> usize {
+        self.decoded + self.failures.len()
+    }
+}
+
+impl Display for Check {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Checked: {} file(s), {} decoded, {} failed, {} skipped",
+            self.checked(),
+            self.decoded,
+            self.failures.len(),
+            self.skipped
+        )
+    }
+}
+
+// Decodes every image under `path` like a conversion would, without encoding or writing
+// anything, and logs the ones that are corrupt or unsupported. WebP files that a
+// conversion would only copy are checked too.
+pub async fn check_batch(path: &Path, recursive: bool, options: &ConversionOptions) -> Check {
+    let semaphore = Arc::new(Semaphore::new(resolve_concurrency(options.jobs)));
+    let max_pixels = options.max_pixels;
+    let check = Arc::new(Mutex::new(Check::default()));
+
+    let mut tasks = vec![];
+    let mut files = helpers::walk_files(
+        path,
+        recursive,
+        options.respect_ignore,
+        &options.exclude_dirs,
+    );
+    if let Some(since) = options.since {
+        helpers::retain_modified_since(&mut files, since);
+    }
+    for file in files {
+        match helpers::which_action_for(&file.path, options.format) {
+            helpers::Actions::Convert
+                if helpers::is_raw(&file.path)
+                    || (cfg!(not(feature = "heif")) && helpers::is_heif(&file.path)) =>
+            {
+                check.lock().unwrap().skipped += 1
+            }
+            helpers::Actions::Convert | helpers::Actions::Copy => {
+                let semaphore = semaphore.clone();
+                let check = check.clone();
+                tasks.push(tokio::spawn(async move {
+                    let _permit = semaphore
+                        .acquire()
+                        .await
+                        .expect("Failed to acquire semaphore permit");
+                    let path = file.path.clone();
+                    let result = check_file(file, max_pixels).await;
+                    let mut check = check.lock().unwrap();
+                    match result {
+                        Ok(_) => check.decoded += 1,
+                        Err(e) => {
+                            warn!(
+                                "{}",
+                                format!("Can't decode {:?}: {}", path, e.message).yellow()
+                            );
+                            check.failures.push((path, e.message));
+                        }
+                    }
+                }));
+            }
+            helpers::Actions::Pdf => check.lock().unwrap().skipped += 1,
+            helpers::Actions::Nothing => {}
+        }
+    }
+
+    for task in tasks {
+        task.await.expect("Task failed to complete");
+    }
+
+    let mut check = check.lock().unwrap().clone();
+    // Tasks finish in any order, the report shouldn't.
+    check.failures.sort();
+    print_check(&check);
+    check
+}
+
+// Checks one image and returns its dimensions.
+pub async fn check_single_photo(
+    path: &Path,
+    max_pixels: u64,
+) -> Result<(u32, u32), WebpConverterError> {
+    check_file(FileInfo::read(path)?, max_pixels).await
+}
+
+// The header first, like a conversion reads it, then the pixels.
+async fn check_file(file: FileInfo, max_pixels: u64) -> Result<(u32, u32), WebpConverterError> {
+    spawn_blocking(move || {
+        let source = ImageSource::File(file);
+        let dimensions = source.dimensions()?;
+        source.decode(max_pixels)?;
+        Ok(dimensions)
+    })
+    .await?
+}
+
+fn print_check(check: &Check) {
+    if check.failures.is_empty() {
+        info!("{}", check.to_string().bright_green().bold());
+        return;
+    }
+    info!("{}", check.to_string().bright_cyan().bold());
+    let list: String = check
+        .failures
+        .iter()
+        .map(|(path, message)| format!("\n  {:?}: {}", path, message))
+        .collect();
+    error!(
+        "{}",
+        format!("{} file(s) can't be decoded:{}", check.failures.len(), list)
+            .red()
+            .bold()
+    );
+}
@@ -0,0 +1,92 @@
+use crate::types::{ColorSpace, ErrorKind, WebpConverterError};
+use image::codecs::jpeg::JpegDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::tiff::TiffDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::io::Reader as ImageReader;
+use image::{DynamicImage, ImageDecoder, ImageFormat};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Seek};
+use std::path::Path;
+
+// The embedded ICC profile of `path`, for the formats whose decoders expose one.
+pub fn read_icc_profile(path: &Path) -> Option<Vec<u8>> {
+    let reader = ImageReader::open(path).ok()?.with_guessed_format().ok()?;
+    let format = reader.format()?;
+    icc_profile(BufReader::new(File::open(path).ok()?), format)
+}
+
+// Same as `read_icc_profile`, for an image held in memory.
+pub fn icc_profile_from_bytes(bytes: &[u8]) -> Option<Vec<u8>> {
+    let format = image::guess_format(bytes).ok()?;
+    icc_profile(Cursor::new(bytes), format)
+}
+
+fn icc_profile<R: BufRead + Seek>(reader: R, format: ImageFormat) -> Option<Vec<u8>> {
+    match format {
+        ImageFormat::Jpeg => JpegDecoder::new(reader).ok()?.icc_profile(),
+        ImageFormat::Png => PngDecoder::new(reader).ok()?.icc_profile(),
+        ImageFormat::Tiff => TiffDecoder::new(reader).ok()?.icc_profile(),
+        ImageFormat::WebP => WebPDecoder::new(reader).ok()?.icc_profile(),
+        _ => None,
+    }
+}
+
+// Moves `image` from the color space described by `icc_profile` into `target`.
+// Images without an RGB profile are taken to be sRGB already and returned as they are.
+#[cfg(feature = "color")]
+pub fn convert_to_colorspace(
+    image: DynamicImage,
+    icc_profile: Option<&[u8]>,
+    target: ColorSpace,
+) -> Result<DynamicImage, WebpConverterError> {
+    use lcms2::{ColorSpaceSignature, Flags, Intent, PixelFormat, Profile, Transform};
+
+    let Some(icc_profile) = icc_profile else {
+        return Ok(image);
+    };
+    let source = Profile::new_icc(icc_profile)?;
+    // Decoders hand out RGB(A) even for CMYK or gray sources, their profile doesn't apply.
+    if source.color_space() != ColorSpaceSignature::RgbData {
+        return Ok(image);
+    }
+    let target = match target {
+        ColorSpace::Srgb => Profile::new_srgb(),
+    };
+
+    let mut rgba = image.to_rgba8();
+    let transform: Transform<[u8; 4], [u8; 4]> = Transform::new_flags(
+        &source,
+        PixelFormat::RGBA_8,
+        &target,
+        PixelFormat::RGBA_8,
+        Intent::Perceptual,
+        Flags::COPY_ALPHA,
+    )?;
+    let pixels: &mut [u8] = &mut rgba;
+    let (pixels, _) = pixels.as_chunks_mut::<4>();
+    transform.transform_in_place(pixels);
+    Ok(DynamicImage::ImageRgba8(rgba))
+}
+
+#[cfg(not(feature = "color"))]
+pub fn convert_to_colorspace(
+    _image: DynamicImage,
+    _icc_profile: Option<&[u8]>,
+    _target: ColorSpace,
+) -> Result<DynamicImage, WebpConverterError> {
+    Err(WebpConverterError {
+        message: "Color space conversion requires building with `--features color`".to_string(),
+        kind: ErrorKind::Other,
+    })
+}
+
+#[cfg(feature = "color")]
+impl From<lcms2::Error> for WebpConverterError {
+    fn from(error: lcms2::Error) -> Self {
+        WebpConverterError {
+            message: format!("Color Management Error: {}", error),
+            kind: ErrorKind::Other,
+        }
+    }
+}
@@ -0,0 +1,431 @@
+use crate::encoder::OutputFormat;
+use crate::helpers::Args;
+use crate::types::{
+    Background, ColorSpace, ErrorKind, Jobs, QualityMap, ResizeMode, WebpConverterError,
+};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, ValueEnum};
+use colored::Colorize;
+use log::{info, warn};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Conversion settings loaded with `--config`, from a TOML file or an http(s) URL.
+///
+/// Keys are named like the command line flags; anything given on the command line wins.
+///
+/// ```toml
+/// quality = 80
+/// compression-factor = 0
+/// jobs = 4
+/// format = "webp"
+///
+/// [quality-map]
+/// png = 90
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub recursive: Option<bool>,
+    pub quality: Option<f32>,
+    pub lossless: Option<bool>,
+    pub compression_factor: Option<f32>,
+    pub resize: Option<bool>,
+    pub resize_mode: Option<ResizeMode>,
+    pub noise_ratio: Option<f32>,
+    pub max_pixels: Option<u64>,
+    pub jobs: Option<Jobs>,
+    pub deterministic: Option<bool>,
+    pub pdf_dpi: Option<f32>,
+    pub min_savings_percent: Option<f32>,
+    pub quality_map: Option<QualityMap>,
+    pub ico_all_sizes: Option<bool>,
+    pub format: Option<Vec<OutputFormat>>,
+    pub respect_ignore: Option<bool>,
+    pub timeout: Option<Duration>,
+    pub normalize_extension: Option<bool>,
+    pub grayscale: Option<bool>,
+    pub no_alpha: Option<bool>,
+    pub background: Option<Background>,
+    pub target_colorspace: Option<ColorSpace>,
+    pub auto_format: Option<bool>,
+    pub effort: Option<i32>,
+    pub lossless_effort: Option<f32>,
+}
+
+// The file as written, before its values are checked.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RawConfig {
+    recursive: Option<bool>,
+    quality: Option<f32>,
+    lossless: Option<bool>,
+    compression_factor: Option<f32>,
+    resize: Option<bool>,
+    resize_mode: Option<String>,
+    noise_ratio: Option<f32>,
+    max_pixels: Option<u64>,
+    jobs: Option<RawJobs>,
+    deterministic: Option<bool>,
+    pdf_dpi: Option<f32>,
+    min_savings_percent: Option<f32>,
+    quality_map: Option<HashMap<String, f32>>,
+    ico_all_sizes: Option<bool>,
+    format: Option<String>,
+    respect_ignore: Option<bool>,
+    timeout: Option<f64>,
+    normalize_extension: Option<bool>,
+    grayscale: Option<bool>,
+    no_alpha: Option<bool>,
+    background: Option<String>,
+    target_colorspace: Option<String>,
+    auto_format: Option<bool>,
+    effort: Option<i32>,
+    lossless_effort: Option<f32>,
+}
+
+// `jobs = 4` and `jobs = "auto"` are both accepted.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RawJobs {
+    Count(usize),
+    Text(String),
+}
+
+impl Config {
+    // Parses and validates a config file, so a bad download is rejected before anything is applied.
+    pub fn parse(text: &str) -> Result<Config, String> {
+        let raw: RawConfig = toml::from_str(text).map_err(|e| e.to_string())?;
+
+        let percent = |key: &str, value: Option<f32>| match value {
+            Some(v) if !(0.0..=100.0).contains(&v) => {
+                Err(format!("`{}` must be between 0 and 100, got {}", key, v))
+            }
+            _ => Ok(value),
+        };
+        let non_negative = |key: &str, value: Option<f32>| match value {
+            Some(v) if !v.is_finite() || v < 0.0 => {
+                Err(format!("`{}` must be a positive number, got {}", key, v))
+            }
+            _ => Ok(value),
+        };
+
+        let jobs = match raw.jobs {
+            Some(RawJobs::Count(count)) => Some(count.to_string().parse::<Jobs>()?),
+            Some(RawJobs::Text(text)) => Some(text.parse::<Jobs>()?),
+            None => None,
+        };
+        let quality_map = match raw.quality_map {
+            Some(map) => Some(
+                map.iter()
+                    .map(|(extension, quality)| format!("{}={}", extension, quality))
+                    .collect::<Vec<_>>()
+                    .join(",")
+                    .parse::<QualityMap>()?,
+            ),
+            None => None,
+        };
+        let pdf_dpi = match non_negative("pdf-dpi", raw.pdf_dpi)? {
+            Some(0.0) => return Err("`pdf-dpi` must be a positive number, got 0".to_string()),
+            dpi => dpi,
+        };
+        let timeout = match raw.timeout {
+            Some(seconds) if seconds > 0.0 => Some(
+                Duration::try_from_secs_f64(seconds)
+                    .map_err(|_| format!("`timeout` of {} seconds is too long", seconds))?,
+            ),
+            Some(seconds) => {
+                return Err(format!(
+                    "`timeout` must be a positive number of seconds, got {}",
+                    seconds
+                ))
+            }
+            None => None,
+        };
+
+        Ok(Config {
+            recursive: raw.recursive,
+            quality: percent("quality", raw.quality)?,
+            lossless: raw.lossless,
+            compression_factor: non_negative("compression-factor", raw.compression_factor)?,
+            resize: raw.resize,
+            resize_mode: raw
+                .resize_mode
+                .map(|m| {
+                    ResizeMode::from_str(&m, true).map_err(|e| format!("`resize-mode`: {}", e))
+                })
+                .transpose()?,
+            noise_ratio: non_negative("noise-ratio", raw.noise_ratio)?,
+            max_pixels: raw.max_pixels,
+            jobs,
+            deterministic: raw.deterministic,
+            pdf_dpi,
+            min_savings_percent: percent("min-savings-percent", raw.min_savings_percent)?,
+            quality_map,
+            ico_all_sizes: raw.ico_all_sizes,
+            format: raw
+                .format
+                .map(|f| {
+                    f.split(',')
+                        .map(|f| {
+                            OutputFormat::from_str(f.trim(), true)
+                                .map_err(|e| format!("`format`: {}", e))
+                        })
+                        .collect()
+                })
+                .transpose()?,
+            respect_ignore: raw.respect_ignore,
+            timeout,
+            normalize_extension: raw.normalize_extension,
+            grayscale: raw.grayscale,
+            no_alpha: raw.no_alpha,
+            background: raw
+                .background
+                .map(|b| {
+                    b.parse::<Background>()
+                        .map_err(|e| format!("`background`: {}", e))
+                })
+                .transpose()?,
+            target_colorspace: raw
+                .target_colorspace
+                .map(|c| {
+                    ColorSpace::from_str(&c, true)
+                        .map_err(|e| format!("`target-colorspace`: {}", e))
+                })
+                .transpose()?,
+            auto_format: raw.auto_format,
+            effort: match raw.effort {
+                Some(effort) if !(0..=6).contains(&effort) => {
+                    return Err(format!("`effort` must be between 0 and 6, got {}", effort))
+                }
+                effort => effort,
+            },
+            lossless_effort: percent("lossless-effort", raw.lossless_effort)?,
+        })
+    }
+
+    // Fills in every setting that wasn't given on the command line. Returns the ids of
+    // the `Args` it filled in, for `--echo-settings`.
+    pub fn apply(self, args: &mut Args, matches: &ArgMatches) -> Vec<&'static str> {
+        let mut applied = Applied {
+            matches,
+            ids: vec![],
+        };
+
+        applied.pick("recursive", &mut args.recursive, self.recursive.map(Some));
+        applied.pick("quality", &mut args.quality, self.quality.map(Some));
+        applied.pick("lossless", &mut args.lossless, self.lossless.map(Some));
+        applied.pick(
+            "compression_factor",
+            &mut args.compression_factor,
+            self.compression_factor.map(Some),
+        );
+        applied.pick("resize", &mut args.resize, self.resize.map(Some));
+        applied.pick(
+            "resize_mode",
+            &mut args.resize_mode,
+            self.resize_mode.map(Some),
+        );
+        applied.pick("psnr", &mut args.psnr, self.noise_ratio.map(Some));
+        applied.pick(
+            "max_pixels",
+            &mut args.max_pixels,
+            self.max_pixels.map(Some),
+        );
+        applied.pick("jobs", &mut args.jobs, self.jobs.map(Some));
+        applied.pick("deterministic", &mut args.deterministic, self.deterministic);
+        applied.pick("pdf_dpi", &mut args.pdf_dpi, self.pdf_dpi.map(Some));
+        applied.pick(
+            "min_savings_percent",
+            &mut args.min_savings_percent,
+            self.min_savings_percent.map(Some),
+        );
+        applied.pick(
+            "quality_map",
+            &mut args.quality_map,
+            self.quality_map.map(Some),
+        );
+        applied.pick("ico_all_sizes", &mut args.ico_all_sizes, self.ico_all_sizes);
+        applied.pick("format", &mut args.format, self.format);
+        applied.pick(
+            "respect_ignore",
+            &mut args.respect_ignore,
+            self.respect_ignore,
+        );
+        applied.pick("timeout", &mut args.timeout, self.timeout.map(Some));
+        applied.pick(
+            "normalize_extension",
+            &mut args.normalize_extension,
+            self.normalize_extension,
+        );
+        applied.pick("grayscale", &mut args.grayscale, self.grayscale);
+        applied.pick("no_alpha", &mut args.no_alpha, self.no_alpha);
+        applied.pick(
+            "background",
+            &mut args.background,
+            self.background.map(Some),
+        );
+        applied.pick(
+            "target_colorspace",
+            &mut args.target_colorspace,
+            self.target_colorspace.map(Some),
+        );
+        applied.pick("auto_format", &mut args.auto_format, self.auto_format);
+        // `--fast` and `--best` are an effort too, the config doesn't override them.
+        if !applied.given_on_cli("fast") && !applied.given_on_cli("best") {
+            applied.pick("effort", &mut args.effort, self.effort.map(Some));
+        }
+        applied.pick(
+            "lossless_effort",
+            &mut args.lossless_effort,
+            self.lossless_effort.map(Some),
+        );
+        applied.ids
+    }
+}
+
+// The `Args` a config filled in while being applied.
+struct Applied<'a> {
+    matches: &'a ArgMatches,
+    ids: Vec<&'static str>,
+}
+
+impl Applied<'_> {
+    fn given_on_cli(&self, id: &str) -> bool {
+        self.matches.value_source(id) == Some(ValueSource::CommandLine)
+    }
+
+    // Sets `arg` to the config's `value` unless the command line gave it.
+    fn pick<T>(&mut self, id: &'static str, arg: &mut T, value: Option<T>) {
+        if let (false, Some(value)) = (self.given_on_cli(id), value) {
+            *arg = value;
+            self.ids.push(id);
+        }
+    }
+}
+
+// Loads `--config` from a local path or an http(s) URL.
+pub async fn load(source: &str) -> Result<Config, WebpConverterError> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        return load_remote(source).await;
+    }
+    let text = tokio::fs::read_to_string(source)
+        .await
+        .map_err(|e| WebpConverterError {
+            message: format!("Can't read config {}: {}", source, e),
+            kind: ErrorKind::Other,
+        })?;
+    Config::parse(&text).map_err(|e| invalid(source, e))
+}
+
+// Downloads a shared config, keeping the last good copy on disk so a run still
+// has the team's settings when the server can't be reached.
+async fn load_remote(url: &str) -> Result<Config, WebpConverterError> {
+    let cache_path = cache_path(url);
+    match fetch(url).await {
+        Ok(text) => {
+            let config = Config::parse(&text).map_err(|e| invalid(url, e))?;
+            // Without a cached copy the next offline run fails, but this one has its config.
+            if let Err(e) = write_cache(&cache_path, &text).await {
+                warn!(
+                    "{}",
+                    format!(
+                        "Couldn't cache the config from {} at {:?}: {}",
+                        url, cache_path, e
+                    )
+                    .yellow()
+                );
+            }
+            info!("{}", format!("Loaded config from {}", url).green());
+            Ok(config)
+        }
+        Err(e) if cache_path.exists() => {
+            warn!(
+                "{}",
+                format!(
+                    "Couldn't fetch config from {} ({}), using the cached copy {:?}",
+                    url, e.message, cache_path
+                )
+                .yellow()
+                .bold()
+            );
+            let text = tokio::fs::read_to_string(&cache_path).await?;
+            Config::parse(&text).map_err(|e| invalid(&cache_path.to_string_lossy(), e))
+        }
+        Err(e) => Err(WebpConverterError {
+            message: format!(
+                "Couldn't fetch config from {} and there is no cached copy: {}",
+                url, e.message
+            ),
+            kind: ErrorKind::Other,
+        }),
+    }
+}
+
+#[cfg(feature = "remote-config")]
+async fn fetch(url: &str) -> Result<String, WebpConverterError> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?;
+    Ok(client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?)
+}
+
+#[cfg(not(feature = "remote-config"))]
+async fn fetch(_url: &str) -> Result<String, WebpConverterError> {
+    Err(WebpConverterError {
+        message: "Loading --config from a URL requires building with `--features remote-config`"
+            .to_string(),
+        kind: ErrorKind::Other,
+    })
+}
+
+#[cfg(any(feature = "remote-config", feature = "remote-input"))]
+impl From<reqwest::Error> for WebpConverterError {
+    fn from(error: reqwest::Error) -> Self {
+        WebpConverterError {
+            message: format!("HTTP Error: {}", error),
+            kind: ErrorKind::Other,
+        }
+    }
+}
+
+fn invalid(source: &str, error: String) -> WebpConverterError {
+    WebpConverterError {
+        message: format!("Invalid config {}: {}", source, error),
+        kind: ErrorKind::Other,
+    }
+}
+
+async fn write_cache(cache_path: &Path, text: &str) -> std::io::Result<()> {
+    if let Some(parent) = cache_path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(cache_path, text).await
+}
+
+// One cache file per URL, under the platform's cache directory.
+fn cache_path(url: &str) -> PathBuf {
+    let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .unwrap_or_else(std::env::temp_dir);
+    let file_name: String = url
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    cache_dir.join("webp_converter").join(file_name)
+}
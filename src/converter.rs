@@ -0,0 +1,2861 @@
+use crate::animation::{self, Frame};
+use crate::batch::BatchEntry;
+use crate::cache::{self, Cache, CacheEntry};
+use crate::encoder::{Channels, Encoder, OutputFormat, WebpEncoder};
+use crate::metadata::{self, Metadata};
+use crate::progress::Progress;
+use crate::source::ImageSource;
+use crate::types::{
+    AutoFormat, Background, ConversionOptions, ConversionRecord, ConversionStatus, Crop,
+    EncodeOptions, ErrorKind, FileInfo, Flip, Jobs, ReportFormat, ResizeMode, ResizeTarget,
+    Rotation, Summary, TiffPages, WebpConverterError,
+};
+use crate::wio::WrittenFiles;
+use crate::{color, helpers, reoptimize, report, sprite, trace, types, wio};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use colored::Colorize;
+use futures::{Stream, StreamExt};
+use image::imageops::FilterType;
+use image::io::{Limits, Reader as ImageReader};
+use image::{ColorType, DynamicImage, GenericImageView, Rgb, RgbImage};
+use log::{error, info, warn};
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use sysinfo::System;
+use tokio::sync::Semaphore;
+use tokio::task::spawn_blocking;
+
+// Function to decide on using resized_img or img
+// Returns the encoded bytes along with the dimensions of the image they were encoded from.
+pub(crate) async fn decide_and_encode(
+    img: DynamicImage,
+    resized_img: DynamicImage,
+    options: EncodeOptions,
+    format: OutputFormat,
+) -> Result<(Vec<u8>, (u32, u32)), WebpConverterError> {
+    let original_dimensions = img.dimensions();
+    let resized_dimensions = resized_img.dimensions();
+    // `--roi` is given in the pixels of `img`.
+    let resized_options = EncodeOptions {
+        roi: options
+            .roi
+            .map(|roi| roi.scaled(original_dimensions, resized_dimensions)),
+        ..options
+    };
+    // Encode both images in memory to compare file sizes
+    let original_encoded = encode_image(img, options, format).await?;
+    let resized_encoded = encode_image(resized_img, resized_options, format).await?;
+    // Use the smaller one, or the original if sizes are equal
+    // This is a simplistic approach; you might choose based on other criteria
+    if resized_encoded.len() < original_encoded.len() {
+        Ok((resized_encoded, resized_dimensions))
+    } else {
+        Ok((original_encoded, original_dimensions))
+    }
+}
+
+// Writes an encoded output and, with `--verify-output`, decodes it again as `format` to
+// make sure it comes back at `dimensions`. A bad local output is deleted before the
+// error is returned; object storage outputs are checked before they are uploaded.
+async fn write_encoded_output(
+    source: &Path,
+    file_name: &OsStr,
+    bytes: &[u8],
+    dimensions: (u32, u32),
+    format: OutputFormat,
+    options: &ConversionOptions,
+) -> Result<PathBuf, WebpConverterError> {
+    if !options.verify_output {
+        return wio::write_output(source, file_name, bytes, options).await;
+    }
+    if options.output.is_some() {
+        verify_encoded(bytes, dimensions, file_name, format)?;
+        return wio::write_output(source, file_name, bytes, options).await;
+    }
+
+    let output_path = wio::write_output(source, file_name, bytes, options).await?;
+    verify_written(&output_path, dimensions, format).await?;
+    Ok(output_path)
+}
+
+/// Reads back the output written at `path` and checks it decodes as `format` at
+/// `dimensions`, deleting it if it doesn't. This is what `--verify-output` does after
+/// every local write.
+pub async fn verify_written(
+    path: &Path,
+    dimensions: (u32, u32),
+    format: OutputFormat,
+) -> Result<(), WebpConverterError> {
+    let written = tokio::fs::read(path).await?;
+    let file_name = path.file_name().unwrap_or(path.as_os_str());
+    if let Err(e) = verify_encoded(&written, dimensions, file_name, format) {
+        tokio::fs::remove_file(path).await?;
+        return Err(e);
+    }
+    Ok(())
+}
+
+// With `--overwrite-if-smaller`, the output an earlier run left for `file_name` and its
+// size, when the new `encoded_len` bytes don't beat it. The decision is logged whenever
+// there is an earlier output. Outputs in object storage aren't compared.
+async fn smaller_existing_output(
+    source: &Path,
+    file_name: &OsStr,
+    encoded_len: usize,
+    options: &ConversionOptions,
+) -> Option<(PathBuf, u64)> {
+    if !options.overwrite_if_smaller || options.output.is_some() {
+        return None;
+    }
+    let existing = wio::output_path_named(source, file_name, options);
+    let existing_size = tokio::fs::metadata(&existing).await.ok()?.len();
+    if existing_size <= encoded_len as u64 {
+        info!(
+            "{}",
+            format!(
+                "Keeping {:?}: it is {} bytes, the new encode is {}",
+                existing, existing_size, encoded_len
+            )
+            .yellow()
+        );
+        return Some((existing, existing_size));
+    }
+    info!(
+        "{}",
+        format!(
+            "Replacing {:?}: {} -> {} bytes",
+            existing, existing_size, encoded_len
+        )
+        .cyan()
+    );
+    None
+}
+
+fn verify_encoded(
+    bytes: &[u8],
+    dimensions: (u32, u32),
+    file_name: &OsStr,
+    format: OutputFormat,
+) -> Result<(), WebpConverterError> {
+    let decoded = format
+        .decoded_dimensions(bytes)
+        .map_err(|e| WebpConverterError {
+            message: format!(
+                "Output {:?} failed verification, it can't be decoded: {}",
+                file_name, e.message
+            ),
+            kind: ErrorKind::Other,
+        })?;
+    if decoded != dimensions {
+        return Err(WebpConverterError {
+            message: format!(
+                "Output {:?} failed verification, it decodes to {}x{} instead of {}x{}",
+                file_name, decoded.0, decoded.1, dimensions.0, dimensions.1
+            ),
+            kind: ErrorKind::Other,
+        });
+    }
+    Ok(())
+}
+
+pub async fn convert_images_to_webp<P: Into<PathBuf>>(
+    path: P,
+    recursive: bool,
+    options: &ConversionOptions,
+) -> Summary {
+    let path = path.into();
+    if options.clean_output {
+        if let Err(e) = wio::clean_output(&path, recursive, options) {
+            error!(
+                "{}",
+                format!("Failed to clean the stale outputs: {}", e)
+                    .red()
+                    .bold()
+            );
+        }
+    }
+    let mut files = helpers::walk_files(
+        &path,
+        recursive,
+        options.respect_ignore,
+        &options.exclude_dirs,
+    );
+    // An `--output-dir` inside the input folder holds outputs of earlier runs, not
+    // sources, and the `--cache` file isn't one either. Both folders are compared
+    // canonicalized, `./photos/out` is under `photos` too. An output dir that doesn't
+    // exist yet holds nothing.
+    if let (Some(output_dir), Ok(root)) = (&options.output_dir, fs::canonicalize(&path)) {
+        if let Ok(output_dir) = fs::canonicalize(output_dir) {
+            files.retain(|file| match file.path.strip_prefix(&path) {
+                Ok(relative) => !root.join(relative).starts_with(&output_dir),
+                Err(_) => true,
+            });
+        }
+    }
+    if let Some(cache) = &options.cache {
+        files.retain(|file| file.path != path.join(cache));
+    }
+    convert_files(path, files, Summary::default(), options).await
+}
+
+/// Converts `paths`, found by the caller instead of by walking a folder, exactly as
+/// if a walk of `root` had found them: outputs are placed relative to `root` and the
+/// manifests and summary are written for it. `--since`, `--sort` and `--sample` still
+/// apply. Paths that aren't readable files are counted as failed.
+pub async fn convert_paths<P, I>(root: P, paths: I, options: &ConversionOptions) -> Summary
+where
+    P: Into<PathBuf>,
+    I: IntoIterator<Item = PathBuf>,
+{
+    let mut summary = Summary::default();
+    let mut files = vec![];
+    for path in paths {
+        let error = match fs::metadata(&path) {
+            Ok(metadata) if metadata.is_file() => {
+                files.push(FileInfo::from_metadata(path, &metadata));
+                continue;
+            }
+            Ok(_) => WebpConverterError {
+                message: "Not a file".to_string(),
+                kind: ErrorKind::Other,
+            },
+            Err(e) => WebpConverterError::from(e),
+        };
+        error!(
+            "\n{}\n",
+            format!("Can't convert: {:?} {}", path, error).red().bold()
+        );
+        summary.fail(&path, &error);
+    }
+    convert_files(root.into(), files, summary, options).await
+}
+
+/// Converts exactly the files of a `--batch-spec`, each with its row's settings over
+/// `options`. `root` (the spec's folder) is where manifests are written. `--since`,
+/// `--sort` and `--sample` don't apply: the spec already says what to convert.
+pub async fn convert_batch<P: Into<PathBuf>>(
+    root: P,
+    entries: Vec<BatchEntry>,
+    options: &ConversionOptions,
+) -> Summary {
+    let root = root.into();
+    let options = ConversionOptions {
+        input_root: Some(root.clone()),
+        ..with_cache(&root, options)
+    };
+    let semaphore = Arc::new(Semaphore::new(resolve_concurrency(options.jobs)));
+    let summary = Arc::new(Mutex::new(Summary {
+        matched: entries.len(),
+        ..Summary::default()
+    }));
+    let stop = Arc::new(BatchStop::default());
+    let _abandon_on_drop = AbandonOnDrop(stop.clone());
+
+    let mut tasks = vec![];
+    for entry in entries {
+        let source = match ImageSource::open(&entry.path) {
+            Ok(source) => source,
+            Err(e) => {
+                let error = WebpConverterError::from(e);
+                error!(
+                    "\n{}\n",
+                    format!(
+                        "Can't convert: {:?} (line {}) {}",
+                        entry.path, entry.line, error
+                    )
+                    .red()
+                    .bold()
+                );
+                summary.lock().unwrap().fail(&entry.path, &error);
+                continue;
+            }
+        };
+        let options = entry.options(&options);
+        let semaphore = semaphore.clone();
+        let summary = summary.clone();
+        let stop = stop.clone();
+        tasks.push(tokio::spawn(async move {
+            let _permit = semaphore
+                .acquire()
+                .await
+                .expect("Failed to acquire semaphore permit");
+            convert_batch_source(&source, &options, &summary, &stop).await;
+        }));
+    }
+    for task in tasks {
+        task.await.expect("Task failed to complete");
+    }
+
+    finish_batch(&root, &summary, &options).await
+}
+
+// Everything a batch does once its files are known, `summary` holding what went wrong
+// while finding them.
+async fn convert_files(
+    path: PathBuf,
+    mut files: Vec<FileInfo>,
+    summary: Summary,
+    options: &ConversionOptions,
+) -> Summary {
+    let options = Arc::new(ConversionOptions {
+        input_root: Some(path.clone()),
+        written: WrittenFiles::default(),
+        ..with_cache(&path, options)
+    });
+    let max_concurrency = resolve_concurrency(options.jobs);
+    let semaphore = Arc::new(Semaphore::new(max_concurrency));
+    let summary = Arc::new(Mutex::new(summary));
+    // Set once the output disk is full, files that haven't started are skipped.
+    let stop = Arc::new(BatchStop::default());
+    let _abandon_on_drop = AbandonOnDrop(stop.clone());
+
+    let mut tasks = vec![];
+
+    if let Some(since) = options.since {
+        helpers::retain_modified_since(&mut files, since);
+    }
+    if let Some(order) = options.sort {
+        helpers::sort_files(&mut files, order);
+    }
+    let entries: Vec<(FileInfo, helpers::Actions)> = files
+        .into_iter()
+        .map(|file| {
+            let action = helpers::which_action_for(&file.path, options.format);
+            (file, action)
+        })
+        .collect();
+
+    let mut entries = match options.sample {
+        Some(sample) => sample_entries(entries, sample),
+        None => entries,
+    };
+    let colliding = take_colliding_outputs(&mut entries, &options);
+
+    // Other files `--copy-others` passes through count too, a folder holding only
+    // those isn't empty.
+    let matched = entries
+        .iter()
+        .filter(|(_, action)| passthrough(*action, &options) != helpers::Actions::Nothing)
+        .count()
+        + colliding.len();
+    for (file, error) in &colliding {
+        error!(
+            "\n{}\n",
+            format!("Can't convert: {:?} {}", file.path, error)
+                .red()
+                .bold()
+        );
+        summary.lock().unwrap().fail(&file.path, error);
+    }
+    if matched == 0 {
+        warn!(
+            "{}",
+            format!("No convertible images found under {:?}", path)
+                .yellow()
+                .bold()
+        );
+        let summary = summary.lock().unwrap().clone();
+        // Tools reading a JSON or CSV report still get one, with every count at zero.
+        if options.report_format != ReportFormat::Text || !summary.failures.is_empty() {
+            print_summary(&summary, options.report_format);
+        }
+        return summary;
+    }
+    summary.lock().unwrap().matched = matched;
+
+    if options.interactive && !confirm_plan(&path, &entries, &options) {
+        warn!("{}", "Aborted, nothing was converted.".yellow().bold());
+        let mut summary = summary.lock().unwrap().clone();
+        summary.declined = true;
+        return summary;
+    }
+    // Every overwrite question is asked before the first file starts, so the answers
+    // aren't mixed up with the log lines and progress of files already converting.
+    let mut declined = 0;
+    if options.interactive && !options.yes {
+        let asked = entries.len();
+        entries.retain(|(file, action)| keep_overwriting(file, *action, &options));
+        declined = asked - entries.len();
+        summary.lock().unwrap().skipped += declined;
+    }
+    let progress = Progress::start(matched - colliding.len() - declined, options.progress);
+
+    for (file, action) in entries {
+        let action = passthrough(action, &options);
+        if options.skip_existing {
+            if let Some(output) = up_to_date_output(&file, action, &options) {
+                info!(
+                    "{}",
+                    format!("Up to date, skipping: {:?} -> {:?}", file.path, output).bright_blue()
+                );
+                summary.lock().unwrap().skipped += 1;
+                progress.tick();
+                continue;
+            }
+        }
+
+        match action {
+            helpers::Actions::Pdf if cfg!(not(feature = "pdf")) => {
+                warn!(
+                    "\n{}\n",
+                    format!(
+                        "Skipping PDF {:?}: PDF support requires building with `--features pdf`",
+                        file.path
+                    )
+                    .yellow()
+                    .bold()
+                );
+                summary.lock().unwrap().skipped += 1;
+                progress.tick();
+            }
+            helpers::Actions::Convert
+                if cfg!(not(feature = "raw")) && helpers::is_raw(&file.path) =>
+            {
+                warn!(
+                    "\n{}\n",
+                    format!(
+                        "Skipping RAW {:?}: RAW support requires building with `--features raw`",
+                        file.path
+                    )
+                    .yellow()
+                    .bold()
+                );
+                summary.lock().unwrap().skipped += 1;
+                progress.tick();
+            }
+            helpers::Actions::Convert
+                if cfg!(not(feature = "heif")) && helpers::is_heif(&file.path) =>
+            {
+                warn!(
+                    "\n{}\n",
+                    format!(
+                        "Skipping HEIC/HEIF {:?}: HEIF support requires building with `--features heif`",
+                        file.path
+                    )
+                    .yellow()
+                    .bold()
+                );
+                summary.lock().unwrap().skipped += 1;
+                progress.tick();
+            }
+            helpers::Actions::Convert | helpers::Actions::Pdf => {
+                let sem_clone = semaphore.clone();
+                let options = options.clone();
+                let summary = summary.clone();
+                let stop = stop.clone();
+                let progress = progress.clone();
+                let source = ImageSource::File(file);
+
+                let task = tokio::task::spawn(async move {
+                    let _permit = sem_clone
+                        .acquire()
+                        .await
+                        .expect("Failed to acquire semaphore permit");
+                    convert_batch_source(&source, &options, &summary, &stop).await;
+                    progress.tick();
+                });
+
+                tasks.push(task);
+            }
+            helpers::Actions::Copy => {
+                let sem_clone = semaphore.clone();
+                let options = options.clone();
+                let summary = summary.clone();
+                let stop = stop.clone();
+                let progress = progress.clone();
+
+                let task = tokio::spawn(async move {
+                    let _permit = sem_clone
+                        .acquire()
+                        .await
+                        .expect("Failed to acquire semaphore permit");
+                    if stop.skip(&options, &summary) {
+                        progress.tick();
+                        return;
+                    }
+                    let located =
+                        has_stripped_location(&ImageSource::File(file.clone()), &options).await;
+                    match wio::copy_image_to_output_folder(&file.path, &options).await {
+                        Ok(output) => {
+                            let mut summary = summary.lock().unwrap();
+                            summary.record(&ConversionRecord {
+                                input: file.path.clone(),
+                                output,
+                                input_size: file.size,
+                                output_size: file.size,
+                                status: ConversionStatus::Copied,
+                                auto_format: None,
+                            });
+                            summary.located += located as usize;
+                        }
+                        Err(e) if e.is_disk_full() => {
+                            stop_on_full_disk(&file.path, &e, &stop.disk_full, &summary);
+                        }
+                        Err(e) => {
+                            error!(
+                                "\n{}\n",
+                                format!("Failed to copy: {:?} {}", file.path, e)
+                                    .red()
+                                    .bold()
+                            );
+                            summary.lock().unwrap().fail(&file.path, &e);
+                        }
+                    }
+                    progress.tick();
+                });
+
+                tasks.push(task);
+            }
+            helpers::Actions::Nothing => warn!(
+                "\n{}\n",
+                format!("Not a valid image file: {:?}", file.path)
+                    .yellow()
+                    .bold()
+            ),
+        }
+    }
+
+    // Await all tasks to complete
+    for task in tasks {
+        task.await.expect("Task failed to complete");
+    }
+    progress.finish();
+    // A cancelled run leaves the output folder as it is, whatever it wrote included.
+    if !summary.lock().unwrap().cancelled {
+        if let Err(e) = wio::cleanup(&options) {
+            error!("Failed to clean up empty outputs: {}", e);
+        }
+    }
+
+    finish_batch(&path, &summary, &options).await
+}
+
+// `options` with the `--cache` of a run started in `root` loaded, its file resolved
+// against `root`.
+fn with_cache(root: &Path, options: &ConversionOptions) -> ConversionOptions {
+    let cache = options.cache.as_ref().map(|file| root.join(file));
+    ConversionOptions {
+        cached: cache.as_deref().map(Cache::load).unwrap_or_default(),
+        cache,
+        ..options.clone()
+    }
+}
+
+// Writes the manifests of a batch whose files are all done and prints its summary.
+async fn finish_batch(
+    path: &Path,
+    summary: &Mutex<Summary>,
+    options: &ConversionOptions,
+) -> Summary {
+    if let Err(e) = wio::write_manifests(path, options).await {
+        error!("Failed to write a manifest: {}", e);
+    }
+    if let Some(file) = &options.cache {
+        if let Err(e) = options.cached.save(file) {
+            error!("Failed to write the cache {:?}: {}", file, e);
+        }
+    }
+    if let Some(dest) = &options.tar {
+        write_tar(dest, path, options);
+    }
+
+    let summary = summary.lock().unwrap().clone();
+    print_summary(&summary, options.report_format);
+    if options.diff_report {
+        eprint!("{}", report::diff_report(&summary));
+    }
+    summary
+}
+
+// Writes the `--tar` archive of a run started in `root`, logging how it went.
+pub fn write_tar(dest: &Path, root: &Path, options: &ConversionOptions) {
+    match wio::write_tar(dest, root, options) {
+        Ok(count) => info!(
+            "{}",
+            format!("Archived {} file(s) to {:?}", count, dest)
+                .bright_blue()
+                .bold()
+        ),
+        Err(e) => error!(
+            "{}",
+            format!("Failed to write the tar archive {:?}: {}", dest, e)
+                .red()
+                .bold()
+        ),
+    }
+}
+
+// Converts one file of a batch and records how it went in `summary`. Files that start
+// once the batch is stopping are skipped.
+async fn convert_batch_source(
+    source: &ImageSource,
+    options: &ConversionOptions,
+    summary: &Mutex<Summary>,
+    stop: &BatchStop,
+) {
+    if stop.skip(options, summary) {
+        return;
+    }
+    let cached = cache_entry(source, options).await;
+    if let Some((key, entry)) = &cached {
+        if let Some(output) = options.cached.unchanged(key, entry) {
+            info!(
+                "{}",
+                format!(
+                    "Unchanged since cached, skipping: {:?} -> {:?}",
+                    source.name(),
+                    output
+                )
+                .bright_blue()
+            );
+            summary.lock().unwrap().skipped += 1;
+            return;
+        }
+    }
+    // Read up front, `--in-place` may have removed the source by the time it's converted.
+    let located = has_stripped_location(source, options).await;
+    match convert_source_with_fallback(source, options).await {
+        Ok(record) => {
+            info!("\n{}\n", record.to_string().bright_green().bold());
+            if let Some((key, entry)) = cached {
+                let output = record.output.clone();
+                options.cached.record(key, CacheEntry { output, ..entry });
+            }
+            let mut summary = summary.lock().unwrap();
+            summary.record(&record);
+            summary.located += located as usize;
+            stop.succeed();
+        }
+        Err(e) if e.is_disk_full() => {
+            stop_on_full_disk(source.name(), &e, &stop.disk_full, summary);
+        }
+        Err(e) if e.is_timeout() => {
+            error!(
+                "\n{}\n",
+                format!("Gave up on: {:?} {}", source.name(), e)
+                    .red()
+                    .bold()
+            );
+            summary.lock().unwrap().fail(source.name(), &e);
+        }
+        Err(e) if e.is_resize_refused() => {
+            warn!("\n{}\n", format!("Skipped: {}", e.message).yellow().bold());
+            summary.lock().unwrap().skipped += 1;
+        }
+        Err(e) if e.is_broken_source() => set_aside(source, &e, options, summary),
+        Err(e) => {
+            error!(
+                "\n{}\n",
+                format!("Failed to convert: {:?} {:?}", source.name(), e)
+                    .red()
+                    .bold()
+            );
+            summary.lock().unwrap().fail(source.name(), &e);
+        }
+    }
+}
+
+// Whether `source` has a GPS location that `--strip-metadata` keeps out of its output.
+// Only read with `--strip-metadata`, for its summary.
+async fn has_stripped_location(source: &ImageSource, options: &ConversionOptions) -> bool {
+    options.strip_metadata
+        && source
+            .read_bytes()
+            .await
+            .is_ok_and(|bytes| Metadata::read(&bytes).has_location())
+}
+
+// The `--cache` key of a walked `source` and what it is about to be converted from and
+// with. The output isn't known yet. `None` without `--cache`, or when the file can't be
+// read, which the conversion will then report.
+async fn cache_entry(
+    source: &ImageSource,
+    options: &ConversionOptions,
+) -> Option<(String, CacheEntry)> {
+    let ImageSource::File(file) = source else {
+        return None;
+    };
+    options.cache.as_ref()?;
+    let bytes = source.read_bytes().await.ok()?;
+    let entry = CacheEntry {
+        source: cache::source_hash(&bytes),
+        settings: cache::settings_hash(options),
+        output: PathBuf::new(),
+    };
+    Some((wio::source_key(&file.path, options), entry))
+}
+
+// Why the files of a batch that haven't started yet are skipped, shared by its tasks.
+#[derive(Debug, Default)]
+struct BatchStop {
+    // The output disk is full, see `stop_on_full_disk`.
+    disk_full: AtomicBool,
+    // The batch's future was dropped, nobody waits for its files any more.
+    abandoned: AtomicBool,
+    // Files that were encoded, for `--limit`. Plain copies don't count.
+    succeeded: AtomicUsize,
+}
+
+impl BatchStop {
+    // Counts a file that is about to start as skipped if the batch is stopping. Files
+    // already running when `--limit` is reached still finish, so it can be overshot by
+    // up to `--jobs` - 1.
+    fn skip(&self, options: &ConversionOptions, summary: &Mutex<Summary>) -> bool {
+        let cancelled =
+            options.cancellation.is_cancelled() || self.abandoned.load(Ordering::SeqCst);
+        let limit = options
+            .limit
+            .filter(|limit| self.succeeded.load(Ordering::SeqCst) >= *limit);
+        if !cancelled && !self.disk_full.load(Ordering::SeqCst) && limit.is_none() {
+            return false;
+        }
+        let mut summary = summary.lock().unwrap();
+        summary.skipped += 1;
+        summary.cancelled |= cancelled;
+        summary.stopped_at_limit = summary.stopped_at_limit.or(limit);
+        true
+    }
+
+    fn succeed(&self) {
+        self.succeeded.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+// Held by a running batch. Its tasks are spawned, so they would carry on converting
+// if the batch's future were dropped half way; this makes the ones not started skip.
+struct AbandonOnDrop(Arc<BatchStop>);
+
+impl Drop for AbandonOnDrop {
+    fn drop(&mut self) {
+        self.0.abandoned.store(true, Ordering::SeqCst);
+    }
+}
+
+// Records an empty or truncated source apart from the failures and, with `--quarantine`,
+// moves it out of the way so the next run doesn't trip on it again.
+fn set_aside(
+    source: &ImageSource,
+    error: &WebpConverterError,
+    options: &ConversionOptions,
+    summary: &Mutex<Summary>,
+) {
+    let moved = match (source, &options.quarantine) {
+        (ImageSource::File(file), Some(dir)) => match wio::quarantine(&file.path, dir, options) {
+            Ok(target) => format!(", moved to {:?}", target),
+            Err(e) => format!(", couldn't move it to {:?}: {}", dir, e),
+        },
+        _ => String::new(),
+    };
+    warn!(
+        "\n{}\n",
+        format!(
+            "Broken source: {:?} {}{}",
+            source.name(),
+            error.message,
+            moved
+        )
+        .yellow()
+        .bold()
+    );
+    summary.lock().unwrap().record_broken(source.name(), error);
+}
+
+// Records a write that ran out of space and stops the batch. Only the first file to hit
+// it says so, the ones that were already running fail the same way.
+fn stop_on_full_disk(
+    path: &Path,
+    error: &WebpConverterError,
+    disk_full: &AtomicBool,
+    summary: &Mutex<Summary>,
+) {
+    if !disk_full.swap(true, Ordering::SeqCst) {
+        error!(
+            "\n{}\n",
+            format!(
+                "Stopping the batch, nothing more can be written: {} (while writing the output of {:?}). Free some space and run again, files already converted are kept.",
+                error.message, path
+            )
+            .red()
+            .bold()
+        );
+    }
+    let mut summary = summary.lock().unwrap();
+    summary.fail(path, error);
+    summary.disk_full = true;
+}
+
+// Converts one file, retrying once with the conservative `fallback` settings.
+// A timed out encode, or one whose output didn't fit on the disk, is not retried.
+pub async fn convert_with_fallback(
+    path: &Path,
+    options: &ConversionOptions,
+) -> Result<ConversionRecord, WebpConverterError> {
+    convert_source_with_fallback(&ImageSource::open(path)?, options).await
+}
+
+async fn convert_source_with_fallback(
+    source: &ImageSource,
+    options: &ConversionOptions,
+) -> Result<ConversionRecord, WebpConverterError> {
+    match convert_source(source, options).await {
+        Err(e)
+            if !e.is_timeout()
+                && !e.is_resize_refused()
+                && !e.is_disk_full()
+                && !e.is_broken_source() =>
+        {
+            convert_source(source, &options.fallback()).await
+        }
+        result => result,
+    }
+}
+
+/// Converts paths as they arrive, for services that discover files over time
+/// (a queue, a watcher) instead of walking a folder up front.
+///
+/// At most `--jobs` files are in flight at once and results come back in completion
+/// order. WebP inputs are copied like in a folder run; anything else that isn't a
+/// supported image yields an error.
+///
+/// ```no_run
+/// use futures::StreamExt;
+/// use webp_converter::{converter, types::ConversionOptions};
+///
+/// # async fn run() {
+/// let paths = futures::stream::iter(vec!["a.jpg".into(), "b.png".into()]);
+/// let results = converter::convert_stream(paths, ConversionOptions::default());
+/// futures::pin_mut!(results);
+/// while let Some(result) = results.next().await {
+///     println!("{:?}", result.map(|record| record.output));
+/// }
+/// # }
+/// ```
+pub fn convert_stream(
+    input: impl Stream<Item = PathBuf>,
+    options: ConversionOptions,
+) -> impl Stream<Item = Result<ConversionRecord, WebpConverterError>> {
+    let concurrency = resolve_concurrency(options.jobs);
+    let options = Arc::new(options);
+    input
+        .map(move |path| {
+            let options = options.clone();
+            // A task per file, so decoding runs in parallel too and not only the encodes.
+            tokio::spawn(async move { convert_stream_item(path, &options).await })
+        })
+        .buffer_unordered(concurrency)
+        .map(|joined| joined?)
+}
+
+// Stats `path` once, like a walk would, and hands the result to the conversion or copy.
+async fn convert_stream_item(
+    path: PathBuf,
+    options: &ConversionOptions,
+) -> Result<ConversionRecord, WebpConverterError> {
+    match passthrough(helpers::which_action_for(&path, options.format), options) {
+        helpers::Actions::Convert | helpers::Actions::Pdf => {
+            convert_source_with_fallback(&ImageSource::open(path)?, options).await
+        }
+        helpers::Actions::Copy => {
+            let file = FileInfo::read(path)?;
+            let output = wio::copy_image_to_output_folder(&file.path, options).await?;
+            Ok(ConversionRecord {
+                input: file.path,
+                output,
+                input_size: file.size,
+                output_size: file.size,
+                status: ConversionStatus::Copied,
+                auto_format: None,
+            })
+        }
+        helpers::Actions::Nothing => Err(WebpConverterError {
+            message: format!("Not a valid image file: {:?}", path),
+            kind: ErrorKind::Other,
+        }),
+    }
+}
+
+// The text summary goes to the log like every other message, JSON and CSV to stdout
+// where they can be piped.
+fn print_summary(summary: &Summary, format: ReportFormat) {
+    match format {
+        ReportFormat::Text => info!("{}", format!("Summary: {}", summary).bright_cyan().bold()),
+        format => println!("{}", report::summary_report(summary, format)),
+    }
+    if !summary.failures.is_empty() {
+        let mut failures = summary.failures.clone();
+        failures.sort();
+        let list: String = failures
+            .iter()
+            .map(|(path, message)| format!("\n  {:?}: {}", path, message))
+            .collect();
+        error!(
+            "{}",
+            format!("{} file(s) failed:{}", failures.len(), list)
+                .red()
+                .bold()
+        );
+    }
+    if !summary.broken.is_empty() {
+        let mut broken = summary.broken.clone();
+        broken.sort();
+        let list: String = broken
+            .iter()
+            .map(|(path, message)| format!("\n  {:?}: {}", path, message))
+            .collect();
+        warn!(
+            "{}",
+            format!(
+                "{} file(s) are empty or truncated and were set aside:{}",
+                broken.len(),
+                list
+            )
+            .yellow()
+            .bold()
+        );
+    }
+    if summary.disk_full {
+        error!(
+            "{}",
+            "Stopped early, the output disk is full: files not converted by then were skipped."
+                .red()
+                .bold()
+        );
+    }
+    if let Some(limit) = summary.stopped_at_limit {
+        info!(
+            "{}",
+            format!(
+                "Stopped at --limit {}: {} file(s) attempted, {} encoded, the other {} skipped.",
+                limit,
+                summary.matched - summary.skipped,
+                summary.encoded(),
+                summary.skipped
+            )
+            .bright_cyan()
+            .bold()
+        );
+    }
+    if summary.cancelled {
+        warn!(
+            "{}",
+            "Cancelled: files not converted by then were skipped and the output folder was left as it was."
+                .yellow()
+                .bold()
+        );
+    }
+}
+
+// With `--copy-others`, files the converter doesn't handle are copied like WebP inputs
+// instead of being skipped.
+pub(crate) fn passthrough(
+    action: helpers::Actions,
+    options: &ConversionOptions,
+) -> helpers::Actions {
+    match action {
+        helpers::Actions::Nothing if options.copy_others => helpers::Actions::Copy,
+        action => action,
+    }
+}
+
+// Rough peak memory of one in-flight conversion: a 24 megapixel photo held as
+// RGBA four times over (decode, resize clone, encoder input and encoded output).
+const ESTIMATED_BYTES_PER_IMAGE: u64 = 24_000_000 * 4 * 4;
+
+pub(crate) fn resolve_concurrency(jobs: Jobs) -> usize {
+    let cpu_cores = num_cpus::get();
+    let cpu_limit = std::cmp::max(1, cpu_cores - 1); // Reserve one core for the system
+
+    if let Jobs::Fixed(jobs) = jobs {
+        info!("{}", format!("Concurrency: {} (fixed)", jobs).cyan());
+        return jobs;
+    }
+
+    let mut system = System::new();
+    system.refresh_memory();
+    let available_memory = system.available_memory();
+    if available_memory == 0 {
+        // The platform doesn't report memory, so the core count is all we have.
+        info!(
+            "{}",
+            format!(
+                "Concurrency: {} (auto: {} cores, available memory unknown)",
+                cpu_limit, cpu_cores
+            )
+            .cyan()
+        );
+        return cpu_limit;
+    }
+
+    let memory_limit = std::cmp::max(1, (available_memory / ESTIMATED_BYTES_PER_IMAGE) as usize);
+    let chosen = std::cmp::min(cpu_limit, memory_limit);
+    info!(
+        "{}",
+        format!(
+            "Concurrency: {} (auto: {} cores allow {}, {} MiB available memory at ~{} MiB per image allows {})",
+            chosen,
+            cpu_cores,
+            cpu_limit,
+            available_memory / (1024 * 1024),
+            ESTIMATED_BYTES_PER_IMAGE / (1024 * 1024),
+            memory_limit
+        )
+        .cyan()
+    );
+    chosen
+}
+
+// Keeps the first `sample` files that would be converted, in walk order, and drops
+// everything else (copies included) so a trial run only touches those.
+fn sample_entries(
+    entries: Vec<(FileInfo, helpers::Actions)>,
+    sample: usize,
+) -> Vec<(FileInfo, helpers::Actions)> {
+    let convertible = entries
+        .iter()
+        .filter(|(_, action)| matches!(action, helpers::Actions::Convert | helpers::Actions::Pdf))
+        .count();
+    info!(
+        "{}",
+        format!(
+            "Sampling the first {} of {} convertible file(s)",
+            sample.min(convertible),
+            convertible
+        )
+        .cyan()
+    );
+    entries
+        .into_iter()
+        .filter(|(_, action)| matches!(action, helpers::Actions::Convert | helpers::Actions::Pdf))
+        .take(sample)
+        .collect()
+}
+
+// With `--in-place`, sources that would be written to the same file (`photo.jpg` and
+// `photo.png` both becoming `photo.webp`) are taken out of `entries`, each with the
+// error to fail it with. None of them is converted: the second output would overwrite
+// the first, and `--replace` would then delete a source whose output is gone.
+fn take_colliding_outputs(
+    entries: &mut Vec<(FileInfo, helpers::Actions)>,
+    options: &ConversionOptions,
+) -> Vec<(FileInfo, WebpConverterError)> {
+    if !options.in_place || options.hash_names {
+        return vec![];
+    }
+    let key = |(file, action): &(FileInfo, helpers::Actions)| match passthrough(*action, options) {
+        // A WebP already there is left alone, or is the output of an earlier run.
+        helpers::Actions::Nothing | helpers::Actions::Copy => None,
+        // Outputs that aren't known up front are all named after the source's stem.
+        action => Some(
+            planned_output(&file.path, action, options)
+                .unwrap_or_else(|| file.path.with_extension("")),
+        ),
+    };
+    let mut sources: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+    for entry in entries.iter() {
+        if let Some(output) = key(entry) {
+            sources
+                .entry(output)
+                .or_default()
+                .push(entry.0.path.clone());
+        }
+    }
+
+    let mut colliding = vec![];
+    for entry in std::mem::take(entries) {
+        match key(&entry).and_then(|output| sources.get(&output).map(|s| (output, s))) {
+            Some((output, sources)) if sources.len() > 1 => {
+                let error = WebpConverterError {
+                    message: format!(
+                        "Output collision: {:?} would all be written to {:?} in place, rename all but one of them",
+                        sources, output
+                    ),
+                    kind: ErrorKind::Other,
+                };
+                colliding.push((entry.0, error));
+            }
+            _ => entries.push(entry),
+        }
+    }
+    colliding
+}
+
+// Prints what a batch is about to do and asks whether to go ahead.
+fn confirm_plan(
+    root: &Path,
+    entries: &[(FileInfo, helpers::Actions)],
+    options: &ConversionOptions,
+) -> bool {
+    let count = |action| entries.iter().filter(|(_, a)| *a == action).count();
+    let target = match (&options.output, &options.output_dir) {
+        (Some(target), _) => format!("s3://{}/{}", target.bucket, target.prefix),
+        (None, Some(dir)) => format!("{:?}", dir),
+        (None, None) if options.in_place => {
+            format!("each source's own folder, starting at {:?}", root)
+        }
+        (None, None) => format!(
+            "a `{}` folder next to each source, starting at {:?}",
+            options.output_dir_name, root
+        ),
+    };
+    // Sources whose original `--min-savings-percent` keeps aren't deleted, so this is
+    // an upper bound.
+    let deleted = if options.replace {
+        format!(
+            " Up to {} original(s) will be deleted once their output is written (--replace).",
+            count(helpers::Actions::Convert) + count(helpers::Actions::Pdf)
+        )
+    } else {
+        String::new()
+    };
+    println!(
+        "{}",
+        format!(
+            "About to convert {} file(s), rasterize {} PDF(s) and copy {} WebP file(s) into {}; {} other file(s) will be {}.{}",
+            count(helpers::Actions::Convert),
+            count(helpers::Actions::Pdf),
+            count(helpers::Actions::Copy),
+            target,
+            count(helpers::Actions::Nothing),
+            if options.copy_others { "copied as they are" } else { "ignored" },
+            deleted
+        )
+        .bright_cyan()
+        .bold()
+    );
+    helpers::confirm("Proceed?")
+}
+
+// Asks before a file replaces an output that already exists, unless `--skip-existing`
+// leaves that output alone anyway. False when the user declines.
+fn keep_overwriting(
+    file: &FileInfo,
+    action: helpers::Actions,
+    options: &ConversionOptions,
+) -> bool {
+    let action = passthrough(action, options);
+    if options.skip_existing && up_to_date_output(file, action, options).is_some() {
+        return true;
+    }
+    match planned_output(&file.path, action, options).filter(|output| output.exists()) {
+        Some(existing) => helpers::confirm(&format!("Overwrite {:?}?", existing)),
+        None => true,
+    }
+}
+
+// The output a file will be written to, when it's a single local file known up front.
+// PDFs, `--ico-all-sizes`, `--tiff-pages all` and `--sizes` produce several outputs and
+// aren't checked.
+pub fn planned_output(
+    path: &Path,
+    action: helpers::Actions,
+    options: &ConversionOptions,
+) -> Option<PathBuf> {
+    // Remote outputs aren't checked, and content hashed names never replace different content.
+    if options.output.is_some() || options.hash_names {
+        return None;
+    }
+    let tiff_pages = options.tiff_pages.filter(|_| helpers::is_tiff(path));
+    match action {
+        helpers::Actions::Convert if options.ico_all_sizes && helpers::is_icon(path) => None,
+        helpers::Actions::Convert if tiff_pages == Some(TiffPages::All) => None,
+        helpers::Actions::Convert if !options.sizes.is_empty() => None,
+        helpers::Actions::Convert
+            if options.reoptimize
+                && !helpers::is_icon(path)
+                && !helpers::is_raw(path)
+                && tiff_pages.is_none() =>
+        {
+            wio::same_format_file_name(path, options)
+                .ok()
+                .map(|name| wio::planned_path(path, &name, options))
+        }
+        helpers::Actions::Convert => wio::output_file_name(path, Some(options.format.extension()))
+            .ok()
+            .map(|name| wio::planned_path(path, &wio::renamed_output(name, options), options)),
+        // Already in place, there's nothing to overwrite.
+        helpers::Actions::Copy if options.in_place => None,
+        helpers::Actions::Copy => wio::same_format_file_name(path, options)
+            .ok()
+            .map(|name| wio::planned_path(path, &name, options)),
+        helpers::Actions::Pdf | helpers::Actions::Nothing => None,
+    }
+}
+
+// The output of `file` if it exists and was written after `file` was last modified,
+// for `--skip-existing`.
+pub(crate) fn up_to_date_output(
+    file: &FileInfo,
+    action: helpers::Actions,
+    options: &ConversionOptions,
+) -> Option<PathBuf> {
+    let output = planned_output(&file.path, action, options)?;
+    let written = fs::metadata(&output).and_then(|m| m.modified()).ok()?;
+    (written > file.modified?).then_some(output)
+}
+
+pub async fn convert_single_photo<P: Into<PathBuf>>(
+    path: P,
+    options: &ConversionOptions,
+) -> Result<ConversionRecord, WebpConverterError> {
+    convert_source(&ImageSource::open(path)?, options).await
+}
+
+/// Converts an image from any [`ImageSource`] and writes it like a walked file would be.
+/// In-memory and downloaded sources go to `webp_converter_output` under the current
+/// directory, or to `--output`.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        name = "convert",
+        skip_all,
+        fields(path = ?source.name(), input_size = source.input_size(), output_size = tracing::field::Empty)
+    )
+)]
+pub async fn convert_source(
+    source: &ImageSource,
+    options: &ConversionOptions,
+) -> Result<ConversionRecord, WebpConverterError> {
+    let record = write_source(source, options).await?;
+    trace::record("output_size", record.output_size);
+    if options.replace {
+        replace_source(source, &record).await?;
+    }
+    Ok(record)
+}
+
+// Deletes the source of an in-place output once it is written (`--replace`). Originals a
+// conversion kept, and outputs written over their own source, stay.
+async fn replace_source(
+    source: &ImageSource,
+    record: &ConversionRecord,
+) -> Result<(), WebpConverterError> {
+    let ImageSource::File(file) = source else {
+        return Ok(());
+    };
+    let replaced = matches!(
+        record.status,
+        ConversionStatus::Converted | ConversionStatus::KeptExisting
+    );
+    if replaced && record.output != file.path {
+        tokio::fs::remove_file(&file.path).await?;
+        info!("{}", format!("Replaced {:?}", file.path).bright_blue());
+    }
+    Ok(())
+}
+
+// Everything `convert_source` does, outside its span.
+async fn write_source(
+    source: &ImageSource,
+    options: &ConversionOptions,
+) -> Result<ConversionRecord, WebpConverterError> {
+    // The size is already known from the walk (or the bytes handed over), so an empty
+    // file is told apart before any decoder gets to fail on it.
+    if source.input_size() == 0 {
+        return Err(WebpConverterError::empty_source());
+    }
+    if let ImageSource::File(file) = source {
+        if helpers::is_pdf(&file.path) {
+            return convert_pdf(file, options).await;
+        }
+        if helpers::is_icon(&file.path) {
+            return convert_icon(file, options).await;
+        }
+        if helpers::is_raw(&file.path) {
+            return convert_raw(file, options).await;
+        }
+        let tiff_pages = options.tiff_pages.filter(|_| helpers::is_tiff(&file.path));
+        if tiff_pages == Some(TiffPages::All) {
+            return convert_tiff_pages(source, file, options).await;
+        }
+        if options.reoptimize && tiff_pages.is_none() {
+            return reoptimize_single_photo(&file.path, options).await;
+        }
+    }
+
+    let name = source.name();
+    let output_name = wio::output_file_name(name, Some(options.format.extension()))?;
+    let output_name = wio::renamed_output(output_name, options);
+    if let ImageSource::File(file) = source {
+        wio::make_file_writable(&file.path)?;
+    }
+
+    let (outputs, auto_format) = encode_source(source, options).await?;
+    write_outputs(source, &output_name, outputs, auto_format, options).await
+}
+
+// Writes the encodes of `source` as `output_name`: the main output and the extra
+// formats next to it, or the `--sizes` variants.
+async fn write_outputs(
+    source: &ImageSource,
+    output_name: &OsStr,
+    mut outputs: Vec<Encoded>,
+    auto_format: Option<AutoFormat>,
+    options: &ConversionOptions,
+) -> Result<ConversionRecord, WebpConverterError> {
+    if !options.sizes.is_empty() {
+        return write_size_variants(source, output_name, outputs, auto_format, options).await;
+    }
+    let name = source.name();
+    let input_size = source.input_size();
+    let Encoded {
+        bytes: encoded,
+        dimensions,
+        ..
+    } = outputs.remove(0);
+
+    let savings = types::savings_percent(input_size, encoded.len() as u64);
+    if options.min_savings_percent > 0.0 && savings < options.min_savings_percent {
+        info!(
+            "{}",
+            format!(
+                "{:?} would only shrink by {:.1}% (below --min-savings-percent {}), keeping the original",
+                name.file_name().unwrap_or_default(),
+                savings,
+                options.min_savings_percent
+            )
+            .yellow()
+        );
+        // Don't leave a WebP from an earlier run next to the kept original.
+        wio::remove_output(name, output_name, options).await?;
+        let original = source.read_bytes().await?;
+        let copy_name = wio::renamed_output(wio::same_format_file_name(name, options)?, options);
+        let copy_path = wio::write_output(name, &copy_name, &original, options).await?;
+        return Ok(ConversionRecord {
+            input: source.input(),
+            output: copy_path,
+            input_size,
+            output_size: input_size,
+            status: ConversionStatus::KeptOriginal,
+            auto_format: None,
+        });
+    }
+
+    let record = match smaller_existing_output(name, output_name, encoded.len(), options).await {
+        Some((output, output_size)) => ConversionRecord {
+            input: source.input(),
+            output,
+            input_size,
+            output_size,
+            status: ConversionStatus::KeptExisting,
+            auto_format: None,
+        },
+        None => {
+            // Finalize the file writing back in the async context
+            let output = write_encoded_output(
+                name,
+                output_name,
+                &encoded,
+                dimensions,
+                options.format,
+                options,
+            )
+            .await?;
+            ConversionRecord {
+                input: source.input(),
+                output,
+                input_size,
+                output_size: encoded.len() as u64,
+                status: ConversionStatus::Converted,
+                auto_format,
+            }
+        }
+    };
+    write_extra_formats(name, output_name, &record.output, outputs, options).await?;
+    Ok(record)
+}
+
+// Writes the `--sizes` variants of `source` as `{stem}-{width}w.webp` next to each other,
+// each weighed on its own by `--overwrite-if-smaller`. The record points at the first.
+async fn write_size_variants(
+    source: &ImageSource,
+    output_name: &OsStr,
+    variants: Vec<Encoded>,
+    auto_format: Option<AutoFormat>,
+    options: &ConversionOptions,
+) -> Result<ConversionRecord, WebpConverterError> {
+    let name = source.name();
+    let mut first_output = None;
+    let mut output_size = 0;
+    for variant in variants {
+        let file_name = wio::variant_file_name(output_name, variant.width.unwrap_or_default());
+        let output =
+            match smaller_existing_output(name, &file_name, variant.bytes.len(), options).await {
+                Some((existing, existing_size)) => {
+                    output_size += existing_size;
+                    existing
+                }
+                None => {
+                    output_size += variant.bytes.len() as u64;
+                    write_encoded_output(
+                        name,
+                        &file_name,
+                        &variant.bytes,
+                        variant.dimensions,
+                        variant.format,
+                        options,
+                    )
+                    .await?
+                }
+            };
+        first_output.get_or_insert(output);
+    }
+    Ok(ConversionRecord {
+        input: source.input(),
+        output: first_output.unwrap_or_else(|| wio::get_or_create_output_directory(name, options)),
+        input_size: source.input_size(),
+        output_size,
+        status: ConversionStatus::Converted,
+        auto_format,
+    })
+}
+
+// The `--sizes` variants of `img`: one for each width it is at least as wide as, scaled
+// down to it, or `img` itself at its own width when it is narrower than all of them.
+// Widths it would have to be enlarged to are left out.
+fn size_variants(img: &DynamicImage, sizes: &[u32], name: &Path) -> Vec<(u32, DynamicImage)> {
+    let width = img.width();
+    let (fitting, wider): (Vec<u32>, Vec<u32>) = sizes.iter().partition(|&&size| size <= width);
+    if !wider.is_empty() {
+        info!(
+            "{}",
+            format!(
+                "{:?} is {}px wide, not enlarging it to --sizes {}",
+                name.file_name().unwrap_or_default(),
+                width,
+                wider
+                    .iter()
+                    .map(|size| size.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            )
+            .cyan()
+        );
+    }
+    if fitting.is_empty() {
+        return vec![(width, img.clone())];
+    }
+    fitting
+        .into_iter()
+        .map(|size| {
+            let target = ResizeTarget::Box {
+                width: Some(size),
+                height: None,
+            };
+            (size, resize_image(img.clone(), ResizeMode::Fit, target))
+        })
+        .collect()
+}
+
+// Writes the `extra_formats` outputs of `name` next to its main `output`, named like
+// `output_name` with their own extension, each weighed on its own by
+// `--overwrite-if-smaller`, and lists them all in the formats manifest.
+async fn write_extra_formats(
+    name: &Path,
+    output_name: &OsStr,
+    output: &Path,
+    extras: Vec<Encoded>,
+    options: &ConversionOptions,
+) -> Result<(), WebpConverterError> {
+    if extras.is_empty() {
+        return Ok(());
+    }
+    let mut outputs = vec![output.to_path_buf()];
+    for extra in extras {
+        let file_name =
+            wio::output_file_name(Path::new(output_name), Some(extra.format.extension()))?;
+        let path = match smaller_existing_output(name, &file_name, extra.bytes.len(), options).await
+        {
+            Some((existing, _)) => existing,
+            None => {
+                let path = write_encoded_output(
+                    name,
+                    &file_name,
+                    &extra.bytes,
+                    extra.dimensions,
+                    extra.format,
+                    options,
+                )
+                .await?;
+                info!(
+                    "Converted: {:?} -> {:?} ({} bytes)",
+                    name,
+                    path,
+                    extra.bytes.len()
+                );
+                path
+            }
+        };
+        outputs.push(path);
+    }
+    let key = wio::source_key(name, options);
+    for path in outputs {
+        let output_key = wio::output_key(name, path.file_name().unwrap_or_default(), options);
+        options.formats_manifest.append(key.clone(), output_key);
+    }
+    Ok(())
+}
+
+// Decodes `source` and runs it through `encode_still`, without writing anything.
+async fn encode_source(
+    source: &ImageSource,
+    options: &ConversionOptions,
+) -> Result<(Vec<Encoded>, Option<AutoFormat>), WebpConverterError> {
+    let target_size = target_size(source.input_size(), options);
+
+    // Read only the header first so a huge image is refused before it is decoded.
+    let (width, height) = source.dimensions()?;
+    check_pixel_budget(width, height, options.max_pixels)?;
+
+    // Animated GIFs and APNGs keep every frame when WebP is the only output; any other
+    // format gets their first frame.
+    if options.format == OutputFormat::Webp
+        && options.extra_formats.is_empty()
+        && options.sizes.is_empty()
+    {
+        if let Some(frames) = source.frames(options.max_pixels)? {
+            let (encoded, auto_format) = encode_animation(frames, source.name(), options).await?;
+            return Ok((vec![encoded], auto_format));
+        }
+    }
+
+    let img = match (source, options.tiff_pages) {
+        // The header above was the first page's, the page asked for may be larger.
+        (ImageSource::File(file), Some(TiffPages::Page(page))) if helpers::is_tiff(&file.path) => {
+            let (tiff, _) =
+                open_tiff_pages(&file.path, TiffPages::Page(page), options.max_pixels).await?;
+            decode_tiff_page(tiff, page, options.max_pixels).await?.1
+        }
+        // Load the image synchronously to avoid async issues with WebPMemory
+        _ => source
+            .decode(options.max_pixels)
+            .map_err(|e| match e.kind {
+                ErrorKind::Undecodable => WebpConverterError::truncated_source((width, height), e),
+                _ => e,
+            })?,
+    };
+    let icc_profile = options.target_colorspace.and_then(|_| source.icc_profile());
+    let metadata = read_metadata(source, options).await?;
+    encode_still(
+        img,
+        icc_profile,
+        &metadata,
+        source.name(),
+        target_size,
+        options,
+    )
+    .await
+}
+
+// The `-c` target size of an encode whose share of the source is `input_size` bytes.
+fn target_size(input_size: u64, options: &ConversionOptions) -> i32 {
+    match options.compression_factor as i32 {
+        0 => 0,
+        _ => (input_size as f32 / options.compression_factor) as i32,
+    }
+}
+
+// The metadata of `source`, read only when the orientation or a `--keep-*` flag needs it.
+// In-memory sources are read where they are. A file is only read again whole for the
+// `--keep-*` flags; the orientation alone comes from its metadata segments.
+async fn read_metadata(
+    source: &ImageSource,
+    options: &ConversionOptions,
+) -> Result<Metadata, WebpConverterError> {
+    let keeps = options.keep_exif || options.keep_icc || options.keep_xmp;
+    if !keeps && !options.apply_orientation {
+        return Ok(Metadata::default());
+    }
+    Ok(match source.in_memory() {
+        Some(bytes) => Metadata::read(bytes),
+        None if keeps => Metadata::read(&tokio::fs::read(source.name()).await?),
+        None => {
+            let path = source.name().to_path_buf();
+            spawn_blocking(move || Metadata::read_header(&path)).await??
+        }
+    })
+}
+
+// A decoded still image through `encode_decoded`, with the source's `metadata` handled
+// around it: the orientation is baked into the pixels and whatever the `--keep-*` flags
+// ask for is carried into the output.
+async fn encode_still(
+    img: DynamicImage,
+    icc_profile: Option<Vec<u8>>,
+    metadata: &Metadata,
+    name: &Path,
+    target_size: i32,
+    options: &ConversionOptions,
+) -> Result<(Vec<Encoded>, Option<AutoFormat>), WebpConverterError> {
+    let img = match metadata.orientation().filter(|_| options.apply_orientation) {
+        Some(orientation) => metadata::apply_orientation(img, orientation),
+        None => img,
+    };
+
+    let (mut outputs, auto_format) =
+        encode_decoded(img, icc_profile, name, target_size, options).await?;
+    let kept = metadata.clone().kept(options);
+    if !kept.is_empty() {
+        for output in outputs.iter_mut() {
+            if output.format == OutputFormat::Webp {
+                output.bytes = metadata::embed(&output.bytes, &kept)?;
+            }
+        }
+    }
+    Ok((outputs, auto_format))
+}
+
+// An animation through the steps `encode_decoded` takes a still image through, frame by
+// frame, encoded as one animated WebP. The first frame is fitted, resized and logged,
+// and picks the auto-format; the others are scaled to match it. The color space
+// conversion, `--roi`, `--compression-factor`, `--noise-ratio` and `--keep-*` only
+// apply to still images.
+async fn encode_animation(
+    frames: Vec<Frame>,
+    name: &Path,
+    options: &ConversionOptions,
+) -> Result<(Encoded, Option<AutoFormat>), WebpConverterError> {
+    info!(
+        "{}",
+        format!(
+            "Animation: {:?} has {} frames, encoding an animated WebP",
+            name.file_name().unwrap_or_default(),
+            frames.len()
+        )
+        .cyan()
+    );
+    let mut prepared: Vec<Frame> = Vec::with_capacity(frames.len());
+    for frame in frames {
+        let image = match options.crop {
+            Some(crop) => crop_image(frame.image, crop)?,
+            None => frame.image,
+        };
+        let image = apply_transforms(image, options);
+        let image = match prepared.first() {
+            None => {
+                let image = fit_format_limits(image, name, options)?;
+                if options.lqip {
+                    wio::record_lqip(name, lqip_data_uri(&image)?, options);
+                }
+                if options.should_resize {
+                    checked_resize(&image, name, options)?
+                } else {
+                    image
+                }
+            }
+            Some(first) if first.image.dimensions() != image.dimensions() => {
+                let (width, height) = first.image.dimensions();
+                image.resize_to_fill(width, height, FilterType::Lanczos3)
+            }
+            Some(_) => image,
+        };
+        prepared.push(Frame { image, ..frame });
+    }
+
+    let dimensions = prepared[0].image.dimensions();
+    let auto_format = options
+        .auto_format
+        .then(|| choose_auto_format(&prepared[0].image));
+    let lossless = match auto_format {
+        Some(AutoFormat::Lossy) => 0,
+        Some(AutoFormat::Lossless) => 1,
+        None => options.lossless,
+    };
+    let encode_options = EncodeOptions {
+        quality: options.encoder_quality(name, lossless),
+        lossless,
+        roi: None,
+        ..options.encode_options(0)
+    };
+    let bytes = spawn_blocking(move || animation::encode(&prepared, &encode_options)).await??;
+    let encoded = Encoded {
+        format: OutputFormat::Webp,
+        bytes,
+        dimensions,
+        width: None,
+    };
+    Ok((encoded, auto_format))
+}
+
+// One encode of a source: its format, the bytes and the dimensions they were encoded at,
+// and with `--sizes` the width it is the variant for.
+struct Encoded {
+    format: OutputFormat,
+    bytes: Vec<u8>,
+    dimensions: (u32, u32),
+    width: Option<u32>,
+}
+
+// Everything between decoding and writing: color space, crop, transforms, resize, the
+// auto-format choice and the encode. `name` is only used for `--quality-map` and logs.
+// Returns one encode per output format, `format` first, and the auto-format choice.
+async fn encode_decoded(
+    img: DynamicImage,
+    icc_profile: Option<Vec<u8>>,
+    name: &Path,
+    target_size: i32,
+    options: &ConversionOptions,
+) -> Result<(Vec<Encoded>, Option<AutoFormat>), WebpConverterError> {
+    // What `--explain` reports about the decoded image, before anything changes it.
+    let decoded = options
+        .explain
+        .then(|| (img.color(), img.dimensions(), choose_auto_format(&img)));
+    let img = match options.target_colorspace {
+        Some(target) => color::convert_to_colorspace(img, icc_profile.as_deref(), target)?,
+        None => img,
+    };
+    let img = match options.crop {
+        Some(crop) => crop_image(img, crop)?,
+        None => img,
+    };
+    let img = apply_transforms(img, options);
+    let transformed_dimensions = img.dimensions();
+    if let Some(roi) = options.roi.filter(|roi| !roi.fits(transformed_dimensions)) {
+        return Err(WebpConverterError {
+            message: format!(
+                "The --roi {} doesn't fit inside the {}x{} image",
+                roi, transformed_dimensions.0, transformed_dimensions.1
+            ),
+            kind: ErrorKind::Other,
+        });
+    }
+    let img = fit_format_limits(img, name, options)?;
+    if options.lqip {
+        wio::record_lqip(name, lqip_data_uri(&img)?, options);
+    }
+    let resized_img = if options.should_resize {
+        checked_resize(&img, name, options)?
+    } else {
+        img.clone()
+    };
+
+    let auto_format = options.auto_format.then(|| choose_auto_format(&img));
+    if let Some(choice) = auto_format {
+        info!(
+            "{}",
+            format!(
+                "Auto-format: {:?} has {}, encoding {}",
+                name.file_name().unwrap_or_default(),
+                match choice {
+                    AutoFormat::Lossy => "no transparency",
+                    AutoFormat::Lossless => "transparency",
+                },
+                match choice {
+                    AutoFormat::Lossy => "lossy",
+                    AutoFormat::Lossless => "lossless",
+                }
+            )
+            .cyan()
+        );
+    }
+
+    let lossless = match auto_format {
+        Some(AutoFormat::Lossy) => 0,
+        Some(AutoFormat::Lossless) => 1,
+        None => options.lossless,
+    };
+    let encode_options = EncodeOptions {
+        quality: options.encoder_quality(name, lossless),
+        lossless,
+        roi: options
+            .roi
+            .map(|roi| roi.scaled(transformed_dimensions, img.dimensions())),
+        ..options.encode_options(target_size)
+    };
+    let prepared_dimensions = img.dimensions();
+    let resized_dimensions = resized_img.dimensions();
+    let mut outputs = vec![];
+    if !options.sizes.is_empty() {
+        // `--sizes` takes the place of `-s` and the extra formats, refused alongside it.
+        for (width, variant) in size_variants(&img, &options.sizes, name) {
+            let dimensions = variant.dimensions();
+            let variant_options = EncodeOptions {
+                roi: options
+                    .roi
+                    .map(|roi| roi.scaled(transformed_dimensions, dimensions)),
+                ..encode_options
+            };
+            let bytes = encode_image(variant, variant_options, options.format).await?;
+            outputs.push(Encoded {
+                format: options.format,
+                bytes,
+                dimensions,
+                width: Some(width),
+            });
+        }
+    } else {
+        // The extra formats get copies of the prepared image, the main one takes it.
+        for &format in &options.extra_formats {
+            let (bytes, dimensions) =
+                decide_and_encode(img.clone(), resized_img.clone(), encode_options, format).await?;
+            outputs.push(Encoded {
+                format,
+                bytes,
+                dimensions,
+                width: None,
+            });
+        }
+        let (bytes, dimensions) =
+            decide_and_encode(img, resized_img, encode_options, options.format).await?;
+        outputs.insert(
+            0,
+            Encoded {
+                format: options.format,
+                bytes,
+                dimensions,
+                width: None,
+            },
+        );
+    }
+    if let Some(decoded) = decoded {
+        explain(
+            name,
+            decoded,
+            (prepared_dimensions, resized_dimensions),
+            auto_format,
+            &encode_options,
+            &outputs,
+            options,
+        );
+    }
+    Ok((outputs, auto_format))
+}
+
+// Logs every choice made for `name` (`--explain`): what was decoded (its color type,
+// dimensions and whether it has transparent pixels), the formats, lossless or lossy and
+// why, the resize and whether it was kept, and the settings the encoder got.
+fn explain(
+    name: &Path,
+    decoded: (ColorType, (u32, u32), AutoFormat),
+    (prepared, resized): ((u32, u32), (u32, u32)),
+    auto_format: Option<AutoFormat>,
+    encode_options: &EncodeOptions,
+    outputs: &[Encoded],
+    options: &ConversionOptions,
+) {
+    let (color, (width, height), transparency) = decoded;
+    let mut trail = vec![format!(
+        "decoded {}x{} {:?}, {}",
+        width,
+        height,
+        color,
+        match (color.has_alpha(), transparency) {
+            (false, _) => "no alpha channel",
+            (true, AutoFormat::Lossless) => "alpha channel with transparent pixels",
+            (true, AutoFormat::Lossy) => "alpha channel, opaque everywhere",
+        }
+    )];
+    if options.no_alpha && color.has_alpha() {
+        trail.push("alpha flattened onto the --background (--no-alpha)".to_string());
+    }
+    match options.channels {
+        Some(Channels::Rgb) => trail.push("channels: RGB, forced by --force-rgb".to_string()),
+        Some(Channels::Rgba) => trail.push("channels: RGBA, forced by --force-rgba".to_string()),
+        None => {}
+    }
+    if prepared != (width, height) {
+        trail.push(format!(
+            "{}x{} after the color space, crop, transforms and format limits",
+            prepared.0, prepared.1
+        ));
+    }
+    let formats: Vec<&str> = outputs.iter().map(|o| o.format.extension()).collect();
+    trail.push(format!("format: {}", formats.join(", ")));
+    // What the WebP output holds, which `--encode-config` can set apart from the request.
+    let written = outputs
+        .iter()
+        .find(|o| o.format == OutputFormat::Webp)
+        .and_then(|o| metadata::is_lossless(&o.bytes));
+    trail.push(match (auto_format, encode_options.lossless, written) {
+        (_, _, Some(lossless)) if lossless != (encode_options.lossless == 1) => format!(
+            "lossless: {}, set by --encode-config",
+            if lossless { "yes" } else { "no" }
+        ),
+        (Some(AutoFormat::Lossless), _, _) => {
+            "lossless: yes, --auto-format found transparent pixels".to_string()
+        }
+        (Some(AutoFormat::Lossy), _, _) => {
+            "lossless: no, --auto-format found no transparent pixels".to_string()
+        }
+        (None, 1, _) => "lossless: yes, asked for (-l, -q 100 or --lossless-effort)".to_string(),
+        (None, _, _) => "lossless: no, the default".to_string(),
+    });
+    for output in outputs {
+        let label = match (outputs.len(), output.width) {
+            (_, Some(width)) => format!(" ({}w)", width),
+            (1, None) => String::new(),
+            (_, None) => format!(" ({})", output.format.extension()),
+        };
+        trail.push(
+            match (options.should_resize, output.dimensions == resized) {
+                (false, _) => format!("resize{}: not asked for (-s)", label),
+                (true, _) if resized == prepared => {
+                    format!(
+                        "resize{}: none, {}x{} already fits",
+                        label, prepared.0, prepared.1
+                    )
+                }
+                (true, true) => format!(
+                    "resize{}: {}x{} -> {}x{}, kept, it encoded smaller",
+                    label, prepared.0, prepared.1, resized.0, resized.1
+                ),
+                (true, false) => format!(
+                    "resize{}: {}x{} dropped, the full size {}x{} encoded smaller",
+                    label, resized.0, resized.1, prepared.0, prepared.1
+                ),
+            },
+        );
+    }
+    let quality_source = match (encode_options.lossless, options.lossless_effort) {
+        (1, Some(_)) => "effort, from --lossless-effort",
+        _ if encode_options.quality != options.quality => "from --quality-map",
+        _ => "from -q",
+    };
+    let mut quality = format!(
+        "quality: {} ({}), method {}",
+        encode_options.quality, quality_source, encode_options.method
+    );
+    if encode_options.target_size > 0 {
+        quality.push_str(&format!(
+            ", target size {} bytes (-c)",
+            encode_options.target_size
+        ));
+    }
+    if encode_options.min_quality > 0.0 || encode_options.max_quality < 100.0 {
+        quality.push_str(&format!(
+            ", searched between {} and {}",
+            encode_options.min_quality, encode_options.max_quality
+        ));
+    }
+    if let Some(roi) = encode_options.roi {
+        quality.push_str(&format!(
+            ", --roi {} at {}",
+            roi, encode_options.roi_quality
+        ));
+    }
+    if encode_options.advanced.is_some() {
+        quality.push_str(", overridden by --encode-config");
+    }
+    trail.push(quality);
+    let list: String = trail.iter().map(|line| format!("\n  {}", line)).collect();
+    info!(
+        "{}",
+        format!(
+            "Explain {:?}:{}",
+            name.file_name().unwrap_or_default(),
+            list
+        )
+        .cyan()
+    );
+}
+
+/// Converts an image held in memory, in any format the `image` crate can decode,
+/// and returns the encoded output. `file_name` is the name the bytes came from; its
+/// extension picks the `--quality-map` entry. Nothing is written and
+/// `min_savings_percent` isn't applied, that's left to the caller. With `sizes`, only
+/// the variant for the first width that fits is returned.
+pub async fn convert_bytes(
+    bytes: &[u8],
+    file_name: &str,
+    options: &ConversionOptions,
+) -> Result<Vec<u8>, WebpConverterError> {
+    let source = ImageSource::from_bytes(file_name, bytes.to_vec());
+    let (mut outputs, _) = encode_source(&source, options).await?;
+    Ok(outputs.swap_remove(0).bytes)
+}
+
+// Downloads an http(s) image and converts it like a local file. The output is named
+// after the last segment of the URL and lands in `webp_converter_output` under the
+// current directory, or under `--output`.
+pub async fn convert_url(
+    url: &str,
+    options: &ConversionOptions,
+) -> Result<ConversionRecord, WebpConverterError> {
+    convert_source(&ImageSource::fetch(url).await?, options).await
+}
+
+/// Packs every image under `path` into one sprite sheet (`--spritesheet`): an atlas
+/// named after the folder, e.g. `icons.webp`, and `icons.json` with the rectangle of
+/// each image, both in the folder's output directory. Images are placed in shelves,
+/// tallest first, no wider than `sprite_max_width` and `sprite_padding` pixels apart.
+/// An image that can't be decoded fails the whole sheet, a sheet with a hole in it
+/// is of no use.
+pub async fn convert_to_spritesheet<P: Into<PathBuf>>(
+    path: P,
+    recursive: bool,
+    options: &ConversionOptions,
+) -> Result<ConversionRecord, WebpConverterError> {
+    let path = path.into();
+    let options = ConversionOptions {
+        input_root: Some(path.clone()),
+        ..options.clone()
+    };
+    let mut files = helpers::walk_files(
+        &path,
+        recursive,
+        options.respect_ignore,
+        &options.exclude_dirs,
+    );
+    // Outputs of earlier runs, the previous sheet among them, aren't sprites.
+    files.retain(|file| {
+        !file
+            .path
+            .strip_prefix(&path)
+            .unwrap_or(&file.path)
+            .components()
+            .any(|c| c.as_os_str() == options.output_dir_name.as_str())
+            && !matches!(
+                helpers::image_kind(&file.path),
+                None | Some(
+                    helpers::ImageKind::Pdf | helpers::ImageKind::Jxl | helpers::ImageKind::Raw
+                )
+            )
+            && !matches!(
+                helpers::image_kind(&file.path),
+                Some(helpers::ImageKind::Heif) if cfg!(not(feature = "heif"))
+            )
+    });
+    if files.is_empty() {
+        return Err(WebpConverterError {
+            message: format!("No images to pack into a sprite sheet under {:?}", path),
+            kind: ErrorKind::Other,
+        });
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    let input_size = files.iter().map(|file| file.size).sum();
+
+    let max_pixels = options.max_pixels;
+    let images = futures::stream::iter(files.iter().cloned())
+        .map(|file| {
+            spawn_blocking(move || {
+                let source = ImageSource::File(file);
+                let decoded = source.dimensions().and_then(|(width, height)| {
+                    check_pixel_budget(width, height, max_pixels)?;
+                    source.decode(max_pixels)
+                });
+                decoded.map_err(|e| WebpConverterError {
+                    message: format!(
+                        "Couldn't add {:?} to the sprite sheet: {}",
+                        source.name(),
+                        e.message
+                    ),
+                    kind: e.kind,
+                })
+            })
+        })
+        .buffered(resolve_concurrency(options.jobs))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .map(|joined| joined?)
+        .map(|image| image.map(|image| apply_transforms(image, &options)))
+        .collect::<Result<Vec<_>, WebpConverterError>>()?;
+
+    let sizes: Vec<(u32, u32)> = images.iter().map(|image| image.dimensions()).collect();
+    let (rects, size) = sprite::pack(&sizes, options.sprite_padding, options.sprite_max_width)?;
+    if let Some(limit) = options
+        .format
+        .max_dimension()
+        .filter(|limit| size.0 > *limit || size.1 > *limit)
+    {
+        return Err(WebpConverterError {
+            message: format!(
+                "The sprite sheet would be {}x{}, over the {}px limit of {}; split the folder or change --sprite-max-width",
+                size.0,
+                size.1,
+                limit,
+                options.format.extension()
+            ),
+            kind: ErrorKind::Other,
+        });
+    }
+    info!(
+        "{}",
+        format!(
+            "Packing {} images into a {}x{} sprite sheet",
+            images.len(),
+            size.0,
+            size.1
+        )
+        .bright_blue()
+    );
+
+    let sprite_rects = rects.clone();
+    let sheet = spawn_blocking(move || sprite::compose(&images, &sprite_rects, size)).await?;
+    let encode_options = EncodeOptions {
+        quality: options.encoder_quality(&path, options.lossless),
+        ..options.encode_options(0)
+    };
+    let encoded = encode_image(
+        DynamicImage::ImageRgba8(sheet),
+        encode_options,
+        options.format,
+    )
+    .await?;
+
+    let stem = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "spritesheet".to_string());
+    // Outputs land in the output directory of files directly in `path`.
+    let anchor = path.join(&stem);
+    let sheet_name = format!("{}.{}", stem, options.format.extension());
+    let output = write_encoded_output(
+        &anchor,
+        sheet_name.as_ref(),
+        &encoded,
+        size,
+        options.format,
+        &options,
+    )
+    .await?;
+
+    let sheet = sprite::SpriteSheet {
+        image: output
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or(sheet_name),
+        width: size.0,
+        height: size.1,
+        sprites: files
+            .iter()
+            .zip(rects)
+            .map(|(file, rect)| (wio::source_key(&file.path, &options), rect))
+            .collect(),
+    };
+    let json = serde_json::to_string_pretty(&sheet).expect("a sprite sheet always serializes");
+    let json_name = format!("{}.json", stem);
+    wio::write_output(&anchor, json_name.as_ref(), json.as_bytes(), &options).await?;
+
+    Ok(ConversionRecord {
+        input: path,
+        output,
+        input_size,
+        output_size: encoded.len() as u64,
+        status: ConversionStatus::Converted,
+        auto_format: None,
+    })
+}
+
+// Rasterizes each page of a PDF and writes it as `{stem}-p{n}.webp`.
+#[cfg(feature = "pdf")]
+pub async fn convert_pdf(
+    file: &FileInfo,
+    options: &ConversionOptions,
+) -> Result<ConversionRecord, WebpConverterError> {
+    let path = &file.path;
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let pdf_path = path.to_path_buf();
+    let dpi = options.pdf_dpi;
+    let pages = spawn_blocking(move || crate::pdf::render_pages(&pdf_path, dpi)).await??;
+
+    let pages = pages
+        .into_iter()
+        .enumerate()
+        .map(|(index, page)| {
+            let file_name = format!("{}-p{}.{}", stem, index + 1, options.format.extension());
+            (file_name, page)
+        })
+        .collect();
+    encode_named_images(path, file.size, pages, options).await
+}
+
+#[cfg(not(feature = "pdf"))]
+pub async fn convert_pdf(
+    _file: &FileInfo,
+    _options: &ConversionOptions,
+) -> Result<ConversionRecord, WebpConverterError> {
+    Err(WebpConverterError {
+        message: "PDF support requires building with `--features pdf`".to_string(),
+        kind: ErrorKind::Other,
+    })
+}
+
+// Converts a camera RAW file from its largest embedded JPEG preview, turned upright
+// unless `--apply-orientation false`, to `{stem}.webp`.
+#[cfg(feature = "raw")]
+pub async fn convert_raw(
+    file: &FileInfo,
+    options: &ConversionOptions,
+) -> Result<ConversionRecord, WebpConverterError> {
+    let path = &file.path;
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let raw_path = path.to_path_buf();
+    let (img, orientation) =
+        spawn_blocking(move || crate::raw::decode_preview(&raw_path)).await??;
+    let img = match orientation.filter(|_| options.apply_orientation) {
+        Some(orientation) => metadata::apply_orientation(img, orientation),
+        None => img,
+    };
+    let file_name = format!("{}.{}", stem, options.format.extension());
+    encode_named_images(path, file.size, vec![(file_name, img)], options).await
+}
+
+#[cfg(not(feature = "raw"))]
+pub async fn convert_raw(
+    _file: &FileInfo,
+    _options: &ConversionOptions,
+) -> Result<ConversionRecord, WebpConverterError> {
+    Err(WebpConverterError {
+        message: "RAW support requires building with `--features raw`".to_string(),
+        kind: ErrorKind::Other,
+    })
+}
+
+// Converts every page of a multi-page TIFF (`--tiff-pages all`) to `{stem}-p{n}.webp`,
+// each through the same steps as a still image. The `-c` target of a page is its share
+// of the file. The record points at the first page's output.
+async fn convert_tiff_pages(
+    source: &ImageSource,
+    file: &FileInfo,
+    options: &ConversionOptions,
+) -> Result<ConversionRecord, WebpConverterError> {
+    let path = &file.path;
+    let output_name = wio::output_file_name(path, Some(options.format.extension()))?;
+    let output_name = wio::renamed_output(output_name, options);
+    wio::make_file_writable(path)?;
+
+    let (mut tiff, pages) = open_tiff_pages(path, TiffPages::All, options.max_pixels).await?;
+    let target_size = target_size(file.size / pages.len().max(1) as u64, options);
+    let icc_profile = options.target_colorspace.and_then(|_| source.icc_profile());
+    let metadata = read_metadata(source, options).await?;
+
+    let mut first_record: Option<ConversionRecord> = None;
+    let mut output_size = 0;
+    for page in pages {
+        let (next, img) = decode_tiff_page(tiff, page, options.max_pixels).await?;
+        tiff = next;
+        // Each page is its own entry for `--quality-map`, `--lqip` and the logs.
+        let page_name = path.with_file_name(wio::page_file_name(
+            path.file_name().unwrap_or_default(),
+            page,
+        ));
+        let (outputs, auto_format) = encode_still(
+            img,
+            icc_profile.clone(),
+            &metadata,
+            &page_name,
+            target_size,
+            options,
+        )
+        .await?;
+        let page_output_name = wio::page_file_name(&output_name, page);
+        let record =
+            write_outputs(source, &page_output_name, outputs, auto_format, options).await?;
+        output_size += record.output_size;
+        first_record.get_or_insert(record);
+    }
+
+    let first_record = first_record.ok_or_else(|| WebpConverterError {
+        message: format!("TIFF Error: {:?} has no pages", path),
+        kind: ErrorKind::Other,
+    })?;
+    Ok(ConversionRecord {
+        output_size,
+        status: ConversionStatus::Converted,
+        ..first_record
+    })
+}
+
+// Opens the TIFF at `path` off the async runtime, with the numbers of its `pages`. Every
+// one of them is checked against `--max-pixels` from its IFD before any is decoded.
+async fn open_tiff_pages(
+    path: &Path,
+    pages: TiffPages,
+    max_pixels: u64,
+) -> Result<(crate::tiff::Pages, Vec<usize>), WebpConverterError> {
+    let path = path.to_path_buf();
+    spawn_blocking(move || {
+        let mut tiff = crate::tiff::Pages::open(&path)?;
+        let numbers = tiff.numbers(pages)?;
+        for &page in &numbers {
+            let (width, height) = tiff.dimensions(page)?;
+            check_pixel_budget(width, height, max_pixels)?;
+        }
+        Ok((tiff, numbers))
+    })
+    .await?
+}
+
+// Decodes `page` of `tiff` off the async runtime, handing `tiff` back for the next one.
+async fn decode_tiff_page(
+    mut tiff: crate::tiff::Pages,
+    page: usize,
+    max_pixels: u64,
+) -> Result<(crate::tiff::Pages, DynamicImage), WebpConverterError> {
+    spawn_blocking(move || {
+        let img = tiff.decode(page, max_pixels)?;
+        Ok((tiff, img))
+    })
+    .await?
+}
+
+// Converts an `.ico` or `.cur`: the largest embedded image as `{stem}.webp`,
+// or with `ico_all_sizes` every embedded image as `{stem}-{w}x{h}.webp`.
+pub async fn convert_icon(
+    file: &FileInfo,
+    options: &ConversionOptions,
+) -> Result<ConversionRecord, WebpConverterError> {
+    let path = &file.path;
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let icon_path = path.to_path_buf();
+    let images = if options.ico_all_sizes {
+        spawn_blocking(move || crate::icon::decode_all(&icon_path))
+            .await??
+            .into_iter()
+            .map(|img| {
+                (
+                    format!(
+                        "{}-{}x{}.{}",
+                        stem,
+                        img.width(),
+                        img.height(),
+                        options.format.extension()
+                    ),
+                    img,
+                )
+            })
+            .collect()
+    } else {
+        let img = spawn_blocking(move || crate::icon::decode_largest(&icon_path)).await??;
+        vec![(format!("{}.{}", stem, options.format.extension()), img)]
+    };
+    encode_named_images(path, file.size, images, options).await
+}
+
+// Encodes several images produced from one source, each to its own named output.
+async fn encode_named_images(
+    path: &Path,
+    input_size: u64,
+    images: Vec<(String, DynamicImage)>,
+    options: &ConversionOptions,
+) -> Result<ConversionRecord, WebpConverterError> {
+    let mut first_output = None;
+    let mut output_size = 0;
+    for (file_name, img) in images {
+        let img = fit_format_limits(
+            apply_transforms(img, options),
+            Path::new(&file_name),
+            options,
+        )?;
+        let encode_options = EncodeOptions {
+            quality: options.encoder_quality(path, options.lossless),
+            ..options.encode_options(0)
+        };
+        let mut encoded_images = vec![];
+        if options.sizes.is_empty() {
+            let resized_img = if options.should_resize {
+                checked_resize(&img, Path::new(&file_name), options)?
+            } else {
+                img.clone()
+            };
+            let encoded =
+                decide_and_encode(img, resized_img, encode_options, options.format).await?;
+            encoded_images.push((OsString::from(file_name), encoded));
+        } else {
+            for (width, variant) in size_variants(&img, &options.sizes, Path::new(&file_name)) {
+                let dimensions = variant.dimensions();
+                let encoded = encode_image(variant, encode_options, options.format).await?;
+                let variant_name = wio::variant_file_name(file_name.as_ref(), width);
+                encoded_images.push((variant_name, (encoded, dimensions)));
+            }
+        }
+
+        for (file_name, (encoded, dimensions)) in encoded_images {
+            let output_path =
+                match smaller_existing_output(path, &file_name, encoded.len(), options).await {
+                    Some((existing, existing_size)) => {
+                        output_size += existing_size;
+                        existing
+                    }
+                    None => {
+                        output_size += encoded.len() as u64;
+                        write_encoded_output(
+                            path,
+                            &file_name,
+                            &encoded,
+                            dimensions,
+                            options.format,
+                            options,
+                        )
+                        .await?
+                    }
+                };
+            first_output.get_or_insert(output_path);
+        }
+    }
+
+    Ok(ConversionRecord {
+        output: first_output.unwrap_or_else(|| wio::get_or_create_output_directory(path, options)),
+        input: path.to_path_buf(),
+        input_size,
+        output_size,
+        status: ConversionStatus::Converted,
+        auto_format: None,
+    })
+}
+
+// Re-encodes a JPEG or PNG in its own format and writes it to the output folder,
+// keeping the original bytes whenever the re-encode doesn't come out smaller.
+pub async fn reoptimize_single_photo<P: Into<PathBuf>>(
+    path: P,
+    options: &ConversionOptions,
+) -> Result<ConversionRecord, WebpConverterError> {
+    let path = path.into();
+    let extension = helpers::image_kind(&path)
+        .map(|kind| kind.canonical_extension())
+        .unwrap_or_default();
+    let output_name = wio::same_format_file_name(&path, options)?;
+
+    let original = tokio::fs::read(&path).await?;
+    let quality = options.quality_for(&path);
+    let (original, optimized) = spawn_blocking(move || {
+        let optimized = reoptimize::reoptimize_bytes(&original, extension, quality);
+        (original, optimized)
+    })
+    .await?;
+    let optimized = optimized?;
+
+    let input_size = original.len() as u64;
+    if optimized.len() < original.len() {
+        let output_path = wio::write_output(&path, &output_name, &optimized, options).await?;
+        Ok(ConversionRecord {
+            input: path,
+            output: output_path,
+            input_size,
+            output_size: optimized.len() as u64,
+            status: ConversionStatus::Converted,
+            auto_format: None,
+        })
+    } else {
+        info!(
+            "{}",
+            format!(
+                "Re-optimizing {:?} didn't save anything, keeping the original",
+                path.file_name().unwrap_or_default()
+            )
+            .yellow()
+        );
+        let output_path = wio::write_output(&path, &output_name, &original, options).await?;
+        Ok(ConversionRecord {
+            input: path,
+            output: output_path,
+            input_size,
+            output_size: input_size,
+            status: ConversionStatus::KeptOriginal,
+            auto_format: None,
+        })
+    }
+}
+
+// Shrinks an image wider or taller than the output format can store (16383px for WebP)
+// until it fits, keeping its aspect ratio. With `--no-auto-fit` it is refused instead of
+// failing inside the encoder with a less helpful error.
+fn fit_format_limits(
+    img: DynamicImage,
+    name: &Path,
+    options: &ConversionOptions,
+) -> Result<DynamicImage, WebpConverterError> {
+    let (width, height) = img.dimensions();
+    // Every output format gets the same pixels, so the strictest limit applies to all.
+    let Some((limit, format)) = options
+        .output_formats()
+        .filter_map(|format| format.max_dimension().map(|limit| (limit, format)))
+        .min_by_key(|(limit, _)| *limit)
+    else {
+        return Ok(img);
+    };
+    if width <= limit && height <= limit {
+        return Ok(img);
+    }
+
+    let name = name.file_name().unwrap_or_default();
+    if options.no_auto_fit {
+        return Err(WebpConverterError {
+            message: format!(
+                "{:?} is {}x{}, over the {}px {} limit (--no-auto-fit is set)",
+                name,
+                width,
+                height,
+                limit,
+                format.extension()
+            ),
+            kind: ErrorKind::Other,
+        });
+    }
+    let fitted = img.resize(limit, limit, FilterType::Lanczos3);
+    warn!(
+        "{}",
+        format!(
+            "Auto-fit: {:?} is {}x{}, over the {}px {} limit, downscaled to {}x{} ({:.2}x smaller)",
+            name,
+            width,
+            height,
+            limit,
+            format.extension(),
+            fitted.width(),
+            fitted.height(),
+            width.max(height) as f64 / limit as f64
+        )
+        .yellow()
+    );
+    Ok(fitted)
+}
+
+// Refuses images whose decoded buffers would not fit in a sensible amount of memory.
+// A decode, its resize clone and the RGBA copy for the encoder each hold ~4 bytes per pixel.
+pub(crate) fn check_pixel_budget(
+    width: u32,
+    height: u32,
+    max_pixels: u64,
+) -> Result<(), WebpConverterError> {
+    let pixels = width as u64 * height as u64;
+    if max_pixels == 0 || pixels <= max_pixels {
+        return Ok(());
+    }
+
+    let estimated_mib = pixels * 4 * 3 / (1024 * 1024);
+    Err(WebpConverterError {
+        message: format!(
+            "Image is {}x{} ({:.1} megapixels, ~{} MiB once decoded), above the --max-pixels limit of {}",
+            width,
+            height,
+            pixels as f64 / 1_000_000.0,
+            estimated_mib,
+            max_pixels
+        ),
+        kind: ErrorKind::Other,
+    })
+}
+
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(
+        name = "encode",
+        skip_all,
+        fields(width = resized_img.width(), height = resized_img.height(), format = ?format, bytes = tracing::field::Empty)
+    )
+)]
+pub async fn encode_image(
+    resized_img: DynamicImage,
+    options: EncodeOptions,
+    format: OutputFormat,
+) -> Result<Vec<u8>, WebpConverterError> {
+    // Use spawn_blocking for the CPU-bound encoding task
+    let encode_task = spawn_blocking(move || format.encoder().encode(&resized_img, &options));
+    let encoded = match options.timeout {
+        None => encode_task.await??, // Handle errors from spawn_blocking and encoding
+        // A blocking task can't be cancelled: on timeout the encoder thread keeps running
+        // until libwebp returns, but its result is dropped and the batch moves on.
+        Some(timeout) => match tokio::time::timeout(timeout, encode_task).await {
+            Ok(encoded) => encoded??,
+            Err(_) => return Err(WebpConverterError::timed_out(timeout)),
+        },
+    };
+    trace::record("bytes", encoded.len() as u64);
+    Ok(encoded)
+}
+
+// Detects the format from the file's contents, so aliases `image` doesn't know by
+// extension (`.jpe`, `.jfif`, `.dib`) and mislabeled files decode too.
+pub(crate) fn open_reader(
+    path: &Path,
+    max_pixels: u64,
+) -> Result<ImageReader<BufReader<File>>, WebpConverterError> {
+    let mut reader = ImageReader::open(path)?.with_guessed_format()?;
+    reader.limits(reader_limits(max_pixels));
+    Ok(reader)
+}
+
+// What a decoder may allocate. `image` stops at 512 MiB, less than an image within
+// `--max-pixels` can need, so the limit grows with the budget (16 bytes being the widest
+// pixel, 32-bit float RGBA) and goes away when the budget does.
+pub(crate) fn reader_limits(max_pixels: u64) -> Limits {
+    if max_pixels == 0 {
+        return Limits::no_limits();
+    }
+    let mut limits = Limits::default();
+    limits.max_alloc = limits
+        .max_alloc
+        .map(|max_alloc| max_alloc.max(max_pixels.saturating_mul(16)));
+    limits
+}
+
+// Cuts `crop` out of `image`, refusing a rectangle that runs past its edges rather
+// than quietly cropping less.
+fn crop_image(image: DynamicImage, crop: Crop) -> Result<DynamicImage, WebpConverterError> {
+    let fits = |offset: u32, size: u32, limit: u32| {
+        offset.checked_add(size).is_some_and(|end| end <= limit)
+    };
+    if !fits(crop.x, crop.width, image.width()) || !fits(crop.y, crop.height, image.height()) {
+        return Err(WebpConverterError {
+            message: format!(
+                "The crop {} doesn't fit inside the {}x{} image",
+                crop,
+                image.width(),
+                image.height()
+            ),
+            kind: ErrorKind::Other,
+        });
+    }
+    Ok(image.crop_imm(crop.x, crop.y, crop.width, crop.height))
+}
+
+// Transforms applied to the decoded image in a fixed order: crop (in the caller),
+// rotate, flip, grayscale, flattening (`--no-alpha`), then resize (in the caller) and
+// encode.
+pub(crate) fn apply_transforms(image: DynamicImage, options: &ConversionOptions) -> DynamicImage {
+    let image = match options.rotate {
+        Some(Rotation::Rotate90) => image.rotate90(),
+        Some(Rotation::Rotate180) => image.rotate180(),
+        Some(Rotation::Rotate270) => image.rotate270(),
+        None => image,
+    };
+    let image = match options.flip {
+        Some(Flip::Horizontal) => image.fliph(),
+        Some(Flip::Vertical) => image.flipv(),
+        None => image,
+    };
+    // Keeps the alpha channel. With at most 256 gray levels, lossless encodes go
+    // through the encoder's palette-friendly settings.
+    let image = if options.grayscale {
+        image.grayscale()
+    } else {
+        image
+    };
+    if options.no_alpha {
+        flatten_alpha(image, options.background)
+    } else {
+        image
+    }
+}
+
+// Composites `image` over an opaque `background`, for `--no-alpha`. Images without
+// an alpha channel are returned as they are.
+pub(crate) fn flatten_alpha(image: DynamicImage, background: Background) -> DynamicImage {
+    if !image.color().has_alpha() {
+        return image;
+    }
+    let rgba = image.to_rgba8();
+    let flattened = RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let [r, g, b, a] = rgba.get_pixel(x, y).0;
+        let blend = |channel: u8, background: u8| {
+            ((channel as u32 * a as u32 + background as u32 * (255 - a as u32) + 127) / 255) as u8
+        };
+        Rgb([
+            blend(r, background.0[0]),
+            blend(g, background.0[1]),
+            blend(b, background.0[2]),
+        ])
+    });
+    DynamicImage::ImageRgb8(flattened)
+}
+
+// Lossless for images with any pixel that isn't fully opaque, lossy otherwise.
+// An alpha channel that is opaque everywhere doesn't count as transparency.
+pub(crate) fn choose_auto_format(image: &DynamicImage) -> AutoFormat {
+    if image.color().has_alpha() && image.pixels().any(|(_, _, pixel)| pixel[3] < u8::MAX) {
+        AutoFormat::Lossless
+    } else {
+        AutoFormat::Lossy
+    }
+}
+
+// Downscales past this factor get a warning that fine detail will be lost.
+const RESIZE_WARN_FACTOR: f64 = 4.0;
+
+/// How many times smaller `resize_image` makes each side of an image of `dimensions`,
+/// 1.0 when it is left as it is and below 1.0 when `Fill` enlarges it.
+pub fn resize_factor(dimensions: (u32, u32), mode: ResizeMode, target: ResizeTarget) -> f64 {
+    match (binding_side(dimensions, mode, target), mode) {
+        (Some((side, limit)), ResizeMode::Fill) => side / limit,
+        (Some((side, limit)), _) => (side / limit).max(1.0),
+        (None, _) => 1.0,
+    }
+}
+
+// The side of an image of `dimensions` that decides its scale, and the length `target`
+// allows it, or `None` when `target` doesn't limit it at all. A box limits the side
+// that overflows it the most; `Fill` the one that overflows it the least, so the other
+// gets cropped. The shrink modes measure their one side against the box's.
+fn binding_side(
+    dimensions: (u32, u32),
+    mode: ResizeMode,
+    target: ResizeTarget,
+) -> Option<(f64, f64)> {
+    let (width, height) = dimensions;
+    let (max_width, max_height) = match target {
+        ResizeTarget::Scale(percent) => return Some((100.0, percent)),
+        ResizeTarget::Box { width, height } => (width, height),
+    };
+    let ratio = |(side, limit): &(f64, f64)| side / limit;
+    let mut sides = [(width, max_width), (height, max_height)]
+        .into_iter()
+        .filter_map(|(side, limit)| Some((side as f64, limit? as f64)));
+    match mode {
+        ResizeMode::Fit => sides.max_by(|a, b| ratio(a).total_cmp(&ratio(b))),
+        ResizeMode::Fill => sides.min_by(|a, b| ratio(a).total_cmp(&ratio(b))),
+        ResizeMode::ShrinkLongest => Some((width.max(height) as f64, sides.next()?.1)),
+        ResizeMode::ShrinkShortest => Some((width.min(height) as f64, sides.next()?.1)),
+    }
+}
+
+// `resize_image` for `-s`, logging the scale of every downscale, warning past
+// `RESIZE_WARN_FACTOR` and refusing past `--max-resize-factor`. Images left as they are
+// because they are already small enough are logged too, as a warning with
+// `--warn-on-no-resize`, so an output that didn't shrink doesn't look like a bug.
+fn checked_resize(
+    img: &DynamicImage,
+    name: &Path,
+    options: &ConversionOptions,
+) -> Result<DynamicImage, WebpConverterError> {
+    let target = options.resize_target;
+    let factor = resize_factor(img.dimensions(), options.resize_mode, target);
+    let resized = match options.max_resize_factor {
+        Some(max_factor) if factor > max_factor => {
+            let (width, height) = img.dimensions();
+            let planned = match (options.resize_mode, target) {
+                (
+                    ResizeMode::Fill,
+                    ResizeTarget::Box {
+                        width: Some(box_width),
+                        height: Some(box_height),
+                    },
+                ) => (box_width, box_height),
+                _ => (
+                    ((width as f64 / factor).round() as u32).max(1),
+                    ((height as f64 / factor).round() as u32).max(1),
+                ),
+            };
+            return Err(WebpConverterError::resize_refused(
+                name,
+                (width, height),
+                planned,
+                factor,
+                max_factor,
+            ));
+        }
+        _ => resize_image(img.clone(), options.resize_mode, target),
+    };
+    // `Fill` scales every image to the box, small ones included.
+    if factor == 1.0 && options.resize_mode != ResizeMode::Fill {
+        let (width, height) = img.dimensions();
+        let reason = match (options.resize_mode, target) {
+            (_, ResizeTarget::Scale(_)) => "--scale is 100%".to_string(),
+            (ResizeMode::ShrinkShortest, _) => {
+                format!("its shortest side is within {}", target)
+            }
+            _ => format!("it already fits in {}", target),
+        };
+        let message = format!(
+            "Not resizing {:?}: {}x{}, {} and -s never enlarges",
+            name.file_name().unwrap_or_default(),
+            width,
+            height,
+            reason
+        );
+        if options.warn_on_no_resize {
+            warn!("{}", message.yellow());
+        } else {
+            info!("{}", message.cyan());
+        }
+    }
+    if factor > 1.0 {
+        let (width, height) = img.dimensions();
+        let (new_width, new_height) = resized.dimensions();
+        let message = format!(
+            "Resizing {:?}: {}x{} -> {}x{} ({:.1}x smaller)",
+            name.file_name().unwrap_or_default(),
+            width,
+            height,
+            new_width,
+            new_height,
+            factor
+        );
+        if factor > RESIZE_WARN_FACTOR {
+            warn!(
+                "{}",
+                format!("{}, fine detail will be lost", message).yellow()
+            );
+        } else {
+            info!("{}", message.cyan());
+        }
+    }
+    Ok(resized)
+}
+
+/// Resizes `image` to `target` (the 700x700 box by default) following `mode`. Only
+/// `Fill` ever enlarges an image; the other modes return images that already fit as
+/// they are.
+pub fn resize_image(image: DynamicImage, mode: ResizeMode, target: ResizeTarget) -> DynamicImage {
+    resize_into(&image, mode, target).unwrap_or(image)
+}
+
+// `resize_image`, `None` when the image already fits.
+fn resize_into(
+    image: &DynamicImage,
+    mode: ResizeMode,
+    target: ResizeTarget,
+) -> Option<DynamicImage> {
+    let (width, height) = image.dimensions();
+
+    // Scaled and center-cropped, the only mode that gives the same size for every input.
+    if let (
+        ResizeMode::Fill,
+        ResizeTarget::Box {
+            width: Some(box_width),
+            height: Some(box_height),
+        },
+    ) = (mode, target)
+    {
+        return Some(image.resize_to_fill(box_width, box_height, FilterType::Lanczos3));
+    }
+    let (scale_side, limit) = binding_side((width, height), mode, target)?;
+    // Images already within the target are left unchanged.
+    if scale_side <= limit {
+        return None;
+    }
+
+    // Calculate the new dimensions while maintaining the aspect ratio.
+    let scale = limit / scale_side;
+    let new_width = ((width as f64 * scale).round() as u32).max(1);
+    let new_height = ((height as f64 * scale).round() as u32).max(1);
+
+    // Resize the image using the Lanczos3 algorithm for high-quality results.
+    Some(image.resize_exact(new_width, new_height, FilterType::Lanczos3))
+}
+
+// Longest side of an `--lqip` placeholder, and the quality it is encoded at.
+const LQIP_SIZE: u32 = 16;
+const LQIP_QUALITY: f32 = 20.0;
+
+// A `data:image/webp;base64,...` placeholder of `image` for `--lqip`: shrunk to
+// `LQIP_SIZE`, blurred and encoded at a low quality, a few hundred bytes at most.
+// Pages show it stretched until the full image has loaded.
+fn lqip_data_uri(image: &DynamicImage) -> Result<String, WebpConverterError> {
+    let small = resize_into(image, ResizeMode::Fit, ResizeTarget::square(LQIP_SIZE))
+        .unwrap_or_else(|| image.clone());
+    let options = EncodeOptions {
+        quality: LQIP_QUALITY,
+        ..EncodeOptions::default()
+    };
+    let encoded = WebpEncoder.encode(&small.blur(1.0), &options)?;
+    Ok(format!("data:image/webp;base64,{}", BASE64.encode(encoded)))
+}
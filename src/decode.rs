@@ -0,0 +1,184 @@
+use crate::converter::{flatten_alpha, resolve_concurrency};
+use crate::helpers::{self, ImageKind};
+use crate::types::{Background, ConversionOptions, ErrorKind, Jobs, WebpConverterError};
+use crate::wio::{self, OUTPUT_DIRECTORY_NAME};
+use colored::Colorize;
+use image::{DynamicImage, ImageOutputFormat};
+use log::{error, info, warn};
+use std::fmt::Display;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+use tokio::task::spawn_blocking;
+
+/// Format the `decode` subcommand writes WebP files back to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum DecodeFormat {
+    // Lossless, keeps transparency.
+    #[default]
+    Png,
+    // Transparent pixels are composited over white.
+    Jpeg,
+}
+
+impl DecodeFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            DecodeFormat::Png => "png",
+            DecodeFormat::Jpeg => "jpg",
+        }
+    }
+}
+
+/// Outcome of a `decode` run.
+#[derive(Debug, Clone, Default)]
+pub struct Decoded {
+    // WebP files decoded and written back.
+    pub decoded: usize,
+    // Images written, one per still WebP and one per frame of an animated one.
+    pub written: usize,
+    // WebP files that couldn't be decoded or written, with the reason.
+    pub failures: Vec<(PathBuf, String)>,
+}
+
+impl Display for Decoded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Decoded: {} WebP file(s) to {} image(s), {} failed",
+            self.decoded,
+            self.written,
+            self.failures.len()
+        )
+    }
+}
+
+// Decodes every `.webp` under `path` to `format`, into the output folder next to it
+// like a conversion's outputs. The output folders of earlier runs aren't walked.
+pub async fn decode_batch(
+    path: &Path,
+    recursive: bool,
+    format: DecodeFormat,
+    jpeg_quality: u8,
+) -> Decoded {
+    let semaphore = Arc::new(Semaphore::new(resolve_concurrency(Jobs::Auto)));
+    let decoded = Arc::new(Mutex::new(Decoded::default()));
+
+    let mut tasks = vec![];
+    let files = helpers::walk_files(path, recursive, false, &[OUTPUT_DIRECTORY_NAME.to_string()]);
+    for file in files {
+        if helpers::image_kind(&file.path) != Some(ImageKind::Webp) {
+            continue;
+        }
+        let semaphore = semaphore.clone();
+        let decoded = decoded.clone();
+        tasks.push(tokio::spawn(async move {
+            let _permit = semaphore
+                .acquire()
+                .await
+                .expect("Failed to acquire semaphore permit");
+            let result = decode_single_photo(&file.path, format, jpeg_quality).await;
+            let mut decoded = decoded.lock().unwrap();
+            match result {
+                Ok(outputs) => {
+                    decoded.decoded += 1;
+                    decoded.written += outputs.len();
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    decoded.failures.push((file.path, e.message));
+                }
+            }
+        }));
+    }
+
+    for task in tasks {
+        task.await.expect("Task failed to complete");
+    }
+
+    let mut decoded = decoded.lock().unwrap().clone();
+    // Tasks finish in any order, the report shouldn't.
+    decoded.failures.sort();
+    if decoded.failures.is_empty() {
+        info!("{}", decoded.to_string().bright_green().bold());
+    } else {
+        warn!("{}", decoded.to_string().yellow().bold());
+    }
+    decoded
+}
+
+// Decodes one WebP to `format` and returns what was written: `{stem}.png` for a still
+// image, `{stem}-f1.png`, `{stem}-f2.png`, ... for the frames of an animation, whose
+// timing is left behind.
+pub async fn decode_single_photo(
+    path: &Path,
+    format: DecodeFormat,
+    jpeg_quality: u8,
+) -> Result<Vec<PathBuf>, WebpConverterError> {
+    let bytes = tokio::fs::read(path).await?;
+    let images = spawn_blocking(move || decode_webp(&bytes)).await??;
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let animated = images.len() > 1;
+
+    let options = ConversionOptions::default();
+    let mut outputs = vec![];
+    for (index, image) in images.into_iter().enumerate() {
+        let file_name = if animated {
+            format!("{}-f{}.{}", stem, index + 1, format.extension())
+        } else {
+            format!("{}.{}", stem, format.extension())
+        };
+        let encoded = spawn_blocking(move || encode(image, format, jpeg_quality)).await??;
+        let output = wio::write_output(path, file_name.as_ref(), &encoded, &options).await?;
+        info!(
+            "{}",
+            format!("Decoded: {:?} -> {:?}", path, output).bright_blue()
+        );
+        outputs.push(output);
+    }
+    Ok(outputs)
+}
+
+// Every frame of `bytes`, or the one image of a still WebP. Goes through libwebp like
+// `--verify-output`, the `image` crate's decoder fails on some valid lossless files.
+fn decode_webp(bytes: &[u8]) -> Result<Vec<DynamicImage>, WebpConverterError> {
+    let unreadable = || WebpConverterError {
+        message: "libwebp can't decode it".to_string(),
+        kind: ErrorKind::Other,
+    };
+    let animated = webp::BitstreamFeatures::new(bytes)
+        .ok_or_else(unreadable)?
+        .has_animation();
+    if !animated {
+        let image = webp::Decoder::new(bytes).decode().ok_or_else(unreadable)?;
+        return Ok(vec![image.to_image()]);
+    }
+    let animation = webp::AnimDecoder::new(bytes)
+        .decode()
+        .map_err(|_| unreadable())?;
+    Ok((0..animation.len())
+        .filter_map(|index| animation.get_frame(index))
+        .map(|frame| (&frame).into())
+        .collect())
+}
+
+fn encode(
+    image: DynamicImage,
+    format: DecodeFormat,
+    jpeg_quality: u8,
+) -> Result<Vec<u8>, WebpConverterError> {
+    let (image, output_format) = match format {
+        DecodeFormat::Png => (image, ImageOutputFormat::Png),
+        DecodeFormat::Jpeg => (
+            flatten_alpha(image, Background::default()),
+            ImageOutputFormat::Jpeg(jpeg_quality),
+        ),
+    };
+    let mut bytes = Cursor::new(vec![]);
+    image.write_to(&mut bytes, output_format)?;
+    Ok(bytes.into_inner())
+}
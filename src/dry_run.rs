@@ -0,0 +1,107 @@
+use crate::converter::{passthrough, planned_output, up_to_date_output};
+use crate::helpers;
+use crate::types::{ConversionOptions, FileInfo};
+use crate::wio;
+use colored::Colorize;
+use log::info;
+use std::fmt::Display;
+use std::io;
+use std::path::Path;
+
+/// What a `--dry-run` found a run would do.
+#[derive(Debug, Clone, Default)]
+pub struct Plan {
+    pub convert: usize,
+    // PDFs, rasterized page by page.
+    pub rasterize: usize,
+    pub copy: usize,
+    // Files that aren't images, and with `--skip-existing` images whose output is up to date.
+    pub skip: usize,
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Dry run: {} file(s) to convert, {} PDF(s) to rasterize, {} to copy, {} skipped",
+            self.convert, self.rasterize, self.copy, self.skip
+        )
+    }
+}
+
+// Walks `path` like a conversion would and logs what would become of every file and
+// where its output would go, from the file names alone: nothing is decoded, written
+// or created. A single file is planned on its own.
+pub fn plan(path: &Path, recursive: bool, options: &ConversionOptions) -> io::Result<Plan> {
+    let (files, options) = if path.is_dir() {
+        let mut files = helpers::walk_files(
+            path,
+            recursive,
+            options.respect_ignore,
+            &options.exclude_dirs,
+        );
+        if let Some(since) = options.since {
+            helpers::retain_modified_since(&mut files, since);
+        }
+        if let Some(order) = options.sort {
+            helpers::sort_files(&mut files, order);
+        }
+        let options = ConversionOptions {
+            input_root: Some(path.to_path_buf()),
+            ..options.clone()
+        };
+        (files, options)
+    } else {
+        (vec![FileInfo::read(path)?], options.clone())
+    };
+
+    let mut plan = Plan::default();
+    for file in files {
+        let action = passthrough(
+            helpers::which_action_for(&file.path, options.format),
+            &options,
+        );
+        let up_to_date =
+            options.skip_existing && up_to_date_output(&file, action, &options).is_some();
+        let (verb, count) = match action {
+            _ if up_to_date => ("Skip (up to date)", &mut plan.skip),
+            helpers::Actions::Convert => ("Convert", &mut plan.convert),
+            helpers::Actions::Pdf => ("Rasterize", &mut plan.rasterize),
+            helpers::Actions::Copy => ("Copy", &mut plan.copy),
+            helpers::Actions::Nothing => {
+                plan.skip += 1;
+                info!(
+                    "{}",
+                    format!("Skip: {:?} (not an image)", file.path).yellow()
+                );
+                continue;
+            }
+        };
+        *count += 1;
+        info!(
+            "{}",
+            format!(
+                "{}: {:?} -> {}",
+                verb,
+                file.path,
+                destination(&file.path, action, &options)
+            )
+            .bright_blue()
+        );
+    }
+    info!("{}", plan.to_string().bright_cyan().bold());
+    Ok(plan)
+}
+
+// The output `path` would be written to, or the folder its outputs would land in when
+// their names aren't known up front (PDF pages, icon sizes, `--hash-names`, `--output`).
+fn destination(path: &Path, action: helpers::Actions, options: &ConversionOptions) -> String {
+    if let Some(target) = &options.output {
+        let prefix = target.key_for(path, options.input_root.as_deref(), "".as_ref());
+        return format!("s3://{}/{}", target.bucket, prefix);
+    }
+    match planned_output(path, action, options) {
+        Some(output) => format!("{:?}", output),
+        None => format!("{:?}", wio::output_directory(path, options)),
+    }
+}
@@ -0,0 +1,214 @@
+use crate::converter::{
+    apply_transforms, encode_image, open_reader, passthrough, resize_image, resolve_concurrency,
+};
+use crate::helpers;
+use crate::types::{
+    savings_percent, ConversionOptions, EncodeOptions, FileInfo, WebpConverterError,
+};
+use colored::Colorize;
+use image::GenericImageView;
+use log::{info, warn};
+use std::fmt::Display;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+use tokio::task::spawn_blocking;
+
+// Images up to this many pixels are encoded as they are, larger ones are first
+// shrunk to about this size. A 512x512 proxy is ~1/50th of a 12 megapixel photo.
+const PROXY_PIXELS: u64 = 512 * 512;
+
+/// Predicted outcome of a batch, from `--estimate`.
+#[derive(Debug, Clone, Default)]
+pub struct Estimate {
+    pub files: usize,
+    pub input_bytes: u64,
+    pub estimated_bytes: u64,
+    // Files that couldn't be estimated (PDFs, undecodable images).
+    pub skipped: usize,
+}
+
+impl Display for Estimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Estimated: {} file(s), {} -> ~{} bytes (~{:.1}% saved), {} skipped",
+            self.files,
+            self.input_bytes,
+            self.estimated_bytes,
+            savings_percent(self.input_bytes, self.estimated_bytes),
+            self.skipped
+        )
+    }
+}
+
+// Predicts the batch without writing anything: every image is encoded as a small
+// proxy and the proxy's bytes per pixel are scaled up to the real pixel count.
+// WebP files that would be copied count at their current size.
+pub async fn estimate_batch(path: &Path, recursive: bool, options: &ConversionOptions) -> Estimate {
+    let options = Arc::new(options.clone());
+    let semaphore = Arc::new(Semaphore::new(resolve_concurrency(options.jobs)));
+    let estimate = Arc::new(Mutex::new(Estimate::default()));
+
+    let mut tasks = vec![];
+    let mut files = helpers::walk_files(
+        path,
+        recursive,
+        options.respect_ignore,
+        &options.exclude_dirs,
+    );
+    if let Some(since) = options.since {
+        helpers::retain_modified_since(&mut files, since);
+    }
+    for file in files {
+        match passthrough(
+            helpers::which_action_for(&file.path, options.format),
+            &options,
+        ) {
+            helpers::Actions::Convert => {
+                let semaphore = semaphore.clone();
+                let options = options.clone();
+                let estimate = estimate.clone();
+                tasks.push(tokio::spawn(async move {
+                    let _permit = semaphore
+                        .acquire()
+                        .await
+                        .expect("Failed to acquire semaphore permit");
+                    let result = estimate_file(&file, &options).await;
+                    let mut estimate = estimate.lock().unwrap();
+                    match result {
+                        Ok((input_size, estimated_size)) => {
+                            estimate.files += 1;
+                            estimate.input_bytes += input_size;
+                            estimate.estimated_bytes += estimated_size;
+                        }
+                        Err(e) => {
+                            warn!(
+                                "{}",
+                                format!("Can't estimate {:?}: {}", file.path, e).yellow()
+                            );
+                            estimate.skipped += 1;
+                        }
+                    }
+                }));
+            }
+            helpers::Actions::Copy => {
+                let mut estimate = estimate.lock().unwrap();
+                estimate.files += 1;
+                estimate.input_bytes += file.size;
+                estimate.estimated_bytes += file.size;
+            }
+            helpers::Actions::Pdf => estimate.lock().unwrap().skipped += 1,
+            helpers::Actions::Nothing => {}
+        }
+    }
+
+    for task in tasks {
+        task.await.expect("Task failed to complete");
+    }
+
+    let estimate = estimate.lock().unwrap().clone();
+    info!("{}", estimate.to_string().bright_cyan().bold());
+    estimate
+}
+
+// `-c` asks libwebp for a target size; shrink it along with the proxy so the
+// encoder aims for the same ratio.
+fn target_size(input_size: u64, options: &ConversionOptions, scale: f64) -> i32 {
+    match options.compression_factor as i32 {
+        0 => 0,
+        _ => (input_size as f64 / options.compression_factor as f64 / scale) as i32,
+    }
+}
+
+// Returns the input size and the predicted output size of one image.
+pub async fn estimate_single_photo(
+    path: &Path,
+    options: &ConversionOptions,
+) -> Result<(u64, u64), WebpConverterError> {
+    estimate_file(&FileInfo::read(path)?, options).await
+}
+
+async fn estimate_file(
+    file: &FileInfo,
+    options: &ConversionOptions,
+) -> Result<(u64, u64), WebpConverterError> {
+    let input_size = file.size;
+    let estimated_size = estimated_output_size(file, options).await?;
+    // Like a real run, an image that doesn't save enough keeps its original.
+    if options.min_savings_percent > 0.0
+        && savings_percent(input_size, estimated_size) < options.min_savings_percent
+    {
+        return Ok((input_size, input_size));
+    }
+    Ok((input_size, estimated_size))
+}
+
+async fn estimated_output_size(
+    file: &FileInfo,
+    options: &ConversionOptions,
+) -> Result<u64, WebpConverterError> {
+    let path = file.path.clone();
+    let input_size = file.size;
+
+    let decode_options = options.clone();
+    let decode_path = path.clone();
+    let img = spawn_blocking(move || -> Result<_, WebpConverterError> {
+        let options = decode_options;
+        let img = open_reader(&decode_path, options.max_pixels)?.decode()?;
+        let img = apply_transforms(img, &options);
+        Ok(if options.should_resize {
+            resize_image(img, options.resize_mode, options.resize_target)
+        } else {
+            img
+        })
+    })
+    .await??;
+
+    let (width, height) = img.dimensions();
+    let pixels = width as u64 * height as u64;
+    let quality = options.encoder_quality(&path, options.lossless);
+    if pixels <= PROXY_PIXELS {
+        let target_size = target_size(input_size, options, 1.0);
+        let encode_options = EncodeOptions {
+            quality,
+            ..options.encode_options(target_size)
+        };
+        let size = encode_image(img, encode_options, options.format).await?;
+        return Ok(size.len() as u64);
+    }
+
+    // Downscaled images pack more detail into each pixel, so bytes don't grow linearly
+    // with the pixel count. Encode proxies at two sizes and fit `size = a * pixels^k`.
+    let proxies = spawn_blocking(move || {
+        [PROXY_PIXELS / 4, PROXY_PIXELS].map(|proxy_pixels| {
+            let scale = (proxy_pixels as f64 / pixels as f64).sqrt();
+            img.thumbnail(
+                ((width as f64 * scale).round() as u32).max(1),
+                ((height as f64 * scale).round() as u32).max(1),
+            )
+        })
+    })
+    .await?;
+    let mut samples = vec![];
+    for proxy in proxies {
+        let (proxy_width, proxy_height) = proxy.dimensions();
+        let proxy_pixels = (proxy_width as u64 * proxy_height as u64) as f64;
+        let target_size = target_size(input_size, options, pixels as f64 / proxy_pixels);
+        let encode_options = EncodeOptions {
+            quality,
+            ..options.encode_options(target_size)
+        };
+        let size = encode_image(proxy, encode_options, options.format)
+            .await?
+            .len();
+        samples.push((proxy_pixels, size as f64));
+    }
+    let [(small_pixels, small_size), (large_pixels, large_size)] = samples[..] else {
+        unreachable!()
+    };
+    // Clamp the exponent so an odd pair of samples can't run away when extrapolated.
+    let exponent =
+        ((large_size / small_size).ln() / (large_pixels / small_pixels).ln()).clamp(0.5, 1.0);
+    Ok((large_size * (pixels as f64 / large_pixels).powf(exponent)).round() as u64)
+}
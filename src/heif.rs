@@ -0,0 +1,65 @@
+use crate::types::{ErrorKind, WebpConverterError};
+use image::DynamicImage;
+
+#[cfg(feature = "heif")]
+impl From<libheif_rs::HeifError> for WebpConverterError {
+    fn from(error: libheif_rs::HeifError) -> Self {
+        WebpConverterError {
+            message: format!("HEIF Error: {}", error),
+            kind: ErrorKind::Other,
+        }
+    }
+}
+
+// The width and height of the primary image of a HEIC/HEIF file, from its container.
+#[cfg(feature = "heif")]
+pub fn dimensions(bytes: &[u8]) -> Result<(u32, u32), WebpConverterError> {
+    let context = libheif_rs::HeifContext::read_from_bytes(bytes)?;
+    let handle = context.primary_image_handle()?;
+    Ok((handle.width(), handle.height()))
+}
+
+#[cfg(not(feature = "heif"))]
+pub fn dimensions(_bytes: &[u8]) -> Result<(u32, u32), WebpConverterError> {
+    Err(unavailable())
+}
+
+// Decodes the primary image (the photo, not its thumbnail or depth map) as RGBA. libheif
+// applies the rotation and mirroring the file records, so it comes out upright.
+#[cfg(feature = "heif")]
+pub fn decode(bytes: &[u8]) -> Result<DynamicImage, WebpConverterError> {
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let context = HeifContext::read_from_bytes(bytes)?;
+    let handle = context.primary_image_handle()?;
+    let image = LibHeif::new().decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)?;
+    let malformed = || WebpConverterError {
+        message: "HEIF Error: libheif returned no interleaved RGBA plane".to_string(),
+        kind: ErrorKind::Other,
+    };
+    let plane = image.planes().interleaved.ok_or_else(malformed)?;
+
+    // Rows are `stride` bytes apart, which can be more than the pixels of a row.
+    let row = plane.width as usize * 4;
+    let mut pixels = Vec::with_capacity(row * plane.height as usize);
+    for line in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        pixels.extend_from_slice(line.get(..row).ok_or_else(malformed)?);
+    }
+    let rgba =
+        image::RgbaImage::from_raw(plane.width, plane.height, pixels).ok_or_else(malformed)?;
+    Ok(DynamicImage::ImageRgba8(rgba))
+}
+
+#[cfg(not(feature = "heif"))]
+pub fn decode(_bytes: &[u8]) -> Result<DynamicImage, WebpConverterError> {
+    Err(unavailable())
+}
+
+#[cfg(not(feature = "heif"))]
+fn unavailable() -> WebpConverterError {
+    WebpConverterError {
+        message: "HEIC/HEIF decoding requires building with `--features heif` (libheif)"
+            .to_string(),
+        kind: ErrorKind::Other,
+    }
+}
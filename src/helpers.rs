@@ -0,0 +1,727 @@
+use crate::cache;
+use crate::decode::DecodeFormat;
+use crate::encoder::{Chroma, OutputFormat};
+use crate::logging::LogFileMode;
+use crate::settings::SettingsFormat;
+use crate::trace;
+use crate::types::{
+    Background, ColorProfile, ColorSpace, FileInfo, Flip, Jobs, QualityMap, ReportFormat,
+    ResizeMode, Roi, Rotation, SortOrder, TiffPages, DEFAULT_SPRITE_MAX_WIDTH,
+};
+use crate::wio::{self, S3Target};
+use clap::{Parser, Subcommand};
+use colored::Colorize;
+use ignore::WalkBuilder;
+use log::info;
+#[cfg(windows)]
+use log::warn;
+use std::cmp::Reverse;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use walkdir::WalkDir;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct Args {
+    #[arg(short = 'p', long = "PATH")]
+    pub path: Option<String>,
+    /// Path to an image file or folder, the same as `-p` (`webp_converter ./images`).
+    #[arg(value_name = "PATH", conflicts_with = "path")]
+    pub input: Option<String>,
+    #[arg(short = 'r', long = "RECURSIVE")]
+    pub recursive: Option<bool>,
+    #[arg(short = 'q', long = "QUALITY")]
+    pub quality: Option<f32>,
+    #[arg(short = 'l', long = "LOSSLESS")]
+    pub lossless: Option<bool>,
+    #[arg(short = 'c', long = "COMPRESSIONFACTOR")]
+    pub compression_factor: Option<f32>,
+    #[arg(short = 's', long = "RESIZE")]
+    pub resize: Option<bool>,
+    /// How -s fits images to its box (700x700 unless set below): fit inside, fill and crop, shrink the longest side, or shrink the shortest side. Implies -s.
+    #[arg(long = "resize-mode", value_enum)]
+    pub resize_mode: Option<ResizeMode>,
+    /// Widest -s lets an image be, in pixels. Without --max-height the height isn't limited. Implies -s.
+    #[arg(long = "max-width", value_name = "PX", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_width: Option<u32>,
+    /// Tallest -s lets an image be, in pixels. Without --max-width the width isn't limited. Implies -s.
+    #[arg(long = "max-height", value_name = "PX", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_height: Option<u32>,
+    /// Side of the square box -s resizes into, in pixels, instead of 700. Implies -s.
+    #[arg(long = "max-dimension", value_name = "PX", value_parser = clap::value_parser!(u32).range(1..), conflicts_with_all = ["max_width", "max_height"])]
+    pub max_dimension: Option<u32>,
+    /// Scale every image by this percentage (`50%`) instead of fitting it to a box. Implies -s.
+    #[arg(long = "scale", value_name = "PERCENT", value_parser = parse_scale, conflicts_with_all = ["max_width", "max_height", "max_dimension"])]
+    pub scale: Option<f64>,
+    #[arg(short = 'n', long = "NOISERATIO")]
+    pub psnr: Option<f32>,
+    /// Lowest quality the -n or -c search may pick, even if that misses its target (0-100).
+    #[arg(long = "min-quality")]
+    pub min_quality: Option<f32>,
+    /// Highest quality the -n or -c search may pick, even if that misses its target (0-100).
+    #[arg(long = "max-quality")]
+    pub max_quality: Option<f32>,
+    /// Region to keep sharper than the rest, `x,y,width,height` in pixels after rotate and flip; it is encoded at --roi-quality, the rest at the usual settings. Even a small region makes the file several times larger. Lossy WebP only.
+    #[arg(long = "roi")]
+    pub roi: Option<Roi>,
+    /// Quality of the --roi region (0-100). Defaults to 90.
+    #[arg(long = "roi-quality", requires = "roi")]
+    pub roi_quality: Option<f32>,
+    /// Refuse images larger than this many pixels instead of risking running out of memory (0 disables).
+    #[arg(long = "max-pixels")]
+    pub max_pixels: Option<u64>,
+    /// Fail images wider or taller than the output format allows (16383px for WebP) instead of downscaling them to fit.
+    #[arg(long = "no-auto-fit")]
+    pub no_auto_fit: bool,
+    /// Number of images converted in parallel, or `auto` to size it from cores and available memory.
+    #[arg(short = 'j', long = "jobs", default_value = "auto")]
+    pub jobs: Option<Jobs>,
+    /// Encode single-threaded so the same input always yields the same bytes. Can't be combined with `--keep-*`, outputs carry no metadata.
+    #[arg(long = "deterministic")]
+    pub deterministic: bool,
+    /// Re-encode JPEG and PNG files in their own format (mozjpeg / oxipng) instead of converting to WebP.
+    #[arg(long = "reoptimize")]
+    pub reoptimize: bool,
+    /// Resolution used to rasterize PDF pages (requires the `pdf` feature).
+    #[arg(long = "pdf-dpi", default_value = "150")]
+    pub pdf_dpi: Option<f32>,
+    /// Keep the original instead of the WebP when converting saves less than this percentage.
+    #[arg(long = "min-savings-percent")]
+    pub min_savings_percent: Option<f32>,
+    /// When an output already exists, only replace it if the new encode is smaller.
+    #[arg(long = "overwrite-if-smaller", conflicts_with = "hash_names")]
+    pub overwrite_if_smaller: bool,
+    /// Per-extension quality overrides, e.g. `jpg=85,png=90`; other extensions use --QUALITY.
+    #[arg(long = "quality-map")]
+    pub quality_map: Option<QualityMap>,
+    /// Write one WebP per size embedded in `.ico`/`.cur` files (`name-32x32.webp`) instead of only the largest.
+    #[arg(long = "ico-all-sizes")]
+    pub ico_all_sizes: bool,
+    /// Pages of multi-page TIFFs to convert: `all` writes one WebP per page (`name-p2.webp`), a number converts only that page (from 1). Defaults to the first page.
+    #[arg(long = "tiff-pages", value_name = "all|N")]
+    pub tiff_pages: Option<TiffPages>,
+    /// Widths to write each image at instead of one output, comma-separated (`--sizes 320,640,1280`), as `name-320w.webp` and so on for a responsive `srcset`. Images are never enlarged.
+    #[arg(long = "sizes", value_name = "PX,...", value_delimiter = ',', value_parser = clap::value_parser!(u32).range(1..))]
+    pub sizes: Vec<u32>,
+    /// Output format to encode images to. Several can be given, comma-separated (`webp,avif`), to decode each image once and write one output per format.
+    #[arg(
+        short = 'f',
+        long = "format",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "webp"
+    )]
+    pub format: Vec<OutputFormat>,
+    /// Rotate clockwise by 90, 180 or 270 degrees (applied before flip and resize).
+    #[arg(long = "rotate", value_enum)]
+    pub rotate: Option<Rotation>,
+    /// Mirror horizontally (`h`) or vertically (`v`), after rotating and before resizing.
+    #[arg(long = "flip", value_enum)]
+    pub flip: Option<Flip>,
+    /// Upload outputs to `s3://bucket/prefix` instead of writing them locally (requires the `s3` feature).
+    #[arg(short = 'o', long = "output")]
+    pub output: Option<S3Target>,
+    /// Print a histogram of per-file compression ratios with min/median/max to stderr after a batch.
+    #[arg(long = "diff-report")]
+    pub diff_report: bool,
+    /// Log, for every file, the choices made for it: color type, formats, lossless or not and why, resize and quality.
+    #[arg(long = "explain")]
+    pub explain: bool,
+    /// Write the end-of-batch summary as a log line (`text`), or as JSON or CSV on stdout for other tools.
+    #[arg(long = "report-format", value_enum, default_value_t = ReportFormat::Text)]
+    pub report_format: ReportFormat,
+    /// Skip files excluded by `.gitignore`/`.ignore` files while walking folders.
+    #[arg(long = "respect-ignore")]
+    pub respect_ignore: bool,
+    /// Show what a run will do and ask for confirmation, then ask before overwriting each existing output.
+    #[arg(short = 'i', long = "interactive")]
+    pub interactive: bool,
+    /// With --interactive, overwrite existing outputs without asking.
+    #[arg(short = 'y', long = "yes")]
+    pub yes: bool,
+    /// Give up on a file whose encode takes longer than this many seconds and count it as failed.
+    #[arg(long = "timeout", value_parser = parse_seconds)]
+    pub timeout: Option<Duration>,
+    /// Give outputs that keep their format (copies, --reoptimize) the canonical lowercase extension, e.g. `.JPEG` -> `.jpg`.
+    #[arg(long = "normalize-extension")]
+    pub normalize_extension: bool,
+    /// Convert images to grayscale before encoding (after rotate and flip, before resize).
+    #[arg(long = "grayscale")]
+    pub grayscale: bool,
+    /// Drop transparency: composite images over --background and encode opaque WebP.
+    #[arg(long = "no-alpha")]
+    pub no_alpha: bool,
+    /// Color transparent pixels are composited over with --no-alpha: white, black or hex (#ffcc00). Implies --no-alpha. Defaults to white.
+    #[arg(long = "background")]
+    pub background: Option<Background>,
+    /// Encode every output as RGB without an alpha channel, dropping any transparency instead of detecting it.
+    #[arg(long = "force-rgb", conflicts_with = "force_rgba")]
+    pub force_rgb: bool,
+    /// Encode every output with an alpha channel, opaque images included, for pipelines that expect uniform RGBA.
+    #[arg(long = "force-rgba")]
+    pub force_rgba: bool,
+    /// Predict the output size by encoding small proxies of each image, without writing anything.
+    #[arg(long = "estimate")]
+    pub estimate: bool,
+    /// Only decode every image (header and pixels) to find corrupt or unsupported files, without encoding or writing anything. Exits 1 if any fail.
+    #[arg(long = "check", conflicts_with = "estimate")]
+    pub check: bool,
+    /// List what would be converted, copied or skipped and where each output would go, without decoding or writing anything.
+    #[arg(long = "dry-run", conflicts_with_all = ["estimate", "check", "spritesheet", "batch_spec", "clean_output", "tar"])]
+    pub dry_run: bool,
+    /// Convert wide-gamut sources (Display P3, Adobe RGB) to this color space using their ICC profile (requires the `color` feature).
+    #[arg(long = "target-colorspace", value_enum)]
+    pub target_colorspace: Option<ColorSpace>,
+    /// Copy the source's EXIF (camera, date, location) into WebP outputs; its orientation is reset to normal once applied.
+    #[arg(long = "keep-exif")]
+    pub keep_exif: bool,
+    /// Copy the source's ICC color profile into WebP outputs.
+    #[arg(long = "keep-icc", conflicts_with = "target_colorspace")]
+    pub keep_icc: bool,
+    /// Copy the source's XMP packet into WebP outputs.
+    #[arg(long = "keep-xmp")]
+    pub keep_xmp: bool,
+    /// Copy all of the source's metadata into WebP outputs, the same as `--keep-exif --keep-icc --keep-xmp`.
+    #[arg(long = "keep-metadata", conflicts_with = "target_colorspace")]
+    pub keep_metadata: bool,
+    /// Make sure no EXIF or XMP (GPS location, camera serial numbers) reaches any output, copied WebP files included, and count the files that had a location.
+    #[arg(long = "strip-metadata", conflicts_with_all = ["keep_exif", "keep_xmp", "keep_metadata"])]
+    pub strip_metadata: bool,
+    /// What to do with the source's ICC profile: embed it in WebP outputs, convert the pixels to sRGB (requires the `color` feature) or strip it. Overrides `--keep-metadata` for the profile.
+    #[arg(long = "color-profile", value_enum, conflicts_with_all = ["keep_icc", "target_colorspace"])]
+    pub color_profile: Option<ColorProfile>,
+    /// Rotate and flip images as their EXIF orientation says before encoding (true by default); false keeps the pixels as stored.
+    #[arg(long = "apply-orientation")]
+    pub apply_orientation: Option<bool>,
+    /// Keep the pixels as stored instead of rotating them upright, the same as `--apply-orientation false`.
+    #[arg(long = "no-auto-orient", conflicts_with = "apply_orientation")]
+    pub no_auto_orient: bool,
+    /// Exit with code 3 when a folder contains no convertible images.
+    #[arg(long = "error-on-empty")]
+    pub error_on_empty: bool,
+    /// Keep the exit code at 0 when files fail to convert; by default any failure exits with code 1.
+    #[arg(long = "ignore-errors")]
+    pub ignore_errors: bool,
+    /// Load settings from a TOML file or an http(s) URL; flags given on the command line take precedence.
+    #[arg(long = "config")]
+    pub config: Option<String>,
+    /// Choose per file: lossless for images with transparency, lossy for opaque ones.
+    #[arg(long = "auto-format")]
+    pub auto_format: bool,
+    /// Encoder effort from 0 to 6: higher is slower but produces smaller files.
+    #[arg(long = "effort", value_parser = clap::value_parser!(i32).range(0..=6), conflicts_with_all = ["fast", "best"])]
+    pub effort: Option<i32>,
+    /// How lossy WebP keeps color detail: `420` (the default) averages it, `sharp` uses libwebp's sharp YUV to keep colored edges crisp, at a slower conversion.
+    #[arg(long = "chroma", value_enum, default_value_t = Chroma::Fast)]
+    pub chroma: Chroma,
+    /// Same as --effort 1, for a much faster run at a slightly larger size.
+    #[arg(long = "fast", conflicts_with = "best")]
+    pub fast: bool,
+    /// Same as --effort 6, the smallest files (the default).
+    #[arg(long = "best")]
+    pub best: bool,
+    /// Only convert the first N convertible files found while walking, to try settings quickly.
+    #[arg(long = "sample", value_parser = clap::value_parser!(u64).range(1..))]
+    pub sample: Option<u64>,
+    /// Stop starting new files once N have been converted successfully; failures and plain copies don't count. Files already running may take it slightly over.
+    #[arg(long = "limit", value_parser = clap::value_parser!(u64).range(1..))]
+    pub limit: Option<u64>,
+    /// Process files by path (`name`), largest first (`size`) or oldest first (`mtime`) instead of walk order.
+    #[arg(long = "sort", value_enum)]
+    pub sort: Option<SortOrder>,
+    /// Skip files that -s would shrink by more than this factor (e.g. 4 for 4000px -> 1000px) instead of converting them.
+    #[arg(long = "max-resize-factor", value_parser = parse_resize_factor)]
+    pub max_resize_factor: Option<f64>,
+    /// Log a warning for every image -s leaves as it is because it is already small enough, instead of an info line.
+    #[arg(long = "warn-on-no-resize")]
+    pub warn_on_no_resize: bool,
+    /// Only process files modified within this long before the run, e.g. `90m`, `2h` or `3d`.
+    #[arg(long = "since", value_parser = parse_since)]
+    pub since: Option<Duration>,
+    /// Encode losslessly, spending this much effort (0-100) on compression: higher is slower but smaller. Independent of --QUALITY.
+    #[arg(long = "lossless-effort", value_parser = parse_percent)]
+    pub lossless_effort: Option<f32>,
+    /// Decode every written output again and delete it if it doesn't come back at the expected size. AVIF outputs aren't decoded, only the size in their `ispe` box is read.
+    #[arg(long = "verify-output")]
+    pub verify_output: bool,
+    /// Also write the log, without colors, to this file.
+    #[arg(long = "log-file")]
+    pub log_file: Option<PathBuf>,
+    /// What to do with an existing --log-file: keep appending, truncate it, or rotate it to FILE.1.
+    #[arg(long = "log-file-mode", value_enum, default_value_t = LogFileMode::Append, requires = "log_file")]
+    pub log_file_mode: LogFileMode,
+    /// Pack every image in the folder into one sprite sheet, plus a JSON of where each one is, instead of converting them one by one.
+    #[arg(long = "spritesheet", conflicts_with_all = ["estimate", "check"])]
+    pub spritesheet: bool,
+    /// Pixels left between the images of a --spritesheet.
+    #[arg(long = "sprite-padding", default_value_t = 0, requires = "spritesheet")]
+    pub sprite_padding: u32,
+    /// Widest a --spritesheet may get before images wrap to a new row (at most 16383).
+    #[arg(long = "sprite-max-width", default_value_t = DEFAULT_SPRITE_MAX_WIDTH, value_parser = clap::value_parser!(u32).range(1..=16383), requires = "spritesheet")]
+    pub sprite_max_width: u32,
+    /// CSV of the files to convert, one row each with its own quality, resize, crop, rotate, flip or output name. Replaces -p.
+    #[arg(long = "batch-spec", conflicts_with_all = ["path", "input", "estimate", "check", "spritesheet"])]
+    pub batch_spec: Option<PathBuf>,
+    /// Print every setting the run will use, where it came from (command line, --config or default), as a table or JSON, then exit.
+    #[arg(long = "echo-settings", value_enum, num_args = 0..=1, default_missing_value = "table")]
+    pub echo_settings: Option<SettingsFormat>,
+    /// After --echo-settings, go on and convert instead of exiting.
+    #[arg(long = "echo-and-convert", requires = "echo_settings")]
+    pub echo_and_convert: bool,
+    /// Name of the folder created next to each source for its outputs.
+    #[arg(long = "output-dir-name", default_value = wio::OUTPUT_DIRECTORY_NAME, value_parser = parse_dir_name)]
+    pub output_dir_name: String,
+    /// Write every output to this folder, created if missing, instead of an output folder next to each source.
+    #[arg(long = "output-dir", value_name = "PATH", conflicts_with_all = ["output", "output_dir_name"])]
+    pub output_dir: Option<PathBuf>,
+    /// Recreate the input folder's tree under --output-dir, e.g. photos/2023/a.jpg -> out/photos/2023/a.webp.
+    #[arg(long = "mirror", requires = "output_dir")]
+    pub mirror: bool,
+    /// Write each output next to its source instead of in a webp_converter_output folder, keeping the directory layout.
+    #[arg(long = "in-place", conflicts_with_all = ["output", "output_dir", "output_dir_name", "clean_output"])]
+    pub in_place: bool,
+    /// With --in-place, delete each source once its output is written, so the WebP replaces it.
+    #[arg(long = "replace", requires = "in_place")]
+    pub replace: bool,
+    /// Draw a progress bar while a folder converts: files done out of the total, files per second and the time left.
+    #[arg(long = "progress")]
+    pub progress: bool,
+    /// Skip files whose output already exists and is newer than they are, so re-running on a large folder only converts what changed.
+    #[arg(long = "skip-existing", conflicts_with_all = ["output", "hash_names"])]
+    pub skip_existing: bool,
+    /// Only re-encode files whose content or settings changed since a run with the same cache FILE (.webp_converter_cache.json in the input folder by default).
+    #[arg(long = "cache", value_name = "FILE", num_args = 0..=1, default_missing_value = cache::CACHE_FILE_NAME, conflicts_with_all = ["output", "hash_names"])]
+    pub cache: Option<PathBuf>,
+    /// Don't descend into folders with this name (e.g. node_modules) while walking; repeat for more names.
+    #[arg(long = "exclude-dir", value_parser = parse_dir_name)]
+    pub exclude_dirs: Vec<String>,
+    /// Name outputs by a hash of their content (e.g. 3f9a0c1b2d4e5f60.webp) and write hash-manifest.json mapping the original names to them.
+    #[arg(long = "hash-names")]
+    pub hash_names: bool,
+    /// Also make a ~16px blurred WebP placeholder of every image and write them, base64 data URIs keyed by source path, to lqip-manifest.json.
+    #[arg(long = "lqip")]
+    pub lqip: bool,
+    /// TOML or JSON file of libwebp WebPConfig fields (quality, sns_strength, qmax, ...) to encode with, overriding the defaults.
+    #[arg(long = "encode-config")]
+    pub encode_config: Option<PathBuf>,
+    /// Copy files that aren't images (CSS, JS, fonts, ...) into the output folders unchanged instead of skipping them.
+    #[arg(long = "copy-others")]
+    pub copy_others: bool,
+    /// Before converting a folder, delete the outputs in its output folders whose source file no longer exists.
+    #[arg(long = "clean-output", conflicts_with_all = ["estimate", "check", "spritesheet", "batch_spec"])]
+    pub clean_output: bool,
+    /// Move empty or truncated source files into this folder, keeping their relative location, instead of only skipping them.
+    #[arg(long = "quarantine", value_name = "DIR")]
+    pub quarantine: Option<PathBuf>,
+    /// After the run, write everything it wrote to a tar archive at FILE, or stream it to stdout with `-`, keeping paths relative to the input folder.
+    #[arg(long = "tar", value_name = "FILE", conflicts_with_all = ["estimate", "check", "spritesheet"])]
+    pub tar: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Convert WebP files back to PNG or JPEG, animated ones to one image per frame, into the output folder next to them.
+    Decode(DecodeArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct DecodeArgs {
+    /// A `.webp` file or a folder of them.
+    #[arg(value_name = "PATH")]
+    pub path: PathBuf,
+    /// Also decode the WebP files in subfolders.
+    #[arg(short = 'r', long = "recursive")]
+    pub recursive: bool,
+    /// Format to write: png keeps transparency, jpeg composites it over white.
+    #[arg(long = "to", value_enum, default_value_t = DecodeFormat::Png)]
+    pub to: DecodeFormat,
+    /// Quality of --to jpeg outputs (1-100).
+    #[arg(short = 'q', long = "quality", default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub quality: u8,
+}
+
+fn parse_percent(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(value) if (0.0..=100.0).contains(&value) => Ok(value),
+        _ => Err(format!("expected a number between 0 and 100, got `{}`", s)),
+    }
+}
+
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    match s.parse::<f64>() {
+        // Finite values too large for a `Duration` are refused rather than overflowing.
+        Ok(seconds) if seconds > 0.0 => {
+            Duration::try_from_secs_f64(seconds).map_err(|_| format!("`{}` seconds is too long", s))
+        }
+        _ => Err(format!(
+            "expected a positive number of seconds, got `{}`",
+            s
+        )),
+    }
+}
+
+fn parse_resize_factor(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(factor) if factor.is_finite() && factor >= 1.0 => Ok(factor),
+        _ => Err(format!("expected a factor of at least 1, got `{}`", s)),
+    }
+}
+
+fn parse_scale(s: &str) -> Result<f64, String> {
+    match s.strip_suffix('%').unwrap_or(s).parse::<f64>() {
+        Ok(percent) if percent > 0.0 && percent <= 100.0 => Ok(percent),
+        _ => Err(format!(
+            "expected a percentage above 0 and up to 100 like `50%`, got `{}`",
+            s
+        )),
+    }
+}
+
+fn parse_dir_name(s: &str) -> Result<String, String> {
+    wio::validate_output_dir_name(s).map(|_| s.to_string())
+}
+
+fn parse_since(s: &str) -> Result<Duration, String> {
+    humantime::parse_duration(s)
+        .map_err(|e| format!("expected a duration like `2h` or `3d`, got `{}`: {}", s, e))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Actions {
+    Convert,
+    // Rasterized page by page, only available with the `pdf` feature.
+    Pdf,
+    Copy,
+    Nothing,
+}
+/// What kind of file an extension stands for, with every alias mapped to one kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageKind {
+    Jpeg,
+    Png,
+    Tiff,
+    Bmp,
+    Avif,
+    Gif,
+    Ico,
+    Cur,
+    Pdf,
+    Webp,
+    Jxl,
+    // HEIC/HEIF, decoded through libheif with the `heif` feature.
+    Heif,
+    // Camera RAW, converted from its embedded preview with the `raw` feature.
+    Raw,
+}
+
+// Every extension the converter recognizes, compared case-insensitively.
+// Supporting a new alias is one more line here.
+const EXTENSION_ALIASES: &[(&str, ImageKind)] = &[
+    ("jpg", ImageKind::Jpeg),
+    ("jpeg", ImageKind::Jpeg),
+    ("jpe", ImageKind::Jpeg),
+    ("jfif", ImageKind::Jpeg),
+    ("png", ImageKind::Png),
+    ("tif", ImageKind::Tiff),
+    ("tiff", ImageKind::Tiff),
+    ("bmp", ImageKind::Bmp),
+    ("dib", ImageKind::Bmp),
+    ("avif", ImageKind::Avif),
+    ("gif", ImageKind::Gif),
+    ("ico", ImageKind::Ico),
+    ("cur", ImageKind::Cur),
+    ("pdf", ImageKind::Pdf),
+    ("webp", ImageKind::Webp),
+    ("jxl", ImageKind::Jxl),
+    ("heic", ImageKind::Heif),
+    ("heif", ImageKind::Heif),
+    ("cr2", ImageKind::Raw),
+    ("nef", ImageKind::Raw),
+    ("arw", ImageKind::Raw),
+    ("dng", ImageKind::Raw),
+];
+
+impl ImageKind {
+    pub fn from_extension(extension: &str) -> Option<ImageKind> {
+        EXTENSION_ALIASES
+            .iter()
+            .find(|(alias, _)| alias.eq_ignore_ascii_case(extension))
+            .map(|(_, kind)| *kind)
+    }
+
+    // The extension every alias of this kind is normalized to.
+    pub fn canonical_extension(&self) -> &'static str {
+        match self {
+            ImageKind::Jpeg => "jpg",
+            ImageKind::Png => "png",
+            ImageKind::Tiff => "tiff",
+            ImageKind::Bmp => "bmp",
+            ImageKind::Avif => "avif",
+            ImageKind::Gif => "gif",
+            ImageKind::Ico => "ico",
+            ImageKind::Cur => "cur",
+            ImageKind::Pdf => "pdf",
+            ImageKind::Webp => "webp",
+            ImageKind::Jxl => "jxl",
+            ImageKind::Heif => "heic",
+            // Never written, but lets `--quality-map raw=...` cover every maker's format.
+            ImageKind::Raw => "raw",
+        }
+    }
+}
+
+pub fn image_kind(path: &Path) -> Option<ImageKind> {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.trim_end_matches('"'))
+        .and_then(ImageKind::from_extension)
+}
+
+pub fn which_action(path: &Path) -> Actions {
+    which_action_for(path, OutputFormat::default())
+}
+
+// Same as `which_action` when writing `format`: files already in it are copied.
+pub fn which_action_for(path: &Path, format: OutputFormat) -> Actions {
+    match image_kind(path) {
+        Some(ImageKind::Pdf) => Actions::Pdf,
+        Some(kind) if kind.canonical_extension() == format.extension() => Actions::Copy,
+        // JPEG XL can't be decoded, only passed through when it is the output format.
+        Some(ImageKind::Jxl) | None => Actions::Nothing,
+        Some(_) => Actions::Convert,
+    }
+}
+
+// Lists the files to process under `root`. `walkdir` sees everything; with `respect_ignore`
+// the `ignore` crate skips whatever `.gitignore`/`.ignore` files exclude. Folders named
+// in `exclude_dirs` are pruned, their contents never read. Each file is stat'd here once,
+// symlinks followed, and the result travels with it from then on.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "walk", skip_all, fields(root = ?root, recursive, files = tracing::field::Empty))
+)]
+pub fn walk_files(
+    root: &Path,
+    recursive: bool,
+    respect_ignore: bool,
+    exclude_dirs: &[String],
+) -> Vec<FileInfo> {
+    let files = walk(root, recursive, respect_ignore, exclude_dirs);
+    trace::record("files", files.len() as u64);
+    files
+}
+
+fn walk(
+    root: &Path,
+    recursive: bool,
+    respect_ignore: bool,
+    exclude_dirs: &[String],
+) -> Vec<FileInfo> {
+    if respect_ignore {
+        let mut builder = WalkBuilder::new(root);
+        // Only filter on ignore files, hidden files are processed like in a plain walk.
+        builder.hidden(false).require_git(false);
+        if !recursive {
+            builder.max_depth(Some(1));
+        }
+        if !exclude_dirs.is_empty() {
+            let exclude_dirs = exclude_dirs.to_vec();
+            builder.filter_entry(move |e| {
+                let is_dir = e.file_type().is_some_and(|t| t.is_dir());
+                !is_excluded_dir(e.depth(), is_dir, e.file_name(), &exclude_dirs)
+            });
+        }
+        return builder
+            .build()
+            .filter_map(|e| e.ok())
+            .filter_map(|e| file_info(e.into_path()))
+            .collect();
+    }
+
+    // Configure WalkDir based on the `recursive` flag
+    let walker = WalkDir::new(root);
+    let walker = if recursive {
+        walker
+    } else {
+        walker.min_depth(1).max_depth(1)
+    };
+    walker
+        .into_iter()
+        .filter_entry(|e| {
+            !is_excluded_dir(
+                e.depth(),
+                e.file_type().is_dir(),
+                e.file_name(),
+                exclude_dirs,
+            )
+        })
+        .filter_map(|e| e.ok())
+        .filter_map(|e| file_info(e.into_path()))
+        .collect()
+}
+
+// Whether the walk should skip a folder and everything under it. The root is walked
+// even when its own name is excluded, it was asked for.
+fn is_excluded_dir(depth: usize, is_dir: bool, name: &OsStr, exclude_dirs: &[String]) -> bool {
+    depth > 0 && is_dir && exclude_dirs.iter().any(|dir| name == dir.as_str())
+}
+
+// The walked entry at `path`, if it is (or links to) a regular file.
+fn file_info(path: PathBuf) -> Option<FileInfo> {
+    let metadata = fs::metadata(&path).ok()?;
+    metadata
+        .is_file()
+        .then(|| FileInfo::from_metadata(path, &metadata))
+}
+
+// Asks a yes/no question on stdin, anything but `y`/`yes` counts as no.
+pub fn confirm(question: &str) -> bool {
+    print!("{} [y/N] ", question.purple().bold());
+    io::stdout().flush().unwrap(); // Make sure the prompt is displayed immediately
+    let mut input = String::new();
+    if io::stdin().read_line(&mut input).is_err() {
+        return false;
+    }
+    matches!(input.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+// Orders a walk's files for `--sort`. Files whose modification time isn't known
+// sort last, ties keep their path order.
+pub(crate) fn sort_files(files: &mut [FileInfo], order: SortOrder) {
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    match order {
+        SortOrder::Name => {}
+        SortOrder::Size => files.sort_by_key(|file| Reverse(file.size)),
+        SortOrder::Mtime => {
+            files.sort_by_key(|file| file.modified.map_or((true, None), |m| (false, Some(m))))
+        }
+    }
+}
+
+// Keeps the files modified less than `since` ago, for `--since`. A file whose
+// modification time isn't known is kept, converting it again is the cheaper mistake.
+pub(crate) fn retain_modified_since(files: &mut Vec<FileInfo>, since: Duration) {
+    let Some(cutoff) = SystemTime::now().checked_sub(since) else {
+        return;
+    };
+    files.retain(|file| file.modified.is_none_or(|modified| modified >= cutoff));
+}
+
+pub(crate) fn is_pdf(path: &Path) -> bool {
+    image_kind(path) == Some(ImageKind::Pdf)
+}
+
+pub(crate) fn is_heif(path: &Path) -> bool {
+    image_kind(path) == Some(ImageKind::Heif)
+}
+
+pub(crate) fn is_tiff(path: &Path) -> bool {
+    image_kind(path) == Some(ImageKind::Tiff)
+}
+
+pub(crate) fn is_raw(path: &Path) -> bool {
+    image_kind(path) == Some(ImageKind::Raw)
+}
+
+pub(crate) fn is_icon(path: &Path) -> bool {
+    matches!(image_kind(path), Some(ImageKind::Ico | ImageKind::Cur))
+}
+
+pub fn process_path_for_os<S: Into<String>>(path: S) -> String {
+    let path = path.into();
+    info!(
+        "{}",
+        format!("Path before modifications: {}", path)
+            .green()
+            .bold()
+    );
+    #[cfg(windows)]
+    {
+        // For Windows, if the path contains spaces and is not already quoted, quote it.
+        if path.contains(' ') && !path.starts_with('"') && !path.ends_with('"') {
+            warn!(
+                "{}",
+                format!("Path contains spaces, wrapping in quotes: {}", path)
+                    .yellow()
+                    .bold()
+            );
+            // return format!("\"{}\"", path);
+        }
+
+        windows_separators(&path)
+    }
+    #[cfg(not(windows))]
+    {
+        let path = path.replace('\\', "/"); // Convert to unix style.
+                                            // For Unix-like systems, ensure the path is escaped properly.
+                                            // This simplistic approach handles spaces; adapt as needed for other special characters.
+        path.replace(' ', "\\ ")
+    }
+}
+
+// Rebuilds `path` from its components with `\` separators. Going through `Path` keeps
+// the prefix intact: `//server/share/a.png` becomes `\\server\share\a.png` and `C:foo`
+// stays relative to drive C. Verbatim paths (`\\?\C:\...`, `\\?\UNC\...`) are handed to
+// the OS as they are, `/` is an ordinary character in them, so they are never touched.
+#[cfg(windows)]
+fn windows_separators(path: &str) -> String {
+    use std::path::{Component, Prefix};
+
+    if path.starts_with(r"\\?\") {
+        return path.to_string();
+    }
+    // Both separators mean the same outside verbatim paths, but `Path` only recognizes
+    // `\\server\share` and `\\.\device` prefixes when they are written with backslashes.
+    let path = path.replace('/', "\\");
+
+    let mut rebuilt = String::with_capacity(path.len());
+    let mut needs_separator = false;
+    let mut share_root = false;
+    for component in Path::new(&path).components() {
+        match component {
+            Component::Prefix(prefix) => {
+                match prefix.kind() {
+                    Prefix::Verbatim(_) | Prefix::VerbatimUNC(..) | Prefix::VerbatimDisk(_) => {
+                        return path;
+                    }
+                    Prefix::UNC(server, share) => {
+                        rebuilt.push_str(&format!(
+                            r"\\{}\{}",
+                            server.to_string_lossy(),
+                            share.to_string_lossy()
+                        ));
+                        share_root = true;
+                    }
+                    Prefix::DeviceNS(device) => {
+                        rebuilt.push_str(&format!(r"\\.\{}", device.to_string_lossy()));
+                        share_root = true;
+                    }
+                    Prefix::Disk(_) => rebuilt.push_str(&prefix.as_os_str().to_string_lossy()),
+                }
+                needs_separator = false;
+            }
+            // A share or device always has a root; only write it when something follows,
+            // so `\\server\share` doesn't grow a trailing separator.
+            Component::RootDir if share_root => {
+                needs_separator = true;
+            }
+            Component::RootDir => {
+                rebuilt.push('\\');
+                needs_separator = false;
+            }
+            other => {
+                if needs_separator {
+                    rebuilt.push('\\');
+                }
+                rebuilt.push_str(&other.as_os_str().to_string_lossy());
+                needs_separator = true;
+            }
+        }
+    }
+    rebuilt
+}
@@ -0,0 +1,54 @@
+use crate::types::{ErrorKind, WebpConverterError};
+use image::codecs::ico::IcoDecoder;
+use image::DynamicImage;
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+const HEADER_LEN: usize = 6;
+const ENTRY_LEN: usize = 16;
+
+// Decodes the largest, deepest image of an `.ico` or `.cur` file.
+pub fn decode_largest(path: &Path) -> Result<DynamicImage, WebpConverterError> {
+    let data = fs::read(path)?;
+    Ok(DynamicImage::from_decoder(IcoDecoder::new(Cursor::new(
+        data,
+    ))?)?)
+}
+
+// Decodes every image embedded in an `.ico` or `.cur` file, in directory order.
+// `IcoDecoder` only ever picks the best entry, so each entry is handed to it
+// wrapped in a directory of its own.
+pub fn decode_all(path: &Path) -> Result<Vec<DynamicImage>, WebpConverterError> {
+    let data = fs::read(path)?;
+    let malformed = || WebpConverterError {
+        message: format!("Icon Error: {:?} has a malformed directory", path),
+        kind: ErrorKind::Other,
+    };
+
+    let header = data.get(..HEADER_LEN).ok_or_else(malformed)?;
+    let count = u16::from_le_bytes([header[4], header[5]]) as usize;
+
+    let mut images = Vec::with_capacity(count);
+    for index in 0..count {
+        let start = HEADER_LEN + index * ENTRY_LEN;
+        let entry = data.get(start..start + ENTRY_LEN).ok_or_else(malformed)?;
+        let length = u32::from_le_bytes([entry[8], entry[9], entry[10], entry[11]]) as usize;
+        let offset = u32::from_le_bytes([entry[12], entry[13], entry[14], entry[15]]) as usize;
+        let image_data = data
+            .get(offset..offset.saturating_add(length))
+            .ok_or_else(malformed)?;
+
+        let mut single = Vec::with_capacity(HEADER_LEN + ENTRY_LEN + length);
+        single.extend_from_slice(&header[..4]);
+        single.extend_from_slice(&1u16.to_le_bytes());
+        single.extend_from_slice(&entry[..12]);
+        single.extend_from_slice(&((HEADER_LEN + ENTRY_LEN) as u32).to_le_bytes());
+        single.extend_from_slice(image_data);
+
+        images.push(DynamicImage::from_decoder(IcoDecoder::new(Cursor::new(
+            single,
+        ))?)?);
+    }
+    Ok(images)
+}
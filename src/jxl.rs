@@ -0,0 +1,97 @@
+use crate::types::{EncodeOptions, ErrorKind, WebpConverterError};
+use image::DynamicImage;
+
+#[cfg(feature = "jxl")]
+impl From<jpegxl_rs::EncodeError> for WebpConverterError {
+    fn from(error: jpegxl_rs::EncodeError) -> Self {
+        WebpConverterError {
+            message: format!("JPEG XL Encoding Error: {}", error),
+            kind: ErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(feature = "jxl")]
+impl From<jpegxl_rs::DecodeError> for WebpConverterError {
+    fn from(error: jpegxl_rs::DecodeError) -> Self {
+        WebpConverterError {
+            message: format!("JPEG XL Decoding Error: {}", error),
+            kind: ErrorKind::Other,
+        }
+    }
+}
+
+// Encodes `img` as JPEG XL. Lossy `quality` becomes a Butteraugli distance the way
+// libjxl's own tools map a JPEG quality, `method` picks the effort and `lossless`
+// switches to JXL's lossless mode. `target_size` and `noise_ratio` only exist in libwebp.
+#[cfg(feature = "jxl")]
+pub fn encode(img: &DynamicImage, options: &EncodeOptions) -> Result<Vec<u8>, WebpConverterError> {
+    let has_alpha = match options.channels {
+        Some(channels) => channels == crate::encoder::Channels::Rgba,
+        None => !options.no_alpha && img.color().has_alpha(),
+    };
+    let mut builder = jpegxl_rs::encoder_builder();
+    builder.has_alpha(has_alpha).speed(speed(options.method));
+    if options.lossless == 1 {
+        // libjxl refuses lossless unless it keeps the original color encoding.
+        builder.lossless(true).uses_original_profile(true);
+    } else {
+        builder.jpeg_quality(options.quality);
+    }
+    let mut encoder = builder.build()?;
+
+    let (width, height) = (img.width(), img.height());
+    let result: jpegxl_rs::encode::EncoderResult<u8> = if has_alpha {
+        encoder.encode(img.to_rgba8().as_raw(), width, height)?
+    } else {
+        encoder.encode(img.to_rgb8().as_raw(), width, height)?
+    };
+    Ok(result.data)
+}
+
+#[cfg(not(feature = "jxl"))]
+pub fn encode(
+    _img: &DynamicImage,
+    _options: &EncodeOptions,
+) -> Result<Vec<u8>, WebpConverterError> {
+    Err(unavailable())
+}
+
+// The size a JPEG XL stream decodes to, for `--verify-output`.
+#[cfg(feature = "jxl")]
+pub fn decoded_dimensions(bytes: &[u8]) -> Result<(u32, u32), WebpConverterError> {
+    let (metadata, _) = jpegxl_rs::decoder_builder()
+        .build()?
+        .decode_with::<u8>(bytes)?;
+    Ok((metadata.width, metadata.height))
+}
+
+#[cfg(not(feature = "jxl"))]
+pub fn decoded_dimensions(_bytes: &[u8]) -> Result<(u32, u32), WebpConverterError> {
+    Err(unavailable())
+}
+
+// libwebp's `method` 0-6 spread over libjxl's efforts 1-8. The top two (tortoise,
+// glacier) are left out, they take many times longer for a few percent.
+#[cfg(feature = "jxl")]
+fn speed(method: i32) -> jpegxl_rs::encode::EncoderSpeed {
+    use jpegxl_rs::encode::EncoderSpeed;
+
+    match method {
+        i32::MIN..=0 => EncoderSpeed::Lightning,
+        1 => EncoderSpeed::Falcon,
+        2 => EncoderSpeed::Cheetah,
+        3 => EncoderSpeed::Hare,
+        4 => EncoderSpeed::Wombat,
+        5 => EncoderSpeed::Squirrel,
+        _ => EncoderSpeed::Kitten,
+    }
+}
+
+#[cfg(not(feature = "jxl"))]
+fn unavailable() -> WebpConverterError {
+    WebpConverterError {
+        message: "JPEG XL output requires building with `--features jxl`".to_string(),
+        kind: ErrorKind::Other,
+    }
+}
@@ -0,0 +1,70 @@
+//! Converts JPEG, PNG, GIF, TIFF, BMP and other images to WebP (or JPEG XL), the
+//! pipeline behind the `webp_converter` binary, for programs that embed it instead of
+//! shelling out.
+//!
+//! [`WebpConverter`] is the entry point: its builder resolves the encoding settings that
+//! depend on each other and refuses the ones that contradict each other, the same way
+//! the command line does. Everything else is a plain field of [`ConversionOptions`].
+//!
+//! ```no_run
+//! use std::path::Path;
+//! use webp_converter::{ConversionOptions, WebpConverter};
+//!
+//! # async fn run() -> Result<(), webp_converter::WebpConverterError> {
+//! let converter = WebpConverter::builder()
+//!     .quality(80.0)
+//!     .options(ConversionOptions {
+//!         should_resize: true,
+//!         ..ConversionOptions::default()
+//!     })
+//!     .build()?;
+//! let record = converter.convert_file(Path::new("photo.jpg")).await?;
+//! println!("{}", record);
+//! let summary = converter.convert_dir(Path::new("images"), true).await;
+//! println!("{} converted, {} failed", summary.converted, summary.failed);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The modules below are public too, for callers that need a single step: the
+//! [`converter`] functions the builder wraps, [`encoder`] to encode a decoded image,
+//! [`wio`] for where outputs go and [`helpers`] for the walk.
+
+pub mod animation;
+pub mod avif;
+pub mod batch;
+pub mod builder;
+pub mod cache;
+pub mod check;
+pub mod color;
+pub mod config;
+pub mod converter;
+pub mod decode;
+pub mod dry_run;
+pub mod encoder;
+pub mod estimate;
+pub mod heif;
+pub mod helpers;
+pub mod icon;
+pub mod jxl;
+pub mod logging;
+pub mod metadata;
+#[cfg(feature = "pdf")]
+pub mod pdf;
+mod progress;
+#[cfg(feature = "raw")]
+pub mod raw;
+pub mod reoptimize;
+pub mod report;
+pub mod settings;
+pub mod source;
+pub mod sprite;
+pub mod tiff;
+mod trace;
+pub mod types;
+pub mod wio;
+
+pub use builder::{Converter as WebpConverter, ConverterBuilder};
+pub use types::{
+    ConversionOptions, ConversionRecord, ConversionStatus, Summary, WebpConverterError,
+};
@@ -0,0 +1,143 @@
+use crate::progress;
+use crate::types::{ErrorKind, WebpConverterError};
+use clap::ValueEnum;
+use log::{Log, Metadata, Record};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// How many earlier logs `--log-file-mode rotate` keeps, as `{file}.1` (newest) to `{file}.5`.
+const ROTATED_LOGS: usize = 5;
+
+/// What happens to an existing `--log-file` when a run starts.
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum LogFileMode {
+    /// Keep writing after the previous runs.
+    #[default]
+    Append,
+    /// Start the file over.
+    Truncate,
+    /// Move the previous log to `{file}.1`, shifting older ones up to `{file}.5`.
+    Rotate,
+}
+
+// Sends every record to the terminal logger and, with `--log-file`, to the file logger.
+struct TeeLogger {
+    terminal: env_logger::Logger,
+    file: Option<env_logger::Logger>,
+}
+
+impl Log for TeeLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.terminal.enabled(metadata) || self.file.as_ref().is_some_and(|f| f.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        if self.terminal.matches(record) {
+            progress::suspend(|| self.terminal.log(record));
+        }
+        if let Some(file) = self.file.as_ref().filter(|f| f.matches(record)) {
+            file.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.terminal.flush();
+        if let Some(file) = &self.file {
+            file.flush();
+        }
+    }
+}
+
+// Installs the global logger: colored output on the terminal as before, plus an
+// uncolored copy of every line in `log_file` when one is given.
+pub fn init(log_file: Option<&Path>, mode: LogFileMode) -> Result<(), WebpConverterError> {
+    let terminal = env_logger::Builder::from_default_env().build();
+    let file = match log_file {
+        Some(path) => {
+            let file = open_log_file(path, mode)?;
+            Some(
+                env_logger::Builder::from_default_env()
+                    .target(env_logger::Target::Pipe(Box::new(file)))
+                    .write_style(env_logger::WriteStyle::Never)
+                    .format(|buf, record| {
+                        writeln!(
+                            buf,
+                            "[{} {} {}] {}",
+                            buf.timestamp(),
+                            record.level(),
+                            record.target(),
+                            strip_ansi(&record.args().to_string())
+                        )
+                    })
+                    .build(),
+            )
+        }
+        None => None,
+    };
+
+    let max_level = terminal.filter().max(
+        file.as_ref()
+            .map(|f| f.filter())
+            .unwrap_or(log::LevelFilter::Off),
+    );
+    log::set_boxed_logger(Box::new(TeeLogger { terminal, file })).map_err(|e| {
+        WebpConverterError {
+            message: format!("Couldn't install the logger: {}", e),
+            kind: ErrorKind::Other,
+        }
+    })?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+fn open_log_file(path: &Path, mode: LogFileMode) -> Result<File, WebpConverterError> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    if mode == LogFileMode::Rotate && path.exists() {
+        for index in (1..ROTATED_LOGS).rev() {
+            let older = rotated_path(path, index);
+            if older.exists() {
+                fs::rename(&older, rotated_path(path, index + 1))?;
+            }
+        }
+        fs::rename(path, rotated_path(path, 1))?;
+    }
+    let mut options = OpenOptions::new();
+    options.create(true);
+    match mode {
+        LogFileMode::Append => options.append(true),
+        LogFileMode::Truncate | LogFileMode::Rotate => options.write(true).truncate(true),
+    };
+    options.open(path).map_err(|e| WebpConverterError {
+        message: format!("Couldn't open log file {:?}: {}", path, e),
+        kind: ErrorKind::Other,
+    })
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+// Messages are colored with `colored` before they reach the logger, so the escape
+// codes are part of the text and have to be taken out again for the file.
+fn strip_ansi(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // CSI sequences end with a letter, e.g. `\x1b[1;36m`.
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
@@ -1,638 +1,496 @@
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
 use colored::Colorize;
-use env_logger;
 use log::{error, info, warn};
 use std::env;
+use std::io;
 use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::{fs, io};
-use tokio::sync::Semaphore;
-use tokio::task::spawn_blocking;
-use walkdir::WalkDir;
+use std::process;
+use webp_converter::builder::Converter;
+use webp_converter::encoder::{Channels, EncodeConfig};
+use webp_converter::{
+    batch, check, config, converter, decode, dry_run, estimate, helpers, logging, settings, types,
+    wio,
+};
+
+// Exit code when any file failed to convert, unless `--ignore-errors` is given, or
+// when the run couldn't start at all.
+const EXIT_FAILED: i32 = 1;
+// Exit code for `--error-on-empty`, distinct from clap's usage errors (2).
+const EXIT_NO_IMAGES: i32 = 3;
+// Exit code when the output disk filled up, even with `--ignore-errors`: the run
+// didn't get through its files.
+const EXIT_DISK_FULL: i32 = 4;
+// Exit code when the `--interactive` plan is declined, so scripts don't take it for a
+// folder without images.
+const EXIT_DECLINED: i32 = 5;
+// Exit code of a batch cancelled with Ctrl-C, the shell's code for a SIGINT.
+const EXIT_CANCELLED: i32 = 130;
 
 #[tokio::main]
 async fn main() {
     env::set_var("RUST_LOG", "info");
-    env_logger::init();
 
-    let args = helpers::Args::parse();
-    let directory_path = args.path.unwrap_or_else(|| {
-        info!("{}", "Please provide a directory path:".purple().bold());
-        io::stdout().flush().unwrap(); // Make sure the prompt is displayed immediately
-        let mut input = String::new();
-        io::stdin().read_line(&mut input).unwrap();
-        input.trim().to_string() // Remove the newline character at the end
-    });
+    let matches = helpers::Args::command().get_matches();
+    let mut args = helpers::Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if let Err(e) = logging::init(args.log_file.as_deref(), args.log_file_mode) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+    if let Some(helpers::Command::Decode(decode_args)) = &args.command {
+        let path = &decode_args.path;
+        let failed = if path.is_dir() {
+            !decode::decode_batch(
+                path,
+                decode_args.recursive,
+                decode_args.to,
+                decode_args.quality,
+            )
+            .await
+            .failures
+            .is_empty()
+        } else {
+            match decode::decode_single_photo(path, decode_args.to, decode_args.quality).await {
+                Ok(_) => false,
+                Err(e) => {
+                    error!("{}", e);
+                    true
+                }
+            }
+        };
+        if failed {
+            process::exit(EXIT_FAILED);
+        }
+        return;
+    }
+    // The settings a `--config` filled in, for `--echo-settings`.
+    let mut from_config = vec![];
+    if let Some(source) = args.config.clone() {
+        match config::load(&source).await {
+            Ok(config) => from_config = config.apply(&mut args, &matches),
+            Err(e) => {
+                error!("{}", e);
+                process::exit(EXIT_FAILED);
+            }
+        }
+    }
+    let echo_only = args.echo_settings.is_some() && !args.echo_and_convert;
+    let directory_path = match args.path.take().or(args.input.take()) {
+        Some(path) => path,
+        // A batch spec lists its own files, and echoing the settings needs none.
+        None if args.batch_spec.is_some() || echo_only => String::new(),
+        None => {
+            info!("{}", "Please provide a directory path:".purple().bold());
+            io::stdout().flush().unwrap(); // Make sure the prompt is displayed immediately
+            let mut input = String::new();
+            io::stdin().read_line(&mut input).unwrap();
+            input.trim().to_string() // Remove the newline character at the end
+        }
+    };
 
     let recursive = args.recursive.unwrap_or(false);
-    let quality = args.quality.unwrap_or(75.0);
-
-    let compression_factor = args.compression_factor.unwrap_or(2.0);
-
-    let lossless = if compression_factor != 0.0 || quality < 100.0 {
-        0
-    } else {
-        match args.lossless.unwrap_or(true) {
-            true => 1,
-            false => 0,
+    let resize_target = match (args.scale, args.max_dimension) {
+        (Some(percent), _) => Some(types::ResizeTarget::Scale(percent)),
+        (None, Some(side)) => Some(types::ResizeTarget::square(side)),
+        (None, None) if args.max_width.is_some() || args.max_height.is_some() => {
+            Some(types::ResizeTarget::Box {
+                width: args.max_width,
+                height: args.max_height,
+            })
         }
+        (None, None) => None,
     };
+    let should_resize = args
+        .resize
+        .unwrap_or(args.resize_mode.is_some() || resize_target.is_some());
 
-    let should_resize = args.resize.unwrap_or(false);
-
+    // URLs are kept as given, the OS path clean-up below would mangle their slashes.
+    let url = (directory_path.starts_with("http://") || directory_path.starts_with("https://"))
+        .then(|| directory_path.clone());
     let path = helpers::process_path_for_os(directory_path);
     let path_buff = PathBuf::from(path);
-    let noise_ratio = args.psnr.unwrap_or(40.0);
-
-    if !path_buff.exists() {
-        let msg = "Path does not exist, terminating....".red().underline();
-        error!("{}", msg);
-        return;
-    }
+    let max_pixels = args.max_pixels.unwrap_or(types::DEFAULT_MAX_PIXELS);
 
-    let msg = format!("Path: {}", path_buff.to_string_lossy())
-        .green()
-        .underline();
-    info!("{}", msg);
+    let encode_config = match args.encode_config.as_deref().map(EncodeConfig::load) {
+        Some(Ok(encode_config)) => Some(encode_config),
+        Some(Err(e)) => {
+            error!("{}", e);
+            process::exit(EXIT_FAILED);
+        }
+        None => None,
+    };
 
-    if path_buff.is_dir() {
-        info!(
-            "{}",
-            "Directory Detected Working on it...".bright_cyan().bold()
-        );
-        converter::convert_images_to_webp(
-            path_buff,
-            recursive,
-            quality,
-            lossless,
-            compression_factor,
-            should_resize,
-            noise_ratio,
-        )
-        .await;
-    } else {
-        info!("{}", "Single Image File Detected...".bright_blue().bold());
-        let _ = converter::convert_single_photo(
-            path_buff,
-            quality,
-            lossless,
-            compression_factor,
-            should_resize,
-            noise_ratio,
-        )
-        .await;
+    // Quality, lossless, compression factor, PSNR and effort depend on each other, the
+    // builder resolves them. Resize and the encode config go through it as well, like a
+    // library caller would set them; everything else maps straight from the flags.
+    let mut builder = Converter::builder();
+    if let Some(quality) = args.quality {
+        builder = builder.quality(quality);
     }
-}
-
-pub(crate) mod types {
-    use colored::Colorize;
-    use std::fmt::Display;
-    use std::io;
-    use std::path::PathBuf;
-    use tokio::task::JoinError;
-    use webp::WebPMemory;
-
-    #[derive(Debug, Clone)]
-    pub(crate) struct WebpConverterError {
-        pub(crate) message: String,
+    if let Some(lossless) = args.lossless {
+        builder = builder.lossless(lossless);
     }
-
-    impl From<image::ImageError> for WebpConverterError {
-        fn from(error: image::ImageError) -> Self {
-            WebpConverterError {
-                message: format!("Image Error: {:?}", error),
-            }
-        }
+    if let Some(effort) = args.lossless_effort {
+        builder = builder.lossless_effort(effort);
     }
-
-    impl From<io::Error> for WebpConverterError {
-        fn from(error: io::Error) -> Self {
-            WebpConverterError {
-                message: format!("IO Error: {:?}", error),
-            }
-        }
+    if let Some(factor) = args.compression_factor {
+        builder = builder.compression_factor(factor);
     }
-
-    impl From<webp::WebPEncodingError> for WebpConverterError {
-        fn from(error: webp::WebPEncodingError) -> Self {
-            WebpConverterError {
-                message: format!("WebP Encoding Error: {:?}", error),
-            }
-        }
+    if let Some(psnr) = args.psnr {
+        builder = builder.noise_ratio(psnr);
     }
-
-    impl Display for WebpConverterError {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(f, "{}", format!("Error: {}", self.message).red().bold())
-        }
+    match (args.effort, args.fast, args.best) {
+        (Some(effort), _, _) => builder = builder.effort(effort),
+        (None, true, _) => builder = builder.effort(1),
+        (None, _, true) => builder = builder.effort(6),
+        (None, false, false) => {}
+    }
+    if should_resize {
+        builder = builder.resize(args.resize_mode.unwrap_or_default());
+    }
+    if let Some(config) = encode_config {
+        builder = builder.encode_config(config);
     }
+    let builder = builder.options(types::ConversionOptions {
+        resize_mode: args.resize_mode.unwrap_or_default(),
+        resize_target: resize_target.unwrap_or_default(),
+        max_pixels,
+        no_auto_fit: args.no_auto_fit,
+        jobs: args.jobs.unwrap_or(types::Jobs::Auto),
+        deterministic: args.deterministic,
+        reoptimize: args.reoptimize,
+        pdf_dpi: args.pdf_dpi.unwrap_or(150.0),
+        min_savings_percent: args.min_savings_percent.unwrap_or(0.0),
+        overwrite_if_smaller: args.overwrite_if_smaller,
+        quality_map: args.quality_map.unwrap_or_default(),
+        ico_all_sizes: args.ico_all_sizes,
+        tiff_pages: args.tiff_pages,
+        sizes: args.sizes,
+        format: args.format[0],
+        extra_formats: args.format[1..].to_vec(),
+        rotate: args.rotate,
+        flip: args.flip,
+        output: args.output,
+        input_root: None,
+        diff_report: args.diff_report,
+        explain: args.explain,
+        report_format: args.report_format,
+        respect_ignore: args.respect_ignore,
+        exclude_dirs: args.exclude_dirs,
+        interactive: args.interactive,
+        yes: args.yes,
+        timeout: args.timeout,
+        normalize_extension: args.normalize_extension,
+        grayscale: args.grayscale,
+        no_alpha: args.no_alpha || args.background.is_some(),
+        background: args.background.unwrap_or_default(),
+        channels: if args.force_rgb {
+            Some(Channels::Rgb)
+        } else if args.force_rgba {
+            Some(Channels::Rgba)
+        } else {
+            None
+        },
+        target_colorspace: match args.color_profile {
+            Some(types::ColorProfile::Convert) => Some(types::ColorSpace::Srgb),
+            _ => args.target_colorspace,
+        },
+        keep_exif: args.keep_exif || args.keep_metadata,
+        keep_icc: match args.color_profile {
+            Some(profile) => profile == types::ColorProfile::Embed,
+            None => args.keep_icc || args.keep_metadata,
+        },
+        keep_xmp: args.keep_xmp || args.keep_metadata,
+        strip_metadata: args.strip_metadata,
+        apply_orientation: !args.no_auto_orient && args.apply_orientation.unwrap_or(true),
+        auto_format: args.auto_format,
+        chroma: args.chroma,
+        min_quality: args.min_quality.unwrap_or(0.0),
+        max_quality: args.max_quality.unwrap_or(100.0),
+        roi: args.roi,
+        roi_quality: args.roi_quality.unwrap_or(types::DEFAULT_ROI_QUALITY),
+        sample: args.sample.map(|sample| sample as usize),
+        limit: args.limit.map(|limit| limit as usize),
+        sort: args.sort,
+        max_resize_factor: args.max_resize_factor,
+        warn_on_no_resize: args.warn_on_no_resize,
+        since: args.since,
+        verify_output: args.verify_output,
+        sprite_padding: args.sprite_padding,
+        sprite_max_width: args.sprite_max_width,
+        output_dir_name: args.output_dir_name,
+        output_dir: args.output_dir,
+        mirror: args.mirror,
+        in_place: args.in_place,
+        replace: args.replace,
+        progress: args.progress,
+        skip_existing: args.skip_existing,
+        cache: args.cache,
+        hash_names: args.hash_names,
+        manifest: wio::Manifest::default(),
+        lqip: args.lqip,
+        lqip_manifest: wio::Manifest::default(),
+        copy_others: args.copy_others,
+        clean_output: args.clean_output,
+        quarantine: args.quarantine,
+        tar: args.tar,
+        ..types::ConversionOptions::default()
+    });
+    let converter = match builder.build() {
+        Ok(converter) => converter,
+        Err(e) => {
+            error!("{}", e);
+            process::exit(EXIT_FAILED);
+        }
+    };
+    let options = converter.options();
 
-    impl From<Result<PathBuf, WebpConverterError>> for WebpConverterError {
-        fn from(error: Result<PathBuf, WebpConverterError>) -> Self {
-            match error {
-                Ok(_) => WebpConverterError {
-                    message: "Unknown Error".to_string(),
-                },
-                Err(e) => e,
-            }
+    if let Some(format) = args.echo_settings {
+        let settings = settings::resolve(options, recursive, &matches, &from_config);
+        println!("{}", settings::render(&settings, format));
+        if echo_only {
+            return;
         }
     }
 
-    impl From<Result<WebPMemory, WebpConverterError>> for WebpConverterError {
-        fn from(error: Result<WebPMemory, WebpConverterError>) -> Self {
-            match error {
-                Ok(_) => WebpConverterError {
-                    message: "Unknown Error".to_string(),
-                },
-                Err(e) => e,
+    if let Some(spec) = &args.batch_spec {
+        let entries = match batch::read(spec) {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("{}", e);
+                process::exit(EXIT_FAILED);
             }
+        };
+        info!(
+            "{}",
+            format!("Batch spec: {} files from {:?}", entries.len(), spec)
+                .bright_cyan()
+                .bold()
+        );
+        let root = spec.parent().unwrap_or(Path::new(""));
+        tokio::spawn(cancel_on_ctrl_c(options.cancellation.clone()));
+        let summary = converter.convert_batch(root, entries).await;
+        if summary.cancelled {
+            process::exit(EXIT_CANCELLED);
+        }
+        if summary.declined {
+            process::exit(EXIT_DECLINED);
+        }
+        if summary.disk_full {
+            process::exit(EXIT_DISK_FULL);
+        }
+        if summary.failed > 0 && !args.ignore_errors {
+            process::exit(EXIT_FAILED);
         }
+        return;
     }
 
-    impl From<JoinError> for WebpConverterError {
-        fn from(error: JoinError) -> Self {
-            WebpConverterError {
-                message: format!("Join Error: {:?}", error),
+    if let Some(url) = url {
+        if cfg!(not(feature = "remote-input")) {
+            let msg = "Converting a URL requires building with `--features remote-input`, terminating...."
+                .red()
+                .underline();
+            error!("{}", msg);
+            process::exit(EXIT_FAILED);
+        }
+        if args.estimate || args.check || args.dry_run || options.reoptimize {
+            let msg = "--estimate, --check, --dry-run and --reoptimize need a local path, terminating...."
+                .red()
+                .underline();
+            error!("{}", msg);
+            process::exit(EXIT_FAILED);
+        }
+        info!("{}", format!("Downloading {}", url).bright_blue().bold());
+        let converted = match converter::convert_url(&url, options).await {
+            Ok(record) => {
+                info!("{}", record.to_string().bright_green().bold());
+                true
             }
+            Err(e) => {
+                error!("{}", e);
+                false
+            }
+        };
+        if let Err(e) = wio::write_manifests(Path::new(""), options).await {
+            error!("{}", e);
+        }
+        if !converted && !args.ignore_errors {
+            process::exit(EXIT_FAILED);
         }
+        return;
     }
-}
-
-pub(crate) mod helpers {
-    use super::*;
-    use clap::Parser;
-    use walkdir::DirEntry;
 
-    #[derive(Parser, Debug)]
-    #[command(author, version, about, long_about = None)]
-    pub(crate) struct Args {
-        #[arg(short = 'p', long = "PATH")]
-        pub(crate) path: Option<String>,
-        #[arg(short = 'r', long = "RECURSIVE")]
-        pub(crate) recursive: Option<bool>,
-        #[arg(short = 'q', long = "QUALITY", default_value = "75")]
-        pub(crate) quality: Option<f32>,
-        #[arg(short = 'l', long = "LOSSLESS", default_value = "true")]
-        pub(crate) lossless: Option<bool>,
-        #[arg(short = 'c', long = "COMPRESSIONFACTOR", default_value = "0.0")]
-        pub(crate) compression_factor: Option<f32>,
-        #[arg(short = 's', long = "RESIZE")]
-        pub(crate) resize: Option<bool>,
-        #[arg(short = 'n', long = "NOISERATIO")]
-        pub(crate) psnr: Option<f32>,
+    if !path_buff.exists() {
+        let msg = "Path does not exist, terminating....".red().underline();
+        error!("{}", msg);
+        process::exit(EXIT_FAILED);
     }
 
-    pub(crate) enum Actions {
-        Convert,
-        Copy,
-        Nothing,
-    }
-    pub(crate) fn which_action(path: DirEntry) -> Actions {
-        // Check if the file is an image and should be converted or copied.
-        let p = path.path().to_string_lossy().to_string().replace('"', "");
-        let path = PathBuf::from(&p);
-        match path
-            .extension()
-            .and_then(|e| e.to_str())
-            .map(|s| s.to_ascii_lowercase())
-        {
-            Some(extension)
-                if [
-                    "jpg", "jpeg", "png", "tiff", "tif", "bmp", "avif", "gif", "jfif",
-                ]
-                .contains(&extension.as_str()) =>
-            {
-                Actions::Convert
-            }
-            Some(extension) if extension == "webp" => Actions::Copy,
-            _ => Actions::Nothing,
-        }
-    }
+    let msg = format!("Path: {}", path_buff.to_string_lossy())
+        .green()
+        .underline();
+    info!("{}", msg);
 
-    pub(crate) fn process_path_for_os<S: Into<String>>(path: S) -> String {
-        let mut path = path.into();
-        info!(
-            "{}",
-            format!("Path before modifications: {}", path)
-                .green()
-                .bold()
-        );
-        #[cfg(windows)]
-        {
-            // For Windows, if the path contains spaces and is not already quoted, quote it.
-            if path.contains(' ') && !path.starts_with('"') && !path.ends_with('"') {
-                warn!(
+    if args.estimate {
+        if options.reoptimize {
+            error!(
+                "{}",
+                "--estimate predicts WebP conversion and can't be combined with --reoptimize"
+                    .red()
+                    .underline()
+            );
+            process::exit(EXIT_FAILED);
+        }
+        if path_buff.is_dir() {
+            estimate::estimate_batch(&path_buff, recursive, options).await;
+        } else {
+            match estimate::estimate_single_photo(&path_buff, options).await {
+                Ok((input_size, estimated_size)) => info!(
                     "{}",
-                    format!("Path contains spaces, wrapping in quotes: {}", path)
-                        .yellow()
+                    format!("Estimated: {} -> ~{} bytes", input_size, estimated_size)
+                        .bright_cyan()
                         .bold()
-                );
-                // return format!("\"{}\"", path);
+                ),
+                Err(e) => error!("{}", e),
             }
-
-            path = path.replace("/", "\\"); // Convert Unix-style slashes to Windows-style.
-            path = path.replace('\\', "\\"); // Convert spaces to windows separators.
-
-            path
-        }
-        #[cfg(not(windows))]
-        {
-            path = path.replace('\\', "/"); // Convert to unix style.
-                                            // For Unix-like systems, ensure the path is escaped properly.
-                                            // This simplistic approach handles spaces; adapt as needed for other special characters.
-            path.replace(" ", "\\ ")
         }
+        return;
     }
-}
-
-pub(crate) mod wio {
-    use super::*;
-    pub(crate) async fn copy_image_to_output_folder(p0: &Path) -> Result<(), io::Error> {
-        let filename = p0.file_name().unwrap();
 
-        let copy_path = get_or_create_output_directory(p0).join(filename);
-        fs::copy(p0, copy_path.clone())?;
+    if args.dry_run {
+        if let Err(e) = dry_run::plan(&path_buff, recursive, options) {
+            error!("{}", e);
+            process::exit(EXIT_FAILED);
+        }
+        return;
+    }
 
-        if let Some(last_component) = get_or_create_output_directory(p0).components().last() {
-            match last_component {
-                std::path::Component::Normal(name) => {
-                    #[cfg(windows)]
-                    info!(
-                        "\n{}\n",
-                        format!(
-                            "Copying: {:?} to {:?}\\{:?}",
-                            p0.file_name().unwrap(),
-                            name,
-                            copy_path.file_name().unwrap()
-                        )
-                        .bright_blue()
-                        .bold()
-                    );
-                    #[cfg(not(windows))]
+    if args.check {
+        let failed = if path_buff.is_dir() {
+            !check::check_batch(&path_buff, recursive, options)
+                .await
+                .failures
+                .is_empty()
+        } else {
+            match check::check_single_photo(&path_buff, max_pixels).await {
+                Ok((width, height)) => {
                     info!(
                         "{}",
-                        format!(
-                            "Copying: {:?} to {:?}/{:?}",
-                            p0.file_name().unwrap(),
-                            name,
-                            copy_path.file_name().unwrap()
-                        )
-                        .bright_blue()
-                        .bold()
+                        format!("Decoded: {:?} ({}x{})", path_buff, width, height)
+                            .bright_green()
+                            .bold()
                     );
+                    false
+                }
+                Err(e) => {
+                    error!("{}", e);
+                    true
                 }
-                _ => println!("The last component is not a normal directory or file name."),
             }
+        };
+        if failed {
+            process::exit(EXIT_FAILED);
         }
-
-        Ok(())
+        return;
     }
 
-    pub(crate) fn get_or_create_output_directory(path: &Path) -> PathBuf {
-        // Create the "webp_converter" directory inside the original image's directory
-        let parent_dir = path.parent().unwrap_or_else(|| Path::new(""));
-        let webp_dir = parent_dir.join("webp_converter_output");
-        if webp_dir.exists() {
-            webp_dir
-        } else {
-            fs::create_dir_all(&webp_dir).unwrap();
-            webp_dir
+    if args.spritesheet {
+        if !path_buff.is_dir() {
+            let msg = "--spritesheet packs a folder, -p must be one, terminating...."
+                .red()
+                .underline();
+            error!("{}", msg);
+            process::exit(EXIT_FAILED);
+        }
+        match converter::convert_to_spritesheet(path_buff, recursive, options).await {
+            Ok(record) => info!("{}", record.to_string().bright_green().bold()),
+            Err(e) => {
+                error!("{}", e);
+                process::exit(EXIT_FAILED);
+            }
         }
+        return;
     }
 
-    #[cfg(unix)]
-    use std::os::unix::fs::PermissionsExt;
-    pub(crate) fn make_file_writable<P: AsRef<Path>>(path: P) -> io::Result<()> {
-        let path = path.as_ref();
-        let metadata = fs::metadata(path)?;
-        let mut perms = metadata.permissions();
-
-        #[cfg(windows)]
-        {
-            perms.set_readonly(false);
+    if path_buff.is_dir() {
+        info!(
+            "{}",
+            "Directory Detected Working on it...".bright_cyan().bold()
+        );
+        tokio::spawn(cancel_on_ctrl_c(options.cancellation.clone()));
+        let summary = converter::convert_images_to_webp(path_buff, recursive, options).await;
+        if summary.cancelled {
+            process::exit(EXIT_CANCELLED);
         }
-
-        #[cfg(unix)]
-        {
-            let mode = perms.mode();
-            let new_mode = mode | 0o200;
-            perms.set_mode(new_mode);
+        if summary.declined {
+            process::exit(EXIT_DECLINED);
         }
-
-        fs::set_permissions(path, perms)?;
-        Ok(())
-    }
-
-    pub(crate) fn cleanup(workspace_path: PathBuf) -> io::Result<()> {
-        let output_dir = get_or_create_output_directory(&workspace_path);
-        if workspace_path.exists() {
-            // check for empty or zero bytes files
-            // delete them from the filesystem.
-            for entry in fs::read_dir(output_dir)? {
-                let entry = entry?;
-                let file_size = entry.metadata()?.len();
-                if file_size == 0 {
-                    fs::remove_file(entry.path())?;
-                }
-            }
+        if summary.disk_full {
+            process::exit(EXIT_DISK_FULL);
         }
-        Ok(())
-    }
-}
-
-pub(crate) mod converter {
-    use crate::types::WebpConverterError;
-    use image::imageops::FilterType;
-    use image::{DynamicImage, GenericImageView, RgbaImage};
-    use std::io::ErrorKind;
-    use std::sync::Arc;
-    use tokio::io::{AsyncWriteExt, BufWriter};
-    use webp::WebPMemory;
-
-    use super::*;
-
-    // Function to decide on using resized_img or img
-    pub(crate) async fn decide_and_encode(
-        img: DynamicImage,
-        resized_img: DynamicImage,
-        quality: f32,
-        lossless: i32,
-        noise_ratio: f32,
-        target_size: i32,
-    ) -> Result<Vec<u8>, WebpConverterError> {
-        // Encode both images to WebP format in memory to compare file sizes
-        let original_encoded =
-            encode_webp(quality, lossless, noise_ratio, target_size, img).await?;
-        let resized_encoded =
-            encode_webp(quality, lossless, noise_ratio, target_size, resized_img).await?;
-        // Use the smaller one, or the original if sizes are equal
-        // This is a simplistic approach; you might choose based on other criteria
-        if resized_encoded.len() < original_encoded.len() {
-            Ok(resized_encoded)
-        } else {
-            Ok(original_encoded)
+        if args.error_on_empty && summary.matched == 0 {
+            process::exit(EXIT_NO_IMAGES);
         }
-    }
-
-    pub(crate) async fn convert_images_to_webp<P: Into<PathBuf>>(
-        path: P,
-        recursive: bool,
-        quality: f32,
-        lossless: i32,
-        compression_factor: f32,
-        should_resize: bool,
-        noise_ratio: f32,
-    ) {
-        let path = path.into();
-        let cpu_cores = num_cpus::get();
-        let max_concurrency = std::cmp::max(1, cpu_cores - 1); // Reserve one core for the system
-        let semaphore = Arc::new(Semaphore::new(max_concurrency));
-
-        let mut tasks = vec![];
-
-        // Configure WalkDir based on the `recursive` flag
-        let walker = WalkDir::new(&path);
-        let walker = if recursive {
-            walker
-        } else {
-            walker.min_depth(1).max_depth(1)
+        if summary.failed > 0 && !args.ignore_errors {
+            process::exit(EXIT_FAILED);
         }
-        .into_iter();
-
-        for entry in walker.filter_map(|e| e.ok()).filter(|e| e.path().is_file()) {
-            match helpers::which_action(entry.clone()) {
-                helpers::Actions::Convert => {
-                    let sem_clone = semaphore.clone();
-                    let entry_path = entry.into_path();
-
-                    let task = tokio::task::spawn(async move {
-                        let _permit = sem_clone
-                            .acquire()
-                            .await
-                            .expect("Failed to acquire semaphore permit");
-                        match convert_single_photo(
-                            &entry_path,
-                            quality,
-                            lossless,
-                            compression_factor,
-                            should_resize,
-                            noise_ratio,
-                        )
-                        .await
-                        {
-                            Ok(_) => {
-                                info!(
-                                    "\n{}\n",
-                                    format!("Converted: {:?}", &entry_path)
-                                        .bright_green()
-                                        .bold()
-                                );
-                            }
-                            Err(_) => {
-                                match convert_single_photo(&entry_path, 75.0, 0, 0.0, false, 40.0)
-                                    .await
-                                {
-                                    Ok(_) => {
-                                        info!(
-                                            "\n{}\n",
-                                            format!("Converted: {:?}", &entry_path)
-                                                .bright_green()
-                                                .bold()
-                                        );
-                                    }
-                                    Err(e) => {
-                                        error!(
-                                            "\n{}\n",
-                                            format!("Failed to convert: {:?} {:?}", &entry_path, e)
-                                                .red()
-                                                .bold()
-                                        );
-                                    }
-                                }
-                            }
-                        }
-                    });
-
-                    tasks.push(task);
-                }
-                helpers::Actions::Copy => {
-                    let sem_clone = semaphore.clone();
-                    let entry_path = entry.into_path();
-
-                    let task = tokio::spawn(async move {
-                        let _permit = sem_clone
-                            .acquire()
-                            .await
-                            .expect("Failed to acquire semaphore permit");
-                        wio::copy_image_to_output_folder(&entry_path)
-                            .await
-                            .expect("Failed to copy image");
-                    });
-
-                    tasks.push(task);
+    } else {
+        info!("{}", "Single Image File Detected...".bright_blue().bold());
+        if options.interactive && !options.yes {
+            let existing = converter::planned_output(
+                &path_buff,
+                helpers::which_action_for(&path_buff, options.format),
+                options,
+            )
+            .filter(|output| output.exists());
+            if let Some(existing) = existing {
+                if !helpers::confirm(&format!("Overwrite {:?}?", existing)) {
+                    info!("{}", "Kept the existing output.".yellow().bold());
+                    return;
                 }
-                helpers::Actions::Nothing => warn!(
-                    "\n{}\n",
-                    format!("Not a valid image file: {:?}", entry.path())
-                        .yellow()
-                        .bold()
-                ),
             }
         }
-
-        // Await all tasks to complete
-        for task in tasks {
-            task.await.expect("Task failed to complete");
-        }
-
-        wio::cleanup(path).expect("Failed to cleanup empty files.");
-    }
-
-    pub(crate) async fn convert_single_photo<P: Into<PathBuf>>(
-        path: P,
-        quality: f32,
-        lossless: i32,
-        compression_factor: f32,
-        should_resize: bool,
-        noise_ratio: f32,
-    ) -> Result<(), WebpConverterError> {
-        let path = path.into();
-        let original_size = fs::metadata(&path)?.len() as f32;
-        let target_size = match compression_factor as i32 {
-            0 => 0,
-            _ => (original_size / compression_factor) as i32,
+        let dir = path_buff.parent().unwrap_or(Path::new("")).to_path_buf();
+        let converted = match converter::convert_single_photo(path_buff, options).await {
+            Ok(record) => {
+                info!("{}", record.to_string().bright_green().bold());
+                true
+            }
+            Err(e) if e.is_disk_full() => {
+                error!("{}", e);
+                process::exit(EXIT_DISK_FULL);
+            }
+            Err(e) => {
+                error!("{}", e);
+                false
+            }
         };
-
-        let mut webp_dir = wio::get_or_create_output_directory(&path);
-
-        if let Some(filename) = path.with_extension("webp").file_name() {
-            webp_dir = webp_dir.join(filename);
-        } else {
-            webp_dir = webp_dir.join(path.file_name().ok_or_else(|| {
-                Err::<PathBuf, WebpConverterError>(types::WebpConverterError::from(io::Error::new(
-                    ErrorKind::NotFound,
-                    "File not found!",
-                )))
-            })?);
+        if let Err(e) = wio::write_manifests(&dir, options).await {
+            error!("{}", e);
         }
-
-        wio::make_file_writable(&path)?;
-
-        let img = image::open(&path)?; // Load the image synchronously to avoid async issues with WebPMemory
-        let mut resized_img: DynamicImage = img.clone();
-        // Prepare the file creation outside of the spawn_blocking to keep async operations out of the blocking context
-        let webp_dir_clone = webp_dir.clone(); // Clone path for use in async context
-        if webp_dir_clone.exists() {
-            tokio::fs::remove_file(&webp_dir_clone).await?;
+        if let Some(dest) = &options.tar {
+            converter::write_tar(dest, &dir, options);
         }
-        let file = tokio::fs::File::create(&webp_dir_clone).await?;
-        let mut writer = BufWriter::new(file);
-
-        if should_resize {
-            resized_img = resize_image(img.clone());
+        if !converted && !args.ignore_errors {
+            process::exit(EXIT_FAILED);
         }
-
-        let encode_task = decide_and_encode(
-            img.clone(),
-            resized_img.clone(),
-            quality,
-            lossless,
-            noise_ratio,
-            target_size,
-        )
-        .await?;
-        // Finalize the file writing back in the async context
-        if encode_task.len() != 0 {
-            writer.write_all(&encode_task).await?;
-        }
-
-        Ok(())
     }
+}
 
-    pub async fn encode_webp(
-        quality: f32,
-        lossless: i32,
-        noise_ratio: f32,
-        target_size: i32,
-        resized_img: DynamicImage,
-    ) -> Result<Vec<u8>, WebpConverterError> {
-        // Use spawn_blocking for the CPU-bound encoding task
-        // Handle errors from spawn_blocking and encoding
-        let encode_task = spawn_blocking(move || {
-            let rgba_img: RgbaImage = resized_img.to_rgba8();
-
-            // Configure WebP encoding
-            let config = webp::WebPConfig {
-                lossless,
-                quality,
-                method: 6,
-                image_hint: libwebp_sys::WebPImageHint::WEBP_HINT_DEFAULT,
-                target_size,
-                target_PSNR: noise_ratio,
-                segments: 4,
-                sns_strength: 75,
-                filter_strength: 60,
-                filter_sharpness: 0,
-                filter_type: 1,
-                autofilter: 0,
-                alpha_compression: 1,
-                alpha_filtering: 1,
-                alpha_quality: 90,
-                pass: 3,
-                show_compressed: 0,
-                preprocessing: 2,
-                partitions: 0,
-                partition_limit: 2,
-                emulate_jpeg_size: 0,
-                thread_level: 1,
-                low_memory: 0,
-                near_lossless: 75,
-                exact: 0,
-                use_delta_palette: 0,
-                use_sharp_yuv: 0,
-                qmin: 0,
-                qmax: 0,
-            };
-
-            let memory: WebPMemory =
-                webp::Encoder::from_rgba(&rgba_img, resized_img.width(), resized_img.height())
-                    .encode_advanced(&config)
-                    .map_err(|_| {
-                        Err::<WebPMemory, WebpConverterError>(WebpConverterError::from(
-                            webp::WebPEncodingError::VP8_ENC_ERROR_BITSTREAM_OUT_OF_MEMORY,
-                        ))
-                    })?; // Handle encoding errors
-            let memory_bytes: Vec<u8> = memory.to_vec();
-            Ok::<Vec<u8>, WebpConverterError>(memory_bytes)
-        })
-        .await??; // Handle errors from spawn_blocking and encoding
-        Ok(encode_task)
+// The first Ctrl-C during a batch cancels it: the files already converting finish and
+// the summary is still printed. A second one exits right away.
+async fn cancel_on_ctrl_c(cancellation: types::Cancellation) {
+    if tokio::signal::ctrl_c().await.is_err() {
+        return;
     }
-
-    pub(crate) fn resize_image(image: DynamicImage) -> DynamicImage {
-        let (width, height) = image.dimensions();
-
-        // For images smaller than 700x700, return the original image.
-        if width <= 700 && height <= 700 {
-            return image;
-        }
-
-        // Calculate the new dimensions while maintaining the aspect ratio.
-        let aspect_ratio = width as f32 / height as f32;
-        let (new_width, new_height) = if width > height {
-            let new_width = 700;
-            let new_height = (700f32 / aspect_ratio).round() as u32;
-            (new_width, new_height)
-        } else if height > width {
-            let new_height = 700;
-            let new_width = (700f32 * aspect_ratio).round() as u32;
-            (new_width, new_height)
-        } else {
-            // For square images or when width == height
-            (700, 700)
-        };
-
-        // Resize the image using the Lanczos3 algorithm for high-quality results.
-        image.resize_exact(new_width, new_height, FilterType::Lanczos3)
+    warn!(
+        "{}",
+        "Cancelling, waiting for the files already converting. Press Ctrl-C again to exit now."
+            .yellow()
+            .bold()
+    );
+    cancellation.cancel();
+    if tokio::signal::ctrl_c().await.is_ok() {
+        process::exit(EXIT_CANCELLED);
     }
 }
@@ -0,0 +1,561 @@
+use crate::color;
+use crate::types::{ConversionOptions, ErrorKind, WebpConverterError};
+use image::{DynamicImage, ImageFormat};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+// JPEG's APP1 segments start with one of these to say what they hold.
+const JPEG_EXIF_PREFIX: &[u8] = b"Exif\0\0";
+const JPEG_XMP_PREFIX: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+// The keyword of the PNG `iTXt` chunk holding XMP.
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp\0";
+
+const EXIF_ORIENTATION_TAG: u16 = 0x0112;
+const EXIF_GPS_IFD_TAG: u16 = 0x8825;
+const EXIF_SHORT: u16 = 3;
+const EXIF_LONG: u16 = 4;
+// The property XMP writers put a location in, under the `exif` namespace.
+const XMP_GPS_PROPERTY: &[u8] = b"GPSLatitude";
+
+// `VP8X` flags, see the WebP container specification.
+const ICC_FLAG: u8 = 0b0010_0000;
+const ALPHA_FLAG: u8 = 0b0001_0000;
+const EXIF_FLAG: u8 = 0b0000_1000;
+const XMP_FLAG: u8 = 0b0000_0100;
+
+/// Metadata a source carries that can go into its WebP output, see `--keep-exif`,
+/// `--keep-icc` and `--keep-xmp`. `exif` is the TIFF structure, without JPEG's
+/// `Exif\0\0` prefix; `xmp` is the XML packet.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    pub exif: Option<Vec<u8>>,
+    pub icc: Option<Vec<u8>>,
+    pub xmp: Option<Vec<u8>>,
+}
+
+impl Metadata {
+    /// Everything a JPEG, PNG or WebP holds. Other formats only give their ICC profile,
+    /// where the `image` crate's decoder exposes it (TIFF).
+    pub fn read(bytes: &[u8]) -> Metadata {
+        let icc = || color::icc_profile_from_bytes(bytes);
+        match image::guess_format(bytes) {
+            Ok(ImageFormat::Jpeg) => Metadata {
+                icc: icc(),
+                ..jpeg_metadata(bytes)
+            },
+            Ok(ImageFormat::Png) => Metadata {
+                icc: icc(),
+                ..png_metadata(bytes)
+            },
+            Ok(ImageFormat::WebP) => webp_metadata(bytes),
+            _ => Metadata {
+                icc: icc(),
+                ..Metadata::default()
+            },
+        }
+    }
+
+    /// The EXIF and XMP of the JPEG, PNG or WebP at `path`, like `read` finds them, read
+    /// segment by segment so the image data in between is skipped over rather than read.
+    /// There's no ICC profile, and other formats give nothing.
+    pub fn read_header(path: &Path) -> io::Result<Metadata> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut magic = [0; 12];
+        if file.read_exact(&mut magic).is_err() {
+            return Ok(Metadata::default());
+        }
+        // Each parser gets the file's signature followed by its metadata segments alone,
+        // and a file cut short still gives what came before the cut.
+        let mut signature = |len: usize| -> io::Result<Vec<u8>> {
+            file.seek(SeekFrom::Start(len as u64))?;
+            Ok(magic[..len].to_vec())
+        };
+        Ok(match image::guess_format(&magic) {
+            Ok(ImageFormat::Jpeg) => {
+                let signature = signature(2)?;
+                jpeg_metadata(&jpeg_segments(&mut file, signature))
+            }
+            Ok(ImageFormat::Png) => {
+                let signature = signature(8)?;
+                png_metadata(&png_segments(&mut file, signature))
+            }
+            Ok(ImageFormat::WebP) => {
+                let signature = signature(12)?;
+                webp_metadata(&webp_segments(&mut file, signature))
+            }
+            _ => Metadata::default(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exif.is_none() && self.icc.is_none() && self.xmp.is_none()
+    }
+
+    /// The EXIF orientation, 1 (as stored) to 8, when the EXIF has a valid one.
+    pub fn orientation(&self) -> Option<u16> {
+        let exif = self.exif.as_deref()?;
+        let (offset, big_endian) = orientation_offset(exif)?;
+        read_u16(exif, offset, big_endian).filter(|o| (1..=8).contains(o))
+    }
+
+    /// Whether the EXIF points at GPS data or the XMP holds a latitude.
+    pub fn has_location(&self) -> bool {
+        let exif_gps = self.exif.as_deref().is_some_and(|exif| {
+            ifd0_entry(exif, EXIF_GPS_IFD_TAG, EXIF_LONG)
+                .and_then(|(offset, big_endian)| read_u32(exif, offset, big_endian))
+                .is_some_and(|gps_ifd| gps_ifd != 0)
+        });
+        let xmp_gps = self.xmp.as_deref().is_some_and(|xmp| {
+            xmp.windows(XMP_GPS_PROPERTY.len())
+                .any(|window| window == XMP_GPS_PROPERTY)
+        });
+        exif_gps || xmp_gps
+    }
+
+    // The parts `options` keep. Once the orientation is baked into the pixels with
+    // `apply_orientation`, the kept EXIF says 1 so viewers don't rotate again.
+    pub(crate) fn kept(self, options: &ConversionOptions) -> Metadata {
+        let exif = self.exif.filter(|_| options.keep_exif).map(|mut exif| {
+            if options.apply_orientation {
+                if let Some((offset, big_endian)) = orientation_offset(&exif) {
+                    let normal = if big_endian {
+                        1u16.to_be_bytes()
+                    } else {
+                        1u16.to_le_bytes()
+                    };
+                    exif[offset..offset + 2].copy_from_slice(&normal);
+                }
+            }
+            exif
+        });
+        Metadata {
+            exif,
+            icc: self.icc.filter(|_| options.keep_icc),
+            xmp: self.xmp.filter(|_| options.keep_xmp),
+        }
+    }
+}
+
+/// Rotates and flips `image` the way EXIF `orientation` says it should be shown.
+pub fn apply_orientation(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        // Transposed: mirrored along the top-left to bottom-right diagonal.
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        // Transversed: mirrored along the other diagonal.
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// Adds `metadata` to an encoded WebP. A simple file (a lone `VP8 ` or `VP8L` chunk)
+/// becomes an extended one with a `VP8X` header whose flags match what it holds, and
+/// chunks are laid out in the order the container specification asks for: `VP8X`,
+/// `ICCP`, the image, `EXIF`, `XMP `.
+pub fn embed(webp: &[u8], metadata: &Metadata) -> Result<Vec<u8>, WebpConverterError> {
+    if metadata.is_empty() {
+        return Ok(webp.to_vec());
+    }
+    rebuild(webp, metadata)
+}
+
+/// Whether an encoded WebP holds a lossless (`VP8L`) or a lossy (`VP8 `) image, `None`
+/// for anything else, such as an animation.
+pub fn is_lossless(webp: &[u8]) -> Option<bool> {
+    riff_chunks(webp)?.iter().find_map(|(id, _)| match id {
+        b"VP8L" => Some(true),
+        b"VP8 " => Some(false),
+        _ => None,
+    })
+}
+
+/// Removes the EXIF and XMP chunks of a WebP, for copies with `--strip-metadata`. The
+/// ICC profile stays: it says how to show the colors, not who took the photo where.
+pub fn strip(webp: &[u8]) -> Result<Vec<u8>, WebpConverterError> {
+    let existing = webp_metadata(webp);
+    if existing.exif.is_none() && existing.xmp.is_none() {
+        return Ok(webp.to_vec());
+    }
+    rebuild(
+        webp,
+        &Metadata {
+            icc: existing.icc,
+            ..Metadata::default()
+        },
+    )
+}
+
+// `webp` with its metadata chunks replaced by `metadata`.
+fn rebuild(webp: &[u8], metadata: &Metadata) -> Result<Vec<u8>, WebpConverterError> {
+    let invalid = || WebpConverterError {
+        message: "Can't add metadata, the encoded output isn't a valid WebP".to_string(),
+        kind: ErrorKind::Other,
+    };
+    let chunks = riff_chunks(webp).ok_or_else(invalid)?;
+    let (flags, width, height) = canvas(&chunks).ok_or_else(invalid)?;
+
+    let mut flags = flags & !(ICC_FLAG | EXIF_FLAG | XMP_FLAG);
+    for (present, flag) in [
+        (metadata.icc.is_some(), ICC_FLAG),
+        (metadata.exif.is_some(), EXIF_FLAG),
+        (metadata.xmp.is_some(), XMP_FLAG),
+    ] {
+        if present {
+            flags |= flag;
+        }
+    }
+    let mut vp8x = vec![flags, 0, 0, 0];
+    vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+    vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+
+    let mut out = b"RIFF\0\0\0\0WEBP".to_vec();
+    push_chunk(&mut out, b"VP8X", &vp8x);
+    if let Some(icc) = &metadata.icc {
+        push_chunk(&mut out, b"ICCP", icc);
+    }
+    for (id, data) in &chunks {
+        if ![b"VP8X", b"ICCP", b"EXIF", b"XMP "].contains(&id) {
+            push_chunk(&mut out, id, data);
+        }
+    }
+    if let Some(exif) = &metadata.exif {
+        push_chunk(&mut out, b"EXIF", exif);
+    }
+    if let Some(xmp) = &metadata.xmp {
+        push_chunk(&mut out, b"XMP ", xmp);
+    }
+    let riff_size = (out.len() - 8) as u32;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(out)
+}
+
+/// Gives an encoded WebP an alpha channel (`--force-rgba`), so decoders hand out RGBA
+/// even when libwebp left the alpha out because every pixel is opaque. A simple lossless
+/// file only needs the alpha bit of its `VP8L` header; a lossy one becomes an extended
+/// file with the `ALPH` chunk `opaque_alpha` returns (what goes after the chunk's header
+/// byte) in front of its `VP8 ` chunk. The color pixels are untouched.
+pub fn with_alpha(
+    webp: &[u8],
+    opaque_alpha: impl FnOnce() -> Result<Vec<u8>, WebpConverterError>,
+) -> Result<Vec<u8>, WebpConverterError> {
+    let invalid = || WebpConverterError {
+        message: "Can't force an alpha channel, the encoded output isn't a valid WebP".to_string(),
+        kind: ErrorKind::Other,
+    };
+    let chunks = riff_chunks(webp).ok_or_else(invalid)?;
+    let (flags, width, height) = canvas(&chunks).ok_or_else(invalid)?;
+    if flags & ALPHA_FLAG != 0 {
+        return Ok(webp.to_vec());
+    }
+
+    let mut out = b"RIFF\0\0\0\0WEBP".to_vec();
+    if let [(id, data)] = chunks.as_slice() {
+        if id == b"VP8L" {
+            let mut data = data.to_vec();
+            // Bit 28 of the header after the signature byte.
+            data[4] |= 0b0001_0000;
+            push_chunk(&mut out, b"VP8L", &data);
+            let riff_size = (out.len() - 8) as u32;
+            out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+            return Ok(out);
+        }
+    }
+
+    // Lossless compressed, no filtering or preprocessing.
+    let mut alph = vec![0b0000_0001];
+    alph.extend_from_slice(&opaque_alpha()?);
+    let mut vp8x = vec![flags | ALPHA_FLAG, 0, 0, 0];
+    vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+    vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+    push_chunk(&mut out, b"VP8X", &vp8x);
+    for (id, data) in chunks.iter().filter(|(id, _)| id != b"VP8X") {
+        if id == b"VP8 " {
+            push_chunk(&mut out, b"ALPH", &alph);
+        }
+        push_chunk(&mut out, id, data);
+    }
+    let riff_size = (out.len() - 8) as u32;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(out)
+}
+
+// Walks the segments before the image data, which is where JPEG keeps its metadata.
+fn jpeg_metadata(bytes: &[u8]) -> Metadata {
+    let mut metadata = Metadata::default();
+    let mut at = 2;
+    while let Some(&[0xFF, marker]) = bytes.get(at..at + 2) {
+        match marker {
+            // Fill byte before a marker.
+            0xFF => {
+                at += 1;
+                continue;
+            }
+            // Start of scan or end of image, no metadata follows.
+            0xDA | 0xD9 => break,
+            // Markers without a length.
+            0x01 | 0xD0..=0xD7 => {
+                at += 2;
+                continue;
+            }
+            _ => {}
+        }
+        let Some(length) = bytes.get(at + 2..at + 4) else {
+            break;
+        };
+        let length = u16::from_be_bytes([length[0], length[1]]) as usize;
+        let Some(data) = bytes.get(at + 4..at + 2 + length) else {
+            break;
+        };
+        if marker == 0xE1 {
+            if let Some(exif) = data.strip_prefix(JPEG_EXIF_PREFIX) {
+                metadata.exif.get_or_insert_with(|| exif.to_vec());
+            } else if let Some(xmp) = data.strip_prefix(JPEG_XMP_PREFIX) {
+                metadata.xmp.get_or_insert_with(|| xmp.to_vec());
+            }
+        }
+        at += 2 + length;
+    }
+    metadata
+}
+
+fn png_metadata(bytes: &[u8]) -> Metadata {
+    let mut metadata = Metadata::default();
+    let mut at = 8;
+    while let Some(header) = bytes.get(at..at + 8) {
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let Some(data) = bytes.get(at + 8..at + 8 + length) else {
+            break;
+        };
+        match &header[4..8] {
+            b"eXIf" => metadata.exif = Some(data.to_vec()),
+            b"iTXt" => {
+                if let Some(xmp) = png_xmp(data) {
+                    metadata.xmp = Some(xmp.to_vec());
+                }
+            }
+            b"IEND" => break,
+            _ => {}
+        }
+        // Length, type, data and CRC.
+        at += 12 + length;
+    }
+    metadata
+}
+
+// Appends the APP1 segments of the JPEG in `file` to `out`, up to the start of scan.
+fn jpeg_segments(file: &mut (impl Read + Seek), mut out: Vec<u8>) -> Vec<u8> {
+    let mut byte = [0; 1];
+    // A marker is 0xFF, maybe fill bytes (0xFF again), then its code.
+    while file.read_exact(&mut byte).is_ok() && byte[0] == 0xFF {
+        while byte[0] == 0xFF {
+            if file.read_exact(&mut byte).is_err() {
+                return out;
+            }
+        }
+        let code = byte[0];
+        match code {
+            // Start of scan or end of image, no metadata follows.
+            0xDA | 0xD9 => break,
+            // Markers without a length.
+            0x01 | 0xD0..=0xD7 => continue,
+            _ => {}
+        }
+        let mut length = [0; 2];
+        if file.read_exact(&mut length).is_err() {
+            break;
+        }
+        let data_len = (u16::from_be_bytes(length) as u64).saturating_sub(2);
+        let prefix = [0xFF, code, length[0], length[1]];
+        if !copy_or_skip(file, &mut out, &prefix, data_len, code == 0xE1) {
+            break;
+        }
+    }
+    out
+}
+
+// Appends the `eXIf` and `iTXt` chunks of the PNG in `file` to `out`, up to `IEND`.
+fn png_segments(file: &mut (impl Read + Seek), mut out: Vec<u8>) -> Vec<u8> {
+    let mut header = [0; 8];
+    while file.read_exact(&mut header).is_ok() && &header[4..8] != b"IEND" {
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let keep = &header[4..8] == b"eXIf" || &header[4..8] == b"iTXt";
+        // The data and its CRC.
+        if !copy_or_skip(file, &mut out, &header, length + 4, keep) {
+            break;
+        }
+    }
+    out
+}
+
+// Appends the `EXIF` and `XMP ` chunks of the WebP in `file` to `out`.
+fn webp_segments(file: &mut (impl Read + Seek), mut out: Vec<u8>) -> Vec<u8> {
+    let mut header = [0; 8];
+    while file.read_exact(&mut header).is_ok() {
+        let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as u64;
+        let keep = &header[..4] == b"EXIF" || &header[..4] == b"XMP ";
+        // Chunks are padded to an even size.
+        if !copy_or_skip(file, &mut out, &header, length + length % 2, keep) {
+            break;
+        }
+    }
+    out
+}
+
+// Appends `prefix` and the next `len` bytes of `file` to `out` when `keep`, or seeks
+// past them. False once the file is cut short.
+fn copy_or_skip(
+    file: &mut (impl Read + Seek),
+    out: &mut Vec<u8>,
+    prefix: &[u8],
+    len: u64,
+    keep: bool,
+) -> bool {
+    if !keep {
+        return file.seek(SeekFrom::Current(len as i64)).is_ok();
+    }
+    let mut data = vec![];
+    match file.take(len).read_to_end(&mut data) {
+        Ok(read) if read as u64 == len => {
+            out.extend_from_slice(prefix);
+            out.extend_from_slice(&data);
+            true
+        }
+        _ => false,
+    }
+}
+
+// The packet of an uncompressed XMP `iTXt` chunk: keyword, compression flag and
+// method, then a language tag and a translated keyword, both null terminated.
+fn png_xmp(data: &[u8]) -> Option<&[u8]> {
+    let rest = data.strip_prefix(PNG_XMP_KEYWORD)?;
+    if *rest.first()? != 0 {
+        return None;
+    }
+    let rest = rest.get(2..)?;
+    let after_nul = |data: &[u8]| -> Option<usize> { Some(data.iter().position(|&b| b == 0)? + 1) };
+    let rest = &rest[after_nul(rest)?..];
+    Some(&rest[after_nul(rest)?..])
+}
+
+fn webp_metadata(bytes: &[u8]) -> Metadata {
+    let mut metadata = Metadata::default();
+    for (id, data) in riff_chunks(bytes).unwrap_or_default() {
+        match &id {
+            // Some writers keep JPEG's prefix, which the container doesn't use.
+            b"EXIF" => {
+                let exif = data.strip_prefix(JPEG_EXIF_PREFIX).unwrap_or(data);
+                metadata.exif = Some(exif.to_vec());
+            }
+            b"ICCP" => metadata.icc = Some(data.to_vec()),
+            b"XMP " => metadata.xmp = Some(data.to_vec()),
+            _ => {}
+        }
+    }
+    metadata
+}
+
+// The chunks of a WebP file, as their ID and payload, or `None` if it isn't one.
+fn riff_chunks(bytes: &[u8]) -> Option<Vec<([u8; 4], &[u8])>> {
+    if bytes.get(..4)? != b"RIFF" || bytes.get(8..12)? != b"WEBP" {
+        return None;
+    }
+    let mut chunks = vec![];
+    let mut at = 12;
+    while let Some(header) = bytes.get(at..at + 8) {
+        let id = [header[0], header[1], header[2], header[3]];
+        let length = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        chunks.push((id, bytes.get(at + 8..at + 8 + length)?));
+        // Chunks are padded to an even size.
+        at += 8 + length + length % 2;
+    }
+    Some(chunks)
+}
+
+// The `VP8X` flags, width and height of a WebP: from its `VP8X` chunk, or for a simple
+// file from the bitstream header, where only lossless streams can have alpha.
+fn canvas(chunks: &[([u8; 4], &[u8])]) -> Option<(u8, u32, u32)> {
+    let u24 = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], 0]);
+    for (id, data) in chunks {
+        match id {
+            b"VP8X" if data.len() >= 10 => {
+                return Some((data[0], u24(&data[4..7]) + 1, u24(&data[7..10]) + 1))
+            }
+            // Frame tag, start code, then 14 bit width and height.
+            b"VP8 " if data.len() >= 10 && data[3..6] == [0x9D, 0x01, 0x2A] => {
+                let width = u16::from_le_bytes([data[6], data[7]]) & 0x3FFF;
+                let height = u16::from_le_bytes([data[8], data[9]]) & 0x3FFF;
+                return Some((0, width.into(), height.into()));
+            }
+            // Signature, then 14 bit width - 1, height - 1 and the alpha bit.
+            b"VP8L" if data.len() >= 5 && data[0] == 0x2F => {
+                let bits = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+                let alpha = if bits >> 28 & 1 == 1 { ALPHA_FLAG } else { 0 };
+                return Some((alpha, (bits & 0x3FFF) + 1, (bits >> 14 & 0x3FFF) + 1));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn push_chunk(out: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+// Where the value of the orientation tag of the first IFD sits in `exif`, and whether
+// the EXIF is big endian.
+fn orientation_offset(exif: &[u8]) -> Option<(usize, bool)> {
+    ifd0_entry(exif, EXIF_ORIENTATION_TAG, EXIF_SHORT)
+        .filter(|&(offset, _)| offset + 2 <= exif.len())
+}
+
+// Where the value of the first IFD's `tag` entry of type `kind` is, when it fits in
+// the entry, and whether the EXIF is big endian.
+fn ifd0_entry(exif: &[u8], tag: u16, kind: u16) -> Option<(usize, bool)> {
+    let big_endian = match exif.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    if read_u16(exif, 2, big_endian)? != 42 {
+        return None;
+    }
+    let ifd = read_u32(exif, 4, big_endian)? as usize;
+    let entries = read_u16(exif, ifd, big_endian)? as usize;
+    // Each entry is a tag, a type, a count and the value itself when it fits.
+    (0..entries)
+        .map(|i| ifd + 2 + i * 12)
+        .find(|&entry| {
+            read_u16(exif, entry, big_endian) == Some(tag)
+                && read_u16(exif, entry + 2, big_endian) == Some(kind)
+        })
+        .map(|entry| (entry + 8, big_endian))
+}
+
+fn read_u16(bytes: &[u8], at: usize, big_endian: bool) -> Option<u16> {
+    let b = [*bytes.get(at)?, *bytes.get(at + 1)?];
+    Some(if big_endian {
+        u16::from_be_bytes(b)
+    } else {
+        u16::from_le_bytes(b)
+    })
+}
+
+fn read_u32(bytes: &[u8], at: usize, big_endian: bool) -> Option<u32> {
+    let b: [u8; 4] = bytes.get(at..at + 4)?.try_into().ok()?;
+    Some(if big_endian {
+        u32::from_be_bytes(b)
+    } else {
+        u32::from_le_bytes(b)
+    })
+}
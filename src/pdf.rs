@@ -0,0 +1,30 @@
+use crate::types::{ErrorKind, WebpConverterError};
+use image::DynamicImage;
+use pdfium_render::prelude::*;
+use std::path::Path;
+
+impl From<PdfiumError> for WebpConverterError {
+    fn from(error: PdfiumError) -> Self {
+        WebpConverterError {
+            message: format!("PDF Error: {:?}", error),
+            kind: ErrorKind::Other,
+        }
+    }
+}
+
+// Renders every page of the PDF at `dpi`, in page order.
+// Pdfium is looked up next to the executable first, then on the system library path.
+pub fn render_pages(path: &Path, dpi: f32) -> Result<Vec<DynamicImage>, WebpConverterError> {
+    let bindings = Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path("./"))
+        .or_else(|_| Pdfium::bind_to_system_library())?;
+    let pdfium = Pdfium::new(bindings);
+    let document = pdfium.load_pdf_from_file(path, None)?;
+
+    // PDF user space is 72 points per inch.
+    let config = PdfRenderConfig::new().scale_page_by_factor(dpi / 72.0);
+    let mut pages = vec![];
+    for page in document.pages().iter() {
+        pages.push(page.render_with_config(&config)?.as_image());
+    }
+    Ok(pages)
+}
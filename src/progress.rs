@@ -0,0 +1,59 @@
+// The `--progress` bar of a folder run. Log lines go to the same terminal, so the
+// logger writes them through `suspend`, above the bar instead of across it.
+
+use indicatif::{ProgressBar, ProgressStyle};
+use std::sync::Mutex;
+
+// The bar being drawn, if any.
+static ACTIVE: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+// Files done out of the total, how many a second and the time left. Nothing is drawn
+// when it's off or stderr isn't a terminal.
+#[derive(Clone)]
+pub(crate) struct Progress {
+    bar: ProgressBar,
+    enabled: bool,
+}
+
+impl Progress {
+    pub(crate) fn start(total: usize, enabled: bool) -> Progress {
+        let bar = if enabled {
+            ProgressBar::new(total as u64)
+        } else {
+            ProgressBar::hidden()
+        };
+        bar.set_style(
+            ProgressStyle::with_template(
+                "{bar:40.cyan/blue} {pos}/{len} files, {per_sec}, ETA {eta}",
+            )
+            .expect("Invalid progress bar template")
+            .progress_chars("=> "),
+        );
+        if enabled {
+            *ACTIVE.lock().unwrap() = Some(bar.clone());
+        }
+        Progress { bar, enabled }
+    }
+
+    // One more file done, whatever came of it.
+    pub(crate) fn tick(&self) {
+        self.bar.inc(1);
+    }
+
+    // Clears the bar, so the summary isn't printed under a stale one.
+    pub(crate) fn finish(&self) {
+        self.bar.finish_and_clear();
+        if self.enabled {
+            ACTIVE.lock().unwrap().take();
+        }
+    }
+}
+
+// Runs `write` with the bar, if one is drawn, cleared out of the way.
+pub(crate) fn suspend<R>(write: impl FnOnce() -> R) -> R {
+    let bar = ACTIVE.lock().unwrap().clone();
+    match bar {
+        Some(bar) => bar.suspend(write),
+        None => write(),
+    }
+}
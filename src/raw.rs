@@ -0,0 +1,203 @@
+use crate::types::{ErrorKind, WebpConverterError};
+use image::{DynamicImage, ImageFormat};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+// The TIFF tags the previews are found with.
+const COMPRESSION: u16 = 0x0103;
+const STRIP_OFFSETS: u16 = 0x0111;
+const ORIENTATION: u16 = 0x0112;
+const STRIP_BYTE_COUNTS: u16 = 0x0117;
+const SUB_IFDS: u16 = 0x014a;
+const JPEG_OFFSET: u16 = 0x0201;
+const JPEG_LENGTH: u16 = 0x0202;
+
+// Old-style and new-style JPEG compression.
+const JPEG_COMPRESSION: [u32; 2] = [6, 7];
+
+// A corrupt file can chain its IFDs in a loop or point everywhere, stop after this many.
+const MAX_IFDS: usize = 64;
+
+// Decodes the largest JPEG preview embedded in a CR2, NEF, ARW or DNG file, with the
+// orientation of the photo. These formats are TIFF files whose directories point at
+// the sensor data and at JPEG previews the camera rendered, usually one at full size.
+// Demosaicing the sensor data is left to RAW developers: the preview is what the
+// camera showed, white balance and picture style included.
+pub fn decode_preview(path: &Path) -> Result<(DynamicImage, Option<u16>), WebpConverterError> {
+    let data = fs::read(path)?;
+    let tiff = Tiff::new(&data).ok_or_else(|| WebpConverterError {
+        message: format!("RAW Error: {:?} isn't a TIFF based RAW file", path),
+        kind: ErrorKind::Other,
+    })?;
+    let (mut previews, orientation) = tiff.previews();
+    // Lossless JPEG (the sensor data of some formats) is left out by `jpeg_dimensions`.
+    previews.sort_by_key(|(_, (width, height))| std::cmp::Reverse(*width as u32 * *height as u32));
+    for (preview, _) in previews {
+        if let Ok(image) = image::load_from_memory_with_format(preview, ImageFormat::Jpeg) {
+            return Ok((image, orientation));
+        }
+    }
+    Err(WebpConverterError {
+        message: format!("RAW Error: {:?} has no JPEG preview that decodes", path),
+        kind: ErrorKind::Other,
+    })
+}
+
+// A JPEG in the file, with its width and height.
+type Preview<'a> = (&'a [u8], (u16, u16));
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+    first_ifd: usize,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Tiff<'a>> {
+        let little_endian = match data.get(..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        let mut tiff = Tiff {
+            data,
+            little_endian,
+            first_ifd: 0,
+        };
+        // 42 for TIFF, DNG, NEF, ARW and CR2 alike.
+        if tiff.u16(2)? != 42 {
+            return None;
+        }
+        tiff.first_ifd = tiff.u32(4)? as usize;
+        Some(tiff)
+    }
+
+    fn u16(&self, at: usize) -> Option<u16> {
+        let bytes: [u8; 2] = self.data.get(at..at + 2)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(at..at + 4)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    // The values of the 12 byte IFD entry at `at`, for the SHORT, LONG and IFD types.
+    // Up to 4 bytes of them are stored in the entry, more are stored at an offset.
+    fn values(&self, at: usize) -> Vec<u32> {
+        let (Some(kind), Some(count)) = (self.u16(at + 2), self.u32(at + 4)) else {
+            return vec![];
+        };
+        let size = match kind {
+            3 => 2,
+            4 | 13 => 4,
+            _ => return vec![],
+        };
+        let count = count as usize;
+        let start = if count * size <= 4 {
+            at + 8
+        } else {
+            match self.u32(at + 8) {
+                Some(offset) => offset as usize,
+                None => return vec![],
+            }
+        };
+        (0..count.min(MAX_IFDS))
+            .filter_map(|i| match size {
+                2 => self.u16(start + i * 2).map(u32::from),
+                _ => self.u32(start + i * 4),
+            })
+            .collect()
+    }
+
+    // Every JPEG referenced from the IFD chain and the SubIFDs, with its dimensions,
+    // and the orientation in the first IFD.
+    fn previews(&self) -> (Vec<Preview<'a>>, Option<u16>) {
+        let mut previews = vec![];
+        let mut orientation = None;
+        let mut pending = vec![self.first_ifd];
+        let mut seen = HashSet::new();
+        while let Some(ifd) = pending.pop() {
+            if ifd == 0 || seen.len() >= MAX_IFDS || !seen.insert(ifd) {
+                continue;
+            }
+            let Some(count) = self.u16(ifd) else {
+                continue;
+            };
+            let mut tags = vec![];
+            for index in 0..count as usize {
+                let at = ifd + 2 + index * 12;
+                if let Some(tag) = self.u16(at) {
+                    tags.push((tag, self.values(at)));
+                }
+            }
+            let tag = |wanted: u16| -> &[u32] {
+                tags.iter()
+                    .find(|(tag, _)| *tag == wanted)
+                    .map_or(&[], |(_, values)| values.as_slice())
+            };
+
+            if ifd == self.first_ifd {
+                orientation = tag(ORIENTATION).first().map(|&o| o as u16);
+            }
+            if let ([offset], [length]) = (tag(JPEG_OFFSET), tag(JPEG_LENGTH)) {
+                previews.extend(self.jpeg(*offset, *length));
+            }
+            let compressed = tag(COMPRESSION)
+                .first()
+                .is_some_and(|c| JPEG_COMPRESSION.contains(c));
+            if let ([offset], [length], true) =
+                (tag(STRIP_OFFSETS), tag(STRIP_BYTE_COUNTS), compressed)
+            {
+                previews.extend(self.jpeg(*offset, *length));
+            }
+            pending.extend(tag(SUB_IFDS).iter().map(|&offset| offset as usize));
+            if let Some(next) = self.u32(ifd + 2 + count as usize * 12) {
+                pending.push(next as usize);
+            }
+        }
+        (previews, orientation)
+    }
+
+    // The JPEG at `offset`, if that is one a baseline or progressive decoder reads.
+    fn jpeg(&self, offset: u32, length: u32) -> Option<Preview<'a>> {
+        let start = offset as usize;
+        let bytes = self.data.get(start..start.checked_add(length as usize)?)?;
+        Some((bytes, jpeg_dimensions(bytes)?))
+    }
+}
+
+// Width and height from the frame header of a baseline or progressive JPEG. `None`
+// for anything else, lossless JPEG included.
+fn jpeg_dimensions(bytes: &[u8]) -> Option<(u16, u16)> {
+    if bytes.get(..2)? != [0xff, 0xd8] {
+        return None;
+    }
+    let mut at = 2;
+    loop {
+        let marker = bytes.get(at..at + 2)?;
+        if marker[0] != 0xff {
+            return None;
+        }
+        let length = u16::from_be_bytes([*bytes.get(at + 2)?, *bytes.get(at + 3)?]) as usize;
+        match marker[1] {
+            0xc0..=0xc2 => {
+                let height = u16::from_be_bytes([*bytes.get(at + 5)?, *bytes.get(at + 6)?]);
+                let width = u16::from_be_bytes([*bytes.get(at + 7)?, *bytes.get(at + 8)?]);
+                return Some((width, height));
+            }
+            // Any other frame type: lossless, hierarchical or arithmetic coded.
+            0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf => return None,
+            _ => at += 2 + length,
+        }
+    }
+}
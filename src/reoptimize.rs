@@ -0,0 +1,70 @@
+use crate::types::{ErrorKind, WebpConverterError};
+
+// Re-encodes `data` in its own container instead of converting it to WebP.
+// JPEG goes through mozjpeg at `quality`, PNG through oxipng which is always lossless.
+#[cfg(feature = "reoptimize")]
+pub fn reoptimize_bytes(
+    data: &[u8],
+    extension: &str,
+    quality: f32,
+) -> Result<Vec<u8>, WebpConverterError> {
+    match extension {
+        "jpg" | "jpeg" | "jfif" => reoptimize_jpeg(data, quality),
+        "png" => {
+            oxipng::optimize_from_memory(data, &oxipng::Options::from_preset(2)).map_err(|e| {
+                WebpConverterError {
+                    message: format!("PNG Optimization Error: {}", e),
+                    kind: ErrorKind::Other,
+                }
+            })
+        }
+        _ => Err(unsupported(extension)),
+    }
+}
+
+#[cfg(not(feature = "reoptimize"))]
+pub fn reoptimize_bytes(
+    _data: &[u8],
+    extension: &str,
+    _quality: f32,
+) -> Result<Vec<u8>, WebpConverterError> {
+    match extension {
+        "jpg" | "jpeg" | "jfif" | "png" => Err(WebpConverterError {
+            message: "Re-optimization requires building with `--features reoptimize`".to_string(),
+            kind: ErrorKind::Other,
+        }),
+        _ => Err(unsupported(extension)),
+    }
+}
+
+#[cfg(feature = "reoptimize")]
+fn reoptimize_jpeg(data: &[u8], quality: f32) -> Result<Vec<u8>, WebpConverterError> {
+    let rgb = image::load_from_memory(data)?.to_rgb8();
+    let (width, height) = rgb.dimensions();
+
+    // mozjpeg reports libjpeg errors by unwinding, so keep them from taking down the task.
+    std::panic::catch_unwind(|| -> std::io::Result<Vec<u8>> {
+        let mut compress = mozjpeg::Compress::new(mozjpeg::ColorSpace::JCS_RGB);
+        compress.set_size(width as usize, height as usize);
+        compress.set_quality(quality);
+        compress.set_optimize_scans(true);
+        let mut started = compress.start_compress(Vec::new())?;
+        started.write_scanlines(rgb.as_raw())?;
+        started.finish()
+    })
+    .map_err(|_| WebpConverterError {
+        message: "JPEG Optimization Error: mozjpeg aborted".to_string(),
+        kind: ErrorKind::Other,
+    })?
+    .map_err(WebpConverterError::from)
+}
+
+fn unsupported(extension: &str) -> WebpConverterError {
+    WebpConverterError {
+        message: format!(
+            "Re-optimizing .{} files is not supported, only JPEG and PNG",
+            extension
+        ),
+        kind: ErrorKind::Other,
+    }
+}
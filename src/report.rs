@@ -0,0 +1,152 @@
+use crate::types::{savings_percent, ReportFormat, Summary};
+use serde::Serialize;
+use std::fmt::Write;
+
+/// The aggregate of a batch run as `--report-format` writes it, one field per column.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SummaryReport {
+    pub matched: usize,
+    pub converted: usize,
+    pub kept_original: usize,
+    pub copied: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub auto_lossy: usize,
+    pub auto_lossless: usize,
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub saved_percent: f32,
+    pub kept_existing: usize,
+    pub broken: usize,
+    pub converted_input_bytes: u64,
+    pub converted_output_bytes: u64,
+    pub converted_saved_percent: f32,
+    pub copied_image_bytes: u64,
+    pub copied_other_bytes: u64,
+}
+
+impl From<&Summary> for SummaryReport {
+    fn from(summary: &Summary) -> Self {
+        SummaryReport {
+            matched: summary.matched,
+            converted: summary.converted,
+            kept_original: summary.kept_original,
+            copied: summary.copied,
+            skipped: summary.skipped,
+            failed: summary.failed,
+            auto_lossy: summary.auto_lossy,
+            auto_lossless: summary.auto_lossless,
+            input_bytes: summary.input_bytes,
+            output_bytes: summary.output_bytes,
+            saved_percent: savings_percent(summary.input_bytes, summary.output_bytes),
+            kept_existing: summary.kept_existing,
+            broken: summary.broken.len(),
+            converted_input_bytes: summary.converted_input_bytes,
+            converted_output_bytes: summary.converted_output_bytes,
+            converted_saved_percent: savings_percent(
+                summary.converted_input_bytes,
+                summary.converted_output_bytes,
+            ),
+            copied_image_bytes: summary.copied_image_bytes,
+            copied_other_bytes: summary.copied_other_bytes,
+        }
+    }
+}
+
+const CSV_HEADER: &str = "matched,converted,kept_original,copied,skipped,failed,auto_lossy,auto_lossless,input_bytes,output_bytes,saved_percent,kept_existing,broken,converted_input_bytes,converted_output_bytes,converted_saved_percent,copied_image_bytes,copied_other_bytes";
+
+/// Renders the summary of a batch in `format`. CSV is a header line and one row, so runs
+/// can be pasted one under the other into a spreadsheet.
+pub fn summary_report(summary: &Summary, format: ReportFormat) -> String {
+    let report = SummaryReport::from(summary);
+    match format {
+        ReportFormat::Text => format!("Summary: {}", summary),
+        ReportFormat::Json => {
+            serde_json::to_string_pretty(&report).expect("a summary report always serializes")
+        }
+        ReportFormat::Csv => format!(
+            "{}\n{},{},{},{},{},{},{},{},{},{},{:.1},{},{},{},{},{:.1},{},{}",
+            CSV_HEADER,
+            report.matched,
+            report.converted,
+            report.kept_original,
+            report.copied,
+            report.skipped,
+            report.failed,
+            report.auto_lossy,
+            report.auto_lossless,
+            report.input_bytes,
+            report.output_bytes,
+            report.saved_percent,
+            report.kept_existing,
+            report.broken,
+            report.converted_input_bytes,
+            report.converted_output_bytes,
+            report.converted_saved_percent,
+            report.copied_image_bytes,
+            report.copied_other_bytes
+        ),
+    }
+}
+
+// Upper bounds of the compression ratio buckets (input size / output size); the last
+// bucket catches everything above. Anything below 1.0x got bigger.
+const BUCKETS: [(f64, &str); 5] = [
+    (1.0, " grew"),
+    (2.0, " 1-2x"),
+    (3.0, " 2-3x"),
+    (5.0, " 3-5x"),
+    (10.0, "5-10x"),
+];
+const LAST_BUCKET: &str = " 10x+";
+const BAR_WIDTH: usize = 40;
+
+/// Renders the per-file compression ratios of a batch as an ASCII histogram with
+/// min/median/max, for `--diff-report`.
+pub fn diff_report(summary: &Summary) -> String {
+    let mut ratios = summary.ratios.clone();
+    if ratios.is_empty() {
+        return "No files were converted, nothing to report.\n".to_string();
+    }
+    ratios.sort_by(|a, b| a.total_cmp(b));
+
+    let mut counts = [0usize; BUCKETS.len() + 1];
+    for ratio in &ratios {
+        let bucket = BUCKETS
+            .iter()
+            .position(|(upper, _)| ratio < upper)
+            .unwrap_or(BUCKETS.len());
+        counts[bucket] += 1;
+    }
+    let largest = counts.iter().copied().max().unwrap_or(0).max(1);
+
+    let mut report = String::new();
+    let _ = writeln!(report, "Compression ratios ({} files):", ratios.len());
+    let labels = BUCKETS.iter().map(|(_, label)| *label).chain([LAST_BUCKET]);
+    for (label, count) in labels.zip(counts) {
+        // Round up so a bucket with a single file still shows a bar.
+        let bar = "#".repeat((count * BAR_WIDTH).div_ceil(largest));
+        let _ = writeln!(
+            report,
+            "{} | {:<width$} {}",
+            label,
+            bar,
+            count,
+            width = BAR_WIDTH
+        );
+    }
+
+    let median = if ratios.len().is_multiple_of(2) {
+        (ratios[ratios.len() / 2 - 1] + ratios[ratios.len() / 2]) / 2.0
+    } else {
+        ratios[ratios.len() / 2]
+    };
+    let _ = writeln!(
+        report,
+        "min {:.2}x, median {:.2}x, max {:.2}x",
+        ratios[0],
+        median,
+        ratios[ratios.len() - 1]
+    );
+    report
+}
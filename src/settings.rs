@@ -0,0 +1,443 @@
+use crate::encoder::Channels;
+use crate::types::{ConversionOptions, Jobs, ResizeTarget};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, ValueEnum};
+use serde::Serialize;
+use std::time::Duration;
+
+/// How `--echo-settings` prints the resolved settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum SettingsFormat {
+    // Aligned columns on stdout.
+    #[default]
+    Table,
+    // A JSON array of `{"setting", "value", "source"}` objects on stdout.
+    Json,
+}
+
+/// Where a resolved setting came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Source {
+    CommandLine,
+    // A `--config` file.
+    Config,
+    // Nothing set it: the built-in default, or what the builder derived from the
+    // settings it depends on (`-l` from `-q 100`).
+    Default,
+}
+
+/// One setting the run will use, named like its command line flag.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Setting {
+    pub setting: &'static str,
+    pub value: String,
+    pub source: Source,
+}
+
+/// Every setting that shapes a conversion, as `options` resolved them. `matches` are the
+/// parsed command line and `from_config` the `Args` ids a `--config` filled in, as
+/// returned by [`Config::apply`](crate::config::Config::apply).
+pub fn resolve(
+    options: &ConversionOptions,
+    recursive: bool,
+    matches: &ArgMatches,
+    from_config: &[&str],
+) -> Vec<Setting> {
+    let source = |ids: &[&str]| {
+        if ids
+            .iter()
+            .any(|id| matches.value_source(id) == Some(ValueSource::CommandLine))
+        {
+            Source::CommandLine
+        } else if ids.iter().any(|id| from_config.contains(id)) {
+            Source::Config
+        } else {
+            Source::Default
+        }
+    };
+    let seconds = |value: Option<Duration>| {
+        value.map_or("none".to_string(), |d| {
+            humantime::format_duration(d).to_string()
+        })
+    };
+    let mut quality_map: Vec<_> = options
+        .quality_map
+        .0
+        .iter()
+        .map(|(extension, quality)| format!("{}={}", extension, quality))
+        .collect();
+    quality_map.sort();
+    let pixels = |value: Option<u32>| value.map_or("none".to_string(), |px| px.to_string());
+    let (box_width, box_height, scale) = match options.resize_target {
+        ResizeTarget::Box { width, height } => (width, height, "none".to_string()),
+        ResizeTarget::Scale(percent) => (None, None, format!("{}%", percent)),
+    };
+
+    let rows: Vec<(&'static str, &[&str], String)> = vec![
+        ("-r", &["recursive"], recursive.to_string()),
+        ("-q", &["quality"], options.quality.to_string()),
+        ("--quality-map", &["quality_map"], quality_map.join(",")),
+        ("-l", &["lossless"], (options.lossless != 0).to_string()),
+        (
+            "--lossless-effort",
+            &["lossless_effort"],
+            options
+                .lossless_effort
+                .map_or("none".to_string(), |e| e.to_string()),
+        ),
+        (
+            "-c",
+            &["compression_factor"],
+            options.compression_factor.to_string(),
+        ),
+        ("-n", &["psnr"], options.noise_ratio.to_string()),
+        (
+            "--min-quality",
+            &["min_quality"],
+            options.min_quality.to_string(),
+        ),
+        (
+            "--max-quality",
+            &["max_quality"],
+            options.max_quality.to_string(),
+        ),
+        (
+            "--effort",
+            &["effort", "fast", "best"],
+            options.effort.to_string(),
+        ),
+        (
+            "--roi",
+            &["roi"],
+            options
+                .roi
+                .map_or("none".to_string(), |roi| roi.to_string()),
+        ),
+        (
+            "--roi-quality",
+            &["roi_quality"],
+            options.roi_quality.to_string(),
+        ),
+        ("--chroma", &["chroma"], name(&options.chroma)),
+        (
+            "-f",
+            &["format"],
+            options
+                .output_formats()
+                .map(|format| name(&format))
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+        (
+            "--auto-format",
+            &["auto_format"],
+            options.auto_format.to_string(),
+        ),
+        (
+            "--encode-config",
+            &["encode_config"],
+            if options.encode_config.is_some() {
+                "set"
+            } else {
+                "none"
+            }
+            .to_string(),
+        ),
+        (
+            "-s",
+            &[
+                "resize",
+                "resize_mode",
+                "max_width",
+                "max_height",
+                "max_dimension",
+                "scale",
+            ],
+            options.should_resize.to_string(),
+        ),
+        (
+            "--resize-mode",
+            &["resize_mode"],
+            name(&options.resize_mode),
+        ),
+        (
+            "--max-width",
+            &["max_width", "max_dimension"],
+            pixels(box_width),
+        ),
+        (
+            "--max-height",
+            &["max_height", "max_dimension"],
+            pixels(box_height),
+        ),
+        ("--scale", &["scale"], scale),
+        (
+            "--sizes",
+            &["sizes"],
+            if options.sizes.is_empty() {
+                "none".to_string()
+            } else {
+                options
+                    .sizes
+                    .iter()
+                    .map(|size| size.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            },
+        ),
+        (
+            "--max-resize-factor",
+            &["max_resize_factor"],
+            options
+                .max_resize_factor
+                .map_or("none".to_string(), |f| f.to_string()),
+        ),
+        (
+            "--max-pixels",
+            &["max_pixels"],
+            options.max_pixels.to_string(),
+        ),
+        (
+            "--no-auto-fit",
+            &["no_auto_fit"],
+            options.no_auto_fit.to_string(),
+        ),
+        ("--rotate", &["rotate"], optional(options.rotate.as_ref())),
+        ("--flip", &["flip"], optional(options.flip.as_ref())),
+        ("--grayscale", &["grayscale"], options.grayscale.to_string()),
+        (
+            "--no-alpha",
+            &["no_alpha", "background"],
+            options.no_alpha.to_string(),
+        ),
+        (
+            "--background",
+            &["background"],
+            format!(
+                "#{:02x}{:02x}{:02x}",
+                options.background.0[0], options.background.0[1], options.background.0[2]
+            ),
+        ),
+        (
+            "--force-rgb",
+            &["force_rgb"],
+            (options.channels == Some(Channels::Rgb)).to_string(),
+        ),
+        (
+            "--force-rgba",
+            &["force_rgba"],
+            (options.channels == Some(Channels::Rgba)).to_string(),
+        ),
+        (
+            "--target-colorspace",
+            &["target_colorspace", "color_profile"],
+            optional(options.target_colorspace.as_ref()),
+        ),
+        (
+            "--apply-orientation",
+            &["apply_orientation", "no_auto_orient"],
+            options.apply_orientation.to_string(),
+        ),
+        (
+            "--keep-exif",
+            &["keep_exif", "keep_metadata"],
+            options.keep_exif.to_string(),
+        ),
+        (
+            "--keep-icc",
+            &["keep_icc", "keep_metadata", "color_profile"],
+            options.keep_icc.to_string(),
+        ),
+        (
+            "--keep-xmp",
+            &["keep_xmp", "keep_metadata"],
+            options.keep_xmp.to_string(),
+        ),
+        (
+            "--strip-metadata",
+            &["strip_metadata"],
+            options.strip_metadata.to_string(),
+        ),
+        (
+            "--min-savings-percent",
+            &["min_savings_percent"],
+            options.min_savings_percent.to_string(),
+        ),
+        (
+            "--clean-output",
+            &["clean_output"],
+            options.clean_output.to_string(),
+        ),
+        (
+            "--quarantine",
+            &["quarantine"],
+            options
+                .quarantine
+                .as_ref()
+                .map_or("none".to_string(), |dir| dir.display().to_string()),
+        ),
+        ("--mirror", &["mirror"], options.mirror.to_string()),
+        ("--in-place", &["in_place"], options.in_place.to_string()),
+        ("--replace", &["replace"], options.replace.to_string()),
+        (
+            "--skip-existing",
+            &["skip_existing"],
+            options.skip_existing.to_string(),
+        ),
+        (
+            "--cache",
+            &["cache"],
+            options
+                .cache
+                .as_ref()
+                .map_or("none".to_string(), |file| file.display().to_string()),
+        ),
+        (
+            "--overwrite-if-smaller",
+            &["overwrite_if_smaller"],
+            options.overwrite_if_smaller.to_string(),
+        ),
+        (
+            "-j",
+            &["jobs"],
+            match options.jobs {
+                Jobs::Auto => "auto".to_string(),
+                Jobs::Fixed(jobs) => jobs.to_string(),
+            },
+        ),
+        (
+            "--deterministic",
+            &["deterministic"],
+            options.deterministic.to_string(),
+        ),
+        ("--timeout", &["timeout"], seconds(options.timeout)),
+        (
+            "--respect-ignore",
+            &["respect_ignore"],
+            options.respect_ignore.to_string(),
+        ),
+        (
+            "--exclude-dir",
+            &["exclude_dirs"],
+            options.exclude_dirs.join(","),
+        ),
+        ("--since", &["since"], seconds(options.since)),
+        ("--sort", &["sort"], optional(options.sort.as_ref())),
+        (
+            "--sample",
+            &["sample"],
+            options.sample.map_or("none".to_string(), |s| s.to_string()),
+        ),
+        (
+            "--limit",
+            &["limit"],
+            options.limit.map_or("none".to_string(), |l| l.to_string()),
+        ),
+        (
+            "--output-dir-name",
+            &["output_dir_name"],
+            options.output_dir_name.clone(),
+        ),
+        (
+            "--output-dir",
+            &["output_dir"],
+            options
+                .output_dir
+                .as_ref()
+                .map_or("none".to_string(), |dir| dir.display().to_string()),
+        ),
+        (
+            "-o",
+            &["output"],
+            options
+                .output
+                .as_ref()
+                .map_or("none".to_string(), |target| {
+                    format!("s3://{}/{}", target.bucket, target.prefix)
+                }),
+        ),
+        (
+            "--hash-names",
+            &["hash_names"],
+            options.hash_names.to_string(),
+        ),
+        (
+            "--normalize-extension",
+            &["normalize_extension"],
+            options.normalize_extension.to_string(),
+        ),
+        ("--lqip", &["lqip"], options.lqip.to_string()),
+        (
+            "--reoptimize",
+            &["reoptimize"],
+            options.reoptimize.to_string(),
+        ),
+        ("--pdf-dpi", &["pdf_dpi"], options.pdf_dpi.to_string()),
+        (
+            "--verify-output",
+            &["verify_output"],
+            options.verify_output.to_string(),
+        ),
+        (
+            "--report-format",
+            &["report_format"],
+            name(&options.report_format),
+        ),
+    ];
+    rows.into_iter()
+        .map(|(setting, ids, value)| Setting {
+            setting,
+            value,
+            source: source(ids),
+        })
+        .collect()
+}
+
+/// `settings` as `format` prints them.
+pub fn render(settings: &[Setting], format: SettingsFormat) -> String {
+    match format {
+        SettingsFormat::Json => {
+            serde_json::to_string_pretty(settings).expect("settings always serialize")
+        }
+        SettingsFormat::Table => {
+            let width = |column: fn(&Setting) -> usize, header: &str| {
+                settings
+                    .iter()
+                    .map(column)
+                    .max()
+                    .unwrap_or(0)
+                    .max(header.len())
+            };
+            let setting_width = width(|s| s.setting.len(), "SETTING");
+            let value_width = width(|s| s.value.len(), "VALUE");
+            let mut table = format!(
+                "{:<setting_width$}  {:<value_width$}  SOURCE",
+                "SETTING", "VALUE"
+            );
+            for setting in settings {
+                let source = match setting.source {
+                    Source::CommandLine => "command line",
+                    Source::Config => "config",
+                    Source::Default => "default",
+                };
+                table.push_str(&format!(
+                    "\n{:<setting_width$}  {:<value_width$}  {}",
+                    setting.setting, setting.value, source
+                ));
+            }
+            table
+        }
+    }
+}
+
+// The name a `ValueEnum` value is given on the command line.
+fn name<T: ValueEnum>(value: &T) -> String {
+    value
+        .to_possible_value()
+        .map_or_else(String::new, |value| value.get_name().to_string())
+}
+
+fn optional<T: ValueEnum>(value: Option<&T>) -> String {
+    value.map_or("none".to_string(), name)
+}
@@ -0,0 +1,254 @@
+use crate::animation::Frame;
+use crate::converter::{open_reader, reader_limits};
+use crate::types::{FileInfo, WebpConverterError};
+use crate::{color, helpers, wio};
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::io::Reader as ImageReader;
+use image::{AnimationDecoder, DynamicImage, ImageDecoder, ImageFormat, ImageResult};
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, BufRead, Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+
+/// Where an image to convert comes from. Every way into the converter (a walked file,
+/// `-p` with a path or a URL, bytes handed to the library) becomes one of these, and
+/// [`convert_source`](crate::converter::convert_source) takes it from there.
+#[derive(Debug, Clone)]
+pub enum ImageSource {
+    // A local file, with the metadata read when it was found.
+    File(FileInfo),
+    // An image already in memory, named like the file it would have been so outputs
+    // get a name and formats known only by extension still work.
+    Bytes {
+        name: PathBuf,
+        bytes: Vec<u8>,
+    },
+    // An image streamed through a named pipe (FIFO) at `path`, read to the end when
+    // the source was opened since a pipe can't be rewound. Named after the pipe.
+    Pipe {
+        path: PathBuf,
+        bytes: Vec<u8>,
+    },
+    // An image downloaded from `url`, named after the URL's last segment.
+    Url {
+        url: String,
+        name: PathBuf,
+        bytes: Vec<u8>,
+    },
+}
+
+impl ImageSource {
+    // A FIFO is read whole here, blocking until its writer closes it; anything else is
+    // only stat'd and read when it is converted.
+    pub fn open<P: Into<PathBuf>>(path: P) -> io::Result<ImageSource> {
+        let path = path.into();
+        let metadata = fs::metadata(&path)?;
+        if !is_fifo(&metadata) {
+            return Ok(ImageSource::File(FileInfo::from_metadata(path, &metadata)));
+        }
+        let bytes = fs::read(&path)?;
+        if bytes.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{:?} was closed before any image data was written", path),
+            ));
+        }
+        Ok(ImageSource::Pipe { path, bytes })
+    }
+
+    pub fn from_bytes<N: Into<PathBuf>>(name: N, bytes: Vec<u8>) -> ImageSource {
+        ImageSource::Bytes {
+            name: name.into(),
+            bytes,
+        }
+    }
+
+    // Reads `reader` (stdin, a pipe, an archive entry) to the end.
+    pub fn from_reader<N: Into<PathBuf>, R: Read>(
+        name: N,
+        mut reader: R,
+    ) -> io::Result<ImageSource> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Ok(ImageSource::from_bytes(name, bytes))
+    }
+
+    // Downloads `url`, see `wio::fetch_image` for what is accepted.
+    pub async fn fetch(url: &str) -> Result<ImageSource, WebpConverterError> {
+        let remote = wio::fetch_image(url).await?;
+        Ok(ImageSource::Url {
+            url: url.to_string(),
+            name: PathBuf::from(remote.file_name),
+            bytes: remote.bytes,
+        })
+    }
+
+    /// The path outputs are named and placed after. For in-memory sources it is a bare
+    /// file name, so their outputs land under the current directory.
+    pub fn name(&self) -> &Path {
+        match self {
+            ImageSource::File(file) => &file.path,
+            ImageSource::Pipe { path, .. } => path,
+            ImageSource::Bytes { name, .. } | ImageSource::Url { name, .. } => name,
+        }
+    }
+
+    /// What a `ConversionRecord` reports as its input: the path, or the URL.
+    pub fn input(&self) -> PathBuf {
+        match self {
+            ImageSource::Url { url, .. } => PathBuf::from(url),
+            _ => self.name().to_path_buf(),
+        }
+    }
+
+    pub fn input_size(&self) -> u64 {
+        match self {
+            ImageSource::File(file) => file.size,
+            ImageSource::Bytes { bytes, .. }
+            | ImageSource::Pipe { bytes, .. }
+            | ImageSource::Url { bytes, .. } => bytes.len() as u64,
+        }
+    }
+
+    pub(crate) fn in_memory(&self) -> Option<&[u8]> {
+        match self {
+            ImageSource::File(_) => None,
+            ImageSource::Bytes { bytes, .. }
+            | ImageSource::Pipe { bytes, .. }
+            | ImageSource::Url { bytes, .. } => Some(bytes),
+        }
+    }
+
+    // Width and height from the header alone, so oversized images can be refused
+    // before they are decoded.
+    pub(crate) fn dimensions(&self) -> Result<(u32, u32), WebpConverterError> {
+        if helpers::is_heif(self.name()) {
+            return crate::heif::dimensions(&self.heif_bytes()?);
+        }
+        // Reading the header allocates next to nothing, the limits don't come into it.
+        let dimensions = || -> Result<_, WebpConverterError> {
+            Ok(match self.in_memory() {
+                Some(bytes) => memory_reader(bytes, 0)?.into_dimensions()?,
+                None => open_reader(self.name(), 0)?.into_dimensions()?,
+            })
+        };
+        dimensions().map_err(|e| self.explain_pipe_error(e))
+    }
+
+    /// Decodes the image, letting the decoder allocate what `max_pixels` (`--max-pixels`,
+    /// 0 for no limit) allows.
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "decode", skip_all))]
+    pub fn decode(&self, max_pixels: u64) -> Result<DynamicImage, WebpConverterError> {
+        if helpers::is_heif(self.name()) {
+            return crate::heif::decode(&self.heif_bytes()?);
+        }
+        let decode = || -> Result<_, WebpConverterError> {
+            Ok(match self.in_memory() {
+                Some(bytes) => memory_reader(bytes, max_pixels)?.decode()?,
+                None => open_reader(self.name(), max_pixels)?.decode()?,
+            })
+        };
+        decode().map_err(|e| self.explain_pipe_error(e))
+    }
+
+    // Every frame of an animated GIF or APNG. `None` for other formats and for
+    // animations of a single frame, which convert like any still image.
+    pub(crate) fn frames(&self, max_pixels: u64) -> Result<Option<Vec<Frame>>, WebpConverterError> {
+        let frames = || -> Result<_, WebpConverterError> {
+            Ok(match self.in_memory() {
+                Some(bytes) => animation_frames(memory_reader(bytes, max_pixels)?, max_pixels)?,
+                None => animation_frames(open_reader(self.name(), max_pixels)?, max_pixels)?,
+            })
+        };
+        frames().map_err(|e| self.explain_pipe_error(e))
+    }
+
+    // A pipe gives no sign of a writer that stopped early, its image just ends. Says so
+    // when a piped image can't be decoded, which is the usual reason.
+    fn explain_pipe_error(&self, error: WebpConverterError) -> WebpConverterError {
+        match self {
+            ImageSource::Pipe { path, bytes } => WebpConverterError {
+                message: format!(
+                    "Couldn't decode the {} bytes read from the pipe {:?}, was it closed mid-image? {}",
+                    bytes.len(),
+                    path,
+                    error.message
+                ),
+                kind: error.kind,
+            },
+            _ => error,
+        }
+    }
+
+    // The whole file, libheif reads HEIC/HEIF from memory.
+    fn heif_bytes(&self) -> io::Result<Cow<'_, [u8]>> {
+        Ok(match self.in_memory() {
+            Some(bytes) => Cow::Borrowed(bytes),
+            None => Cow::Owned(fs::read(self.name())?),
+        })
+    }
+
+    pub(crate) fn icc_profile(&self) -> Option<Vec<u8>> {
+        match self.in_memory() {
+            Some(bytes) => color::icc_profile_from_bytes(bytes),
+            None => color::read_icc_profile(self.name()),
+        }
+    }
+
+    // The original bytes, for writing the source as it is (`--min-savings-percent`).
+    pub(crate) async fn read_bytes(&self) -> io::Result<Vec<u8>> {
+        match self.in_memory() {
+            Some(bytes) => Ok(bytes.to_vec()),
+            None => tokio::fs::read(self.name()).await,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_fifo(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    metadata.file_type().is_fifo()
+}
+
+#[cfg(not(unix))]
+fn is_fifo(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+// Detects the format from the content, like `open_reader` does for files.
+fn memory_reader(bytes: &[u8], max_pixels: u64) -> io::Result<ImageReader<Cursor<&[u8]>>> {
+    let mut reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    reader.limits(reader_limits(max_pixels));
+    Ok(reader)
+}
+
+// The frames of `reader` when it holds an animation. A PNG is only decoded when its
+// header says it is an APNG.
+fn animation_frames<R: BufRead + Seek>(
+    reader: ImageReader<R>,
+    max_pixels: u64,
+) -> ImageResult<Option<Vec<Frame>>> {
+    let format = reader.format();
+    let reader = reader.into_inner();
+    let limits = reader_limits(max_pixels);
+    let frames = match format {
+        Some(ImageFormat::Gif) => {
+            let mut decoder = GifDecoder::new(reader)?;
+            decoder.set_limits(limits)?;
+            decoder.into_frames()
+        }
+        Some(ImageFormat::Png) => {
+            let decoder = PngDecoder::with_limits(reader, limits)?;
+            if !decoder.is_apng() {
+                return Ok(None);
+            }
+            decoder.apng().into_frames()
+        }
+        _ => return Ok(None),
+    };
+    let frames = frames.collect_frames()?;
+    Ok(Some(frames.into_iter().map(Frame::from).collect())
+        .filter(|frames: &Vec<Frame>| frames.len() > 1))
+}
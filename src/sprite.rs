@@ -0,0 +1,88 @@
+use crate::types::{ErrorKind, WebpConverterError};
+use image::{imageops, DynamicImage, RgbaImage};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Where one image landed in a sprite sheet, in pixels from the top left corner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SpriteRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The JSON written next to a sprite sheet: the atlas file, its size and the rectangle
+/// of every sprite, keyed by its path relative to the packed folder.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SpriteSheet {
+    pub image: String,
+    pub width: u32,
+    pub height: u32,
+    pub sprites: BTreeMap<String, SpriteRect>,
+}
+
+// Packs rectangles of `sizes` into shelves no wider than `max_width`, tallest first,
+// with `padding` pixels between neighbours. Returns each rectangle, in the order of
+// `sizes`, and the size of the sheet they fill.
+pub fn pack(
+    sizes: &[(u32, u32)],
+    padding: u32,
+    max_width: u32,
+) -> Result<(Vec<SpriteRect>, (u32, u32)), WebpConverterError> {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| (std::cmp::Reverse(sizes[i].1), std::cmp::Reverse(sizes[i].0)));
+
+    let mut rects = vec![
+        SpriteRect {
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+        };
+        sizes.len()
+    ];
+    let (mut x, mut y, mut shelf_height, mut sheet_width) = (0, 0, 0, 0);
+    for i in order {
+        let (width, height) = sizes[i];
+        if width > max_width {
+            return Err(WebpConverterError {
+                message: format!(
+                    "A {}x{} image doesn't fit a sprite sheet at most {}px wide, raise --sprite-max-width",
+                    width, height, max_width
+                ),
+                kind: ErrorKind::Other,
+            });
+        }
+        if x > 0 && x + width > max_width {
+            // Next shelf, under the tallest image of this one.
+            y += shelf_height + padding;
+            x = 0;
+            shelf_height = 0;
+        }
+        rects[i] = SpriteRect {
+            x,
+            y,
+            width,
+            height,
+        };
+        sheet_width = sheet_width.max(x + width);
+        shelf_height = shelf_height.max(height);
+        x += width + padding;
+    }
+    Ok((rects, (sheet_width, y + shelf_height)))
+}
+
+// Draws every image at its rectangle on a transparent sheet of `size`.
+pub fn compose(images: &[DynamicImage], rects: &[SpriteRect], size: (u32, u32)) -> RgbaImage {
+    let mut sheet = RgbaImage::new(size.0, size.1);
+    for (image, rect) in images.iter().zip(rects) {
+        imageops::replace(
+            &mut sheet,
+            &image.to_rgba8(),
+            i64::from(rect.x),
+            i64::from(rect.y),
+        );
+    }
+    sheet
+}
@@ -0,0 +1,160 @@
+use crate::converter::reader_limits;
+use crate::types::{ErrorKind, TiffPages, WebpConverterError};
+use image::io::Reader as ImageReader;
+use image::{DynamicImage, ImageFormat};
+use std::collections::HashSet;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+// A corrupt file can chain its pages in a loop, stop after this many.
+const MAX_PAGES: usize = 4096;
+
+/// The pages of a multi-page TIFF, each read on its own so only one is ever decoded at
+/// a time. `TiffDecoder` only ever reads the first page, so a page is handed to it as
+/// the file with its header pointing at that page instead.
+pub struct Pages {
+    path: PathBuf,
+    data: Vec<u8>,
+    layout: Layout,
+    offsets: Vec<u64>,
+}
+
+impl Pages {
+    pub fn open(path: &Path) -> Result<Pages, WebpConverterError> {
+        let data = fs::read(path)?;
+        let layout = Layout::new(&data).ok_or_else(|| WebpConverterError {
+            message: format!("TIFF Error: {:?} has a malformed header", path),
+            kind: ErrorKind::Other,
+        })?;
+        let offsets = layout.page_offsets(&data);
+        Ok(Pages {
+            path: path.to_path_buf(),
+            data,
+            layout,
+            offsets,
+        })
+    }
+
+    /// The numbers, counted from 1, of the `pages` to convert.
+    pub fn numbers(&self, pages: TiffPages) -> Result<Vec<usize>, WebpConverterError> {
+        match pages {
+            TiffPages::All => Ok((1..=self.offsets.len()).collect()),
+            TiffPages::Page(page) if page <= self.offsets.len() => Ok(vec![page]),
+            TiffPages::Page(page) => Err(WebpConverterError {
+                message: format!(
+                    "TIFF Error: {:?} has {} page(s), --tiff-pages {} is past the last",
+                    self.path,
+                    self.offsets.len(),
+                    page
+                ),
+                kind: ErrorKind::Other,
+            }),
+        }
+    }
+
+    /// The width and height of `page`, read from its IFD without decoding it.
+    pub fn dimensions(&mut self, page: usize) -> Result<(u32, u32), WebpConverterError> {
+        Ok(self.reader(page, 0).into_dimensions()?)
+    }
+
+    /// Decodes `page`, letting the decoder allocate what `max_pixels` allows.
+    pub fn decode(
+        &mut self,
+        page: usize,
+        max_pixels: u64,
+    ) -> Result<DynamicImage, WebpConverterError> {
+        Ok(self.reader(page, max_pixels).decode()?)
+    }
+
+    fn reader(&mut self, page: usize, max_pixels: u64) -> ImageReader<Cursor<&[u8]>> {
+        self.layout.point_at(&mut self.data, self.offsets[page - 1]);
+        let mut reader = ImageReader::with_format(Cursor::new(&self.data[..]), ImageFormat::Tiff);
+        reader.limits(reader_limits(max_pixels));
+        reader
+    }
+}
+
+// Where the offsets are in a classic or a BigTIFF file, and their byte order.
+struct Layout {
+    little_endian: bool,
+    big_tiff: bool,
+}
+
+impl Layout {
+    fn new(data: &[u8]) -> Option<Layout> {
+        let little_endian = match data.get(..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        let mut layout = Layout {
+            little_endian,
+            big_tiff: false,
+        };
+        match layout.read(data, 2, 2)? {
+            42 => {}
+            43 => layout.big_tiff = true,
+            _ => return None,
+        }
+        Some(layout)
+    }
+
+    // The unsigned integer of `len` bytes at `at`.
+    fn read(&self, data: &[u8], at: usize, len: usize) -> Option<u64> {
+        let bytes = data.get(at..at.checked_add(len)?)?;
+        let fold = |value: u64, byte: &u8| value << 8 | *byte as u64;
+        Some(if self.little_endian {
+            bytes.iter().rev().fold(0, fold)
+        } else {
+            bytes.iter().fold(0, fold)
+        })
+    }
+
+    // The header offset of the first page, and the size of an offset.
+    fn first_page_field(&self) -> (usize, usize) {
+        if self.big_tiff {
+            (8, 8)
+        } else {
+            (4, 4)
+        }
+    }
+
+    // The offsets of every page, following the chain from the header.
+    fn page_offsets(&self, data: &[u8]) -> Vec<u64> {
+        let (at, len) = self.first_page_field();
+        let (count_len, entry_len) = if self.big_tiff { (8, 20) } else { (2, 12) };
+
+        let mut offsets = vec![];
+        let mut seen = HashSet::new();
+        let mut next = self.read(data, at, len);
+        while let Some(offset) = next.filter(|&offset| offset != 0) {
+            if offsets.len() >= MAX_PAGES || !seen.insert(offset) {
+                break;
+            }
+            let Some(count) = self.read(data, offset as usize, count_len) else {
+                break;
+            };
+            offsets.push(offset);
+            let link = (count as usize)
+                .checked_mul(entry_len)
+                .and_then(|entries| entries.checked_add(offset as usize + count_len));
+            next = link.and_then(|link| self.read(data, link, len));
+        }
+        offsets
+    }
+
+    // Rewrites the header of `data` so the page at `offset` comes first.
+    fn point_at(&self, data: &mut [u8], offset: u64) {
+        let (at, len) = self.first_page_field();
+        let bytes = offset.to_le_bytes();
+        let field = &mut data[at..at + len];
+        if self.little_endian {
+            field.copy_from_slice(&bytes[..len]);
+        } else {
+            for (index, byte) in field.iter_mut().enumerate() {
+                *byte = bytes[len - 1 - index];
+            }
+        }
+    }
+}
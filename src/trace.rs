@@ -0,0 +1,12 @@
+// Helpers for the spans behind the `tracing` feature. Spans themselves are declared with
+// `#[cfg_attr(feature = "tracing", tracing::instrument(...))]` on the functions they time;
+// fields only known once the work is done are filled in here, and vanish without the feature.
+
+// Sets `field`, declared `tracing::field::Empty`, on the current span.
+#[cfg(feature = "tracing")]
+pub(crate) fn record(field: &str, value: u64) {
+    tracing::Span::current().record(field, value);
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn record(_field: &str, _value: u64) {}
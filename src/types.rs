@@ -0,0 +1,1238 @@
+use crate::cache::Cache;
+use crate::encoder::{Channels, Chroma, EncodeConfig, OutputFormat};
+use crate::helpers;
+use crate::wio::{self, Manifest, S3Target, WrittenFiles};
+use colored::Colorize;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinError;
+use webp::WebPMemory;
+
+#[derive(Debug, Clone)]
+pub struct WebpConverterError {
+    pub(crate) message: String,
+    pub(crate) kind: ErrorKind,
+}
+
+// What went wrong, for the errors a batch handles differently from a plain failure. Set
+// where the error is made, so it survives callers adding context to the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ErrorKind {
+    Other,
+    Timeout,
+    ResizeRefused,
+    DiskFull,
+    BrokenSource,
+    // The decoder ran out of data or hit data it couldn't make sense of.
+    Undecodable,
+}
+
+impl From<image::ImageError> for WebpConverterError {
+    fn from(error: image::ImageError) -> Self {
+        // Limits and unsupported features aren't about the file being damaged.
+        let kind = match &error {
+            image::ImageError::Decoding(_) => ErrorKind::Undecodable,
+            image::ImageError::IoError(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                ErrorKind::Undecodable
+            }
+            _ => ErrorKind::Other,
+        };
+        WebpConverterError {
+            message: format!("Image Error: {:?}", error),
+            kind,
+        }
+    }
+}
+
+impl From<io::Error> for WebpConverterError {
+    fn from(error: io::Error) -> Self {
+        if matches!(
+            error.kind(),
+            io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded
+        ) {
+            return WebpConverterError {
+                message: format!("{}: {}", DISK_FULL_MESSAGE, error),
+                kind: ErrorKind::DiskFull,
+            };
+        }
+        WebpConverterError {
+            message: format!("IO Error: {:?}", error),
+            kind: ErrorKind::Other,
+        }
+    }
+}
+
+impl From<webp::WebPEncodingError> for WebpConverterError {
+    fn from(error: webp::WebPEncodingError) -> Self {
+        WebpConverterError {
+            message: format!("WebP Encoding Error: {:?}", error),
+            kind: ErrorKind::Other,
+        }
+    }
+}
+
+impl Display for WebpConverterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", format!("Error: {}", self.message).red().bold())
+    }
+}
+
+impl From<Result<PathBuf, WebpConverterError>> for WebpConverterError {
+    fn from(error: Result<PathBuf, WebpConverterError>) -> Self {
+        match error {
+            Ok(_) => WebpConverterError {
+                message: "Unknown Error".to_string(),
+                kind: ErrorKind::Other,
+            },
+            Err(e) => e,
+        }
+    }
+}
+
+impl From<Result<WebPMemory, WebpConverterError>> for WebpConverterError {
+    fn from(error: Result<WebPMemory, WebpConverterError>) -> Self {
+        match error {
+            Ok(_) => WebpConverterError {
+                message: "Unknown Error".to_string(),
+                kind: ErrorKind::Other,
+            },
+            Err(e) => e,
+        }
+    }
+}
+
+const TIMEOUT_MESSAGE: &str = "Encoding timed out";
+const RESIZE_REFUSED_MESSAGE: &str = "Refusing to resize";
+const DISK_FULL_MESSAGE: &str = "The output disk is full";
+const EMPTY_SOURCE_MESSAGE: &str = "The source is empty";
+const TRUNCATED_SOURCE_MESSAGE: &str = "The source is truncated";
+
+impl WebpConverterError {
+    pub(crate) fn timed_out(timeout: Duration) -> Self {
+        WebpConverterError {
+            message: format!("{} after {:?}", TIMEOUT_MESSAGE, timeout),
+            kind: ErrorKind::Timeout,
+        }
+    }
+
+    // A timed out encode is not retried, it would most likely stall again.
+    pub fn is_timeout(&self) -> bool {
+        self.kind == ErrorKind::Timeout
+    }
+
+    pub(crate) fn resize_refused(
+        name: &Path,
+        from: (u32, u32),
+        to: (u32, u32),
+        factor: f64,
+        max_factor: f64,
+    ) -> Self {
+        WebpConverterError {
+            message: format!(
+                "{} {:?}: {}x{} -> {}x{} is a {:.1}x downscale, more than --max-resize-factor {}",
+                RESIZE_REFUSED_MESSAGE,
+                name.file_name().unwrap_or_default(),
+                from.0,
+                from.1,
+                to.0,
+                to.1,
+                factor,
+                max_factor
+            ),
+            kind: ErrorKind::ResizeRefused,
+        }
+    }
+
+    // A downscale over `--max-resize-factor` skips the file; the retry without `-s`
+    // would silently write it at full size instead.
+    pub fn is_resize_refused(&self) -> bool {
+        self.kind == ErrorKind::ResizeRefused
+    }
+
+    // Out of space (or quota) on the output disk: every write after it would fail the
+    // same way, so a batch stops instead of trying the rest.
+    pub fn is_disk_full(&self) -> bool {
+        self.kind == ErrorKind::DiskFull
+    }
+
+    pub(crate) fn empty_source() -> Self {
+        WebpConverterError {
+            message: format!("{} (0 bytes)", EMPTY_SOURCE_MESSAGE),
+            kind: ErrorKind::BrokenSource,
+        }
+    }
+
+    // The header read fine but the pixels didn't (an `Undecodable` error): the file was
+    // cut off (an interrupted copy or download) or damaged past its header.
+    pub(crate) fn truncated_source(dimensions: (u32, u32), error: WebpConverterError) -> Self {
+        WebpConverterError {
+            message: format!(
+                "{}: its header says {}x{} but the pixels don't decode: {}",
+                TRUNCATED_SOURCE_MESSAGE, dimensions.0, dimensions.1, error.message
+            ),
+            kind: ErrorKind::BrokenSource,
+        }
+    }
+
+    // An empty or truncated source: nothing an encoder setting can fix, so it isn't
+    // retried and a batch sets it aside instead of counting it as failed.
+    pub fn is_broken_source(&self) -> bool {
+        self.kind == ErrorKind::BrokenSource
+    }
+}
+
+impl From<JoinError> for WebpConverterError {
+    fn from(error: JoinError) -> Self {
+        WebpConverterError {
+            message: format!("Join Error: {:?}", error),
+            kind: ErrorKind::Other,
+        }
+    }
+}
+
+/// Settings handed to the WebP encoder for a single image.
+#[derive(Debug, Clone, Copy)]
+pub struct EncodeOptions {
+    pub quality: f32,
+    pub lossless: i32,
+    pub noise_ratio: f32,
+    pub target_size: i32,
+    // libwebp's speed/size tradeoff, 0 (fast) to 6 (slowest, smallest).
+    pub method: i32,
+    // Non-zero lets libwebp spread the work over extra threads.
+    pub thread_level: i32,
+    // Give up on the encode after this long (`--timeout`).
+    pub timeout: Option<Duration>,
+    // The image was flattened by `--no-alpha`, no alpha plane is needed.
+    pub no_alpha: bool,
+    // RGB to YUV conversion for lossy WebP, see `--chroma`.
+    pub chroma: Chroma,
+    // The bounds of libwebp's quality search, see `--min-quality` and `--max-quality`.
+    pub min_quality: f32,
+    pub max_quality: f32,
+    // A region encoded again at `roi_quality` over the rest of the image, see `--roi`.
+    pub roi: Option<Roi>,
+    pub roi_quality: f32,
+    // Encode with these channels whatever the image has, see `--force-rgb`.
+    pub channels: Option<Channels>,
+    // libwebp settings from `--encode-config`, applied over everything above.
+    pub advanced: Option<EncodeConfig>,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            quality: 75.0,
+            lossless: 0,
+            noise_ratio: 40.0,
+            target_size: 0,
+            method: DEFAULT_EFFORT,
+            thread_level: 1,
+            timeout: None,
+            no_alpha: false,
+            chroma: Chroma::default(),
+            min_quality: 0.0,
+            max_quality: 100.0,
+            roi: None,
+            roi_quality: DEFAULT_ROI_QUALITY,
+            channels: None,
+            advanced: None,
+        }
+    }
+}
+
+// Slowest and smallest, what the converter always used before `--effort`.
+pub const DEFAULT_EFFORT: i32 = 6;
+
+// Images above this many pixels are refused unless `--max-pixels` says otherwise.
+pub const DEFAULT_MAX_PIXELS: u64 = 100_000_000;
+
+/// Default `--roi-quality`, high enough to keep a product's texture and edges.
+pub const DEFAULT_ROI_QUALITY: f32 = 90.0;
+
+/// Default `--sprite-max-width`, a texture size every GPU handles.
+pub const DEFAULT_SPRITE_MAX_WIDTH: u32 = 4096;
+
+/// How many images may be processed at the same time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Jobs {
+    // Derived from the core count and the memory currently available.
+    Auto,
+    Fixed(usize),
+}
+
+impl FromStr for Jobs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Jobs::Auto);
+        }
+        match s.parse::<usize>() {
+            Ok(0) | Err(_) => Err(format!(
+                "expected `auto` or a positive number of jobs, got `{}`",
+                s
+            )),
+            Ok(n) => Ok(Jobs::Fixed(n)),
+        }
+    }
+}
+
+/// The pages of a multi-page TIFF to convert, see `--tiff-pages`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TiffPages {
+    // Every page, each to its own output.
+    All,
+    // One page, counted from 1.
+    Page(usize),
+}
+
+impl FromStr for TiffPages {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("all") {
+            return Ok(TiffPages::All);
+        }
+        match s.parse::<usize>() {
+            Ok(0) | Err(_) => Err(format!(
+                "expected `all` or a page number from 1, got `{}`",
+                s
+            )),
+            Ok(page) => Ok(TiffPages::Page(page)),
+        }
+    }
+}
+
+impl Display for TiffPages {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TiffPages::All => write!(f, "all"),
+            TiffPages::Page(page) => write!(f, "{}", page),
+        }
+    }
+}
+
+/// Per-extension quality overrides, parsed from `jpg=85,png=90`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QualityMap(pub HashMap<String, f32>);
+
+impl FromStr for QualityMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut map = HashMap::new();
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (extension, quality) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected `extension=quality`, got `{}`", pair))?;
+            let quality: f32 = quality
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid quality `{}` for `{}`", quality, extension))?;
+            if !(0.0..=100.0).contains(&quality) {
+                return Err(format!(
+                    "quality for `{}` must be between 0 and 100, got {}",
+                    extension, quality
+                ));
+            }
+            let extension = extension
+                .trim()
+                .trim_start_matches('.')
+                .to_ascii_lowercase();
+            map.insert(extension, quality);
+        }
+        Ok(QualityMap(map))
+    }
+}
+
+/// A rectangle cut out of the image before anything else is done to it, written
+/// `WIDTHxHEIGHT+X+Y` with X and Y from the top left corner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crop {
+    pub width: u32,
+    pub height: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl FromStr for Crop {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected `WIDTHxHEIGHT+X+Y`, got `{}`", s);
+        let (size, offset) = s.trim().split_once('+').ok_or_else(invalid)?;
+        let (width, height) = size.split_once(['x', 'X']).ok_or_else(invalid)?;
+        let (x, y) = offset.split_once('+').ok_or_else(invalid)?;
+        let number = |n: &str| n.parse::<u32>().map_err(|_| invalid());
+        let crop = Crop {
+            width: number(width)?,
+            height: number(height)?,
+            x: number(x)?,
+            y: number(y)?,
+        };
+        if crop.width == 0 || crop.height == 0 {
+            return Err(format!("a crop can't be empty, got `{}`", s));
+        }
+        Ok(crop)
+    }
+}
+
+impl Display for Crop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}+{}+{}", self.width, self.height, self.x, self.y)
+    }
+}
+
+/// The region `--roi` encodes at a higher quality, written `X,Y,WIDTH,HEIGHT` in the
+/// pixels of the image after `--crop`, `--rotate` and `--flip`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Roi {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Roi {
+    pub fn fits(&self, (width, height): (u32, u32)) -> bool {
+        let fits = |offset: u32, size: u32, limit: u32| {
+            offset.checked_add(size).is_some_and(|end| end <= limit)
+        };
+        fits(self.x, self.width, width) && fits(self.y, self.height, height)
+    }
+
+    // The same region once an image of `from` pixels is resized to `to`. It covers at
+    // least one pixel and stays inside `to`.
+    pub fn scaled(&self, from: (u32, u32), to: (u32, u32)) -> Roi {
+        if from == to {
+            return *self;
+        }
+        let scale = |value: u32, from: u32, to: u32| {
+            (value as f64 * to as f64 / from as f64).round() as u32
+        };
+        let x = scale(self.x, from.0, to.0).min(to.0.saturating_sub(1));
+        let y = scale(self.y, from.1, to.1).min(to.1.saturating_sub(1));
+        Roi {
+            x,
+            y,
+            width: scale(self.width, from.0, to.0).clamp(1, to.0 - x),
+            height: scale(self.height, from.1, to.1).clamp(1, to.1 - y),
+        }
+    }
+}
+
+impl FromStr for Roi {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected `X,Y,WIDTH,HEIGHT`, got `{}`", s);
+        let numbers = s
+            .split(',')
+            .map(|n| n.trim().parse::<u32>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let [x, y, width, height] = numbers[..] else {
+            return Err(invalid());
+        };
+        if width == 0 || height == 0 {
+            return Err(format!("a region can't be empty, got `{}`", s));
+        }
+        Ok(Roi {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+}
+
+impl Display for Roi {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{},{},{},{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+/// Opaque color transparent pixels are composited over with `--no-alpha`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Background(pub [u8; 3]);
+
+impl Default for Background {
+    fn default() -> Self {
+        Background([255, 255, 255])
+    }
+}
+
+// `white`, `black`, or hex: `#rgb`, `#rrggbb`, with or without the `#`.
+impl FromStr for Background {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.to_ascii_lowercase().as_str() {
+            "white" => return Ok(Background([255, 255, 255])),
+            "black" => return Ok(Background([0, 0, 0])),
+            _ => {}
+        }
+        let hex = s.trim_start_matches('#');
+        let invalid = || {
+            format!(
+                "expected `white`, `black` or a hex color like `#ffcc00`, got `{}`",
+                s
+            )
+        };
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let channel = |digits: &str| u8::from_str_radix(digits, 16).map_err(|_| invalid());
+        match hex.len() {
+            3 => {
+                let mut rgb = [0; 3];
+                for (i, digit) in hex.chars().enumerate() {
+                    rgb[i] = channel(&digit.to_string().repeat(2))?;
+                }
+                Ok(Background(rgb))
+            }
+            6 => Ok(Background([
+                channel(&hex[0..2])?,
+                channel(&hex[2..4])?,
+                channel(&hex[4..6])?,
+            ])),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Color space images are converted to before encoding, see `--target-colorspace`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorSpace {
+    #[value(name = "srgb")]
+    Srgb,
+}
+
+/// What happens to a source's ICC profile, see `--color-profile`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorProfile {
+    // Copy the profile into the output, like `--keep-icc`.
+    Embed,
+    // Convert the pixels to sRGB and drop the profile, like `--target-colorspace srgb`.
+    Convert,
+    // Drop the profile and leave the pixels alone.
+    Strip,
+}
+
+/// Order files are processed in, see `--sort`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortOrder {
+    // By path, alphabetically.
+    Name,
+    // Largest file first.
+    Size,
+    // Least recently modified first.
+    Mtime,
+}
+
+/// Shape of the end-of-batch summary, see `--report-format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ReportFormat {
+    // The colored `Summary:` log line.
+    #[default]
+    Text,
+    // A JSON object on stdout.
+    Json,
+    // A header and a row of comma separated values on stdout.
+    Csv,
+}
+
+/// What `-s` resizes images to, see `--max-width`, `--max-height`, `--max-dimension`
+/// and `--scale`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResizeTarget {
+    // A box in pixels; a side without a limit can be any length.
+    Box {
+        width: Option<u32>,
+        height: Option<u32>,
+    },
+    // Both sides scaled to this percentage, from above 0 up to 100.
+    Scale(f64),
+}
+
+impl ResizeTarget {
+    // Side of the square box `-s` resizes into when no limit is given.
+    pub const DEFAULT_SIDE: u32 = 700;
+
+    pub fn square(side: u32) -> Self {
+        ResizeTarget::Box {
+            width: Some(side),
+            height: Some(side),
+        }
+    }
+}
+
+impl Default for ResizeTarget {
+    fn default() -> Self {
+        ResizeTarget::square(Self::DEFAULT_SIDE)
+    }
+}
+
+impl Display for ResizeTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResizeTarget::Box {
+                width: Some(width),
+                height: Some(height),
+            } => write!(f, "{}x{}", width, height),
+            ResizeTarget::Box {
+                width: Some(width),
+                height: None,
+            } => write!(f, "{}px wide", width),
+            ResizeTarget::Box {
+                width: None,
+                height: Some(height),
+            } => write!(f, "{}px high", height),
+            ResizeTarget::Box {
+                width: None,
+                height: None,
+            } => write!(f, "any size"),
+            ResizeTarget::Scale(percent) => write!(f, "{}%", percent),
+        }
+    }
+}
+
+/// How `-s` fits an image to its box (700x700 by default), see `--resize-mode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ResizeMode {
+    // Shrink so the image fits inside the box, keeping the aspect ratio.
+    #[default]
+    Fit,
+    // Scale and center-crop to exactly fill the box.
+    Fill,
+    // Shrink so the longest side is the box's side; same as `Fit` for a square box.
+    ShrinkLongest,
+    // Shrink so the shortest side is the box's side, only when both sides exceed it.
+    ShrinkShortest,
+}
+
+/// Clockwise rotation applied before resizing and encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Rotation {
+    #[value(name = "90")]
+    Rotate90,
+    #[value(name = "180")]
+    Rotate180,
+    #[value(name = "270")]
+    Rotate270,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Flip {
+    // Mirror left to right.
+    #[value(name = "h")]
+    Horizontal,
+    // Mirror top to bottom.
+    #[value(name = "v")]
+    Vertical,
+}
+
+/// Cancels a batch from outside it, e.g. on Ctrl-C: files already converting finish,
+/// the ones that haven't started are skipped and nothing is cleaned up after the run.
+/// Clones share the same state, so a clone kept by the caller cancels the batch its
+/// options were given to.
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Cancellation {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Everything `convert_single_photo` and `convert_images_to_webp` need to know about a run.
+#[derive(Debug, Clone)]
+pub struct ConversionOptions {
+    pub quality: f32,
+    pub lossless: i32,
+    pub compression_factor: f32,
+    pub should_resize: bool,
+    // Policy `should_resize` applies.
+    pub resize_mode: ResizeMode,
+    // What `should_resize` resizes to.
+    pub resize_target: ResizeTarget,
+    pub noise_ratio: f32,
+    // Upper bound on width * height of a source image; 0 disables the check.
+    pub max_pixels: u64,
+    // Fail images over the format's dimension limit instead of shrinking them to it.
+    pub no_auto_fit: bool,
+    pub jobs: Jobs,
+    // Produce byte-identical output for identical input and settings.
+    pub deterministic: bool,
+    // Re-encode inputs in their own format instead of converting them to WebP.
+    pub reoptimize: bool,
+    // Resolution PDF pages are rendered at before encoding.
+    pub pdf_dpi: f32,
+    // Keep the original when the WebP saves less than this percentage; 0 disables.
+    pub min_savings_percent: f32,
+    // Only replace an existing output when the new encode is smaller than it.
+    pub overwrite_if_smaller: bool,
+    // Overrides `quality` for sources with a matching extension.
+    pub quality_map: QualityMap,
+    // Write every size embedded in an `.ico`/`.cur` instead of only the largest.
+    pub ico_all_sizes: bool,
+    // The pages of multi-page TIFFs to convert; `None` converts the first one.
+    pub tiff_pages: Option<TiffPages>,
+    // Widths of the variants written per image instead of one output, see `--sizes`.
+    pub sizes: Vec<u32>,
+    pub format: OutputFormat,
+    // More formats every image is also encoded to, from the same decode; see `--format`.
+    pub extra_formats: Vec<OutputFormat>,
+    pub rotate: Option<Rotation>,
+    pub flip: Option<Flip>,
+    // Upload outputs to object storage instead of writing them next to the sources.
+    pub output: Option<S3Target>,
+    // Pixels between the images of a `--spritesheet`, and the widest the sheet may get.
+    pub sprite_padding: u32,
+    pub sprite_max_width: u32,
+    // Name of the folder created next to each source for its outputs, see `--output-dir-name`.
+    pub output_dir_name: String,
+    // One folder every output is written to instead of one next to each source, see `--output-dir`.
+    pub output_dir: Option<PathBuf>,
+    // Keep each output in its source's folder relative to the input folder under `output_dir`.
+    pub mirror: bool,
+    // Write outputs next to their sources instead of in an output folder, see `--in-place`.
+    pub in_place: bool,
+    // With `in_place`, delete each source once its output is written.
+    pub replace: bool,
+    // Draw a progress bar while a folder converts, see `--progress`.
+    pub progress: bool,
+    // Skip files whose output is newer than they are, see `--skip-existing`.
+    pub skip_existing: bool,
+    // File recording what each source was converted from and with, see `--cache`. A
+    // relative path is taken from the input folder.
+    pub cache: Option<PathBuf>,
+    // What `cache` held when the run started, plus what it converted since; shared by
+    // every clone of the options.
+    pub cached: Cache,
+    // Directory a batch run started from, so outputs can keep their relative location.
+    pub input_root: Option<PathBuf>,
+    // Name outputs after the hash of their content, see `--hash-names`.
+    pub hash_names: bool,
+    // Where `hash_names` records the names it hands out; shared by every clone of the options.
+    pub manifest: Manifest,
+    // Write a tiny base64 WebP placeholder of every image to a manifest, see `--lqip`.
+    pub lqip: bool,
+    // Where `lqip` records the placeholders, keyed by source path; shared like `manifest`.
+    pub lqip_manifest: Manifest,
+    // The outputs of every format written for a source, when there is more than one;
+    // shared like `manifest`.
+    pub formats_manifest: Manifest,
+    // Local files written during the run, the only ones cleaning up after it may remove.
+    pub written: WrittenFiles,
+    // Stops a batch between files when cancelled, see `Cancellation`.
+    pub cancellation: Cancellation,
+    // Print a histogram of per-file compression ratios to stderr after a batch.
+    pub diff_report: bool,
+    // Log every automatic choice made for each file, see `--explain`.
+    pub explain: bool,
+    // How the end-of-batch summary is written, see `--report-format`.
+    pub report_format: ReportFormat,
+    // Walk folders with the `ignore` crate so `.gitignore`/`.ignore` rules apply.
+    pub respect_ignore: bool,
+    // Folder names the walk doesn't descend into, see `--exclude-dir`.
+    pub exclude_dirs: Vec<String>,
+    // Confirm the run up front and every overwrite of an existing output on stdin.
+    pub interactive: bool,
+    // Skip the per-file overwrite prompts of `interactive`.
+    pub yes: bool,
+    // Per-encode time limit, see `--timeout`.
+    pub timeout: Option<Duration>,
+    // Name same-format outputs with the canonical extension of their kind.
+    pub normalize_extension: bool,
+    // Drop color before encoding, see `apply_transforms`.
+    pub grayscale: bool,
+    // Convert from the source's ICC profile into this space right after decoding.
+    pub target_colorspace: Option<ColorSpace>,
+    // Source metadata copied into WebP outputs, see `--keep-exif`, `--keep-icc`, `--keep-xmp`.
+    pub keep_exif: bool,
+    pub keep_icc: bool,
+    pub keep_xmp: bool,
+    // Also strip the EXIF and XMP of copied WebP files, and count the sources that had
+    // location data, see `--strip-metadata`.
+    pub strip_metadata: bool,
+    // Rotate and flip images as their EXIF orientation says before anything else.
+    pub apply_orientation: bool,
+    // Cut the image down to this rectangle once it is upright, see `--batch-spec`.
+    pub crop: Option<Crop>,
+    // Encode this region at `roi_quality` and the rest at the usual settings, see `--roi`.
+    pub roi: Option<Roi>,
+    pub roi_quality: f32,
+    // Stem of the output file name in place of the source's, see `--batch-spec`.
+    pub output_name: Option<String>,
+    // Pick lossless for images with transparency and lossy for opaque ones, per file.
+    pub auto_format: bool,
+    // libwebp `method`, exposed as `--effort`.
+    pub effort: i32,
+    // How lossy WebP downsamples color, see `--chroma`.
+    pub chroma: Chroma,
+    // Keep libwebp's PSNR or target size search within these qualities.
+    pub min_quality: f32,
+    pub max_quality: f32,
+    // Stop after this many convertible files, see `--sample`.
+    pub sample: Option<usize>,
+    // Stop starting files once this many were encoded, see `--limit`.
+    pub limit: Option<usize>,
+    // Sort the walked files before dispatching them, see `--sort`.
+    pub sort: Option<SortOrder>,
+    // Skip files `-s` would shrink by more than this factor, see `--max-resize-factor`.
+    pub max_resize_factor: Option<f64>,
+    // Log images `-s` leaves alone as warnings rather than info, see `--warn-on-no-resize`.
+    pub warn_on_no_resize: bool,
+    // Skip files last modified longer ago than this, see `--since`.
+    pub since: Option<Duration>,
+    // libwebp `quality` for lossless encodes, which there trades encode time for size.
+    pub lossless_effort: Option<f32>,
+    // Decode each output after writing it, see `--verify-output`.
+    pub verify_output: bool,
+    // libwebp settings from `--encode-config`, overriding the ones derived from the options above.
+    pub encode_config: Option<EncodeConfig>,
+    // Copy files that aren't images into the output tree, see `--copy-others`.
+    pub copy_others: bool,
+    // Remove outputs whose source is gone before a folder run, see `--clean-output`.
+    pub clean_output: bool,
+    // Where a batch moves empty or truncated sources to, instead of only skipping them.
+    pub quarantine: Option<PathBuf>,
+    // Archive everything the run wrote to this tar file, `-` for stdout, see `--tar`.
+    pub tar: Option<PathBuf>,
+    // Composite transparent images over `background` and encode them opaque.
+    pub no_alpha: bool,
+    pub background: Background,
+    // Encode every output with these channels, see `--force-rgb` and `--force-rgba`.
+    pub channels: Option<Channels>,
+}
+
+impl Default for ConversionOptions {
+    fn default() -> Self {
+        ConversionOptions {
+            quality: 75.0,
+            lossless: 0,
+            compression_factor: 0.0,
+            should_resize: false,
+            resize_mode: ResizeMode::default(),
+            resize_target: ResizeTarget::default(),
+            noise_ratio: 40.0,
+            max_pixels: DEFAULT_MAX_PIXELS,
+            no_auto_fit: false,
+            jobs: Jobs::Auto,
+            deterministic: false,
+            reoptimize: false,
+            pdf_dpi: 150.0,
+            min_savings_percent: 0.0,
+            overwrite_if_smaller: false,
+            quality_map: QualityMap::default(),
+            ico_all_sizes: false,
+            tiff_pages: None,
+            sizes: vec![],
+            format: OutputFormat::default(),
+            extra_formats: vec![],
+            rotate: None,
+            flip: None,
+            output: None,
+            sprite_padding: 0,
+            sprite_max_width: DEFAULT_SPRITE_MAX_WIDTH,
+            output_dir_name: wio::OUTPUT_DIRECTORY_NAME.to_string(),
+            output_dir: None,
+            mirror: false,
+            in_place: false,
+            replace: false,
+            progress: false,
+            skip_existing: false,
+            cache: None,
+            cached: Cache::default(),
+            input_root: None,
+            hash_names: false,
+            manifest: Manifest::default(),
+            lqip: false,
+            lqip_manifest: Manifest::default(),
+            formats_manifest: Manifest::default(),
+            written: WrittenFiles::default(),
+            cancellation: Cancellation::default(),
+            diff_report: false,
+            explain: false,
+            report_format: ReportFormat::default(),
+            respect_ignore: false,
+            exclude_dirs: Vec::new(),
+            interactive: false,
+            yes: false,
+            timeout: None,
+            normalize_extension: false,
+            grayscale: false,
+            target_colorspace: None,
+            keep_exif: false,
+            keep_icc: false,
+            keep_xmp: false,
+            strip_metadata: false,
+            apply_orientation: true,
+            crop: None,
+            roi: None,
+            roi_quality: DEFAULT_ROI_QUALITY,
+            output_name: None,
+            auto_format: false,
+            effort: DEFAULT_EFFORT,
+            chroma: Chroma::default(),
+            min_quality: 0.0,
+            max_quality: 100.0,
+            sample: None,
+            limit: None,
+            sort: None,
+            max_resize_factor: None,
+            warn_on_no_resize: false,
+            since: None,
+            lossless_effort: None,
+            verify_output: false,
+            encode_config: None,
+            copy_others: false,
+            clean_output: false,
+            quarantine: None,
+            tar: None,
+            no_alpha: false,
+            background: Background::default(),
+            channels: None,
+        }
+    }
+}
+
+impl ConversionOptions {
+    // `format` followed by `extra_formats`, every format an image is encoded to.
+    pub fn output_formats(&self) -> impl Iterator<Item = OutputFormat> + '_ {
+        std::iter::once(self.format).chain(self.extra_formats.iter().copied())
+    }
+
+    // The conservative settings retried when a conversion fails with the requested ones.
+    pub(crate) fn fallback(&self) -> Self {
+        ConversionOptions {
+            quality: 75.0,
+            lossless: 0,
+            compression_factor: 0.0,
+            should_resize: false,
+            noise_ratio: 40.0,
+            auto_format: false,
+            encode_config: None,
+            ..self.clone()
+        }
+    }
+
+    // The quality for `path`, from `quality_map` when its extension, or the canonical
+    // extension it is an alias of (`jpg` for `.jpeg`), has an entry.
+    pub fn quality_for(&self, path: &Path) -> f32 {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        let canonical = helpers::image_kind(path).map(|kind| kind.canonical_extension());
+        extension
+            .as_deref()
+            .into_iter()
+            .chain(canonical)
+            .find_map(|e| self.quality_map.0.get(e))
+            .copied()
+            .unwrap_or(self.quality)
+    }
+
+    // What goes into libwebp's `quality` field: visual quality when lossy, but
+    // compression effort when lossless, where `--lossless-effort` takes over.
+    pub fn encoder_quality(&self, path: &Path, lossless: i32) -> f32 {
+        match (lossless, self.lossless_effort) {
+            (1, Some(effort)) => effort,
+            _ => self.quality_for(path),
+        }
+    }
+
+    pub(crate) fn encode_options(&self, target_size: i32) -> EncodeOptions {
+        EncodeOptions {
+            quality: self.quality,
+            lossless: self.lossless,
+            noise_ratio: self.noise_ratio,
+            target_size,
+            method: self.effort,
+            thread_level: if self.deterministic { 0 } else { 1 },
+            timeout: self.timeout,
+            no_alpha: self.no_alpha,
+            chroma: self.chroma,
+            min_quality: self.min_quality,
+            max_quality: self.max_quality,
+            // In the pixels of the image being encoded, set where those are known.
+            roi: None,
+            roi_quality: self.roi_quality,
+            channels: self.channels,
+            advanced: self.encode_config,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConversionStatus {
+    Converted,
+    // The conversion didn't pay off and the original was written to the output instead.
+    KeptOriginal,
+    // A WebP input, copied to the output as it is.
+    Copied,
+    // An output from an earlier run was already smaller and was left alone, see
+    // `--overwrite-if-smaller`.
+    KeptExisting,
+}
+
+/// The encoding `--auto-format` picked for a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoFormat {
+    // Opaque, most likely a photo.
+    Lossy,
+    // Has transparency, most likely a UI asset.
+    Lossless,
+}
+
+/// What happened to a single input file.
+#[derive(Debug, Clone)]
+pub struct ConversionRecord {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub input_size: u64,
+    pub output_size: u64,
+    pub status: ConversionStatus,
+    pub auto_format: Option<AutoFormat>,
+}
+
+impl ConversionRecord {
+    pub fn savings_percent(&self) -> f32 {
+        savings_percent(self.input_size, self.output_size)
+    }
+}
+
+// The one line logged per file, in batch and single file runs alike.
+impl Display for ConversionRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let action = match self.status {
+            ConversionStatus::Converted => "Converted",
+            ConversionStatus::KeptOriginal => "Kept original",
+            ConversionStatus::Copied => "Copied",
+            ConversionStatus::KeptExisting => "Kept existing",
+        };
+        write!(f, "{}: {:?} -> {:?}", action, self.input, self.output)?;
+        if self.status != ConversionStatus::Converted {
+            return write!(f, " ({} bytes)", self.output_size);
+        }
+        write!(
+            f,
+            " ({} -> {} bytes, {:.1}% saved",
+            self.input_size,
+            self.output_size,
+            self.savings_percent()
+        )?;
+        if self.output_size > 0 {
+            write!(
+                f,
+                ", {:.1}x",
+                self.input_size as f64 / self.output_size as f64
+            )?;
+        }
+        write!(f, ")")
+    }
+}
+
+pub(crate) fn savings_percent(input_size: u64, output_size: u64) -> f32 {
+    if input_size == 0 {
+        return 0.0;
+    }
+    (1.0 - output_size as f32 / input_size as f32) * 100.0
+}
+
+/// A file to process, with the metadata the walk read for it. Sorting, filtering and
+/// converting all use these values, so every file is only stat'd once.
+///
+/// Dimensions aren't part of it: they take opening and reading every file's header,
+/// files `--since`, `--sample` or `--limit` drop included, while the decoder reads
+/// them anyway for the files that get converted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileInfo {
+    pub path: PathBuf,
+    pub size: u64,
+    // `None` on file systems that don't record it.
+    pub modified: Option<SystemTime>,
+}
+
+impl FileInfo {
+    pub fn read<P: Into<PathBuf>>(path: P) -> io::Result<FileInfo> {
+        let path = path.into();
+        let metadata = fs::metadata(&path)?;
+        Ok(FileInfo::from_metadata(path, &metadata))
+    }
+
+    pub(crate) fn from_metadata(path: PathBuf, metadata: &fs::Metadata) -> FileInfo {
+        FileInfo {
+            path,
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        }
+    }
+}
+
+/// Totals for a whole batch run.
+#[derive(Debug, Clone, Default)]
+pub struct Summary {
+    // Files found with an extension the converter handles, whatever became of them.
+    pub matched: usize,
+    pub converted: usize,
+    pub kept_original: usize,
+    // Outputs of an earlier run that `--overwrite-if-smaller` didn't replace.
+    pub kept_existing: usize,
+    pub copied: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    // How `--auto-format` encoded the converted files.
+    pub auto_lossy: usize,
+    pub auto_lossless: usize,
+    // Every file that came out with an output, copies included.
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    // Only the files that were encoded (converted, or kept existing because an earlier
+    // encode was smaller), so copies don't water down the savings.
+    pub converted_input_bytes: u64,
+    pub converted_output_bytes: u64,
+    // Copied unchanged: images already in the output format, and other files
+    // `--copy-others` copied.
+    pub copied_image_bytes: u64,
+    pub copied_other_bytes: u64,
+    // Per-file compression ratios (input size / output size), for `--diff-report`.
+    pub ratios: Vec<f64>,
+    // Every file counted in `failed`, with why, listed after the batch.
+    pub failures: Vec<(PathBuf, String)>,
+    // The batch stopped early because the output disk filled up; files that hadn't
+    // started by then are counted in `skipped`.
+    pub disk_full: bool,
+    // The batch was cancelled, see `Cancellation`; files that hadn't started by then
+    // are counted in `skipped` and the output folder was left as it was.
+    pub cancelled: bool,
+    // The `--interactive` plan was declined, so no file was started; `matched` still
+    // counts what would have been converted.
+    pub declined: bool,
+    // The `--limit` of successful files the batch stopped at; files that hadn't started
+    // by then are counted in `skipped`.
+    pub stopped_at_limit: Option<usize>,
+    // Empty or truncated sources, with why, skipped or moved to `--quarantine`; they
+    // aren't counted in `failed`.
+    pub broken: Vec<(PathBuf, String)>,
+    // Sources whose GPS location `--strip-metadata` kept out of their output.
+    pub located: usize,
+}
+
+impl Summary {
+    // Files that were encoded: converted, or kept after the encode lost to the original
+    // or an earlier output. Plain copies aren't counted.
+    pub fn encoded(&self) -> usize {
+        self.converted + self.kept_original + self.kept_existing
+    }
+
+    pub fn fail(&mut self, path: &Path, error: &WebpConverterError) {
+        self.failed += 1;
+        self.failures
+            .push((path.to_path_buf(), error.message.clone()));
+    }
+
+    pub fn record_broken(&mut self, path: &Path, error: &WebpConverterError) {
+        self.broken
+            .push((path.to_path_buf(), error.message.clone()));
+    }
+
+    pub fn record(&mut self, record: &ConversionRecord) {
+        match record.status {
+            ConversionStatus::Converted => self.converted += 1,
+            ConversionStatus::KeptOriginal => self.kept_original += 1,
+            ConversionStatus::Copied => self.copied += 1,
+            ConversionStatus::KeptExisting => self.kept_existing += 1,
+        }
+        match record.auto_format {
+            Some(AutoFormat::Lossy) => self.auto_lossy += 1,
+            Some(AutoFormat::Lossless) => self.auto_lossless += 1,
+            None => {}
+        }
+        self.input_bytes += record.input_size;
+        self.output_bytes += record.output_size;
+        match record.status {
+            ConversionStatus::Converted | ConversionStatus::KeptExisting => {
+                self.converted_input_bytes += record.input_size;
+                self.converted_output_bytes += record.output_size;
+            }
+            ConversionStatus::Copied if helpers::image_kind(&record.input).is_some() => {
+                self.copied_image_bytes += record.output_size;
+            }
+            ConversionStatus::Copied => self.copied_other_bytes += record.output_size,
+            ConversionStatus::KeptOriginal => {}
+        }
+        if record.output_size > 0 && record.status != ConversionStatus::Copied {
+            self.ratios
+                .push(record.input_size as f64 / record.output_size as f64);
+        }
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Converted: {}, kept original (below minimum savings): {}, copied: {}, skipped: {}, failed: {}, {} -> {} bytes converted ({:.1}% saved)",
+            self.converted,
+            self.kept_original,
+            self.copied,
+            self.skipped,
+            self.failed,
+            self.converted_input_bytes,
+            self.converted_output_bytes,
+            savings_percent(self.converted_input_bytes, self.converted_output_bytes)
+        )?;
+        if self.copied_image_bytes > 0 {
+            write!(
+                f,
+                ", {} bytes of images already in the output format copied",
+                self.copied_image_bytes
+            )?;
+        }
+        if self.copied_other_bytes > 0 {
+            write!(
+                f,
+                ", {} bytes of other files copied",
+                self.copied_other_bytes
+            )?;
+        }
+        if self.kept_existing > 0 {
+            write!(
+                f,
+                ", kept existing (already smaller): {}",
+                self.kept_existing
+            )?;
+        }
+        if !self.broken.is_empty() {
+            write!(f, ", broken (empty or truncated): {}", self.broken.len())?;
+        }
+        if self.located > 0 {
+            write!(f, ", location data stripped: {}", self.located)?;
+        }
+        if self.auto_lossy + self.auto_lossless > 0 {
+            write!(
+                f,
+                ", auto-format: {} lossy, {} lossless",
+                self.auto_lossy, self.auto_lossless
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,777 @@
+use crate::encoder::OutputFormat;
+use crate::helpers::{self, ImageKind};
+use crate::metadata;
+use crate::types::{ConversionOptions, ErrorKind, WebpConverterError};
+use clap::ValueEnum;
+use colored::Colorize;
+use log::info;
+#[cfg(feature = "s3")]
+use object_store::{aws::AmazonS3Builder, path::Path as ObjectPath, ObjectStore, PutPayload};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::{fs, io};
+use tokio::io::{AsyncWriteExt, BufWriter};
+
+pub async fn copy_image_to_output_folder(
+    p0: &Path,
+    options: &ConversionOptions,
+) -> Result<PathBuf, WebpConverterError> {
+    let file_name = same_format_file_name(p0, options)?;
+    // With `--in-place` the copy of a file already in the output format would be the file
+    // itself, which `--strip-metadata` still rewrites.
+    if options.in_place
+        && !options.hash_names
+        && !options.strip_metadata
+        && p0.file_name() == Some(file_name.as_os_str())
+    {
+        return Ok(p0.to_path_buf());
+    }
+    let mut bytes = tokio::fs::read(p0).await?;
+    if options.strip_metadata && helpers::image_kind(p0) == Some(ImageKind::Webp) {
+        bytes = metadata::strip(&bytes)?;
+    }
+    let copy_path = write_output(p0, &file_name, &bytes, options).await?;
+
+    info!(
+        "{}",
+        format!("Copying: {:?} to {:?}", file_name, copy_path)
+            .bright_blue()
+            .bold()
+    );
+
+    Ok(copy_path)
+}
+
+// The single place that decides what the output for `source` is called, so converted
+// images and copied ones always land side by side. Conversions pass the new
+// extension, copies pass `None` to keep their file name.
+pub fn output_file_name(source: &Path, extension: Option<&str>) -> io::Result<OsString> {
+    match extension {
+        Some(extension) => source
+            .with_extension(extension)
+            .file_name()
+            .map(|n| n.to_owned()),
+        None => source.file_name().map(|n| n.to_owned()),
+    }
+    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "File not found!"))
+}
+
+// `file_name` for its `--sizes` variant `width` pixels wide: `photo.webp` becomes
+// `photo-640w.webp`.
+pub fn variant_file_name(file_name: &OsStr, width: u32) -> OsString {
+    let path = Path::new(file_name);
+    let stem = path.file_stem().unwrap_or(file_name).to_string_lossy();
+    match path.extension() {
+        Some(extension) => format!("{}-{}w.{}", stem, width, extension.to_string_lossy()),
+        None => format!("{}-{}w", stem, width),
+    }
+    .into()
+}
+
+// `file_name` for page `page` of a multi-page source: `scan.webp` becomes `scan-p2.webp`.
+pub fn page_file_name(file_name: &OsStr, page: usize) -> OsString {
+    let path = Path::new(file_name);
+    let stem = path.file_stem().unwrap_or(file_name).to_string_lossy();
+    match path.extension() {
+        Some(extension) => format!("{}-p{}.{}", stem, page, extension.to_string_lossy()),
+        None => format!("{}-p{}", stem, page),
+    }
+    .into()
+}
+
+// `file_name` with the stem `--batch-spec` chose for this file's output, if it did.
+// The extension stays, so kept originals still get theirs.
+pub fn renamed_output(file_name: OsString, options: &ConversionOptions) -> OsString {
+    let Some(stem) = &options.output_name else {
+        return file_name;
+    };
+    let mut renamed = OsString::from(stem);
+    if let Some(extension) = Path::new(&file_name).extension() {
+        renamed.push(".");
+        renamed.push(extension);
+    }
+    renamed
+}
+
+// Name for an output that keeps the source's format. With `--normalize-extension`,
+// aliases and odd casing (`.JPEG`, `.jpe`) become the canonical extension (`.jpg`).
+pub fn same_format_file_name(source: &Path, options: &ConversionOptions) -> io::Result<OsString> {
+    let canonical = helpers::image_kind(source)
+        .filter(|_| options.normalize_extension)
+        .map(|kind| kind.canonical_extension());
+    output_file_name(source, canonical)
+}
+
+// Local path of the output for `source`, see `output_file_name`.
+pub fn output_path(
+    source: &Path,
+    extension: Option<&str>,
+    options: &ConversionOptions,
+) -> io::Result<PathBuf> {
+    Ok(output_path_named(
+        source,
+        &output_file_name(source, extension)?,
+        options,
+    ))
+}
+
+// Same as `output_path`, for outputs that get a name of their own (e.g. one file per PDF page).
+pub fn output_path_named(source: &Path, file_name: &OsStr, options: &ConversionOptions) -> PathBuf {
+    get_or_create_output_directory(source, options).join(file_name)
+}
+
+// `output_path_named` without creating the output folder, for outputs only looked at.
+pub fn planned_path(source: &Path, file_name: &OsStr, options: &ConversionOptions) -> PathBuf {
+    output_directory(source, options).join(file_name)
+}
+
+// Writes one output produced from `source`, either into the local output folder or,
+// with `--output s3://...`, to object storage. Returns where it ended up.
+// With `--hash-names` the file is named after its content and recorded in the manifest.
+pub async fn write_output(
+    source: &Path,
+    file_name: &OsStr,
+    bytes: &[u8],
+    options: &ConversionOptions,
+) -> Result<PathBuf, WebpConverterError> {
+    if !options.hash_names {
+        return write_output_as(source, file_name, bytes, options).await;
+    }
+    let hashed_name = hashed_file_name(source, file_name, bytes, options);
+    let output = write_output_as(source, &hashed_name, bytes, options).await?;
+    let relative_dir = relative_dir(source, options.input_root.as_deref());
+    options.manifest.record(
+        manifest_key(relative_dir, file_name),
+        manifest_key(relative_dir, &hashed_name),
+    );
+    Ok(output)
+}
+
+// `write_output` under exactly `file_name`.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "write", skip_all, fields(file = ?file_name, bytes = bytes.len()))
+)]
+async fn write_output_as(
+    source: &Path,
+    file_name: &OsStr,
+    bytes: &[u8],
+    options: &ConversionOptions,
+) -> Result<PathBuf, WebpConverterError> {
+    if let Some(target) = &options.output {
+        let key = target.key_for(source, options.input_root.as_deref(), file_name);
+        target.put(&key, bytes).await?;
+        return Ok(PathBuf::from(format!("s3://{}/{}", target.bucket, key)));
+    }
+
+    let output_path = output_path_named(source, file_name, options);
+    let temp_path = temp_path_for(&output_path);
+    if let Err(error) = write_and_rename(&temp_path, &output_path, bytes).await {
+        let _ = tokio::fs::remove_file(&temp_path).await;
+        return Err(error.into());
+    }
+    options.written.record(output_path.clone());
+
+    Ok(output_path)
+}
+
+// Where an output is written before it is renamed into place: `{name}.tmp` next to it,
+// so the rename stays on one file system and can't be seen half done.
+pub fn temp_path_for(output_path: &Path) -> PathBuf {
+    let mut name = output_path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    output_path.with_file_name(name)
+}
+
+// Writes `bytes` to `temp_path`, syncs them to disk and only then renames the file to
+// `output_path`, replacing any earlier output. A reader (a watcher, a server pointed at
+// the output folder) sees the old file or the new one, never a partial write, and a
+// run killed midway leaves at most a stray `.tmp`.
+async fn write_and_rename(temp_path: &Path, output_path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let file = tokio::fs::File::create(temp_path).await?;
+    let mut writer = BufWriter::new(file);
+    if !bytes.is_empty() {
+        writer.write_all(bytes).await?;
+    }
+    writer.flush().await?;
+    writer.into_inner().sync_all().await?;
+    tokio::fs::rename(temp_path, output_path).await
+}
+
+// Removes an output left over from an earlier run, if there is one.
+pub async fn remove_output(
+    source: &Path,
+    file_name: &OsStr,
+    options: &ConversionOptions,
+) -> io::Result<()> {
+    if options.output.is_some() {
+        return Ok(());
+    }
+    let output_path = output_path_named(source, file_name, options);
+    if output_path.exists() {
+        tokio::fs::remove_file(&output_path).await?;
+    }
+    Ok(())
+}
+
+/// An `s3://bucket/prefix` destination for `--output`.
+#[derive(Debug, Clone)]
+pub struct S3Target {
+    pub bucket: String,
+    pub prefix: String,
+    #[cfg(feature = "s3")]
+    store: Arc<tokio::sync::OnceCell<Arc<dyn ObjectStore>>>,
+}
+
+impl FromStr for S3Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let location = s
+            .strip_prefix("s3://")
+            .ok_or_else(|| format!("expected an s3://bucket/prefix URL, got `{}`", s))?;
+        let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+        if bucket.is_empty() {
+            return Err(format!("missing bucket name in `{}`", s));
+        }
+        Ok(S3Target {
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+            #[cfg(feature = "s3")]
+            store: Arc::default(),
+        })
+    }
+}
+
+impl S3Target {
+    // Object key for an output, keeping the source's directory relative to the input root.
+    pub fn key_for(&self, source: &Path, input_root: Option<&Path>, file_name: &OsStr) -> String {
+        let relative_dir = relative_dir(source, input_root);
+        let key = manifest_key(relative_dir, file_name);
+        if self.prefix.is_empty() {
+            key
+        } else {
+            format!("{}/{}", self.prefix, key)
+        }
+    }
+
+    #[cfg(feature = "s3")]
+    async fn put(&self, key: &str, bytes: &[u8]) -> Result<(), WebpConverterError> {
+        // Credentials and region come from the usual AWS_* environment variables.
+        let store = self
+            .store
+            .get_or_try_init(|| async {
+                AmazonS3Builder::from_env()
+                    .with_bucket_name(&self.bucket)
+                    .build()
+                    .map(|s3| Arc::new(s3) as Arc<dyn ObjectStore>)
+            })
+            .await?;
+        store
+            .put(&ObjectPath::from(key), PutPayload::from(bytes.to_vec()))
+            .await?;
+        Ok(())
+    }
+
+    #[cfg(not(feature = "s3"))]
+    async fn put(&self, _key: &str, _bytes: &[u8]) -> Result<(), WebpConverterError> {
+        Err(WebpConverterError {
+            message: "Uploading to S3 requires building with `--features s3`".to_string(),
+            kind: ErrorKind::Other,
+        })
+    }
+}
+
+#[cfg(feature = "s3")]
+impl From<object_store::Error> for WebpConverterError {
+    fn from(error: object_store::Error) -> Self {
+        WebpConverterError {
+            message: format!("Object Storage Error: {}", error),
+            kind: ErrorKind::Other,
+        }
+    }
+}
+
+/// An image downloaded for `-p https://...`.
+#[derive(Debug, Clone)]
+pub struct RemoteImage {
+    pub bytes: Vec<u8>,
+    // Last segment of the URL after redirects, with an extension matching the content.
+    pub file_name: String,
+}
+
+// Downloads `url`, following redirects, and refuses responses that aren't images
+// (an HTML error page served with 200 is the usual one).
+#[cfg(feature = "remote-input")]
+pub async fn fetch_image(url: &str) -> Result<RemoteImage, WebpConverterError> {
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::limited(10))
+        .timeout(std::time::Duration::from_secs(60))
+        .build()?;
+    let response = client.get(url).send().await?.error_for_status()?;
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            value
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase()
+        });
+    let final_url = response.url().clone();
+    let bytes = response.bytes().await?.to_vec();
+
+    let detected = image::guess_format(&bytes).ok();
+    match content_type.as_deref() {
+        Some(content_type) if content_type.starts_with("image/") => {}
+        // Servers that don't know better send files as octet-stream, trust the content then.
+        None | Some("application/octet-stream") if detected.is_some() => {}
+        content_type => {
+            return Err(WebpConverterError {
+                message: format!(
+                    "{} returned {}, not an image",
+                    url,
+                    content_type.unwrap_or("no content type")
+                ),
+                kind: ErrorKind::Other,
+            })
+        }
+    }
+
+    let mut file_name = final_url
+        .path_segments()
+        .and_then(|segments| segments.rev().find(|s| !s.is_empty()))
+        .unwrap_or("image")
+        .to_string();
+    if helpers::image_kind(Path::new(&file_name)).is_none() {
+        if let Some(extension) = detected.and_then(|format| format.extensions_str().first()) {
+            file_name = format!("{}.{}", file_name, extension);
+        }
+    }
+    Ok(RemoteImage { bytes, file_name })
+}
+
+#[cfg(not(feature = "remote-input"))]
+pub async fn fetch_image(_url: &str) -> Result<RemoteImage, WebpConverterError> {
+    Err(WebpConverterError {
+        message: "Converting a URL requires building with `--features remote-input`".to_string(),
+        kind: ErrorKind::Other,
+    })
+}
+
+// Folder of `source` relative to the directory the run started from.
+fn relative_dir<'a>(source: &'a Path, input_root: Option<&Path>) -> &'a Path {
+    match (source.parent(), input_root) {
+        (Some(parent), Some(root)) => parent.strip_prefix(root).unwrap_or(Path::new("")),
+        _ => Path::new(""),
+    }
+}
+
+// `dir/file_name` with `/` separators on every platform, as used for object keys
+// and manifest entries.
+fn manifest_key(relative_dir: &Path, file_name: &OsStr) -> String {
+    relative_dir
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .chain(std::iter::once(file_name.to_string_lossy().to_string()))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// Hex digits of the SHA-256 of the content used in `--hash-names` file names.
+const HASH_NAME_LENGTH: usize = 16;
+
+// `{hash}.{extension}` for `bytes`. If a different file already has that name, the
+// hash is lengthened until the name is free, so the same content always ends up with
+// the same name and different content never overwrites it.
+fn hashed_file_name(
+    source: &Path,
+    file_name: &OsStr,
+    bytes: &[u8],
+    options: &ConversionOptions,
+) -> OsString {
+    let hash: String = Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let extension = Path::new(file_name)
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut length = HASH_NAME_LENGTH;
+    loop {
+        let name = OsString::from(format!("{}{}", &hash[..length], extension));
+        // Object storage isn't checked, a clash in 64 bits of SHA-256 isn't worth a request per file.
+        if length == hash.len() || options.output.is_some() {
+            return name;
+        }
+        match fs::read(output_path_named(source, &name, options)) {
+            Ok(existing) if existing != bytes => length += 8,
+            _ => return name,
+        }
+    }
+}
+
+/// Entries collected during a run and written as a JSON object at the end: the hashed
+/// names of `--hash-names`, the placeholders of `--lqip`, the outputs of each source
+/// with several `--format`s. Clones share the same entries.
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    entries: Arc<Mutex<BTreeMap<String, serde_json::Value>>>,
+}
+
+impl Manifest {
+    pub fn record(&self, key: String, value: String) {
+        self.entries.lock().unwrap().insert(key, value.into());
+    }
+
+    // Adds `value` to the list kept under `key`, in the order they come.
+    pub fn append(&self, key: String, value: String) {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries
+            .entry(key)
+            .or_insert_with(|| Vec::<String>::new().into());
+        if let serde_json::Value::Array(values) = entry {
+            values.push(value.into());
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.lock().unwrap().is_empty()
+    }
+
+    // Sorted by key, so the same run always produces the same file.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&*self.entries.lock().unwrap())
+            .expect("a map of strings always serializes")
+    }
+}
+
+/// The local files a run wrote, in the order they were written. Clones share the same
+/// list, like `Manifest`.
+#[derive(Debug, Clone, Default)]
+pub struct WrittenFiles {
+    paths: Arc<Mutex<Vec<PathBuf>>>,
+}
+
+impl WrittenFiles {
+    pub fn record(&self, path: PathBuf) {
+        self.paths.lock().unwrap().push(path);
+    }
+
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.paths.lock().unwrap().clone()
+    }
+}
+
+/// File the `--hash-names` manifest is written to, in the output folder of the input directory.
+pub const MANIFEST_FILE_NAME: &str = "hash-manifest.json";
+
+/// File the `--lqip` placeholders are written to, next to the hash-names manifest.
+pub const LQIP_MANIFEST_FILE_NAME: &str = "lqip-manifest.json";
+
+/// File listing the outputs of each source when `--format` names several formats.
+pub const FORMATS_MANIFEST_FILE_NAME: &str = "formats-manifest.json";
+
+// The path of `source` relative to the input folder, with `/` separators.
+pub(crate) fn source_key(source: &Path, options: &ConversionOptions) -> String {
+    output_key(source, source.file_name().unwrap_or_default(), options)
+}
+
+// The output `file_name` of `source` as the manifests list it: in the source's folder
+// relative to the input folder, like the output folders mirror it.
+pub(crate) fn output_key(source: &Path, file_name: &OsStr, options: &ConversionOptions) -> String {
+    let relative_dir = relative_dir(source, options.input_root.as_deref());
+    manifest_key(relative_dir, file_name)
+}
+
+// Records the `--lqip` placeholder of `source`, keyed by its path relative to the input folder.
+pub(crate) fn record_lqip(source: &Path, data_uri: String, options: &ConversionOptions) {
+    options
+        .lqip_manifest
+        .record(source_key(source, options), data_uri);
+}
+
+// Writes the manifests of a run started in `dir` (`--hash-names`, `--lqip`, several
+// `--format`s) next to the outputs of the files directly in it, or at the root of
+// `--output`. A manifest without entries isn't written.
+pub async fn write_manifests(
+    dir: &Path,
+    options: &ConversionOptions,
+) -> Result<(), WebpConverterError> {
+    let manifests = [
+        (&options.manifest, MANIFEST_FILE_NAME, "hash-names manifest"),
+        (
+            &options.lqip_manifest,
+            LQIP_MANIFEST_FILE_NAME,
+            "LQIP placeholders",
+        ),
+        (
+            &options.formats_manifest,
+            FORMATS_MANIFEST_FILE_NAME,
+            "outputs of each format",
+        ),
+    ];
+    for (manifest, file_name, description) in manifests {
+        if manifest.is_empty() {
+            continue;
+        }
+        let json = manifest.to_json();
+        let path = write_output_as(
+            &dir.join(file_name),
+            OsStr::new(file_name),
+            json.as_bytes(),
+            options,
+        )
+        .await?;
+        info!(
+            "{}",
+            format!("Wrote the {} to {:?}", description, path)
+                .bright_blue()
+                .bold()
+        );
+    }
+    Ok(())
+}
+
+// Writes every local file the run wrote (outputs and manifests) to a tar archive at
+// `dest`, or to stdout when it is `-`, for `--tar`. Entries are named by their source's
+// folder relative to `root`, without the output folder, so extracting the archive
+// gives the input tree with converted files in it. Returns how many were archived.
+pub fn write_tar(dest: &Path, root: &Path, options: &ConversionOptions) -> io::Result<usize> {
+    let mut entries: Vec<(PathBuf, PathBuf)> = options
+        .written
+        .paths()
+        .into_iter()
+        // Removed by the clean-up since, like empty outputs.
+        .filter(|path| path.is_file())
+        .map(|path| (archive_name(&path, root, options), path))
+        .collect();
+    entries.sort();
+    entries.dedup();
+
+    let writer: Box<dyn io::Write> = if dest == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(io::BufWriter::new(fs::File::create(dest)?))
+    };
+    let mut archive = tar::Builder::new(writer);
+    for (name, path) in &entries {
+        archive.append_path_with_name(path, name)?;
+    }
+    archive.into_inner()?.flush()?;
+    Ok(entries.len())
+}
+
+// `output` relative to `root` (or to `--output-dir`), with the output folder left out of it.
+fn archive_name(output: &Path, root: &Path, options: &ConversionOptions) -> PathBuf {
+    let relative = options
+        .output_dir
+        .as_deref()
+        .and_then(|dir| output.strip_prefix(dir).ok())
+        .or_else(|| output.strip_prefix(root).ok())
+        .unwrap_or(output);
+    let name: PathBuf = relative
+        .components()
+        .filter(|c| {
+            matches!(c, std::path::Component::Normal(part) if *part != options.output_dir_name.as_str())
+        })
+        .collect();
+    if name.as_os_str().is_empty() {
+        PathBuf::from(output.file_name().unwrap_or_default())
+    } else {
+        name
+    }
+}
+
+/// Default name of the folder outputs are written to, next to their sources.
+pub const OUTPUT_DIRECTORY_NAME: &str = "webp_converter_output";
+
+// Checks a `--output-dir-name`: it is joined to every source's folder, so it has to be
+// one plain path component, not a path that could lead somewhere else.
+pub fn validate_output_dir_name(name: &str) -> Result<(), String> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(std::path::Component::Normal(component)), None)
+            if component == name && !name.contains(['/', '\\']) =>
+        {
+            Ok(())
+        }
+        _ => Err(format!(
+            "expected a single folder name without separators, got `{}`",
+            name
+        )),
+    }
+}
+
+// Moves a broken `source` into `dir` (`--quarantine`), under the same folder relative to
+// the input root it had, and returns where it went. Falls back to copying and removing
+// it when `dir` is on another file system.
+pub fn quarantine(source: &Path, dir: &Path, options: &ConversionOptions) -> io::Result<PathBuf> {
+    let target_dir = dir.join(relative_dir(source, options.input_root.as_deref()));
+    fs::create_dir_all(&target_dir)?;
+    let target = target_dir.join(source.file_name().unwrap_or_default());
+    if fs::rename(source, &target).is_err() {
+        fs::copy(source, &target)?;
+        fs::remove_file(source)?;
+    }
+    Ok(target)
+}
+
+// The output directory of `path` (`webp_converter_output` by default) inside the original
+// image's directory, or the `--output-dir` every output shares, in the image's folder
+// relative to the input folder with `--mirror`. With `--in-place` it is the image's directory.
+pub fn output_directory(path: &Path, options: &ConversionOptions) -> PathBuf {
+    let parent_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    match &options.output_dir {
+        Some(dir) if options.mirror => dir.join(relative_dir(path, options.input_root.as_deref())),
+        Some(dir) => dir.clone(),
+        None if options.in_place => parent_dir.to_path_buf(),
+        None => parent_dir.join(&options.output_dir_name),
+    }
+}
+
+pub fn get_or_create_output_directory(path: &Path, options: &ConversionOptions) -> PathBuf {
+    let webp_dir = output_directory(path, options);
+    if webp_dir.exists() {
+        webp_dir
+    } else {
+        fs::create_dir_all(&webp_dir).unwrap();
+        webp_dir
+    }
+}
+
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+pub fn make_file_writable<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    let metadata = fs::metadata(path)?;
+    let mut perms = metadata.permissions();
+
+    #[cfg(windows)]
+    {
+        perms.set_readonly(false);
+    }
+
+    #[cfg(unix)]
+    {
+        let mode = perms.mode();
+        let new_mode = mode | 0o200;
+        perms.set_mode(new_mode);
+    }
+
+    fs::set_permissions(path, perms)?;
+    Ok(())
+}
+
+// Removes outputs whose source is gone (`--clean-output`), from the output folder of
+// `root` and, when `recursive`, of every folder under it that the walk would visit.
+// Only files with an extension the converter encodes to are looked at, and one is only
+// stale when no file next to its output folder could have produced it: `photo.webp`
+// stays while there is a `photo.*`, `scan-p2.webp` while there is a `scan.*` (PDF
+// pages) and `app-32x32.webp` while there is an `app.*` (icon sizes). Returns the
+// removed paths.
+pub fn clean_output(
+    root: &Path,
+    recursive: bool,
+    options: &ConversionOptions,
+) -> io::Result<Vec<PathBuf>> {
+    let extensions: Vec<&str> = OutputFormat::value_variants()
+        .iter()
+        .map(|format| format.extension())
+        .collect();
+    let folders = walkdir::WalkDir::new(root)
+        .max_depth(if recursive { usize::MAX } else { 0 })
+        .into_iter()
+        .filter_entry(|e| {
+            e.depth() == 0
+                || (e.file_type().is_dir()
+                    && e.file_name() != options.output_dir_name.as_str()
+                    && !options
+                        .exclude_dirs
+                        .iter()
+                        .any(|dir| e.file_name() == dir.as_str()))
+        })
+        .filter_map(|e| e.ok());
+
+    let mut removed = vec![];
+    for folder in folders {
+        let output_dir = folder.path().join(&options.output_dir_name);
+        if !output_dir.is_dir() {
+            continue;
+        }
+        let mut sources = HashSet::new();
+        for entry in fs::read_dir(folder.path())? {
+            let path = entry?.path();
+            if path.is_file() {
+                sources.insert(path.file_stem().unwrap_or_default().to_owned());
+            }
+        }
+        for entry in fs::read_dir(&output_dir)? {
+            let path = entry?.path();
+            let ours = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| extensions.contains(&e.to_ascii_lowercase().as_str()));
+            if !ours || !path.is_file() || has_source(&path, &sources) {
+                continue;
+            }
+            fs::remove_file(&path)?;
+            info!(
+                "{}",
+                format!("Removed {:?}, its source is gone", path).yellow()
+            );
+            removed.push(path);
+        }
+    }
+    Ok(removed)
+}
+
+// Whether one of the `sources` stems could have produced `output`.
+fn has_source(output: &Path, sources: &HashSet<OsString>) -> bool {
+    let Some(stem) = output.file_stem().and_then(|s| s.to_str()) else {
+        // Not UTF-8, so not something to delete on a guess.
+        return true;
+    };
+    if sources.contains(OsStr::new(stem)) {
+        return true;
+    }
+    // `{stem}-p{page}` for PDF and TIFF pages, `{stem}-{width}x{height}` for icon sizes,
+    // `{stem}-{width}w` for `--sizes`.
+    let Some((source, suffix)) = stem.rsplit_once('-') else {
+        return false;
+    };
+    let numbers = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let is_page = suffix.strip_prefix('p').is_some_and(numbers);
+    let is_size = suffix
+        .split_once('x')
+        .is_some_and(|(width, height)| numbers(width) && numbers(height));
+    let is_width = suffix.strip_suffix('w').is_some_and(numbers);
+    (is_page || is_size || is_width) && sources.contains(OsStr::new(source))
+}
+
+// Removes the empty outputs a finished run left, e.g. copies of zero-byte sources. Only
+// the files the run wrote itself are looked at: anything else in an output folder,
+// empty or not, belongs to someone else and is kept.
+pub fn cleanup(options: &ConversionOptions) -> io::Result<()> {
+    for path in options.written.paths() {
+        match fs::metadata(&path) {
+            Ok(metadata) if metadata.is_file() && metadata.len() == 0 => {
+                fs::remove_file(&path)?;
+            }
+            // Already moved or removed since, there's nothing left to clean.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
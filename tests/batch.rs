@@ -0,0 +1,149 @@
+mod common;
+
+use common::{assert_webp, output_of, workspace};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use webp_converter::batch::{self, BatchEntry};
+use webp_converter::builder::Converter;
+use webp_converter::types::{Crop, Flip, Rotation};
+
+#[test]
+fn rows_are_parsed_with_their_line_and_overrides() {
+    let spec = "path,quality,resize,crop,rotate,flip,output\n\
+                a.jpg,80,,,,,\n\
+                \n\
+                \"b, the second.png\",,true,30x20+5+6,90,h,thumb\n";
+
+    let entries = batch::parse(spec, Path::new("images")).unwrap();
+
+    assert_eq!(
+        entries,
+        vec![
+            BatchEntry {
+                line: 2,
+                path: Path::new("images").join("a.jpg"),
+                quality: Some(80.0),
+                ..BatchEntry::default()
+            },
+            BatchEntry {
+                line: 4,
+                path: Path::new("images").join("b, the second.png"),
+                resize: Some(true),
+                crop: Some(Crop {
+                    width: 30,
+                    height: 20,
+                    x: 5,
+                    y: 6,
+                }),
+                rotate: Some(Rotation::Rotate90),
+                flip: Some(Flip::Horizontal),
+                output: Some("thumb".to_string()),
+                ..BatchEntry::default()
+            },
+        ]
+    );
+}
+
+#[test]
+fn invalid_specs_name_the_line_of_each_problem() {
+    let error = |spec: &str| batch::parse(spec, Path::new("")).unwrap_err().to_string();
+
+    assert!(error("").contains("line 1"));
+    assert!(error("path,size\na.jpg,3\n").contains("line 1: unknown column `size`"));
+    assert!(error("quality\n80\n").contains("no `path` column"));
+    assert!(error("path,path\na,b\n").contains("given twice"));
+    assert!(error("path\n").contains("lists no files"));
+
+    let message = error(
+        "path,quality,crop,output\n\
+         a.jpg,101,,\n\
+         b.jpg,,10x10,\n\
+         c.jpg,,,../c\n\
+         d.jpg,90\n\
+         ,,,\n",
+    );
+    let lines: Vec<&str> = message.lines().collect();
+    assert_eq!(lines.len(), 5, "{}", message);
+    assert!(lines[0].contains("line 2: `quality`"), "{}", message);
+    assert!(lines[1].contains("line 3: `crop`"), "{}", message);
+    assert!(lines[2].contains("line 4: `output`"), "{}", message);
+    assert!(
+        lines[3].contains("line 5: expected 4 values"),
+        "{}",
+        message
+    );
+    assert!(lines[4].contains("line 6: `path` is empty"), "{}", message);
+}
+
+#[tokio::test]
+async fn only_the_listed_files_are_converted_with_their_settings() {
+    let (_dir, input) = workspace(&[
+        ("opaque.jpg", "opaque.jpg"),
+        ("transparent.png", "icons/transparent.png"),
+        ("gray.tiff", "gray.tiff"),
+    ]);
+    let spec = input.join("spec.csv");
+    fs::write(
+        &spec,
+        "path,crop,rotate,output\n\
+         opaque.jpg,50x40+100+70,,thumb\n\
+         icons/transparent.png,,,\n\
+         opaque.jpg,,90,\n\
+         missing.jpg,,,\n",
+    )
+    .unwrap();
+    let entries = batch::read(&spec).unwrap();
+
+    let summary = Converter::builder()
+        .build()
+        .unwrap()
+        .convert_batch(&input, entries)
+        .await;
+
+    assert_eq!((summary.matched, summary.converted), (4, 3));
+    assert_eq!(summary.failed, 1);
+    assert!(summary.failures[0].0.ends_with("missing.jpg"));
+    // One source, two rows: each row gets its own output.
+    assert_webp(
+        &output_of(&input.join("opaque.jpg"), "thumb.webp"),
+        (50, 40),
+    );
+    assert_webp(
+        &output_of(&input.join("opaque.jpg"), "opaque.webp"),
+        (120, 160),
+    );
+    assert_webp(
+        &output_of(&input.join("icons/transparent.png"), "transparent.webp"),
+        (96, 96),
+    );
+    assert!(!output_of(&input.join("gray.tiff"), "gray.webp").exists());
+}
+
+#[test]
+fn a_crop_past_the_edge_fails_its_row_and_a_bad_spec_fails_the_run() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    let run = |spec: &str| {
+        fs::write(input.join("spec.csv"), spec).unwrap();
+        Command::new(env!("CARGO_BIN_EXE_webp_converter"))
+            .arg("--batch-spec")
+            .arg(input.join("spec.csv"))
+            .output()
+            .expect("run webp_converter")
+    };
+
+    let output = run("path,crop\nopaque.jpg,100x100+100+0\n");
+    assert_eq!(output.status.code(), Some(1));
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        log.contains("doesn't fit inside the 160x120 image"),
+        "{}",
+        log
+    );
+
+    let output = run("path,quality\nopaque.jpg,high\n");
+    assert_eq!(output.status.code(), Some(1));
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(log.contains("line 2: `quality`"), "{}", log);
+    assert!(!output_of(&input.join("opaque.jpg"), "opaque.webp").exists());
+}
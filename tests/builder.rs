@@ -0,0 +1,272 @@
+mod common;
+
+use common::{assert_webp, output_of, workspace};
+use webp_converter::builder::Converter;
+use webp_converter::encoder::{EncodeConfig, OutputFormat};
+use webp_converter::types::{
+    ColorSpace, ConversionOptions, ReportFormat, ResizeMode, ResizeTarget, TiffPages,
+};
+
+#[test]
+fn defaults_match_the_cli() {
+    let converter = Converter::builder().build().unwrap();
+    let options = converter.options();
+
+    assert_eq!(options.quality, 75.0);
+    assert_eq!(options.lossless, 0);
+    assert_eq!(options.compression_factor, 0.0);
+    assert_eq!(options.noise_ratio, 40.0);
+}
+
+#[test]
+fn lossless_is_resolved_from_what_was_chosen() {
+    // `-l true` alone used to stay lossy behind the default quality of 75.
+    let lossless = Converter::builder().lossless(true).build().unwrap();
+    assert_eq!(lossless.options().lossless, 1);
+
+    let effort = Converter::builder().lossless_effort(30.0).build().unwrap();
+    assert_eq!(effort.options().lossless, 1);
+    assert_eq!(effort.options().lossless_effort, Some(30.0));
+
+    // The old spelling of lossless still works, unless lossy is asked for.
+    let old = Converter::builder().quality(100.0).build().unwrap();
+    assert_eq!(old.options().lossless, 1);
+    let lossy = Converter::builder()
+        .quality(100.0)
+        .lossless(false)
+        .build()
+        .unwrap();
+    assert_eq!(lossy.options().lossless, 0);
+    let sized = Converter::builder()
+        .quality(100.0)
+        .compression_factor(2.0)
+        .build()
+        .unwrap();
+    assert_eq!(sized.options().lossless, 0);
+}
+
+#[test]
+fn setters_survive_the_options_whatever_the_order() {
+    let config = EncodeConfig {
+        filter_strength: Some(20),
+        ..EncodeConfig::default()
+    };
+    let converter = Converter::builder()
+        .resize(ResizeMode::Fill)
+        .encode_config(config)
+        .options(ConversionOptions::default())
+        .build()
+        .unwrap();
+
+    assert!(converter.options().should_resize);
+    assert_eq!(converter.options().resize_mode, ResizeMode::Fill);
+    assert_eq!(converter.options().encode_config, Some(config));
+
+    let out_of_range = EncodeConfig {
+        filter_sharpness: Some(8),
+        ..EncodeConfig::default()
+    };
+    let error = Converter::builder()
+        .encode_config(out_of_range)
+        .build()
+        .unwrap_err();
+    assert!(error.to_string().contains("filter_sharpness"), "{}", error);
+}
+
+#[cfg(not(feature = "avif"))]
+#[test]
+fn avif_output_needs_the_avif_feature() {
+    let error = Converter::builder()
+        .options(ConversionOptions {
+            extra_formats: vec![OutputFormat::Avif],
+            ..ConversionOptions::default()
+        })
+        .build()
+        .unwrap_err();
+    assert!(error.to_string().contains("`--features avif`"), "{}", error);
+}
+
+#[test]
+fn lossless_avif_is_refused() {
+    let avif = ConversionOptions {
+        extra_formats: vec![OutputFormat::Avif],
+        ..ConversionOptions::default()
+    };
+    for builder in [
+        Converter::builder().lossless(true),
+        Converter::builder().lossless_effort(50.0),
+    ] {
+        let error = builder.options(avif.clone()).build().unwrap_err();
+        assert!(error.to_string().contains("no lossless mode"), "{}", error);
+    }
+}
+
+#[test]
+fn contradicting_choices_are_refused() {
+    let refused = [
+        Converter::builder().lossless(true).quality(80.0),
+        Converter::builder().lossless(true).compression_factor(2.0),
+        Converter::builder().lossless(false).lossless_effort(50.0),
+        Converter::builder()
+            .compression_factor(3.0)
+            .noise_ratio(42.0),
+        Converter::builder().quality(101.0),
+        Converter::builder().effort(7),
+        Converter::builder().options(ConversionOptions {
+            hash_names: true,
+            overwrite_if_smaller: true,
+            ..ConversionOptions::default()
+        }),
+        Converter::builder().options(ConversionOptions {
+            keep_icc: true,
+            target_colorspace: Some(ColorSpace::Srgb),
+            ..ConversionOptions::default()
+        }),
+        Converter::builder()
+            .resize(ResizeMode::Fill)
+            .options(ConversionOptions {
+                resize_target: ResizeTarget::Scale(50.0),
+                ..ConversionOptions::default()
+            }),
+        Converter::builder()
+            .resize(ResizeMode::Fill)
+            .options(ConversionOptions {
+                resize_target: ResizeTarget::Box {
+                    width: Some(800),
+                    height: None,
+                },
+                ..ConversionOptions::default()
+            }),
+        Converter::builder()
+            .resize(ResizeMode::ShrinkShortest)
+            .options(ConversionOptions {
+                resize_target: ResizeTarget::Box {
+                    width: Some(800),
+                    height: Some(600),
+                },
+                ..ConversionOptions::default()
+            }),
+        Converter::builder().options(ConversionOptions {
+            resize_target: ResizeTarget::Scale(150.0),
+            ..ConversionOptions::default()
+        }),
+        Converter::builder().options(ConversionOptions {
+            sizes: vec![320, 640, 320],
+            ..ConversionOptions::default()
+        }),
+        Converter::builder()
+            .resize(ResizeMode::Fit)
+            .options(ConversionOptions {
+                sizes: vec![320, 640],
+                ..ConversionOptions::default()
+            }),
+        Converter::builder().options(ConversionOptions {
+            tiff_pages: Some(TiffPages::All),
+            min_savings_percent: 10.0,
+            ..ConversionOptions::default()
+        }),
+        Converter::builder().options(ConversionOptions {
+            strip_metadata: true,
+            keep_exif: true,
+            ..ConversionOptions::default()
+        }),
+        Converter::builder().options(ConversionOptions {
+            strip_metadata: true,
+            min_savings_percent: 10.0,
+            ..ConversionOptions::default()
+        }),
+        Converter::builder().options(ConversionOptions {
+            deterministic: true,
+            keep_icc: true,
+            ..ConversionOptions::default()
+        }),
+        Converter::builder().options(ConversionOptions {
+            min_quality: 80.0,
+            max_quality: 60.0,
+            ..ConversionOptions::default()
+        }),
+        Converter::builder().options(ConversionOptions {
+            max_quality: 120.0,
+            ..ConversionOptions::default()
+        }),
+        Converter::builder().options(ConversionOptions {
+            extra_formats: vec![OutputFormat::Webp],
+            ..ConversionOptions::default()
+        }),
+        Converter::builder().options(ConversionOptions {
+            clean_output: true,
+            hash_names: true,
+            ..ConversionOptions::default()
+        }),
+        Converter::builder()
+            .lossless(true)
+            .options(ConversionOptions {
+                roi: "0,0,10,10".parse().ok(),
+                ..ConversionOptions::default()
+            }),
+        Converter::builder().options(ConversionOptions {
+            roi: "0,0,10,10".parse().ok(),
+            roi_quality: 101.0,
+            ..ConversionOptions::default()
+        }),
+        Converter::builder().options(ConversionOptions {
+            tar: Some("-".into()),
+            report_format: ReportFormat::Json,
+            ..ConversionOptions::default()
+        }),
+        Converter::builder().options(ConversionOptions {
+            clean_output: true,
+            output_dir: Some("out".into()),
+            ..ConversionOptions::default()
+        }),
+        Converter::builder().options(ConversionOptions {
+            mirror: true,
+            ..ConversionOptions::default()
+        }),
+        Converter::builder().options(ConversionOptions {
+            replace: true,
+            ..ConversionOptions::default()
+        }),
+        Converter::builder().options(ConversionOptions {
+            skip_existing: true,
+            hash_names: true,
+            ..ConversionOptions::default()
+        }),
+        Converter::builder().options(ConversionOptions {
+            cache: Some("cache.json".into()),
+            lqip: true,
+            ..ConversionOptions::default()
+        }),
+    ];
+    for builder in refused {
+        assert!(builder.clone().build().is_err(), "{:?}", builder);
+    }
+
+    // `--lossless-effort` leaves `-q` to the lossy files of `--auto-format`.
+    assert!(Converter::builder()
+        .lossless_effort(50.0)
+        .quality(80.0)
+        .build()
+        .is_ok());
+}
+
+#[tokio::test]
+async fn only_the_paths_a_caller_found_are_converted() {
+    let (_dir, input) = workspace(&[
+        ("opaque.jpg", "a/listed.jpg"),
+        ("transparent.png", "unlisted.png"),
+    ]);
+    let converter = Converter::builder().build().unwrap();
+    let listed = input.join("a/listed.jpg");
+    let missing = input.join("missing.png");
+
+    let summary = converter
+        .convert_dir_with_walker(&input, vec![listed.clone(), missing.clone()])
+        .await;
+
+    assert_eq!(summary.converted, 1);
+    assert_webp(&output_of(&listed, "listed.webp"), (160, 120));
+    assert!(!output_of(&input.join("unlisted.png"), "unlisted.webp").exists());
+    assert_eq!(summary.failed, 1);
+    assert_eq!(summary.failures[0].0, missing);
+}
@@ -0,0 +1,722 @@
+mod common;
+
+use common::{assert_webp, output_of, sideways_jpeg, workspace};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+fn run(input: &Path, extra: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_webp_converter"))
+        .arg("-p")
+        .arg(input)
+        .args(extra)
+        .output()
+        .expect("run webp_converter")
+}
+
+// Like `run`, answering the `-i` prompts with `answers`.
+fn run_answering(input: &Path, extra: &[&str], answers: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_webp_converter"))
+        .arg("-p")
+        .arg(input)
+        .args(extra)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("run webp_converter");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(answers.as_bytes())
+        .unwrap();
+    child.wait_with_output().expect("run webp_converter")
+}
+
+// A folder with one good image and one that can't be decoded.
+fn batch_with_a_broken_file() -> (tempfile::TempDir, std::path::PathBuf) {
+    let (dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    fs::write(input.join("broken.png"), b"not a png").unwrap();
+    (dir, input)
+}
+
+#[test]
+fn a_relative_output_dir_inside_the_input_isnt_walked_again() {
+    let (dir, _input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    let run = || {
+        Command::new(env!("CARGO_BIN_EXE_webp_converter"))
+            .current_dir(dir.path())
+            .args(["-p", "input", "-r", "true", "--output-dir", "./input/out"])
+            .output()
+            .expect("run webp_converter")
+    };
+
+    run();
+    let output = run();
+
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(log.contains("Converted: 1,"), "{}", log);
+    assert!(log.contains("copied: 0,"), "{}", log);
+    assert_webp(&dir.path().join("input/out/opaque.webp"), (160, 120));
+    assert!(!dir.path().join("input/out/out").exists());
+}
+
+#[test]
+fn a_timeout_too_long_for_a_duration_is_refused() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+
+    let output = run(&input, &["--timeout", "1e30"]);
+
+    assert_eq!(output.status.code(), Some(2));
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(log.contains("`1e30` seconds is too long"), "{}", log);
+}
+
+#[test]
+fn a_failed_file_fails_the_run_by_default() {
+    let (_dir, input) = batch_with_a_broken_file();
+
+    let output = run(&input, &[]);
+
+    assert_eq!(output.status.code(), Some(1));
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(log.contains("1 file(s) failed:"), "{}", log);
+    assert!(log.contains("broken.png"), "{}", log);
+    // The other files are still converted.
+    assert!(output_of(&input.join("opaque.jpg"), "opaque.webp").exists());
+}
+
+#[test]
+fn a_missing_path_fails_the_run() {
+    let (dir, _input) = workspace(&[]);
+
+    let output = run(&dir.path().join("does-not-exist"), &[]);
+
+    assert_eq!(output.status.code(), Some(1));
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(log.contains("Path does not exist"), "{}", log);
+}
+
+#[test]
+fn conflicting_flags_fail_the_run_before_anything_is_converted() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+
+    let output = run(&input, &["-l", "true", "-q", "50"]);
+
+    assert_eq!(output.status.code(), Some(1));
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        log.contains("Lossless output and -q 50 conflict"),
+        "{}",
+        log
+    );
+    assert!(!output_of(&input.join("opaque.jpg"), "opaque.webp").exists());
+}
+
+#[test]
+fn error_on_empty_fails_a_run_with_nothing_to_convert() {
+    let (_dir, input) = workspace(&[]);
+    fs::write(input.join("notes.txt"), "not an image").unwrap();
+
+    let output = run(&input, &[]);
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = run(&input, &["--error-on-empty"]);
+    assert_eq!(output.status.code(), Some(3));
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(log.contains("No convertible images found"), "{}", log);
+}
+
+#[test]
+fn copy_others_copies_a_folder_without_images() {
+    let (_dir, input) = workspace(&[]);
+    fs::write(input.join("a.css"), "body { margin: 0 }\n").unwrap();
+    fs::write(input.join("b.js"), "console.log(1);\n").unwrap();
+
+    let output = run(&input, &["--copy-others", "--error-on-empty"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(!log.contains("No convertible images found"), "{}", log);
+    assert!(log.contains("copied: 2,"), "{}", log);
+    for name in ["a.css", "b.js"] {
+        assert_eq!(
+            fs::read(output_of(&input.join(name), name)).unwrap(),
+            fs::read(input.join(name)).unwrap()
+        );
+    }
+}
+
+#[test]
+fn interactive_asks_every_question_before_converting_anything() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "a.jpg"), ("opaque.jpg", "b.jpg")]);
+    let existing = output_of(&input.join("a.jpg"), "a.webp");
+    fs::create_dir_all(existing.parent().unwrap()).unwrap();
+    fs::write(&existing, b"an earlier output").unwrap();
+
+    // Declining the plan isn't mistaken for a folder without images.
+    let output = run_answering(&input, &["-i", "--error-on-empty"], "n\n");
+    assert_eq!(output.status.code(), Some(5));
+    assert!(!output_of(&input.join("b.jpg"), "b.webp").exists());
+    let output = run_answering(&input, &["-i", "--in-place", "--replace"], "n\n");
+    let plan = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        plan.contains("Up to 2 original(s) will be deleted"),
+        "{}",
+        plan
+    );
+    assert!(input.join("a.jpg").exists());
+
+    // Proceed, but keep the existing output.
+    let output = run_answering(&input, &["-i"], "y\nn\n");
+    assert_eq!(output.status.code(), Some(0));
+    let prompts = String::from_utf8_lossy(&output.stdout);
+    assert!(prompts.contains("Overwrite"), "{}", prompts);
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(log.contains("Converted: 1,"), "{}", log);
+    assert!(log.contains("skipped: 1,"), "{}", log);
+    assert_eq!(fs::read(&existing).unwrap(), b"an earlier output");
+    assert_webp(&output_of(&input.join("b.jpg"), "b.webp"), (160, 120));
+}
+
+#[test]
+fn ignore_errors_keeps_a_zero_exit_code() {
+    let (_dir, input) = batch_with_a_broken_file();
+
+    let output = run(&input, &["--ignore-errors"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("1 file(s) failed:"));
+    assert!(output_of(&input.join("opaque.jpg"), "opaque.webp").exists());
+}
+
+#[test]
+fn images_too_small_to_resize_are_reported() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    let not_resized = |log: &str, level: &str| {
+        log.lines().any(|line| {
+            line.contains(level) && line.contains("Not resizing \"opaque.jpg\": 160x120")
+        })
+    };
+
+    let output = run(&input, &["-s", "true"]);
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(not_resized(&log, "INFO"), "{}", log);
+
+    let output = run(&input, &["-s", "true", "--warn-on-no-resize"]);
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(not_resized(&log, "WARN"), "{}", log);
+}
+
+#[test]
+fn a_config_timeout_too_long_for_a_duration_is_refused() {
+    let (dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    let config = dir.path().join("config.toml");
+    fs::write(&config, "timeout = 1e30\n").unwrap();
+
+    let output = run(&input, &["--config", config.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(1));
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(log.contains("seconds is too long"), "{}", log);
+    assert!(!output_of(&input.join("opaque.jpg"), "opaque.webp").exists());
+}
+
+#[test]
+fn a_missing_config_fails_the_run_and_names_the_file() {
+    let (dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    let config = dir.path().join("missing.toml");
+    let config = config.to_str().unwrap();
+
+    let output = run(&input, &["--config", config]);
+
+    assert_eq!(output.status.code(), Some(1));
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        log.contains(&format!("Can't read config {}", config)),
+        "{}",
+        log
+    );
+    assert!(!output_of(&input.join("opaque.jpg"), "opaque.webp").exists());
+}
+
+#[test]
+fn an_unreadable_or_invalid_encode_config_fails_the_run() {
+    let (dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    let invalid = dir.path().join("invalid.toml");
+    fs::write(&invalid, "method = 9\n").unwrap();
+
+    for (config, reason) in [
+        (dir.path().join("missing.toml"), "Can't read encode config"),
+        (invalid, "Invalid encode config"),
+    ] {
+        let output = run(&input, &["--encode-config", config.to_str().unwrap()]);
+
+        assert_eq!(output.status.code(), Some(1), "{}", reason);
+        let log = String::from_utf8_lossy(&output.stderr);
+        assert!(log.contains(reason), "{}", log);
+        assert!(!output_of(&input.join("opaque.jpg"), "opaque.webp").exists());
+    }
+}
+
+#[test]
+fn echo_settings_says_where_each_setting_came_from() {
+    let (dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    let config = dir.path().join("config.toml");
+    fs::write(&config, "quality = 60\neffort = 3\n").unwrap();
+    let config = config.to_str().unwrap();
+
+    let output = run(
+        &input,
+        &["--config", config, "-q", "90", "--echo-settings", "json"],
+    );
+
+    assert_eq!(output.status.code(), Some(0));
+    let settings: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+    let setting = |name: &str| {
+        let setting = settings
+            .iter()
+            .find(|s| s["setting"] == name)
+            .unwrap_or_else(|| panic!("no {} in {:?}", name, settings));
+        (
+            setting["value"].as_str().unwrap(),
+            setting["source"].as_str().unwrap(),
+        )
+    };
+    assert_eq!(setting("-q"), ("90", "command-line"));
+    assert_eq!(setting("--effort"), ("3", "config"));
+    assert_eq!(setting("-n"), ("40", "default"));
+    // Nothing is converted unless asked to.
+    assert!(!output_of(&input.join("opaque.jpg"), "opaque.webp").exists());
+
+    let output = run(&input, &["--echo-settings", "--echo-and-convert"]);
+    assert_eq!(output.status.code(), Some(0));
+    let table = String::from_utf8_lossy(&output.stdout);
+    assert!(table.starts_with("SETTING"), "{}", table);
+    assert!(output_of(&input.join("opaque.jpg"), "opaque.webp").exists());
+}
+
+#[test]
+fn fast_and_best_set_the_effort_over_the_config() {
+    let (dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    let config = dir.path().join("config.toml");
+    fs::write(&config, "effort = 3\n").unwrap();
+    let config = config.to_str().unwrap();
+
+    for (flag, effort) in [("--fast", "1"), ("--best", "6")] {
+        let output = run(
+            &input,
+            &["--config", config, flag, "--echo-settings", "json"],
+        );
+        assert_eq!(output.status.code(), Some(0), "{}", flag);
+        let settings: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+        let setting = settings
+            .iter()
+            .find(|s| s["setting"] == "--effort")
+            .unwrap();
+        assert_eq!(
+            (setting["value"].as_str(), setting["source"].as_str()),
+            (Some(effort), Some("command-line")),
+            "{}",
+            flag
+        );
+    }
+}
+
+#[test]
+fn format_takes_a_comma_separated_list() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+
+    let output = run(&input, &["-f", "webp,webp"]);
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(log.contains("--format lists webp twice"), "{}", log);
+    assert!(!output_of(&input.join("opaque.jpg"), "opaque.webp").exists());
+
+    let output = run(&input, &["-f", "webp,jxl", "--echo-settings", "json"]);
+    if cfg!(feature = "jxl") {
+        let settings: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+        assert!(settings
+            .iter()
+            .any(|s| s["setting"] == "-f" && s["value"] == "webp,jxl"));
+    } else {
+        let log = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            log.contains("requires building with `--features jxl`"),
+            "{}",
+            log
+        );
+    }
+}
+
+#[test]
+fn check_decodes_every_image_without_writing_anything() {
+    let (_dir, input) = batch_with_a_broken_file();
+    // A header that reads fine, with the pixels cut off.
+    let jpeg = fs::read(input.join("opaque.jpg")).unwrap();
+    fs::write(input.join("truncated.jpg"), &jpeg[..jpeg.len() / 3]).unwrap();
+
+    let output = run(&input, &["--check"]);
+
+    assert_eq!(output.status.code(), Some(1));
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        log.contains("Checked: 3 file(s), 1 decoded, 2 failed"),
+        "{}",
+        log
+    );
+    assert!(log.contains("2 file(s) can't be decoded:"), "{}", log);
+    assert!(log.contains("broken.png"), "{}", log);
+    assert!(log.contains("truncated.jpg"), "{}", log);
+    assert!(!output_of(&input.join("opaque.jpg"), "opaque.webp").exists());
+
+    let output = run(&input.join("opaque.jpg"), &["--check"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("(160x120)"));
+}
+
+#[test]
+fn the_path_can_be_given_without_p() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    let binary = env!("CARGO_BIN_EXE_webp_converter");
+
+    let output = Command::new(binary).arg(&input).output().unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output_of(&input.join("opaque.jpg"), "opaque.webp").exists());
+
+    let output = Command::new(binary)
+        .arg(&input)
+        .arg("-p")
+        .arg(&input)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(log.contains("cannot be used with"), "{}", log);
+}
+
+#[test]
+fn explain_logs_the_choices_made_for_each_file() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+
+    let output = run(&input, &["--explain", "--auto-format", "-s", "true"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(log.contains("Explain \"opaque.jpg\":"), "{}", log);
+    assert!(
+        log.contains("decoded 160x120 Rgb8, no alpha channel"),
+        "{}",
+        log
+    );
+    assert!(log.contains("format: webp"), "{}", log);
+    assert!(
+        log.contains("lossless: no, --auto-format found no transparent pixels"),
+        "{}",
+        log
+    );
+    assert!(
+        log.contains("resize: none, 160x120 already fits"),
+        "{}",
+        log
+    );
+    assert!(log.contains("quality: 75 (from -q)"), "{}", log);
+}
+
+#[test]
+fn explain_reports_the_mode_that_was_encoded() {
+    for (extra, said, chunk) in [
+        (None, "lossless: no, the default", b"VP8 "),
+        (
+            Some("lossless = 1\n"),
+            "lossless: yes, set by --encode-config",
+            b"VP8L",
+        ),
+    ] {
+        let (dir, input) = workspace(&[("palette.png", "palette.png")]);
+        let config = dir.path().join("encode.toml");
+        let mut args = vec!["--explain"];
+        if let Some(extra) = extra {
+            fs::write(&config, extra).unwrap();
+            args.extend(["--encode-config", config.to_str().unwrap()]);
+        }
+
+        let output = run(&input, &args);
+
+        assert_eq!(output.status.code(), Some(0));
+        let log = String::from_utf8_lossy(&output.stderr);
+        assert!(log.contains(said), "{}", log);
+        let written = fs::read(output_of(&input.join("palette.png"), "palette.webp")).unwrap();
+        assert_eq!(&written[12..16], chunk);
+    }
+}
+
+#[test]
+fn progress_bar_stays_out_of_redirected_output() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "a.jpg"), ("gray.tiff", "b.tiff")]);
+
+    let output = run(&input, &["--progress"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(log.contains("Summary:"), "{}", log);
+    assert!(!log.contains("files,"), "{}", log);
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn dry_run_lists_the_plan_without_writing_anything() {
+    let (_dir, input) = workspace(&[
+        ("opaque.jpg", "a.jpg"),
+        ("gray.tiff", "nested/b.tiff"),
+        ("already.webp", "c.webp"),
+    ]);
+    fs::write(input.join("notes.txt"), b"not an image").unwrap();
+
+    let output = run(&input, &["--dry-run", "-r", "true"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let log = String::from_utf8_lossy(&output.stderr);
+    let planned = |line: &str| assert!(log.contains(line), "{}\n{}", line, log);
+    planned(&format!(
+        "Convert: {:?} -> {:?}",
+        input.join("nested/b.tiff"),
+        output_of(&input.join("nested/b.tiff"), "b.webp")
+    ));
+    planned(&format!(
+        "Copy: {:?} -> {:?}",
+        input.join("c.webp"),
+        output_of(&input.join("c.webp"), "c.webp")
+    ));
+    planned(&format!(
+        "Skip: {:?} (not an image)",
+        input.join("notes.txt")
+    ));
+    planned("Dry run: 2 file(s) to convert, 0 PDF(s) to rasterize, 1 to copy, 1 skipped");
+    assert!(!output_of(&input.join("a.jpg"), "a.webp")
+        .parent()
+        .unwrap()
+        .exists());
+    assert!(!input.join("nested").join("webp_converter_output").exists());
+}
+
+#[test]
+fn decode_writes_webp_files_back_as_png_or_jpeg() {
+    let (_dir, input) = workspace(&[
+        ("animated.gif", "animated.gif"),
+        ("already.webp", "still.webp"),
+    ]);
+    assert_eq!(run(&input, &[]).status.code(), Some(0));
+    let animated = input.join("animation.webp");
+    fs::rename(
+        output_of(&input.join("animated.gif"), "animated.webp"),
+        &animated,
+    )
+    .unwrap();
+    let decode = |extra: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_webp_converter"))
+            .arg("decode")
+            .args(extra)
+            .output()
+            .expect("run webp_converter decode")
+    };
+
+    let output = decode(&[input.to_str().unwrap()]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        log.contains("Decoded: 2 WebP file(s) to 4 image(s), 0 failed"),
+        "{}",
+        log
+    );
+    for name in [
+        "still.png",
+        "animation-f1.png",
+        "animation-f2.png",
+        "animation-f3.png",
+    ] {
+        let bytes = fs::read(output_of(&animated, name)).unwrap();
+        assert_eq!(
+            image::guess_format(&bytes).unwrap(),
+            image::ImageFormat::Png,
+            "{}",
+            name
+        );
+    }
+    // The copy the conversion left in the output folder isn't decoded again.
+    let copy = output_of(&input.join("still.webp"), "still.webp");
+    assert!(copy.exists());
+    assert!(!output_of(&copy, "still.png").exists());
+
+    let output = decode(&[input.join("still.webp").to_str().unwrap(), "--to", "jpeg"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let bytes = fs::read(output_of(&animated, "still.jpg")).unwrap();
+    assert_eq!(
+        image::guess_format(&bytes).unwrap(),
+        image::ImageFormat::Jpeg
+    );
+}
+
+#[test]
+fn keep_metadata_keeps_exif_icc_and_xmp() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+
+    let output = run(&input, &["--keep-metadata", "--echo-settings", "json"]);
+    let settings: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+    for flag in ["--keep-exif", "--keep-icc", "--keep-xmp"] {
+        assert!(
+            settings.iter().any(|s| s["setting"] == flag
+                && s["value"] == "true"
+                && s["source"] == "command-line"),
+            "{:?}",
+            settings
+        );
+    }
+
+    fs::write(input.join("photo.jpg"), sideways_jpeg()).unwrap();
+    assert!(run(&input, &["--keep-metadata"]).status.success());
+    let webp = fs::read(output_of(&input.join("photo.jpg"), "photo.webp")).unwrap();
+    assert_eq!(&webp[12..16], b"VP8X");
+    assert_eq!(
+        webp[20] & 0b0010_1100,
+        0b0010_1100,
+        "ICC, EXIF and XMP flags"
+    );
+    assert!(webp.windows(4).any(|chunk| chunk == b"EXIF"));
+
+    let output = run(&input, &["--keep-metadata", "--target-colorspace", "srgb"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn color_profile_picks_what_happens_to_the_icc_profile() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    let setting = |extra: &[&str], flag: &str| {
+        let output = run(&input, &[extra, &["--echo-settings", "json"]].concat());
+        let settings: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+        settings
+            .into_iter()
+            .find(|s| s["setting"] == flag)
+            .map(|s| s["value"].as_str().unwrap().to_string())
+            .unwrap()
+    };
+
+    assert_eq!(setting(&["--color-profile", "embed"], "--keep-icc"), "true");
+    let strip = ["--keep-metadata", "--color-profile", "strip"];
+    assert_eq!(setting(&strip, "--keep-icc"), "false");
+    assert_eq!(setting(&strip, "--keep-exif"), "true");
+
+    fs::write(input.join("photo.jpg"), sideways_jpeg()).unwrap();
+    let has_icc = |profile: &str| {
+        assert!(run(&input, &["--color-profile", profile]).status.success());
+        let webp = fs::read(output_of(&input.join("photo.jpg"), "photo.webp")).unwrap();
+        webp.windows(4).any(|chunk| chunk == b"ICCP")
+    };
+    assert!(has_icc("embed"));
+    assert!(!has_icc("strip"));
+
+    let output = run(&input, &["--color-profile", "strip", "--keep-icc"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn no_auto_orient_leaves_the_pixels_as_stored() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+
+    let output = run(&input, &["--no-auto-orient", "--echo-settings", "json"]);
+    let settings: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(settings
+        .iter()
+        .any(|s| s["setting"] == "--apply-orientation"
+            && s["value"] == "false"
+            && s["source"] == "command-line"));
+
+    // The sideways photo is turned upright by default and left as stored without it.
+    fs::write(input.join("photo.jpg"), sideways_jpeg()).unwrap();
+    let output = output_of(&input.join("photo.jpg"), "photo.webp");
+    assert!(run(&input, &[]).status.success());
+    assert_webp(&output, (120, 160));
+    assert!(run(&input, &["--no-auto-orient"]).status.success());
+    assert_webp(&output, (160, 120));
+
+    let output = run(&input, &["--no-auto-orient", "--apply-orientation", "true"]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn max_width_and_scale_set_the_resize_target() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    let output = output_of(&input.join("opaque.jpg"), "opaque.webp");
+
+    assert!(run(&input, &["--max-width", "40"]).status.success());
+    assert_webp(&output, (40, 30));
+    assert!(run(&input, &["--scale", "50%"]).status.success());
+    assert_webp(&output, (80, 60));
+
+    let refused = run(&input, &["--scale", "50%", "--resize-mode", "fill"]);
+    let log = String::from_utf8_lossy(&refused.stderr);
+    assert!(
+        log.contains("--scale only goes with --resize-mode fit"),
+        "{}",
+        log
+    );
+    let refused = run(&input, &["--max-dimension", "40", "--max-width", "30"]);
+    assert!(!refused.status.success());
+}
+
+#[test]
+fn log_file_gets_plain_lines_and_appends_or_truncates() {
+    let (dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    let log_file = dir.path().join("logs/run.log");
+    let run_logging = |mode: &str| {
+        Command::new(env!("CARGO_BIN_EXE_webp_converter"))
+            .env("CLICOLOR_FORCE", "1")
+            .arg("-p")
+            .arg(&input)
+            .args([
+                "--log-file",
+                log_file.to_str().unwrap(),
+                "--log-file-mode",
+                mode,
+            ])
+            .output()
+            .expect("run webp_converter")
+    };
+    let summaries = || {
+        fs::read_to_string(&log_file)
+            .unwrap()
+            .matches("Summary:")
+            .count()
+    };
+
+    let output = run_logging("append");
+
+    assert_eq!(output.status.code(), Some(0));
+    // Colored on the terminal, plain in the file.
+    assert!(String::from_utf8_lossy(&output.stderr).contains("\u{1b}["));
+    let written = fs::read_to_string(&log_file).unwrap();
+    assert!(written.contains("Summary:"), "{}", written);
+    assert!(!written.contains('\u{1b}'), "{}", written);
+    assert_eq!(summaries(), 1);
+
+    run_logging("append");
+    assert_eq!(summaries(), 2);
+
+    run_logging("truncate");
+    assert_eq!(summaries(), 1);
+
+    run_logging("rotate");
+    assert_eq!(summaries(), 1);
+    let rotated = dir.path().join("logs/run.log.1");
+    assert_eq!(
+        fs::read_to_string(rotated)
+            .unwrap()
+            .matches("Summary:")
+            .count(),
+        1
+    );
+}
@@ -0,0 +1,98 @@
+// Shared by the integration tests, each of which uses only part of it.
+#![allow(dead_code)]
+
+use image::{GenericImageView, ImageFormat};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+use webp_converter::wio::OUTPUT_DIRECTORY_NAME;
+
+// Every fixture, with the dimensions its output should have.
+pub const FIXTURES: [(&str, (u32, u32)); 5] = [
+    ("opaque.jpg", (160, 120)),
+    ("transparent.png", (96, 96)),
+    ("palette.png", (512, 512)),
+    ("animated.gif", (48, 48)),
+    ("gray.tiff", (128, 96)),
+];
+
+// A WebP fixture, copied rather than converted.
+pub const WEBP_FIXTURE: &str = "already.webp";
+
+// The ICC profile and XMP packet carried by `sideways_jpeg`.
+pub const ICC: &[u8] = b"not a real profile, but carried over all the same";
+pub const XMP: &[u8] = b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"></x:xmpmeta>";
+
+// Little endian EXIF with one IFD holding only an orientation tag.
+pub fn exif(orientation: u16) -> Vec<u8> {
+    let mut exif = b"II\x2A\x00\x08\x00\x00\x00\x01\x00".to_vec();
+    exif.extend_from_slice(&[0x12, 0x01, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00]);
+    exif.extend_from_slice(&orientation.to_le_bytes());
+    exif.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+    exif
+}
+
+// The 160x120 opaque fixture, stored sideways: EXIF orientation 6 says to rotate it
+// 90 degrees clockwise, into 120x160. It also carries an ICC profile and XMP.
+pub fn sideways_jpeg() -> Vec<u8> {
+    let segment = |marker: u8, parts: &[&[u8]]| {
+        let data = parts.concat();
+        let mut segment = vec![0xFF, marker];
+        segment.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+        segment.extend_from_slice(&data);
+        segment
+    };
+    let jpeg = fs::read(fixture("opaque.jpg")).unwrap();
+    [
+        &jpeg[..2],
+        &segment(0xE1, &[b"Exif\0\0", &exif(6)]),
+        &segment(0xE2, &[b"ICC_PROFILE\0\x01\x01", ICC]),
+        &segment(0xE1, &[b"http://ns.adobe.com/xap/1.0/\0", XMP]),
+        &jpeg[2..],
+    ]
+    .concat()
+}
+
+pub fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name)
+}
+
+// A scratch `input` folder holding copies of `fixtures` at the given relative paths.
+// The converter also touches the output folder next to the input, so the input is
+// kept one level below the temporary directory.
+pub fn workspace(fixtures: &[(&str, &str)]) -> (TempDir, PathBuf) {
+    let dir = TempDir::new().expect("create a temporary directory");
+    let input = dir.path().join("input");
+    for (name, relative) in fixtures {
+        let destination = input.join(relative);
+        fs::create_dir_all(destination.parent().unwrap()).unwrap();
+        fs::copy(fixture(name), destination).unwrap();
+    }
+    fs::create_dir_all(&input).unwrap();
+    (dir, input)
+}
+
+pub fn output_of(source: &Path, file_name: &str) -> PathBuf {
+    source
+        .parent()
+        .unwrap()
+        .join(OUTPUT_DIRECTORY_NAME)
+        .join(file_name)
+}
+
+// Asserts `path` is a WebP that decodes to `dimensions`.
+pub fn assert_webp(path: &Path, dimensions: (u32, u32)) {
+    let bytes = fs::read(path).unwrap_or_else(|e| panic!("reading {:?}: {}", path, e));
+    assert_eq!(
+        image::guess_format(&bytes).unwrap(),
+        ImageFormat::WebP,
+        "{:?} isn't a WebP",
+        path
+    );
+    let decoded = image::load_from_memory_with_format(&bytes, ImageFormat::WebP)
+        .unwrap_or_else(|e| panic!("decoding {:?}: {}", path, e));
+    assert_eq!(decoded.dimensions(), dimensions, "dimensions of {:?}", path);
+}
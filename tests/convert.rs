@@ -0,0 +1,1660 @@
+mod common;
+
+use common::{assert_webp, fixture, output_of, sideways_jpeg, workspace, FIXTURES, WEBP_FIXTURE};
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use webp_converter::builder::Converter;
+use webp_converter::cache::CACHE_FILE_NAME;
+use webp_converter::converter::{
+    convert_bytes, convert_images_to_webp, convert_single_photo, convert_stream, verify_written,
+};
+use webp_converter::estimate::estimate_single_photo;
+use webp_converter::metadata::Metadata;
+use webp_converter::source::ImageSource;
+use webp_converter::types::{
+    Cancellation, ConversionOptions, ConversionStatus, Flip, Jobs, QualityMap, Rotation, SortOrder,
+    TiffPages, DEFAULT_MAX_PIXELS,
+};
+use webp_converter::wio::{
+    clean_output, temp_path_for, validate_output_dir_name, LQIP_MANIFEST_FILE_NAME,
+    MANIFEST_FILE_NAME, OUTPUT_DIRECTORY_NAME,
+};
+
+#[tokio::test]
+async fn converts_every_fixture_to_a_smaller_webp() {
+    let mut files: Vec<(&str, &str)> = FIXTURES.iter().map(|(name, _)| (*name, *name)).collect();
+    files.push((WEBP_FIXTURE, WEBP_FIXTURE));
+    let (_dir, input) = workspace(&files);
+
+    let summary = convert_images_to_webp(&input, false, &ConversionOptions::default()).await;
+
+    assert_eq!(summary.matched, FIXTURES.len() + 1);
+    assert_eq!(summary.converted, FIXTURES.len());
+    assert_eq!(summary.copied, 1);
+    assert_eq!(summary.failed, 0);
+    for (name, dimensions) in FIXTURES {
+        let source = input.join(name);
+        let stem = name.split('.').next().unwrap();
+        let output = output_of(&source, &format!("{}.webp", stem));
+        assert_webp(&output, dimensions);
+        // Flat graphics encoded lossy, as asked, can outgrow their PNG; tests/encode.rs
+        // covers the lossless palette case.
+        if name == "palette.png" {
+            continue;
+        }
+        assert!(
+            fs::metadata(&output).unwrap().len() < fs::metadata(&source).unwrap().len(),
+            "{} didn't get smaller",
+            name
+        );
+    }
+}
+
+#[tokio::test]
+async fn single_file_returns_its_record() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    let source = input.join("opaque.jpg");
+
+    let record = convert_single_photo(&source, &ConversionOptions::default())
+        .await
+        .unwrap();
+
+    assert_eq!(record.status, ConversionStatus::Converted);
+    assert_eq!(record.output, output_of(&source, "opaque.webp"));
+    assert_eq!(record.input_size, fs::metadata(&source).unwrap().len());
+    assert_eq!(
+        record.output_size,
+        fs::metadata(&record.output).unwrap().len()
+    );
+    assert_webp(&record.output, (160, 120));
+}
+
+#[tokio::test]
+async fn recursive_walk_writes_outputs_next_to_nested_sources() {
+    let (_dir, input) = workspace(&[
+        ("opaque.jpg", "top.jpg"),
+        ("transparent.png", "a/middle.png"),
+        ("gray.tiff", "a/b/deep.tiff"),
+    ]);
+
+    let flat = convert_images_to_webp(&input, false, &ConversionOptions::default()).await;
+    assert_eq!(flat.converted, 1);
+    assert!(!output_of(&input.join("a/middle.png"), "middle.webp").exists());
+
+    let summary = convert_images_to_webp(&input, true, &ConversionOptions::default()).await;
+    assert_eq!(summary.converted, 3);
+    assert_webp(&output_of(&input.join("top.jpg"), "top.webp"), (160, 120));
+    assert_webp(
+        &output_of(&input.join("a/middle.png"), "middle.webp"),
+        (96, 96),
+    );
+    assert_webp(
+        &output_of(&input.join("a/b/deep.tiff"), "deep.webp"),
+        (128, 96),
+    );
+}
+
+#[tokio::test]
+async fn excluded_folders_are_not_walked_at_any_depth() {
+    for respect_ignore in [false, true] {
+        let (_dir, input) = workspace(&[
+            ("opaque.jpg", "top.jpg"),
+            ("transparent.png", "node_modules/pkg/icon.png"),
+            ("gray.tiff", "a/vendor/deep.tiff"),
+            ("palette.png", "a/vendored.png"),
+        ]);
+        let options = ConversionOptions {
+            exclude_dirs: vec!["node_modules".to_string(), "vendor".to_string()],
+            respect_ignore,
+            ..ConversionOptions::default()
+        };
+        let summary = convert_images_to_webp(&input, true, &options).await;
+        assert_eq!(summary.matched, 2, "respect_ignore: {}", respect_ignore);
+        assert!(output_of(&input.join("a/vendored.png"), "vendored.webp").exists());
+        assert!(!output_of(&input.join("a/vendor/deep.tiff"), "deep.webp").exists());
+        assert!(!output_of(&input.join("node_modules/pkg/icon.png"), "icon.webp").exists());
+    }
+}
+
+#[tokio::test]
+async fn webp_inputs_are_copied_unchanged_beside_converted_outputs() {
+    let (_dir, input) = workspace(&[
+        (WEBP_FIXTURE, "sub/already.webp"),
+        ("opaque.jpg", "sub/photo.jpg"),
+    ]);
+
+    let summary = convert_images_to_webp(&input, true, &ConversionOptions::default()).await;
+
+    assert_eq!(summary.copied, 1);
+    let source = input.join("sub/already.webp");
+    let copy = output_of(&source, "already.webp");
+    assert_eq!(fs::read(&copy).unwrap(), fs::read(&source).unwrap());
+    assert_eq!(
+        copy.parent(),
+        output_of(&input.join("sub/photo.jpg"), "photo.webp").parent()
+    );
+}
+
+#[tokio::test]
+async fn deterministic_runs_are_byte_identical_and_keep_no_metadata() {
+    // `--keep-metadata` on a photo carrying EXIF, ICC and XMP.
+    let keeping = ConversionOptions {
+        keep_exif: true,
+        keep_icc: true,
+        keep_xmp: true,
+        ..ConversionOptions::default()
+    };
+    assert!(Converter::builder()
+        .options(keeping.clone())
+        .build()
+        .is_ok());
+    let error = Converter::builder()
+        .options(ConversionOptions {
+            deterministic: true,
+            ..keeping
+        })
+        .build()
+        .unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .contains("--deterministic and --keep-exif"),
+        "{}",
+        error
+    );
+
+    let converter = Converter::builder()
+        .options(ConversionOptions {
+            deterministic: true,
+            ..ConversionOptions::default()
+        })
+        .build()
+        .unwrap();
+    let mut outputs = vec![];
+    for _ in 0..2 {
+        let (dir, input) = workspace(&[]);
+        fs::write(input.join("photo.jpg"), sideways_jpeg()).unwrap();
+        let record = converter
+            .convert_file(&input.join("photo.jpg"))
+            .await
+            .unwrap();
+        outputs.push(fs::read(&record.output).unwrap());
+        drop(dir);
+    }
+    assert_eq!(outputs[0], outputs[1]);
+    assert_eq!(Metadata::read(&outputs[0]), Metadata::default());
+}
+
+#[tokio::test]
+async fn copy_others_passes_other_files_through() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    let stylesheet = input.join("site.css");
+    fs::write(&stylesheet, "body { margin: 0 }\n").unwrap();
+
+    let skipped = convert_images_to_webp(&input, false, &ConversionOptions::default()).await;
+    assert_eq!(skipped.copied, 0);
+    assert!(!output_of(&stylesheet, "site.css").exists());
+
+    let options = ConversionOptions {
+        copy_others: true,
+        ..ConversionOptions::default()
+    };
+    let summary = convert_images_to_webp(&input, false, &options).await;
+
+    assert_eq!(summary.converted, 1);
+    assert_eq!(summary.copied, 1);
+    assert_eq!(
+        fs::read(output_of(&stylesheet, "site.css")).unwrap(),
+        fs::read(&stylesheet).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn since_skips_files_modified_before_the_window() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "old.jpg"), ("opaque.jpg", "new.jpg")]);
+    let two_days_ago = SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60);
+    fs::File::options()
+        .write(true)
+        .open(input.join("old.jpg"))
+        .unwrap()
+        .set_modified(two_days_ago)
+        .unwrap();
+    let options = ConversionOptions {
+        since: Some(Duration::from_secs(24 * 60 * 60)),
+        ..ConversionOptions::default()
+    };
+
+    let summary = convert_images_to_webp(&input, false, &options).await;
+
+    assert_eq!(summary.converted, 1);
+    assert!(output_of(&input.join("new.jpg"), "new.webp").exists());
+    assert!(!output_of(&input.join("old.jpg"), "old.webp").exists());
+}
+
+#[tokio::test]
+async fn images_over_the_webp_limit_are_fitted_or_refused() {
+    let (_dir, input) = workspace(&[]);
+    let source = input.join("panorama.png");
+    image::DynamicImage::new_rgb8(17000, 16)
+        .save(&source)
+        .unwrap();
+
+    let record = convert_single_photo(&source, &ConversionOptions::default())
+        .await
+        .unwrap();
+    // A flat image comes out lossless, which the `image` crate's decoder can't read at
+    // this width; libwebp can.
+    let decoded = webp::Decoder::new(&fs::read(&record.output).unwrap())
+        .decode()
+        .unwrap();
+    assert_eq!((decoded.width(), decoded.height()), (16383, 15));
+
+    let options = ConversionOptions {
+        no_auto_fit: true,
+        ..ConversionOptions::default()
+    };
+    let error = convert_single_photo(&source, &options).await.unwrap_err();
+    assert!(error.to_string().contains("16383px"), "{}", error);
+}
+
+#[tokio::test]
+async fn animated_gifs_and_apngs_keep_every_frame() {
+    let (_dir, input) = workspace(&[("animated.gif", "animated.gif")]);
+    let apng = input.join("blink.png");
+    let mut encoder = png::Encoder::new(fs::File::create(&apng).unwrap(), 32, 32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_animated(2, 0).unwrap();
+    encoder.set_frame_delay(1, 5).unwrap();
+    let mut writer = encoder.write_header().unwrap();
+    for pixel in [[255, 0, 0, 255], [0, 0, 255, 128]] {
+        writer.write_image_data(&pixel.repeat(32 * 32)).unwrap();
+    }
+    writer.finish().unwrap();
+    let options = ConversionOptions {
+        verify_output: true,
+        ..ConversionOptions::default()
+    };
+
+    let summary = convert_images_to_webp(&input, false, &options).await;
+
+    assert_eq!((summary.converted, summary.failed), (2, 0));
+    let animation = |source: &str, output: &str| {
+        let bytes = fs::read(output_of(&input.join(source), output)).unwrap();
+        let animation = webp::AnimDecoder::new(&bytes).decode().unwrap();
+        (0..animation.len())
+            .map(|i| {
+                let frame = animation.get_frame(i).unwrap();
+                (frame.width(), frame.get_time_ms())
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(animation("animated.gif", "animated.webp").len(), 3);
+    // Timestamps are when each frame ends, 200ms apart.
+    assert_eq!(animation("blink.png", "blink.webp"), [(32, 200), (32, 400)]);
+}
+
+#[tokio::test]
+async fn verify_output_deletes_a_bad_output_and_says_why() {
+    use webp_converter::encoder::OutputFormat;
+
+    let (_dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    let options = ConversionOptions {
+        verify_output: true,
+        ..ConversionOptions::default()
+    };
+    let summary = convert_images_to_webp(&input, false, &options).await;
+    assert_eq!((summary.converted, summary.failed), (1, 0));
+    let output = output_of(&input.join("opaque.jpg"), "opaque.webp");
+    let good = fs::read(&output).unwrap();
+    verify_written(&output, (160, 120), OutputFormat::Webp)
+        .await
+        .unwrap();
+    assert!(output.exists());
+
+    // A write cut short, then one that decodes at the wrong size.
+    for (bytes, dimensions, reason) in [
+        (&good[..good.len() / 2], (160, 120), "can't be decoded"),
+        (
+            &good[..],
+            (120, 160),
+            "decodes to 160x120 instead of 120x160",
+        ),
+    ] {
+        fs::write(&output, bytes).unwrap();
+        let error = verify_written(&output, dimensions, OutputFormat::Webp)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains(reason), "{}", error);
+        assert!(!output.exists(), "{}", reason);
+    }
+}
+
+#[cfg(not(feature = "heif"))]
+#[tokio::test]
+async fn heic_photos_are_counted_as_skipped_without_the_heif_feature() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "a.jpg")]);
+    fs::write(input.join("IMG_0001.HEIC"), b"ftypheic").unwrap();
+
+    let summary = convert_images_to_webp(&input, false, &ConversionOptions::default()).await;
+
+    assert_eq!(
+        (
+            summary.matched,
+            summary.converted,
+            summary.skipped,
+            summary.failed
+        ),
+        (2, 1, 1, 0)
+    );
+    let error = convert_single_photo(input.join("IMG_0001.HEIC"), &ConversionOptions::default())
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("`--features heif`"), "{}", error);
+}
+
+// `rotated.heic` is a 4x6 HEVC picture, stored as raw PCM samples cropped from 8x8: a
+// white top row over black. Its `irot` box turns it a quarter anticlockwise, into 6x4
+// with a white left column. A 6 pixel RGBA row is 24 bytes, short of libheif's stride.
+#[cfg(feature = "heif")]
+#[tokio::test]
+async fn heic_photos_are_decoded_upright_row_by_row() {
+    let bytes = fs::read(fixture("rotated.heic")).unwrap();
+    let decoded = webp_converter::heif::decode(&bytes).unwrap().to_rgba8();
+    assert_eq!(decoded.dimensions(), (6, 4));
+    for (x, y, pixel) in decoded.enumerate_pixels() {
+        let white = x == 0;
+        assert!(
+            pixel.0[..3]
+                .iter()
+                .all(|&c| if white { c > 200 } else { c < 55 }),
+            "pixel ({}, {}) is {:?}",
+            x,
+            y,
+            pixel
+        );
+    }
+
+    let (_dir, input) = workspace(&[("rotated.heic", "IMG_0001.HEIC")]);
+    let summary = convert_images_to_webp(&input, false, &ConversionOptions::default()).await;
+    assert_eq!((summary.converted, summary.failed), (1, 0));
+    assert_webp(
+        &output_of(&input.join("IMG_0001.HEIC"), "IMG_0001.webp"),
+        (6, 4),
+    );
+}
+
+#[tokio::test]
+async fn file_and_in_memory_sources_encode_alike() {
+    let (_dir, input) = workspace(&[("transparent.png", "transparent.png")]);
+    let source = input.join("transparent.png");
+    let options = ConversionOptions {
+        deterministic: true,
+        ..ConversionOptions::default()
+    };
+
+    let record = convert_single_photo(&source, &options).await.unwrap();
+    let from_reader =
+        ImageSource::from_reader("transparent.png", fs::File::open(&source).unwrap()).unwrap();
+    let in_memory = convert_bytes(&fs::read(&source).unwrap(), "transparent.png", &options)
+        .await
+        .unwrap();
+
+    assert_eq!(from_reader.input_size(), record.input_size);
+    assert_eq!(from_reader.decode(DEFAULT_MAX_PIXELS).unwrap().width(), 96);
+    assert_eq!(in_memory, fs::read(&record.output).unwrap());
+}
+
+#[tokio::test]
+async fn lqip_placeholders_are_written_keyed_by_source_path() {
+    let (_dir, input) = workspace(&[
+        ("opaque.jpg", "opaque.jpg"),
+        ("transparent.png", "icons/transparent.png"),
+    ]);
+    let options = ConversionOptions {
+        lqip: true,
+        ..ConversionOptions::default()
+    };
+
+    convert_images_to_webp(&input, true, &options).await;
+
+    let manifest = fs::read_to_string(output_of(
+        &input.join("opaque.jpg"),
+        LQIP_MANIFEST_FILE_NAME,
+    ))
+    .unwrap();
+    let manifest: BTreeMap<String, String> = serde_json::from_str(&manifest).unwrap();
+    assert_eq!(
+        manifest.keys().collect::<Vec<_>>(),
+        ["icons/transparent.png", "opaque.jpg"]
+    );
+    for uri in manifest.values() {
+        let encoded = uri.strip_prefix("data:image/webp;base64,").unwrap();
+        assert!(encoded.len() < 400, "{} base64 bytes", encoded.len());
+    }
+}
+
+#[tokio::test]
+async fn output_dir_name_renames_the_output_folder() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "opaque.jpg"), ("gray.tiff", "a/gray.tiff")]);
+    let options = ConversionOptions {
+        output_dir_name: "webp".to_string(),
+        ..ConversionOptions::default()
+    };
+
+    let summary = convert_images_to_webp(&input, true, &options).await;
+
+    assert_eq!(summary.converted, 2);
+    assert_webp(&input.join("webp/opaque.webp"), (160, 120));
+    assert_webp(&input.join("a/webp/gray.webp"), (128, 96));
+    assert!(!output_of(&input.join("opaque.jpg"), "opaque.webp").exists());
+    for name in ["a/b", "..", "", "/abs", "x\\y"] {
+        assert!(validate_output_dir_name(name).is_err(), "{:?}", name);
+    }
+}
+
+#[tokio::test]
+async fn output_dir_collects_every_output_in_one_folder() {
+    let (dir, input) = workspace(&[
+        ("opaque.jpg", "opaque.jpg"),
+        ("gray.tiff", "a/b/gray.tiff"),
+        (WEBP_FIXTURE, WEBP_FIXTURE),
+    ]);
+    let out = dir.path().join("site/img");
+    let options = ConversionOptions {
+        output_dir: Some(out.clone()),
+        ..ConversionOptions::default()
+    };
+
+    let summary = convert_images_to_webp(&input, true, &options).await;
+
+    assert_eq!((summary.converted, summary.copied), (2, 1));
+    assert_webp(&out.join("opaque.webp"), (160, 120));
+    assert_webp(&out.join("gray.webp"), (128, 96));
+    assert!(out.join(WEBP_FIXTURE).exists());
+    assert!(!output_of(&input.join("opaque.jpg"), "opaque.webp").exists());
+    assert!(!input.join("a/b").join(OUTPUT_DIRECTORY_NAME).exists());
+
+    // Outputs already in an output dir under the input folder aren't picked up again.
+    let options = ConversionOptions {
+        output_dir: Some(input.join("out")),
+        ..ConversionOptions::default()
+    };
+    convert_images_to_webp(&input, true, &options).await;
+    let summary = convert_images_to_webp(&input, true, &options).await;
+    assert_eq!((summary.converted, summary.copied), (2, 1));
+}
+
+#[tokio::test]
+async fn mirror_keeps_the_source_tree_under_the_output_dir() {
+    let (dir, input) = workspace(&[
+        ("opaque.jpg", "logo.jpg"),
+        ("opaque.jpg", "2023/logo.jpg"),
+        ("gray.tiff", "2023/raw/gray.tiff"),
+    ]);
+    let out = dir.path().join("out");
+    let options = ConversionOptions {
+        output_dir: Some(out.clone()),
+        mirror: true,
+        ..ConversionOptions::default()
+    };
+
+    let summary = convert_images_to_webp(&input, true, &options).await;
+
+    assert_eq!(summary.converted, 3);
+    assert_webp(&out.join("logo.webp"), (160, 120));
+    assert_webp(&out.join("2023/logo.webp"), (160, 120));
+    assert_webp(&out.join("2023/raw/gray.webp"), (128, 96));
+}
+
+#[tokio::test]
+async fn in_place_writes_beside_the_sources_and_replace_removes_them() {
+    let (_dir, input) = workspace(&[
+        ("opaque.jpg", "a.jpg"),
+        ("gray.tiff", "nested/b.tiff"),
+        (WEBP_FIXTURE, WEBP_FIXTURE),
+    ]);
+    let options = ConversionOptions {
+        in_place: true,
+        ..ConversionOptions::default()
+    };
+    let webp = fs::read(input.join(WEBP_FIXTURE)).unwrap();
+
+    let summary = convert_images_to_webp(&input, true, &options).await;
+
+    assert_eq!((summary.converted, summary.copied), (2, 1));
+    assert_webp(&input.join("a.webp"), (160, 120));
+    assert_webp(&input.join("nested/b.webp"), (128, 96));
+    assert_eq!(fs::read(input.join(WEBP_FIXTURE)).unwrap(), webp);
+    assert!(input.join("a.jpg").exists());
+    assert!(!input.join(OUTPUT_DIRECTORY_NAME).exists());
+
+    let options = ConversionOptions {
+        replace: true,
+        ..options
+    };
+    let summary = convert_images_to_webp(&input, true, &options).await;
+
+    assert_eq!((summary.converted, summary.failed), (2, 0));
+    assert!(!input.join("a.jpg").exists());
+    assert!(!input.join("nested/b.tiff").exists());
+    assert_webp(&input.join("a.webp"), (160, 120));
+    assert_webp(&input.join("nested/b.webp"), (128, 96));
+}
+
+#[tokio::test]
+async fn in_place_refuses_sources_that_share_a_stem() {
+    let (_dir, input) = workspace(&[
+        ("opaque.jpg", "photo.jpg"),
+        ("transparent.png", "photo.png"),
+        ("gray.tiff", "other.tiff"),
+    ]);
+    let options = ConversionOptions {
+        in_place: true,
+        replace: true,
+        ..ConversionOptions::default()
+    };
+
+    let summary = convert_images_to_webp(&input, false, &options).await;
+
+    assert_eq!(
+        (summary.matched, summary.converted, summary.failed),
+        (3, 1, 2)
+    );
+    assert!(input.join("photo.jpg").exists());
+    assert!(input.join("photo.png").exists());
+    assert!(!input.join("photo.webp").exists());
+    assert!(!input.join("other.tiff").exists());
+    assert_webp(&input.join("other.webp"), (128, 96));
+}
+
+#[tokio::test]
+async fn skip_existing_only_converts_sources_newer_than_their_output() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "a.jpg"), ("gray.tiff", "b.tiff")]);
+    let hour_ago = SystemTime::now() - Duration::from_secs(60 * 60);
+    for name in ["a.jpg", "b.tiff"] {
+        fs::File::options()
+            .write(true)
+            .open(input.join(name))
+            .unwrap()
+            .set_modified(hour_ago)
+            .unwrap();
+    }
+    let options = ConversionOptions {
+        skip_existing: true,
+        ..ConversionOptions::default()
+    };
+    assert_eq!(
+        convert_images_to_webp(&input, false, &options)
+            .await
+            .converted,
+        2
+    );
+
+    fs::File::options()
+        .write(true)
+        .open(input.join("b.tiff"))
+        .unwrap()
+        .set_modified(SystemTime::now() + Duration::from_secs(60))
+        .unwrap();
+    let summary = convert_images_to_webp(&input, false, &options).await;
+
+    assert_eq!((summary.converted, summary.skipped), (1, 1));
+}
+
+#[tokio::test]
+async fn cache_reconverts_only_changed_content_or_settings() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "a.jpg"), ("gray.tiff", "b.tiff")]);
+    let options = ConversionOptions {
+        cache: Some(CACHE_FILE_NAME.into()),
+        ..ConversionOptions::default()
+    };
+    let run = |options: ConversionOptions| {
+        let input = input.clone();
+        async move {
+            let summary = convert_images_to_webp(&input, false, &options).await;
+            (summary.converted, summary.skipped)
+        }
+    };
+
+    assert_eq!(run(options.clone()).await, (2, 0));
+    assert!(input.join(CACHE_FILE_NAME).exists());
+    assert_eq!(run(options.clone()).await, (0, 2));
+
+    // Rewritten with the same bytes, `b.tiff` is newer but unchanged.
+    fs::copy(fixture("gray.tiff"), input.join("b.tiff")).unwrap();
+    let mut jpeg = fs::read(input.join("a.jpg")).unwrap();
+    jpeg.extend_from_slice(b"trailing bytes");
+    fs::write(input.join("a.jpg"), jpeg).unwrap();
+    assert_eq!(run(options.clone()).await, (1, 1));
+
+    let options = ConversionOptions {
+        quality: 50.0,
+        ..options
+    };
+    assert_eq!(run(options.clone()).await, (2, 0));
+    fs::remove_file(output_of(&input.join("b.tiff"), "b.webp")).unwrap();
+    assert_eq!(run(options).await, (1, 1));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn fifo_inputs_are_read_whole_and_converted() {
+    let (_dir, input) = workspace(&[]);
+    let pipe = input.join("stream.png");
+    let status = std::process::Command::new("mkfifo")
+        .arg(&pipe)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let feed = |bytes: Vec<u8>| {
+        let pipe = pipe.clone();
+        std::thread::spawn(move || fs::write(pipe, bytes).unwrap())
+    };
+    let png = fs::read(common::fixture("transparent.png")).unwrap();
+
+    let writer = feed(png.clone());
+    let record = convert_single_photo(&pipe, &ConversionOptions::default())
+        .await
+        .unwrap();
+    writer.join().unwrap();
+    assert_eq!(record.input_size, png.len() as u64);
+    assert_webp(&output_of(&pipe, "stream.webp"), (96, 96));
+
+    // A writer that stops mid-image.
+    let writer = feed(png[..png.len() / 2].to_vec());
+    let error = convert_single_photo(&pipe, &ConversionOptions::default())
+        .await
+        .unwrap_err();
+    writer.join().unwrap();
+    assert!(error.to_string().contains("closed mid-image"), "{}", error);
+}
+
+#[tokio::test]
+async fn overwrite_if_smaller_keeps_the_smaller_output() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    let source = input.join("opaque.jpg");
+    let output = output_of(&source, "opaque.webp");
+    let small = ConversionOptions {
+        quality: 10.0,
+        ..ConversionOptions::default()
+    };
+    convert_single_photo(&source, &small).await.unwrap();
+    let first = fs::read(&output).unwrap();
+
+    let larger = ConversionOptions {
+        quality: 95.0,
+        overwrite_if_smaller: true,
+        ..ConversionOptions::default()
+    };
+    let record = convert_single_photo(&source, &larger).await.unwrap();
+    assert_eq!(record.status, ConversionStatus::KeptExisting);
+    assert_eq!(record.output_size, first.len() as u64);
+    assert_eq!(fs::read(&output).unwrap(), first);
+
+    // An existing output bigger than the new encode is replaced.
+    fs::write(&output, vec![0; 1 << 20]).unwrap();
+    let record = convert_single_photo(&source, &larger).await.unwrap();
+    assert_eq!(record.status, ConversionStatus::Converted);
+    assert_webp(&output, (160, 120));
+}
+
+#[tokio::test]
+async fn outputs_are_renamed_into_place_once_fully_written() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    let source = input.join("opaque.jpg");
+    let output = output_of(&source, "opaque.webp");
+    let options = ConversionOptions::default();
+
+    convert_single_photo(&source, &options).await.unwrap();
+    assert_webp(&output, (160, 120));
+    assert!(!temp_path_for(&output).exists());
+
+    // A write that can't finish leaves the previous output as it was.
+    let first = fs::read(&output).unwrap();
+    fs::create_dir(temp_path_for(&output)).unwrap();
+    let quality_changed = ConversionOptions {
+        quality: 20.0,
+        ..options
+    };
+    assert!(convert_single_photo(&source, &quality_changed)
+        .await
+        .is_err());
+    assert_eq!(fs::read(&output).unwrap(), first);
+}
+
+// `/dev/full` fails every write with ENOSPC, like a full disk.
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn a_full_disk_stops_the_batch() {
+    let (_dir, input) = workspace(&[
+        ("opaque.jpg", "a.jpg"),
+        ("opaque.jpg", "b.jpg"),
+        ("opaque.jpg", "c.jpg"),
+    ]);
+    let names = ["a", "b", "c"];
+    let output_dir = output_of(&input.join("a.jpg"), "");
+    fs::create_dir_all(&output_dir).unwrap();
+    for name in names {
+        let output = output_dir.join(format!("{}.webp", name));
+        std::os::unix::fs::symlink("/dev/full", temp_path_for(&output)).unwrap();
+    }
+    let options = ConversionOptions {
+        jobs: Jobs::Fixed(1),
+        ..ConversionOptions::default()
+    };
+
+    let summary = convert_images_to_webp(&input, false, &options).await;
+
+    assert!(summary.disk_full);
+    assert_eq!((summary.failed, summary.skipped), (1, 2));
+    assert!(summary.failures[0].1.starts_with("The output disk is full"));
+    for name in names {
+        assert!(!output_dir.join(format!("{}.webp", name)).exists());
+    }
+}
+
+#[tokio::test]
+async fn cleanup_only_removes_the_empty_outputs_of_the_run() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    // An empty WebP source, copied as it is into an empty output.
+    fs::write(input.join("empty.webp"), b"").unwrap();
+    // An empty file someone else put in the output folder.
+    let other = output_of(&input.join("opaque.jpg"), "placeholder.webp");
+    fs::create_dir_all(other.parent().unwrap()).unwrap();
+    fs::write(&other, b"").unwrap();
+
+    let summary = convert_images_to_webp(&input, false, &ConversionOptions::default()).await;
+
+    assert_eq!((summary.converted, summary.copied), (1, 1));
+    assert!(!output_of(&input.join("empty.webp"), "empty.webp").exists());
+    assert!(other.exists());
+}
+
+#[tokio::test]
+async fn a_cancelled_batch_skips_what_is_left_and_cleans_nothing() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "a.jpg"), ("opaque.jpg", "b.jpg")]);
+    fs::write(input.join("empty.webp"), b"").unwrap();
+    let cancellation = Cancellation::default();
+    let options = ConversionOptions {
+        cancellation: cancellation.clone(),
+        ..ConversionOptions::default()
+    };
+    cancellation.cancel();
+
+    let summary = convert_images_to_webp(&input, false, &options).await;
+
+    assert!(summary.cancelled);
+    assert_eq!((summary.matched, summary.skipped), (3, 3));
+    assert_eq!(summary.converted + summary.copied + summary.failed, 0);
+    assert!(!output_of(&input.join("a.jpg"), "a.webp").exists());
+}
+
+// The batch's tasks are spawned; dropping its future must not leave them converting.
+#[tokio::test]
+async fn a_dropped_batch_starts_no_more_files() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "a.jpg"), ("opaque.jpg", "b.jpg")]);
+    let options = ConversionOptions {
+        jobs: Jobs::Fixed(1),
+        ..ConversionOptions::default()
+    };
+
+    // One poll spawns the tasks; a test runtime runs them only once it is idle again.
+    let mut batch = Box::pin(convert_images_to_webp(&input, false, &options));
+    assert!(futures::poll!(batch.as_mut()).is_pending());
+    drop(batch);
+    // Long enough for both files, had they been left running.
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    for name in ["a", "b"] {
+        let source = input.join(format!("{}.jpg", name));
+        assert!(!output_of(&source, &format!("{}.webp", name)).exists());
+    }
+}
+
+#[tokio::test]
+async fn limit_stops_after_enough_successes_not_attempts() {
+    let (_dir, input) = workspace(&[
+        ("opaque.jpg", "b.jpg"),
+        ("opaque.jpg", "c.jpg"),
+        ("opaque.jpg", "d.jpg"),
+    ]);
+    fs::write(input.join("a.png"), b"not a png").unwrap();
+    let options = ConversionOptions {
+        jobs: Jobs::Fixed(1),
+        sort: Some(SortOrder::Name),
+        limit: Some(2),
+        ..ConversionOptions::default()
+    };
+
+    let summary = convert_images_to_webp(&input, false, &options).await;
+
+    assert_eq!(summary.stopped_at_limit, Some(2));
+    assert_eq!((summary.failed, summary.converted), (1, 2));
+    assert_eq!(summary.skipped, 1);
+    assert!(output_of(&input.join("c.jpg"), "c.webp").exists());
+    assert!(!output_of(&input.join("d.jpg"), "d.webp").exists());
+
+    // A limit the batch never reaches doesn't stop it.
+    let options = ConversionOptions {
+        limit: Some(10),
+        ..options
+    };
+    let summary = convert_images_to_webp(&input, false, &options).await;
+    assert_eq!((summary.stopped_at_limit, summary.converted), (None, 3));
+}
+
+#[tokio::test]
+async fn limit_counts_conversions_not_copies() {
+    let (_dir, input) = workspace(&[
+        (WEBP_FIXTURE, "already.webp"),
+        ("opaque.jpg", "opaque.jpg"),
+        ("palette.png", "palette.png"),
+    ]);
+    let options = ConversionOptions {
+        jobs: Jobs::Fixed(1),
+        sort: Some(SortOrder::Name),
+        limit: Some(1),
+        ..ConversionOptions::default()
+    };
+
+    let summary = convert_images_to_webp(&input, false, &options).await;
+
+    assert_eq!(summary.stopped_at_limit, Some(1));
+    assert_eq!(
+        (summary.copied, summary.converted, summary.skipped),
+        (1, 1, 1)
+    );
+    assert!(output_of(&input.join("opaque.jpg"), "opaque.webp").exists());
+    assert!(!output_of(&input.join("palette.png"), "palette.webp").exists());
+}
+
+// Converts `image` to lossless WebP in memory with `options` and decodes it back.
+async fn lossless_round_trip(
+    image: image::DynamicImage,
+    options: ConversionOptions,
+) -> image::RgbaImage {
+    let mut png = std::io::Cursor::new(vec![]);
+    image.write_to(&mut png, image::ImageFormat::Png).unwrap();
+    let options = ConversionOptions {
+        lossless: 1,
+        ..options
+    };
+    let webp = convert_bytes(png.get_ref(), "pixels.png", &options)
+        .await
+        .unwrap();
+    webp::Decoder::new(&webp)
+        .decode()
+        .unwrap()
+        .to_image()
+        .to_rgba8()
+}
+
+#[tokio::test]
+async fn rotate_and_flip_move_the_pixels_they_say() {
+    // 3x2, black but for a red top left and a green top right corner.
+    let corners = image::RgbImage::from_fn(3, 2, |x, y| match (x, y) {
+        (0, 0) => image::Rgb([255, 0, 0]),
+        (2, 0) => image::Rgb([0, 255, 0]),
+        _ => image::Rgb([0, 0, 0]),
+    });
+    // Rotations are clockwise, and applied before the flip.
+    let cases = [
+        (Some(Rotation::Rotate90), None, (2, 3), (1, 0), (1, 2)),
+        (Some(Rotation::Rotate180), None, (3, 2), (2, 1), (0, 1)),
+        (Some(Rotation::Rotate270), None, (2, 3), (0, 2), (0, 0)),
+        (None, Some(Flip::Horizontal), (3, 2), (2, 0), (0, 0)),
+        (None, Some(Flip::Vertical), (3, 2), (0, 1), (2, 1)),
+        (
+            Some(Rotation::Rotate90),
+            Some(Flip::Horizontal),
+            (2, 3),
+            (0, 0),
+            (0, 2),
+        ),
+    ];
+    for (rotate, flip, dimensions, red, green) in cases {
+        let options = ConversionOptions {
+            rotate,
+            flip,
+            ..ConversionOptions::default()
+        };
+        let decoded = lossless_round_trip(corners.clone().into(), options).await;
+        let case = format!("{:?} {:?}", rotate, flip);
+        assert_eq!(decoded.dimensions(), dimensions, "{}", case);
+        for (x, y, pixel) in decoded.enumerate_pixels() {
+            let expected = match (x, y) {
+                at if at == red => [255, 0, 0, 255],
+                at if at == green => [0, 255, 0, 255],
+                _ => [0, 0, 0, 255],
+            };
+            assert_eq!(pixel.0, expected, "{} at ({}, {})", case, x, y);
+        }
+    }
+}
+
+#[tokio::test]
+async fn grayscale_keeps_brightness_and_alpha() {
+    let colors = [
+        [255, 0, 0, 255],
+        [0, 255, 0, 255],
+        [0, 0, 255, 255],
+        [255, 255, 255, 128],
+    ];
+    let source = image::RgbaImage::from_fn(4, 1, |x, _| image::Rgba(colors[x as usize]));
+    let options = ConversionOptions {
+        grayscale: true,
+        ..ConversionOptions::default()
+    };
+
+    let decoded = lossless_round_trip(source.into(), options).await;
+
+    assert_eq!(decoded.dimensions(), (4, 1));
+    // Rec. 709 weights: green is far brighter than red, red than blue.
+    let pixels: Vec<[u8; 4]> = decoded.pixels().map(|pixel| pixel.0).collect();
+    assert_eq!(
+        pixels,
+        [
+            [54, 54, 54, 255],
+            [182, 182, 182, 255],
+            [18, 18, 18, 255],
+            [255, 255, 255, 128]
+        ]
+    );
+}
+
+#[tokio::test]
+async fn no_alpha_composites_over_the_background() {
+    // Opaque red, fully transparent blue and half transparent white.
+    let colors = [[255, 0, 0, 255], [0, 0, 255, 0], [255, 255, 255, 128]];
+    let source = image::RgbaImage::from_fn(3, 1, |x, _| image::Rgba(colors[x as usize]));
+    let cases = [
+        (
+            None,
+            [[255, 0, 0, 255], [255, 255, 255, 255], [255, 255, 255, 255]],
+        ),
+        (
+            Some("#204080"),
+            [[255, 0, 0, 255], [32, 64, 128, 255], [144, 160, 192, 255]],
+        ),
+    ];
+    for (background, expected) in cases {
+        let options = ConversionOptions {
+            no_alpha: true,
+            background: background
+                .map(|color| color.parse().unwrap())
+                .unwrap_or_default(),
+            ..ConversionOptions::default()
+        };
+
+        let decoded = lossless_round_trip(source.clone().into(), options).await;
+
+        let pixels: Vec<[u8; 4]> = decoded.pixels().map(|pixel| pixel.0).collect();
+        assert_eq!(pixels, expected, "background {:?}", background);
+    }
+}
+
+#[tokio::test]
+async fn a_roi_is_checked_against_the_rotated_image() {
+    let jpeg = fs::read(fixture("opaque.jpg")).unwrap();
+    let options = |rotate| ConversionOptions {
+        roi: "80,20,30,110".parse().ok(),
+        rotate,
+        ..ConversionOptions::default()
+    };
+
+    // 160x120 as it is, 120x160 once rotated.
+    let error = convert_bytes(&jpeg, "opaque.jpg", &options(None))
+        .await
+        .unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .contains("--roi 80,20,30,110 doesn't fit inside the 160x120 image"),
+        "{}",
+        error
+    );
+    assert!(
+        convert_bytes(&jpeg, "opaque.jpg", &options(Some(Rotation::Rotate90)))
+            .await
+            .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn clean_output_removes_only_the_outputs_of_deleted_sources() {
+    let (_dir, input) = workspace(&[
+        ("opaque.jpg", "kept.jpg"),
+        ("opaque.jpg", "deleted.jpg"),
+        ("opaque.jpg", "nested/deleted.jpg"),
+    ]);
+    let options = ConversionOptions::default();
+    convert_images_to_webp(&input, true, &options).await;
+    let output = |source: &str, name: &str| output_of(&input.join(source), name);
+    // Not ours, and the page of a source that is still there.
+    fs::write(output("kept.jpg", "notes.txt"), b"mine").unwrap();
+    fs::write(output("kept.jpg", "kept-p2.webp"), b"").unwrap();
+    fs::remove_file(input.join("deleted.jpg")).unwrap();
+    fs::remove_file(input.join("nested/deleted.jpg")).unwrap();
+
+    let options = ConversionOptions {
+        clean_output: true,
+        ..options
+    };
+    let summary = convert_images_to_webp(&input, true, &options).await;
+
+    assert_eq!(summary.converted, 1);
+    assert!(output("kept.jpg", "kept.webp").exists());
+    assert!(output("kept.jpg", "kept-p2.webp").exists());
+    assert!(output("kept.jpg", "notes.txt").exists());
+    assert!(!output("deleted.jpg", "deleted.webp").exists());
+    assert!(!output("nested/deleted.jpg", "deleted.webp").exists());
+}
+
+#[tokio::test]
+async fn clean_output_keeps_the_size_variants_of_sources_still_there() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "kept.jpg"), ("opaque.jpg", "deleted.jpg")]);
+    let options = ConversionOptions {
+        sizes: vec![64, 128],
+        ..ConversionOptions::default()
+    };
+    convert_images_to_webp(&input, false, &options).await;
+    fs::remove_file(input.join("deleted.jpg")).unwrap();
+
+    // Cleaned on its own, a run would write the kept variants again right after.
+    let mut removed = clean_output(&input, false, &options).unwrap();
+    removed.sort();
+
+    let output = |name: &str| output_of(&input.join("kept.jpg"), name);
+    assert_eq!(
+        removed,
+        [output("deleted-128w.webp"), output("deleted-64w.webp")]
+    );
+    assert_webp(&output("kept-64w.webp"), (64, 48));
+    assert_webp(&output("kept-128w.webp"), (128, 96));
+}
+
+#[tokio::test]
+async fn empty_and_truncated_sources_are_set_aside_not_failed() {
+    let (dir, input) = workspace(&[("opaque.jpg", "good.jpg")]);
+    fs::create_dir_all(input.join("nested")).unwrap();
+    fs::write(input.join("nested/empty.png"), b"").unwrap();
+    // A header that reads fine, with the pixels cut off.
+    let jpeg = fs::read(input.join("good.jpg")).unwrap();
+    fs::write(input.join("cut.jpg"), &jpeg[..jpeg.len() / 3]).unwrap();
+    fs::write(input.join("garbage.png"), b"not a png").unwrap();
+    let quarantine = dir.path().join("quarantine");
+    let options = ConversionOptions {
+        quarantine: Some(quarantine.clone()),
+        ..ConversionOptions::default()
+    };
+
+    let summary = convert_images_to_webp(&input, true, &options).await;
+
+    assert_eq!((summary.converted, summary.failed), (1, 1));
+    assert_eq!(summary.failures[0].0, input.join("garbage.png"));
+    let mut broken: Vec<_> = summary.broken.iter().map(|(path, _)| path).collect();
+    broken.sort();
+    assert_eq!(
+        broken,
+        [&input.join("cut.jpg"), &input.join("nested/empty.png")]
+    );
+    assert!(summary
+        .to_string()
+        .contains("broken (empty or truncated): 2"));
+    assert!(quarantine.join("cut.jpg").exists());
+    assert!(quarantine.join("nested/empty.png").exists());
+    assert!(!input.join("cut.jpg").exists());
+    assert!(input.join("garbage.png").exists());
+
+    fs::write(input.join("empty.jpg"), b"").unwrap();
+    let error = convert_single_photo(input.join("empty.jpg"), &ConversionOptions::default())
+        .await
+        .unwrap_err();
+    assert!(error.is_broken_source(), "{}", error);
+}
+
+// A PNG whose header says it is `width`x`height` RGBA, cut off 16 bytes into its pixels:
+// one stored deflate block that isn't the last.
+fn cut_off_png(path: &Path, width: u32, height: u32) {
+    let mut encoder = png::Encoder::new(fs::File::create(path).unwrap(), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    let mut writer = encoder.write_header().unwrap();
+    let mut data = vec![0x78, 0x01, 0x00, 16, 0, !16, 0xFF];
+    data.extend_from_slice(&[0; 16]);
+    writer.write_chunk(png::chunk::IDAT, &data).unwrap();
+}
+
+#[tokio::test]
+async fn images_over_max_pixels_are_refused_before_they_are_decoded() {
+    let (_dir, input) = workspace(&[]);
+    let png = input.join("huge.png");
+    cut_off_png(&png, 20_000, 20_000);
+
+    let error = convert_single_photo(&png, &ConversionOptions::default())
+        .await
+        .unwrap_err();
+
+    assert!(
+        error.to_string().contains("--max-pixels limit"),
+        "{}",
+        error
+    );
+    assert!(!error.is_broken_source(), "{}", error);
+}
+
+#[tokio::test]
+async fn max_pixels_sets_what_the_decoder_may_allocate() {
+    let (_dir, input) = workspace(&[]);
+    // 576 MB of RGBA, over `image`'s own 512 MiB limit.
+    let png = input.join("large.png");
+    cut_off_png(&png, 12_000, 12_000);
+
+    for max_pixels in [0, 200_000_000] {
+        let options = ConversionOptions {
+            max_pixels,
+            ..ConversionOptions::default()
+        };
+        let error = convert_single_photo(&png, &options).await.unwrap_err();
+
+        // The decoder gets as far as the missing pixels instead of refusing to allocate.
+        assert!(error.is_broken_source(), "{}", error);
+    }
+}
+
+#[tokio::test]
+async fn sources_that_decode_into_an_unsupported_feature_are_failed_not_set_aside() {
+    let (dir, input) = workspace(&[]);
+    // The gray fixture with its compression tag changed to CCITT Huffman, which the
+    // header reads fine but the TIFF decoder doesn't support.
+    let mut tiff = fs::read(fixture("gray.tiff")).unwrap();
+    assert_eq!(&tiff[..2], b"II");
+    let ifd = u32::from_le_bytes(tiff[4..8].try_into().unwrap()) as usize;
+    let entries = u16::from_le_bytes([tiff[ifd], tiff[ifd + 1]]) as usize;
+    let compression = (0..entries)
+        .map(|i| ifd + 2 + i * 12)
+        .find(|&entry| tiff[entry..entry + 2] == 259u16.to_le_bytes())
+        .unwrap();
+    tiff[compression + 8..compression + 10].copy_from_slice(&2u16.to_le_bytes());
+    fs::write(input.join("fax.tiff"), tiff).unwrap();
+    let quarantine = dir.path().join("quarantine");
+    let options = ConversionOptions {
+        quarantine: Some(quarantine.clone()),
+        ..ConversionOptions::default()
+    };
+
+    let summary = convert_images_to_webp(&input, true, &options).await;
+
+    assert_eq!(summary.failed, 1);
+    assert!(summary.broken.is_empty(), "{:?}", summary.broken);
+    assert!(input.join("fax.tiff").exists());
+    assert!(!quarantine.exists());
+}
+
+#[tokio::test]
+async fn tar_archives_the_outputs_under_their_source_folders() {
+    let (dir, input) = workspace(&[
+        ("opaque.jpg", "a.jpg"),
+        ("opaque.jpg", "nested/b.jpg"),
+        (WEBP_FIXTURE, WEBP_FIXTURE),
+    ]);
+    let archive = dir.path().join("outputs.tar");
+    let options = ConversionOptions {
+        tar: Some(archive.clone()),
+        ..ConversionOptions::default()
+    };
+
+    convert_images_to_webp(&input, true, &options).await;
+
+    let mut tar = tar::Archive::new(fs::File::open(&archive).unwrap());
+    let mut names: Vec<String> = tar
+        .entries()
+        .unwrap()
+        .map(|entry| entry.unwrap().path().unwrap().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["a.webp", WEBP_FIXTURE, "nested/b.webp"]);
+}
+
+#[tokio::test]
+async fn copies_are_totalled_apart_from_the_converted_bytes() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "a.jpg"), (WEBP_FIXTURE, WEBP_FIXTURE)]);
+    fs::write(input.join("notes.txt"), b"not an image").unwrap();
+    let options = ConversionOptions {
+        copy_others: true,
+        ..ConversionOptions::default()
+    };
+
+    let summary = convert_images_to_webp(&input, false, &options).await;
+
+    let size = |path: std::path::PathBuf| fs::metadata(path).unwrap().len();
+    let jpeg = size(input.join("a.jpg"));
+    let converted = size(output_of(&input.join("a.jpg"), "a.webp"));
+    let webp = size(input.join(WEBP_FIXTURE));
+    assert_eq!(
+        (
+            summary.converted_input_bytes,
+            summary.converted_output_bytes
+        ),
+        (jpeg, converted)
+    );
+    assert_eq!(
+        (summary.copied_image_bytes, summary.copied_other_bytes),
+        (webp, 12)
+    );
+    assert_eq!(summary.input_bytes, jpeg + webp + 12);
+    assert_eq!(summary.ratios.len(), 1);
+    assert!(summary
+        .to_string()
+        .contains(&format!("{} -> {} bytes converted", jpeg, converted)));
+}
+
+// A TIFF with three gray pages, 16x8, 24x12 and 32x16.
+fn multi_page_tiff(path: &Path) {
+    let mut encoder = tiff::encoder::TiffEncoder::new(fs::File::create(path).unwrap()).unwrap();
+    for width in [16, 24, 32] {
+        let pixels = vec![128; (width * width / 2 * 3) as usize];
+        encoder
+            .write_image::<tiff::encoder::colortype::RGB8>(width, width / 2, &pixels)
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn multi_page_tiffs_convert_every_page_or_the_one_asked_for() {
+    let (_dir, input) = workspace(&[]);
+    let scan = input.join("scan.tif");
+    multi_page_tiff(&scan);
+    let output = |name: &str| output_of(&scan, name);
+
+    convert_single_photo(&scan, &ConversionOptions::default())
+        .await
+        .unwrap();
+    assert_webp(&output("scan.webp"), (16, 8));
+
+    let pages = |pages: &str| ConversionOptions {
+        tiff_pages: Some(pages.parse::<TiffPages>().unwrap()),
+        ..ConversionOptions::default()
+    };
+    convert_single_photo(&scan, &pages("2")).await.unwrap();
+    assert_webp(&output("scan.webp"), (24, 12));
+
+    let summary = convert_images_to_webp(&input, false, &pages("all")).await;
+    assert_eq!((summary.converted, summary.failed), (1, 0));
+    for (page, size) in [(1, (16, 8)), (2, (24, 12)), (3, (32, 16))] {
+        assert_webp(&output(&format!("scan-p{}.webp", page)), size);
+    }
+
+    let error = convert_single_photo(&scan, &pages("4")).await.unwrap_err();
+    assert!(error.to_string().contains("has 3 page(s)"), "{}", error);
+    assert!("0".parse::<TiffPages>().is_err());
+}
+
+#[tokio::test]
+async fn tiff_pages_go_through_the_still_image_steps() {
+    let (_dir, input) = workspace(&[]);
+    let scan = input.join("scan.tif");
+    multi_page_tiff(&scan);
+    let output = |name: &str| output_of(&scan, name);
+
+    // The name a `--batch-spec` row picks applies to every page.
+    let options = ConversionOptions {
+        tiff_pages: Some(TiffPages::All),
+        output_name: Some("fax".to_string()),
+        sizes: vec![16],
+        ..ConversionOptions::default()
+    };
+    convert_single_photo(&scan, &options).await.unwrap();
+    for page in 1..=3 {
+        assert_webp(&output(&format!("fax-p{}-16w.webp", page)), (16, 8));
+    }
+
+    // A page that doesn't shrink enough keeps the TIFF, like any still image.
+    let options = ConversionOptions {
+        tiff_pages: Some(TiffPages::Page(3)),
+        min_savings_percent: 99.9,
+        ..ConversionOptions::default()
+    };
+    let record = convert_single_photo(&scan, &options).await.unwrap();
+    assert_eq!(record.status, ConversionStatus::KeptOriginal);
+    assert_eq!(fs::read(&record.output).unwrap(), fs::read(&scan).unwrap());
+    assert!(!output("scan.webp").exists());
+}
+
+#[tokio::test]
+async fn estimates_keep_originals_that_save_too_little_at_any_size() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "small.jpg")]);
+    // Over the 512x512 proxy size, so it is estimated from proxies.
+    let large = input.join("large.png");
+    image::RgbImage::from_fn(1024, 768, |x, y| {
+        image::Rgb([(x ^ y) as u8, x as u8, y as u8])
+    })
+    .save(&large)
+    .unwrap();
+    let options = ConversionOptions {
+        min_savings_percent: 99.9,
+        ..ConversionOptions::default()
+    };
+
+    for source in [input.join("small.jpg"), large] {
+        let input_size = fs::metadata(&source).unwrap().len();
+        let estimate = estimate_single_photo(&source, &options).await.unwrap();
+        assert_eq!(estimate, (input_size, input_size), "{:?}", source);
+        let record = convert_single_photo(&source, &options).await.unwrap();
+        assert_eq!(record.status, ConversionStatus::KeptOriginal);
+    }
+}
+
+#[tokio::test]
+async fn tiff_pages_over_max_pixels_are_refused_before_any_page_is_converted() {
+    let (_dir, input) = workspace(&[]);
+    let scan = input.join("scan.tif");
+    multi_page_tiff(&scan);
+    // Only the 32x16 last page is over it.
+    let options = |pages: TiffPages| ConversionOptions {
+        tiff_pages: Some(pages),
+        max_pixels: 300,
+        ..ConversionOptions::default()
+    };
+
+    let error = convert_single_photo(&scan, &options(TiffPages::All))
+        .await
+        .unwrap_err();
+    assert!(
+        error.to_string().contains("--max-pixels limit"),
+        "{}",
+        error
+    );
+    assert!(!output_of(&scan, "scan-p1.webp").exists());
+
+    let error = convert_single_photo(&scan, &options(TiffPages::Page(3)))
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("32x16"), "{}", error);
+    convert_single_photo(&scan, &options(TiffPages::Page(2)))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn sizes_write_one_variant_per_width() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    let source = input.join("opaque.jpg");
+    let options = |sizes: &[u32]| ConversionOptions {
+        sizes: sizes.to_vec(),
+        ..ConversionOptions::default()
+    };
+
+    let summary = convert_images_to_webp(&input, false, &options(&[40, 80, 320])).await;
+
+    assert_eq!((summary.converted, summary.failed), (1, 0));
+    assert_webp(&output_of(&source, "opaque-40w.webp"), (40, 30));
+    assert_webp(&output_of(&source, "opaque-80w.webp"), (80, 60));
+    assert!(!output_of(&source, "opaque-320w.webp").exists());
+    assert!(!output_of(&source, "opaque.webp").exists());
+
+    // Narrower than every width, it's written once as it is.
+    let record = convert_single_photo(&source, &options(&[320, 640]))
+        .await
+        .unwrap();
+    assert_eq!(record.output, output_of(&source, "opaque-160w.webp"));
+    assert_webp(&record.output, (160, 120));
+}
+
+#[cfg(not(feature = "pdf"))]
+#[tokio::test]
+async fn pdfs_are_counted_as_skipped_without_the_pdf_feature() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "photo.jpg")]);
+    fs::write(input.join("scan.pdf"), b"%PDF-1.4").unwrap();
+
+    let summary = convert_images_to_webp(&input, false, &ConversionOptions::default()).await;
+
+    assert_eq!((summary.converted, summary.skipped), (1, 1));
+}
+
+#[cfg(not(feature = "raw"))]
+#[tokio::test]
+async fn raw_photos_are_counted_as_skipped_without_the_raw_feature() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "photo.jpg")]);
+    fs::write(input.join("shot.nef"), b"not really raw").unwrap();
+
+    let summary = convert_images_to_webp(&input, false, &ConversionOptions::default()).await;
+
+    assert_eq!((summary.converted, summary.skipped), (1, 1));
+}
+
+#[cfg(feature = "avif")]
+#[tokio::test]
+async fn webp_and_avif_are_written_from_one_decode() {
+    use webp_converter::encoder::OutputFormat;
+
+    let (_dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    let source = input.join("opaque.jpg");
+    let options = ConversionOptions {
+        extra_formats: vec![OutputFormat::Avif],
+        verify_output: true,
+        ..ConversionOptions::default()
+    };
+
+    let summary = convert_images_to_webp(&input, false, &options).await;
+
+    assert_eq!((summary.converted, summary.failed), (1, 0));
+    assert_webp(&output_of(&source, "opaque.webp"), (160, 120));
+    let avif = fs::read(output_of(&source, "opaque.avif")).unwrap();
+    assert_eq!(&avif[4..12], b"ftypavif");
+    assert_eq!(
+        OutputFormat::Avif.decoded_dimensions(&avif).unwrap(),
+        (160, 120)
+    );
+}
+
+#[tokio::test]
+async fn quality_map_sets_the_quality_per_extension() {
+    let files = [("opaque.jpg", "photo.jpg"), ("gray.tiff", "scan.tiff")];
+    let run = |quality, quality_map: &str| {
+        let options = ConversionOptions {
+            quality,
+            quality_map: quality_map.parse::<QualityMap>().unwrap(),
+            deterministic: true,
+            ..ConversionOptions::default()
+        };
+        async move {
+            let (dir, input) = workspace(&files);
+            let summary = convert_images_to_webp(&input, false, &options).await;
+            assert_eq!((summary.converted, summary.failed), (2, 0));
+            let size = |source: &str, output: &str| {
+                fs::metadata(output_of(&input.join(source), output))
+                    .unwrap()
+                    .len()
+            };
+            let sizes = (
+                size("photo.jpg", "photo.webp"),
+                size("scan.tiff", "scan.webp"),
+            );
+            drop(dir);
+            sizes
+        }
+    };
+
+    let (low_photo, low_scan) = run(10.0, "").await;
+    let (high_photo, high_scan) = run(95.0, "").await;
+    assert!(low_photo < high_photo && low_scan < high_scan);
+    // `.JPG` and a leading dot are accepted, `tiff` is left to -q.
+    assert_eq!(run(95.0, ".JPG=10").await, (low_photo, high_scan));
+    assert_eq!(run(10.0, "jpg=95, tiff=95").await, (high_photo, high_scan));
+
+    for invalid in ["jpg=400", "garbage", "jpg=high"] {
+        assert!(invalid.parse::<QualityMap>().is_err(), "{}", invalid);
+    }
+}
+
+#[tokio::test]
+async fn icons_convert_their_largest_size_or_every_size() {
+    // favicon.ico holds 32x32, 16x16, 48x48 and 24x24 PNGs, in that order.
+    let (_dir, input) = workspace(&[("favicon.ico", "favicon.ico")]);
+    let icon = input.join("favicon.ico");
+
+    let summary = convert_images_to_webp(&input, false, &ConversionOptions::default()).await;
+
+    assert_eq!((summary.converted, summary.failed), (1, 0));
+    assert_webp(&output_of(&icon, "favicon.webp"), (48, 48));
+    assert!(!output_of(&icon, "favicon-48x48.webp").exists());
+
+    let (_dir, input) = workspace(&[("favicon.ico", "favicon.ico")]);
+    let icon = input.join("favicon.ico");
+    let options = ConversionOptions {
+        ico_all_sizes: true,
+        ..ConversionOptions::default()
+    };
+
+    let summary = convert_images_to_webp(&input, false, &options).await;
+
+    assert_eq!((summary.converted, summary.failed), (1, 0));
+    let mut written: Vec<_> = fs::read_dir(output_of(&icon, "."))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    written.sort();
+    assert_eq!(
+        written,
+        [
+            "favicon-16x16.webp",
+            "favicon-24x24.webp",
+            "favicon-32x32.webp",
+            "favicon-48x48.webp"
+        ]
+    );
+    for size in [16, 24, 32, 48] {
+        let name = format!("favicon-{}x{}.webp", size, size);
+        assert_webp(&output_of(&icon, &name), (size, size));
+    }
+}
+
+#[tokio::test]
+async fn convert_stream_converts_paths_as_they_arrive() {
+    for copy_others in [false, true] {
+        let (_dir, input) =
+            workspace(&[("opaque.jpg", "opaque.jpg"), (WEBP_FIXTURE, "already.webp")]);
+        fs::write(input.join("notes.txt"), "not an image").unwrap();
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        let options = ConversionOptions {
+            copy_others,
+            ..ConversionOptions::default()
+        };
+        let results = convert_stream(receiver, options);
+        for name in ["opaque.jpg", "already.webp", "notes.txt"] {
+            sender.unbounded_send(input.join(name)).unwrap();
+        }
+        drop(sender);
+
+        let mut records = vec![];
+        let mut errors = vec![];
+        for result in results.collect::<Vec<_>>().await {
+            match result {
+                Ok(record) => records.push(record),
+                Err(error) => errors.push(error.to_string()),
+            }
+        }
+        records.sort_by(|a, b| a.input.cmp(&b.input));
+
+        let statuses: Vec<_> = records
+            .iter()
+            .map(|r| (r.input.file_name().unwrap().to_str().unwrap(), r.status))
+            .collect();
+        let expected = if copy_others {
+            assert!(errors.is_empty(), "{:?}", errors);
+            assert_eq!(
+                fs::read(output_of(&input.join("notes.txt"), "notes.txt")).unwrap(),
+                b"not an image"
+            );
+            vec![
+                ("already.webp", ConversionStatus::Copied),
+                ("notes.txt", ConversionStatus::Copied),
+                ("opaque.jpg", ConversionStatus::Converted),
+            ]
+        } else {
+            assert_eq!(errors.len(), 1);
+            assert!(
+                errors[0].contains("Not a valid image file"),
+                "{}",
+                errors[0]
+            );
+            vec![
+                ("already.webp", ConversionStatus::Copied),
+                ("opaque.jpg", ConversionStatus::Converted),
+            ]
+        };
+        assert_eq!(statuses, expected);
+        let copy = &records[0];
+        assert_eq!(
+            copy.output,
+            output_of(&input.join("already.webp"), "already.webp")
+        );
+        assert_eq!(copy.input_size, copy.output_size);
+        assert_webp(&records.last().unwrap().output, (160, 120));
+    }
+}
+
+#[tokio::test]
+async fn hash_names_name_outputs_by_content_and_list_them_in_a_manifest() {
+    let (_dir, input) = workspace(&[
+        ("opaque.jpg", "opaque.jpg"),
+        ("transparent.png", "icons/transparent.png"),
+    ]);
+    let options = ConversionOptions {
+        hash_names: true,
+        deterministic: true,
+        ..ConversionOptions::default()
+    };
+    let run = || async {
+        let options = ConversionOptions {
+            manifest: Default::default(),
+            ..options.clone()
+        };
+        let summary = convert_images_to_webp(&input, true, &options).await;
+        assert_eq!((summary.converted, summary.failed), (2, 0));
+        let manifest =
+            fs::read_to_string(output_of(&input.join("opaque.jpg"), MANIFEST_FILE_NAME)).unwrap();
+        serde_json::from_str::<BTreeMap<String, String>>(&manifest).unwrap()
+    };
+
+    let first = run().await;
+
+    assert_eq!(
+        first.keys().collect::<Vec<_>>(),
+        ["icons/transparent.webp", "opaque.webp"]
+    );
+    for (source, hashed) in [
+        ("opaque.jpg", &first["opaque.webp"]),
+        ("icons/transparent.png", &first["icons/transparent.webp"]),
+    ] {
+        let output = output_of(
+            &input.join(source),
+            Path::new(hashed).file_name().unwrap().to_str().unwrap(),
+        );
+        let hash: String = Sha256::digest(fs::read(&output).unwrap())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        assert!(
+            hashed.ends_with(&format!("{}.webp", &hash[..16])),
+            "{}",
+            hashed
+        );
+    }
+    assert!(first["icons/transparent.webp"].starts_with("icons/"));
+
+    // A source that didn't change keeps its name, one that did gets a new one.
+    image::RgbaImage::from_pixel(8, 8, image::Rgba([0, 0, 255, 128]))
+        .save(input.join("icons/transparent.png"))
+        .unwrap();
+    let second = run().await;
+    assert_eq!(second["opaque.webp"], first["opaque.webp"]);
+    assert_ne!(
+        second["icons/transparent.webp"],
+        first["icons/transparent.webp"]
+    );
+}
@@ -0,0 +1,257 @@
+mod common;
+
+use common::fixture;
+use webp_converter::encoder::{webp_config, Channels, Chroma, Encoder, WebpEncoder};
+use webp_converter::types::{EncodeOptions, Roi};
+
+// Flat color sprites fit a 256 color palette; a lossless encode of them should rebuild
+// the palette instead of spending bytes on the RGBA expansion the decoder hands over.
+// palette.png is too small to show it, so this sheet has 200 colors over 256x256.
+#[test]
+fn palette_sources_beat_a_plain_lossless_encode() {
+    let sheet = image::RgbaImage::from_fn(256, 256, |x, y| {
+        let c = ((x / 4) * 7 + (y / 3) * 13 + (x ^ y) % 5) % 200;
+        image::Rgba([
+            (c * 37 % 256) as u8,
+            (c * 91 % 256) as u8,
+            (c * 53 % 256) as u8,
+            255,
+        ])
+    });
+    let options = EncodeOptions {
+        lossless: 1,
+        ..EncodeOptions::default()
+    };
+    // The settings any other lossless image gets.
+    let plain = webp::Encoder::from_rgba(&sheet, sheet.width(), sheet.height())
+        .encode_advanced(&webp_config(&options))
+        .unwrap();
+
+    let encoded = WebpEncoder
+        .encode(&image::DynamicImage::ImageRgba8(sheet), &options)
+        .unwrap();
+
+    assert!(
+        encoded.len() < plain.len(),
+        "{} bytes, plain lossless is {}",
+        encoded.len(),
+        plain.len()
+    );
+}
+
+#[test]
+fn palette_sources_stay_lossy_when_lossy_is_asked_for() {
+    let img = image::open(fixture("palette.png")).unwrap();
+
+    let encoded = WebpEncoder.encode(&img, &EncodeOptions::default()).unwrap();
+
+    assert_eq!(&encoded[12..16], b"VP8 ");
+}
+
+#[test]
+fn opaque_photos_stay_lossy() {
+    let img = image::open(fixture("opaque.jpg")).unwrap();
+
+    let encoded = WebpEncoder.encode(&img, &EncodeOptions::default()).unwrap();
+
+    // A lossy stream is a `VP8 ` chunk, lossless ones are `VP8L`.
+    assert_eq!(&encoded[12..16], b"VP8 ");
+}
+
+#[test]
+fn sharp_chroma_turns_on_sharp_yuv_for_lossy_only() {
+    let config = |chroma, lossless| {
+        webp_config(&EncodeOptions {
+            chroma,
+            lossless,
+            ..EncodeOptions::default()
+        })
+    };
+
+    assert_eq!(config(Chroma::Fast, 0).use_sharp_yuv, 0);
+    assert_eq!(config(Chroma::Sharp, 0).use_sharp_yuv, 1);
+    // Lossless has no YUV conversion, the setting changes nothing there.
+    assert_eq!(config(Chroma::Sharp, 1).use_sharp_yuv, 0);
+}
+
+// Busy enough that every quality step shows in the size.
+fn noise() -> image::DynamicImage {
+    image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(256, 256, |x, y| {
+        let v = (x.wrapping_mul(2_654_435_761) ^ y.wrapping_mul(40_503)) as u8;
+        image::Rgb([v, (x * 3 + y) as u8, v / 2])
+    }))
+}
+
+#[test]
+fn quality_bounds_clamp_the_size_search() {
+    let img = noise();
+    let encode = |min_quality, max_quality| {
+        let options = EncodeOptions {
+            noise_ratio: 0.0,
+            target_size: 8_000,
+            min_quality,
+            max_quality,
+            ..EncodeOptions::default()
+        };
+        WebpEncoder.encode(&img, &options).unwrap().len()
+    };
+
+    let unbounded = encode(0.0, 100.0);
+    // Held at 60 or above, the search overshoots the target rather than go lower.
+    let floored = encode(60.0, 100.0);
+    assert!(floored > unbounded, "{} vs {}", floored, unbounded);
+    assert!(floored > 8_000 * 2, "{}", floored);
+    // And the ceiling is respected the other way round.
+    let capped = encode(0.0, 10.0);
+    assert!(capped <= unbounded, "{} vs {}", capped, unbounded);
+}
+
+#[test]
+fn quality_is_not_clamped_by_default() {
+    let img = noise();
+    let encode = |quality| {
+        let options = EncodeOptions {
+            quality,
+            noise_ratio: 0.0,
+            ..EncodeOptions::default()
+        };
+        WebpEncoder.encode(&img, &options).unwrap().len()
+    };
+
+    assert!(encode(90.0) > encode(10.0) * 2);
+}
+
+// Mean absolute difference of the pixels of `region` between `a` and `b`.
+fn region_error(a: &image::RgbaImage, b: &image::RgbaImage, region: Roi) -> f64 {
+    let mut total = 0u64;
+    for y in region.y..region.y + region.height {
+        for x in region.x..region.x + region.width {
+            let (a, b) = (a.get_pixel(x, y).0, b.get_pixel(x, y).0);
+            total += (0..3).map(|c| a[c].abs_diff(b[c]) as u64).sum::<u64>();
+        }
+    }
+    total as f64 / (region.width * region.height * 3) as f64
+}
+
+#[test]
+fn roi_is_encoded_sharper_than_the_rest() {
+    let img = image::open(fixture("opaque.jpg")).unwrap();
+    let original = img.to_rgba8();
+    let roi = Roi {
+        x: 40,
+        y: 32,
+        width: 80,
+        height: 56,
+    };
+    let encode = |quality, roi| {
+        let options = EncodeOptions {
+            quality,
+            noise_ratio: 0.0,
+            roi,
+            roi_quality: 95.0,
+            ..EncodeOptions::default()
+        };
+        let encoded = WebpEncoder.encode(&img, &options).unwrap();
+        let decoded = webp::Decoder::new(&encoded).decode().unwrap();
+        (encoded.len(), decoded.to_image().to_rgba8())
+    };
+
+    let (low_size, low) = encode(20.0, None);
+    let (high_size, high) = encode(95.0, None);
+    let (roi_size, boosted) = encode(20.0, Some(roi));
+
+    assert!(
+        low_size < roi_size && roi_size < high_size,
+        "{} < {} < {}",
+        low_size,
+        roi_size,
+        high_size
+    );
+    // Inside the region, as close to the source as encoding everything at 95.
+    let inside = |decoded| region_error(&original, decoded, roi);
+    assert!(inside(&boosted) < inside(&low));
+    assert!(
+        inside(&boosted) < inside(&high) * 1.05,
+        "{} vs {}",
+        inside(&boosted),
+        inside(&high)
+    );
+    // Outside of it, no closer than encoding everything at 20.
+    let corner = |decoded| {
+        let corner = Roi {
+            x: 0,
+            y: 0,
+            width: 32,
+            height: 24,
+        };
+        region_error(&original, decoded, corner)
+    };
+    assert!(
+        corner(&boosted) > corner(&low) * 0.9,
+        "{} vs {}",
+        corner(&boosted),
+        corner(&low)
+    );
+}
+
+// The second encode codes the whole frame at the region's quality, so even a single
+// macroblock costs a multiple of the plain encode, but far less than boosting it all.
+#[test]
+fn a_small_roi_costs_a_fraction_of_boosting_the_whole_image() {
+    let img = image::open(fixture("opaque.jpg")).unwrap();
+    let encode = |quality, roi| {
+        let options = EncodeOptions {
+            quality,
+            noise_ratio: 0.0,
+            roi,
+            roi_quality: 95.0,
+            ..EncodeOptions::default()
+        };
+        WebpEncoder.encode(&img, &options).unwrap().len()
+    };
+    let roi = Roi {
+        x: 0,
+        y: 0,
+        width: 8,
+        height: 8,
+    };
+
+    let plain = encode(30.0, None);
+    let boosted = encode(30.0, Some(roi));
+    let everything = encode(95.0, None);
+
+    assert!(
+        plain < boosted && boosted * 4 < everything,
+        "{} < {} < {} / 4",
+        plain,
+        boosted,
+        everything
+    );
+}
+
+#[test]
+fn forced_channels_override_what_the_image_has() {
+    let encode = |name: &str, lossless, channels| {
+        let img = image::open(fixture(name)).unwrap();
+        let options = EncodeOptions {
+            lossless,
+            channels: Some(channels),
+            ..EncodeOptions::default()
+        };
+        let encoded = WebpEncoder.encode(&img, &options).unwrap();
+        // The `image` crate calls every lossless WebP RGBA, libwebp reads the flags.
+        let has_alpha = webp::BitstreamFeatures::new(&encoded).unwrap().has_alpha();
+        assert!(webp::Decoder::new(&encoded).decode().is_some());
+        let decoded = image::load_from_memory(&encoded).unwrap().to_rgba8();
+        (has_alpha, decoded)
+    };
+
+    for lossless in [0, 1] {
+        let (has_alpha, decoded) = encode("opaque.jpg", lossless, Channels::Rgba);
+        assert!(has_alpha, "lossless {}", lossless);
+        assert!(decoded.pixels().all(|pixel| pixel[3] == u8::MAX));
+        let (has_alpha, decoded) = encode("transparent.png", lossless, Channels::Rgb);
+        assert!(!has_alpha, "lossless {}", lossless);
+        assert!(decoded.pixels().all(|pixel| pixel[3] == u8::MAX));
+    }
+}
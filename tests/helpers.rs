@@ -0,0 +1,70 @@
+use std::path::Path;
+use webp_converter::encoder::OutputFormat;
+use webp_converter::helpers::{which_action, which_action_for, Actions};
+
+#[test]
+fn every_extension_alias_is_converted() {
+    for name in [
+        "a.jpg", "a.jpeg", "a.jpe", "a.jfif", "a.JPEG", "a.Jpg", "a.png", "a.PNG", "a.tif",
+        "a.tiff", "a.bmp", "a.dib", "a.avif", "a.gif", "a.ico", "a.cur", "a.heic", "a.HEIF",
+        "a.cr2", "a.NEF", "a.arw", "a.dng",
+    ] {
+        assert_eq!(which_action(Path::new(name)), Actions::Convert, "{}", name);
+    }
+}
+
+#[test]
+fn other_files_are_copied_rendered_or_ignored() {
+    assert_eq!(which_action(Path::new("a.webp")), Actions::Copy);
+    assert_eq!(which_action(Path::new("a.WEBP")), Actions::Copy);
+    assert_eq!(which_action(Path::new("a.pdf")), Actions::Pdf);
+    assert_eq!(which_action(Path::new("a.txt")), Actions::Nothing);
+    assert_eq!(which_action(Path::new("jpg")), Actions::Nothing);
+}
+
+#[test]
+fn files_already_in_the_output_format_are_copied() {
+    let jxl = |name| which_action_for(Path::new(name), OutputFormat::Jxl);
+    assert_eq!(jxl("a.jxl"), Actions::Copy);
+    assert_eq!(jxl("a.webp"), Actions::Convert);
+    assert_eq!(jxl("a.png"), Actions::Convert);
+    // JPEG XL can't be decoded, so it is only ever copied.
+    assert_eq!(which_action(Path::new("a.jxl")), Actions::Nothing);
+}
+
+#[cfg(windows)]
+mod windows_paths {
+    use webp_converter::helpers::process_path_for_os;
+
+    #[test]
+    fn verbatim_paths_are_left_alone() {
+        assert_eq!(
+            process_path_for_os(r"\\?\C:\long\path"),
+            r"\\?\C:\long\path"
+        );
+        assert_eq!(
+            process_path_for_os(r"\\?\UNC\server\share\img.png"),
+            r"\\?\UNC\server\share\img.png"
+        );
+    }
+
+    #[test]
+    fn unc_paths_keep_their_prefix() {
+        assert_eq!(
+            process_path_for_os(r"\\server\share\img.png"),
+            r"\\server\share\img.png"
+        );
+        assert_eq!(
+            process_path_for_os("//server/share/img.png"),
+            r"\\server\share\img.png"
+        );
+        assert_eq!(process_path_for_os(r"\\server\share"), r"\\server\share");
+    }
+
+    #[test]
+    fn forward_slashes_become_backslashes() {
+        assert_eq!(process_path_for_os("C:/images/a.png"), r"C:\images\a.png");
+        assert_eq!(process_path_for_os("images/a.png"), r"images\a.png");
+        assert_eq!(process_path_for_os("C:images/a.png"), r"C:images\a.png");
+    }
+}
@@ -0,0 +1,202 @@
+mod common;
+
+use common::{exif, fixture, output_of, sideways_jpeg, workspace, ICC, XMP};
+use std::fs;
+use webp_converter::converter::{convert_bytes, convert_images_to_webp};
+use webp_converter::encoder::{Encoder, WebpEncoder};
+use webp_converter::metadata::{embed, Metadata};
+use webp_converter::types::{ConversionOptions, EncodeOptions};
+
+// Little endian EXIF whose IFD points at an empty GPS IFD right after it.
+fn exif_with_gps() -> Vec<u8> {
+    let mut exif = b"II\x2A\x00\x08\x00\x00\x00\x01\x00".to_vec();
+    exif.extend_from_slice(&[0x25, 0x88, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00]);
+    exif.extend_from_slice(&26u32.to_le_bytes());
+    exif.extend_from_slice(&[0; 4 + 6]);
+    exif
+}
+
+fn dimensions(webp: &[u8]) -> (u32, u32) {
+    let decoded = webp::Decoder::new(webp)
+        .decode()
+        .expect("libwebp decodes it");
+    (decoded.width(), decoded.height())
+}
+
+async fn convert(options: ConversionOptions) -> Vec<u8> {
+    convert_bytes(&sideways_jpeg(), "photo.jpg", &options)
+        .await
+        .unwrap()
+}
+
+#[test]
+fn source_metadata_is_read() {
+    let metadata = Metadata::read(&sideways_jpeg());
+
+    assert_eq!(metadata.orientation(), Some(6));
+    assert_eq!(metadata.icc.as_deref(), Some(ICC));
+    assert_eq!(metadata.xmp.as_deref(), Some(XMP));
+}
+
+#[tokio::test]
+async fn files_are_turned_upright_from_their_metadata_segments_alone() {
+    let (_dir, input) = workspace(&[]);
+    let jpeg = input.join("photo.jpg");
+    fs::write(&jpeg, sideways_jpeg()).unwrap();
+
+    let metadata = Metadata::read_header(&jpeg).unwrap();
+    assert_eq!(metadata.orientation(), Some(6));
+    assert_eq!(metadata.xmp.as_deref(), Some(XMP));
+
+    // In a WebP the EXIF chunk comes after the image data.
+    let img = image::open(fixture("opaque.jpg")).unwrap();
+    let encoded = WebpEncoder.encode(&img, &EncodeOptions::default()).unwrap();
+    let webp = input.join("other.webp");
+    let exif_only = Metadata {
+        exif: Some(exif(3)),
+        ..Metadata::default()
+    };
+    fs::write(&webp, embed(&encoded, &exif_only).unwrap()).unwrap();
+    assert_eq!(Metadata::read_header(&webp).unwrap(), exif_only);
+
+    convert_images_to_webp(&input, false, &ConversionOptions::default()).await;
+    let output = fs::read(output_of(&jpeg, "photo.webp")).unwrap();
+    assert_eq!(dimensions(&output), (120, 160));
+}
+
+#[tokio::test]
+async fn by_default_orientation_is_baked_in_and_everything_stripped() {
+    let webp = convert(ConversionOptions::default()).await;
+
+    assert_eq!(dimensions(&webp), (120, 160));
+    assert_eq!(Metadata::read(&webp), Metadata::default());
+    // Nothing to flag, so the file stays a simple lossy one.
+    assert_eq!(&webp[12..16], b"VP8 ");
+}
+
+#[tokio::test]
+async fn kept_exif_says_the_orientation_was_applied() {
+    let webp = convert(ConversionOptions {
+        keep_exif: true,
+        ..ConversionOptions::default()
+    })
+    .await;
+
+    assert_eq!(dimensions(&webp), (120, 160));
+    let metadata = Metadata::read(&webp);
+    assert_eq!(metadata.exif, Some(exif(1)));
+    assert_eq!((metadata.icc, metadata.xmp), (None, None));
+}
+
+#[tokio::test]
+async fn without_apply_orientation_the_pixels_and_tag_are_left_alone() {
+    let webp = convert(ConversionOptions {
+        keep_exif: true,
+        apply_orientation: false,
+        ..ConversionOptions::default()
+    })
+    .await;
+
+    assert_eq!(dimensions(&webp), (160, 120));
+    assert_eq!(Metadata::read(&webp).orientation(), Some(6));
+
+    // Without the EXIF, a viewer has nothing to rotate the stored pixels by.
+    let webp = convert(ConversionOptions {
+        apply_orientation: false,
+        ..ConversionOptions::default()
+    })
+    .await;
+    assert_eq!(dimensions(&webp), (160, 120));
+    assert!(Metadata::read(&webp).is_empty());
+}
+
+#[tokio::test]
+async fn each_kept_part_is_flagged_in_the_container() {
+    for (keep_exif, keep_icc, keep_xmp) in [
+        (false, true, false),
+        (false, false, true),
+        (true, true, true),
+    ] {
+        let webp = convert(ConversionOptions {
+            keep_exif,
+            keep_icc,
+            keep_xmp,
+            ..ConversionOptions::default()
+        })
+        .await;
+
+        assert_eq!(&webp[12..16], b"VP8X");
+        let flags = webp[20];
+        assert_eq!(flags & 0b0010_0000 != 0, keep_icc);
+        assert_eq!(flags & 0b0000_1000 != 0, keep_exif);
+        assert_eq!(flags & 0b0000_0100 != 0, keep_xmp);
+        let metadata = Metadata::read(&webp);
+        assert_eq!(metadata.icc.as_deref(), keep_icc.then_some(ICC));
+        assert_eq!(metadata.xmp.as_deref(), keep_xmp.then_some(XMP));
+        assert_eq!(dimensions(&webp), (120, 160));
+    }
+}
+
+#[test]
+fn lossless_outputs_keep_their_alpha_flag() {
+    let img = image::open(fixture("transparent.png")).unwrap();
+    let options = EncodeOptions {
+        lossless: 1,
+        ..EncodeOptions::default()
+    };
+    let encoded = WebpEncoder.encode(&img, &options).unwrap();
+    assert_eq!(&encoded[12..16], b"VP8L");
+
+    let metadata = Metadata {
+        xmp: Some(XMP.to_vec()),
+        ..Metadata::default()
+    };
+    let webp = embed(&encoded, &metadata).unwrap();
+
+    assert_eq!(webp[20] & 0b0001_0000, 0b0001_0000);
+    let decoded = webp::Decoder::new(&webp).decode().unwrap();
+    assert!(decoded.is_alpha());
+    assert_eq!(Metadata::read(&webp), metadata);
+}
+
+#[tokio::test]
+async fn strip_metadata_clears_copies_and_counts_located_files() {
+    let (_dir, input) = workspace(&[]);
+    let jpeg = fs::read(fixture("opaque.jpg")).unwrap();
+    let app1 = [b"Exif\0\0".as_slice(), &exif_with_gps()].concat();
+    let mut segment = vec![0xFF, 0xE1];
+    segment.extend_from_slice(&(app1.len() as u16 + 2).to_be_bytes());
+    segment.extend_from_slice(&app1);
+    fs::write(
+        input.join("located.jpg"),
+        [&jpeg[..2], &segment, &jpeg[2..]].concat(),
+    )
+    .unwrap();
+    fs::write(input.join("plain.jpg"), sideways_jpeg()).unwrap();
+    let webp = convert(ConversionOptions::default()).await;
+    let tagged = Metadata {
+        exif: Some(exif_with_gps()),
+        icc: Some(ICC.to_vec()),
+        xmp: Some(XMP.to_vec()),
+    };
+    fs::write(input.join("tagged.webp"), embed(&webp, &tagged).unwrap()).unwrap();
+    assert!(!Metadata::read(&sideways_jpeg()).has_location());
+
+    let options = ConversionOptions {
+        strip_metadata: true,
+        ..ConversionOptions::default()
+    };
+    let summary = convert_images_to_webp(&input, false, &options).await;
+
+    assert_eq!((summary.converted, summary.copied), (2, 1));
+    assert_eq!(summary.located, 2);
+    let copy = fs::read(output_of(&input.join("tagged.webp"), "tagged.webp")).unwrap();
+    assert_eq!(
+        Metadata::read(&copy),
+        Metadata {
+            icc: Some(ICC.to_vec()),
+            ..Metadata::default()
+        }
+    );
+    assert_eq!(dimensions(&copy), (120, 160));
+}
@@ -0,0 +1,82 @@
+#![cfg(feature = "raw")]
+
+mod common;
+
+use common::{assert_webp, output_of, workspace};
+use image::{DynamicImage, ImageOutputFormat};
+use std::fs;
+use std::io::Cursor;
+use webp_converter::converter::{convert_images_to_webp, convert_single_photo};
+use webp_converter::types::ConversionOptions;
+
+fn jpeg(width: u32, height: u32) -> Vec<u8> {
+    let mut bytes = Cursor::new(vec![]);
+    DynamicImage::new_rgb8(width, height)
+        .write_to(&mut bytes, ImageOutputFormat::Jpeg(80))
+        .unwrap();
+    bytes.into_inner()
+}
+
+// A little-endian TIFF laid out like a NEF: IFD0 holds the orientation (rotate 90
+// degrees clockwise) and a 16x8 thumbnail, its SubIFD a 64x32 preview stored as a strip.
+fn nef() -> Vec<u8> {
+    let (thumbnail, preview) = (jpeg(16, 8), jpeg(64, 32));
+    let entry = |tag: u16, kind: u16, value: u32| {
+        let mut entry = vec![];
+        entry.extend(tag.to_le_bytes());
+        entry.extend(kind.to_le_bytes());
+        entry.extend(1u32.to_le_bytes());
+        entry.extend(value.to_le_bytes());
+        entry
+    };
+    let ifd = |entries: Vec<Vec<u8>>| {
+        let mut ifd = (entries.len() as u16).to_le_bytes().to_vec();
+        ifd.extend(entries.concat());
+        ifd.extend(0u32.to_le_bytes());
+        ifd
+    };
+    let ifd0_len = 2 + 4 * 12 + 4;
+    let sub_ifd_offset = 8 + ifd0_len;
+    let thumbnail_offset = sub_ifd_offset + 2 + 3 * 12 + 4;
+    let preview_offset = thumbnail_offset + thumbnail.len();
+
+    let mut file = b"II".to_vec();
+    file.extend(42u16.to_le_bytes());
+    file.extend(8u32.to_le_bytes());
+    file.extend(ifd(vec![
+        entry(0x0112, 3, 6),
+        entry(0x014a, 4, sub_ifd_offset as u32),
+        entry(0x0201, 4, thumbnail_offset as u32),
+        entry(0x0202, 4, thumbnail.len() as u32),
+    ]));
+    file.extend(ifd(vec![
+        entry(0x0103, 3, 6),
+        entry(0x0111, 4, preview_offset as u32),
+        entry(0x0117, 4, preview.len() as u32),
+    ]));
+    file.extend(thumbnail);
+    file.extend(preview);
+    file
+}
+
+#[tokio::test]
+async fn raw_files_convert_from_their_largest_preview_turned_upright() {
+    let (_dir, input) = workspace(&[]);
+    fs::write(input.join("DSC_0001.NEF"), nef()).unwrap();
+    fs::write(input.join("broken.dng"), b"II*\0 not really").unwrap();
+
+    let summary = convert_images_to_webp(&input, false, &ConversionOptions::default()).await;
+
+    assert_eq!((summary.converted, summary.failed), (1, 1));
+    let output = output_of(&input.join("DSC_0001.NEF"), "DSC_0001.webp");
+    assert_webp(&output, (32, 64));
+
+    let options = ConversionOptions {
+        apply_orientation: false,
+        ..ConversionOptions::default()
+    };
+    convert_single_photo(input.join("DSC_0001.NEF"), &options)
+        .await
+        .unwrap();
+    assert_webp(&output, (64, 32));
+}
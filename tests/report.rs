@@ -0,0 +1,101 @@
+use webp_converter::report::{diff_report, summary_report};
+use webp_converter::types::{ReportFormat, Summary};
+
+fn summary() -> Summary {
+    Summary {
+        matched: 4,
+        converted: 2,
+        copied: 1,
+        failed: 1,
+        input_bytes: 1000,
+        output_bytes: 250,
+        ..Summary::default()
+    }
+}
+
+#[test]
+fn csv_is_a_header_and_one_row() {
+    let csv = summary_report(&summary(), ReportFormat::Csv);
+    let lines: Vec<&str> = csv.lines().collect();
+
+    assert_eq!(lines.len(), 2);
+    assert_eq!(
+        lines[0].split(',').count(),
+        lines[1].split(',').count(),
+        "{}",
+        csv
+    );
+    assert!(lines[0].starts_with("matched,converted,"));
+    assert_eq!(lines[1], "4,2,0,1,0,1,0,0,1000,250,75.0,0,0,0,0,0.0,0,0");
+}
+
+#[test]
+fn json_has_every_count() {
+    let json: serde_json::Value =
+        serde_json::from_str(&summary_report(&summary(), ReportFormat::Json)).unwrap();
+
+    assert_eq!(json["matched"], 4);
+    assert_eq!(json["converted"], 2);
+    assert_eq!(json["failed"], 1);
+    assert_eq!(json["output_bytes"], 250);
+    assert_eq!(json["saved_percent"], 75.0);
+}
+
+#[test]
+fn text_is_the_log_line() {
+    assert!(summary_report(&summary(), ReportFormat::Text).starts_with("Summary: Converted: 2,"));
+}
+
+fn with_ratios(ratios: &[f64]) -> Summary {
+    Summary {
+        ratios: ratios.to_vec(),
+        ..Summary::default()
+    }
+}
+
+// The file count of each histogram row, `grew` to `10x+`.
+fn bucket_counts(report: &str) -> Vec<usize> {
+    report
+        .lines()
+        .filter(|line| line.contains(" | "))
+        .map(|line| line.rsplit(' ').next().unwrap().parse().unwrap())
+        .collect()
+}
+
+#[test]
+fn diff_report_puts_ratios_on_a_bucket_edge_in_the_bucket_above() {
+    let report = diff_report(&with_ratios(&[0.99, 1.0, 1.99, 2.0, 9.99, 10.0]));
+
+    assert!(
+        report.starts_with("Compression ratios (6 files):"),
+        "{}",
+        report
+    );
+    assert_eq!(bucket_counts(&report), [1, 2, 1, 0, 1, 1], "{}", report);
+    assert!(report.contains(" 10x+ | "), "{}", report);
+}
+
+#[test]
+fn diff_report_median_of_an_odd_and_an_even_count() {
+    let odd = diff_report(&with_ratios(&[4.0, 1.5, 3.0]));
+    assert!(
+        odd.ends_with("min 1.50x, median 3.00x, max 4.00x\n"),
+        "{}",
+        odd
+    );
+
+    let even = diff_report(&with_ratios(&[4.0, 1.5, 3.0, 2.0]));
+    assert!(
+        even.ends_with("min 1.50x, median 2.50x, max 4.00x\n"),
+        "{}",
+        even
+    );
+}
+
+#[test]
+fn diff_report_of_an_empty_summary_says_so() {
+    assert_eq!(
+        diff_report(&Summary::default()),
+        "No files were converted, nothing to report.\n"
+    );
+}
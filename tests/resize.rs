@@ -0,0 +1,186 @@
+mod common;
+
+use common::{assert_webp, output_of, workspace};
+use image::{DynamicImage, GenericImageView};
+use std::path::Path;
+use std::process::Command;
+use webp_converter::converter::{convert_images_to_webp, resize_factor, resize_image};
+use webp_converter::types::{ConversionOptions, ResizeMode, ResizeTarget};
+
+const LANDSCAPE: (u32, u32) = (1400, 800);
+const PORTRAIT: (u32, u32) = (800, 1400);
+const SQUARE: (u32, u32) = (1000, 1000);
+// Taller than the box, but its shortest side already fits.
+const NARROW: (u32, u32) = (600, 900);
+
+fn resized(dimensions: (u32, u32), mode: ResizeMode) -> (u32, u32) {
+    resized_to(dimensions, mode, ResizeTarget::default())
+}
+
+fn resized_to(dimensions: (u32, u32), mode: ResizeMode, target: ResizeTarget) -> (u32, u32) {
+    let image = DynamicImage::new_rgb8(dimensions.0, dimensions.1);
+    resize_image(image, mode, target).dimensions()
+}
+
+#[test]
+fn fit_shrinks_into_the_box() {
+    assert_eq!(resized(LANDSCAPE, ResizeMode::Fit), (700, 400));
+    assert_eq!(resized(PORTRAIT, ResizeMode::Fit), (400, 700));
+    assert_eq!(resized(SQUARE, ResizeMode::Fit), (700, 700));
+    assert_eq!(resized(NARROW, ResizeMode::Fit), (467, 700));
+    assert_eq!(resized((300, 200), ResizeMode::Fit), (300, 200));
+}
+
+#[test]
+fn fill_always_gives_the_box() {
+    for dimensions in [LANDSCAPE, PORTRAIT, SQUARE, NARROW, (300, 200)] {
+        assert_eq!(resized(dimensions, ResizeMode::Fill), (700, 700));
+    }
+}
+
+#[test]
+fn shrink_longest_matches_fit() {
+    for dimensions in [LANDSCAPE, PORTRAIT, SQUARE, NARROW, (300, 200)] {
+        assert_eq!(
+            resized(dimensions, ResizeMode::ShrinkLongest),
+            resized(dimensions, ResizeMode::Fit)
+        );
+    }
+}
+
+#[test]
+fn shrink_shortest_keeps_a_minimum_dimension() {
+    assert_eq!(resized(LANDSCAPE, ResizeMode::ShrinkShortest), (1225, 700));
+    assert_eq!(resized(PORTRAIT, ResizeMode::ShrinkShortest), (700, 1225));
+    assert_eq!(resized(SQUARE, ResizeMode::ShrinkShortest), (700, 700));
+    assert_eq!(resized(NARROW, ResizeMode::ShrinkShortest), (600, 900));
+}
+
+#[test]
+fn resize_factor_is_the_per_side_downscale() {
+    assert_eq!(
+        resize_factor(LANDSCAPE, ResizeMode::Fit, ResizeTarget::default()),
+        2.0
+    );
+    assert_eq!(
+        resize_factor(
+            LANDSCAPE,
+            ResizeMode::ShrinkShortest,
+            ResizeTarget::default()
+        ),
+        800.0 / 700.0
+    );
+    assert_eq!(
+        resize_factor(NARROW, ResizeMode::ShrinkShortest, ResizeTarget::default()),
+        1.0
+    );
+    assert_eq!(
+        resize_factor((300, 200), ResizeMode::Fit, ResizeTarget::default()),
+        1.0
+    );
+    assert_eq!(
+        resize_factor((350, 350), ResizeMode::Fill, ResizeTarget::default()),
+        0.5
+    );
+}
+
+#[test]
+fn the_target_can_be_any_box_or_a_percentage() {
+    let wide = ResizeTarget::Box {
+        width: Some(1000),
+        height: Some(200),
+    };
+    assert_eq!(resized_to(LANDSCAPE, ResizeMode::Fit, wide), (350, 200));
+    assert_eq!(resized_to(LANDSCAPE, ResizeMode::Fill, wide), (1000, 200));
+    let narrow = ResizeTarget::Box {
+        width: Some(350),
+        height: None,
+    };
+    assert_eq!(resized_to(PORTRAIT, ResizeMode::Fit, narrow), (350, 613));
+    assert_eq!(resized_to(NARROW, ResizeMode::Fit, narrow), (350, 525));
+    let square = ResizeTarget::square(500);
+    assert_eq!(
+        resized_to(LANDSCAPE, ResizeMode::ShrinkShortest, square),
+        (875, 500)
+    );
+
+    let half = ResizeTarget::Scale(50.0);
+    assert_eq!(resized_to(LANDSCAPE, ResizeMode::Fit, half), (700, 400));
+    assert_eq!(resized_to((3, 3), ResizeMode::Fit, half), (2, 2));
+    assert_eq!(resize_factor(SQUARE, ResizeMode::Fit, half), 2.0);
+    assert_eq!(
+        resize_factor(SQUARE, ResizeMode::Fit, ResizeTarget::Scale(100.0)),
+        1.0
+    );
+}
+
+// A 400x200 image in a folder of its own.
+fn large_photo() -> (tempfile::TempDir, std::path::PathBuf) {
+    let (dir, input) = workspace(&[]);
+    DynamicImage::new_rgb8(400, 200)
+        .save(input.join("large.png"))
+        .unwrap();
+    (dir, input)
+}
+
+#[tokio::test]
+async fn max_resize_factor_skips_downscales_past_it() {
+    for (max_factor, converted) in [(1.5, false), (2.0, true), (3.0, true)] {
+        let (_dir, input) = large_photo();
+        let options = ConversionOptions {
+            should_resize: true,
+            // 2x down.
+            resize_target: ResizeTarget::Box {
+                width: Some(200),
+                height: None,
+            },
+            max_resize_factor: Some(max_factor),
+            ..ConversionOptions::default()
+        };
+
+        let summary = convert_images_to_webp(&input, false, &options).await;
+
+        let output = output_of(&input.join("large.png"), "large.webp");
+        if converted {
+            assert_eq!(
+                (summary.converted, summary.skipped),
+                (1, 0),
+                "{}",
+                max_factor
+            );
+            assert_webp(&output, (200, 100));
+        } else {
+            // Skipped rather than failed, and not written at full size either.
+            assert_eq!((summary.skipped, summary.failed), (1, 0), "{}", max_factor);
+            assert!(!output.exists());
+        }
+    }
+}
+
+#[test]
+fn downscales_past_4x_are_logged_as_warnings() {
+    let (_dir, input) = large_photo();
+    let run = |input: &Path, max_width: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_webp_converter"))
+            .arg("-p")
+            .arg(input)
+            .args(["--max-width", max_width])
+            .output()
+            .expect("run webp_converter");
+        assert_eq!(output.status.code(), Some(0));
+        String::from_utf8_lossy(&output.stderr).to_string()
+    };
+
+    let log = run(&input, "80");
+    assert!(log.contains("WARN"), "{}", log);
+    assert!(
+        log.contains("400x200 -> 80x40 (5.0x smaller), fine detail will be lost"),
+        "{}",
+        log
+    );
+
+    let (_dir, input) = large_photo();
+    let log = run(&input, "200");
+    assert!(log.contains("400x200 -> 200x100 (2.0x smaller)"), "{}", log);
+    assert!(!log.contains("fine detail will be lost"), "{}", log);
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use common::{fixture, output_of, workspace};
+use std::collections::BTreeMap;
+use std::fs;
+use webp_converter::converter::convert_to_spritesheet;
+use webp_converter::sprite::{pack, SpriteRect};
+use webp_converter::types::ConversionOptions;
+
+fn overlap(a: &SpriteRect, b: &SpriteRect, padding: u32) -> bool {
+    a.x < b.x + b.width + padding
+        && b.x < a.x + a.width + padding
+        && a.y < b.y + b.height + padding
+        && b.y < a.y + a.height + padding
+}
+
+#[test]
+fn packed_rectangles_keep_their_padding_inside_the_width() {
+    let sizes = [(50, 20), (30, 40), (70, 10), (20, 20), (64, 64), (10, 50)];
+
+    let (rects, (width, height)) = pack(&sizes, 2, 100).unwrap();
+
+    assert!(width <= 100);
+    for (i, (rect, size)) in rects.iter().zip(sizes).enumerate() {
+        assert_eq!((rect.width, rect.height), size);
+        assert!(rect.x + rect.width <= width && rect.y + rect.height <= height);
+        for other in &rects[i + 1..] {
+            assert!(!overlap(rect, other, 2), "{:?} and {:?}", rect, other);
+        }
+    }
+    assert!(pack(&[(101, 1)], 0, 100).is_err());
+}
+
+#[tokio::test]
+async fn a_folder_becomes_one_sheet_and_its_map() {
+    let (_dir, input) = workspace(&[
+        ("transparent.png", "transparent.png"),
+        ("opaque.jpg", "buttons/opaque.jpg"),
+    ]);
+    let options = ConversionOptions {
+        lossless: 1,
+        sprite_padding: 1,
+        ..ConversionOptions::default()
+    };
+
+    let record = convert_to_spritesheet(&input, true, &options)
+        .await
+        .unwrap();
+
+    let anchor = input.join("input");
+    assert_eq!(record.output, output_of(&anchor, "input.webp"));
+    let json = fs::read_to_string(output_of(&anchor, "input.json")).unwrap();
+    let map: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(map["image"], "input.webp");
+    let sprites: BTreeMap<String, serde_json::Value> =
+        serde_json::from_value(map["sprites"].clone()).unwrap();
+    assert_eq!(
+        sprites.keys().collect::<Vec<_>>(),
+        ["buttons/opaque.jpg", "transparent.png"]
+    );
+
+    // Lossless (near-lossless 75 by default), so every sprite comes back at its
+    // rectangle within a few levels.
+    let bytes = fs::read(&record.output).unwrap();
+    let sheet = webp::Decoder::new(&bytes).decode().unwrap().to_image();
+    assert_eq!(
+        (sheet.width(), sheet.height()),
+        (
+            map["width"].as_u64().unwrap() as u32,
+            map["height"].as_u64().unwrap() as u32
+        )
+    );
+    let rect = &sprites["buttons/opaque.jpg"];
+    let field = |name: &str| rect[name].as_u64().unwrap() as u32;
+    let sprite = image::open(fixture("opaque.jpg")).unwrap();
+    let cropped = sheet.crop_imm(field("x"), field("y"), field("width"), field("height"));
+    let (sheet_pixels, sprite_pixels) = (cropped.to_rgba8(), sprite.to_rgba8());
+    let off = sheet_pixels
+        .pixels()
+        .zip(sprite_pixels.pixels())
+        .flat_map(|(a, b)| a.0.into_iter().zip(b.0).map(|(a, b)| a.abs_diff(b)))
+        .max();
+    assert!(off <= Some(4), "{:?}", off);
+
+    // A second run leaves the first sheet out.
+    let again = convert_to_spritesheet(&input, true, &options)
+        .await
+        .unwrap();
+    assert_eq!(again.input_size, record.input_size);
+}
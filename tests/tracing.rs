@@ -0,0 +1,104 @@
+#![cfg(feature = "tracing")]
+
+mod common;
+
+use common::workspace;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+use tracing_core::span::Current;
+use webp_converter::converter::convert_images_to_webp;
+use webp_converter::types::ConversionOptions;
+
+// Writes every span as `name field=value ...`, with fields recorded later appended.
+// Tracks the entered spans so `Span::current()` works; the runtime runs on one thread.
+#[derive(Default, Clone)]
+struct Spans {
+    spans: Arc<Mutex<Vec<String>>>,
+    metadata: Arc<Mutex<Vec<&'static Metadata<'static>>>>,
+    entered: Arc<Mutex<Vec<Id>>>,
+}
+
+struct Line<'a>(&'a mut String);
+
+impl Visit for Line<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0.push_str(&format!(" {}={:?}", field.name(), value));
+    }
+}
+
+impl Subscriber for Spans {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut line = span.metadata().name().to_string();
+        span.record(&mut Line(&mut line));
+        let mut spans = self.spans.lock().unwrap();
+        spans.push(line);
+        self.metadata.lock().unwrap().push(span.metadata());
+        Id::from_u64(spans.len() as u64)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut spans = self.spans.lock().unwrap();
+        values.record(&mut Line(&mut spans[span.into_u64() as usize - 1]));
+    }
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+    fn event(&self, _: &Event<'_>) {}
+    fn enter(&self, span: &Id) {
+        self.entered.lock().unwrap().push(span.clone());
+    }
+
+    fn exit(&self, _: &Id) {
+        self.entered.lock().unwrap().pop();
+    }
+
+    fn current_span(&self) -> Current {
+        match self.entered.lock().unwrap().last() {
+            Some(id) => Current::new(
+                id.clone(),
+                self.metadata.lock().unwrap()[id.into_u64() as usize - 1],
+            ),
+            None => Current::none(),
+        }
+    }
+}
+
+#[test]
+fn each_file_gets_a_span_with_its_path_and_sizes() {
+    let (_dir, input) = workspace(&[("opaque.jpg", "opaque.jpg")]);
+    let subscriber = Spans::default();
+    let spans = subscriber.spans.clone();
+
+    tracing::subscriber::with_default(subscriber, || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(convert_images_to_webp(
+            &input,
+            false,
+            &ConversionOptions::default(),
+        ));
+    });
+
+    let spans = spans.lock().unwrap();
+    let find = |name: &str| {
+        spans
+            .iter()
+            .find(|span| span.split(' ').next() == Some(name))
+            .unwrap_or_else(|| panic!("no {} span in {:?}", name, spans))
+    };
+    assert!(find("walk").contains(" files=1"));
+    let convert = find("convert");
+    assert!(convert.contains("opaque.jpg"), "{}", convert);
+    assert!(convert.contains(" input_size=15524"), "{}", convert);
+    assert!(convert.contains(" output_size="), "{}", convert);
+    for name in ["decode", "encode", "write"] {
+        find(name);
+    }
+}